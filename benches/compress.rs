@@ -0,0 +1,56 @@
+//! Compression and checksum throughput benchmarks.
+//!
+//! To compare deflate backends, run these once with the default features,
+//! which use miniz_oxide, once with `--features zlib-ng` and once with
+//! `--features zlib-rs`.
+
+use std::io;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tempdir::TempDir;
+
+fn compress(c: &mut Criterion) {
+    let t = TempDir::new("makecab-bench").expect("failed to create temp dir");
+    let data = include_bytes!("../testdata/firefox.exe");
+    let input = t.path().join("firefox.exe");
+    std::fs::write(&input, &data[..]).expect("failed to write test data");
+
+    let mut group = c.benchmark_group("compress");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("firefox.exe", |b| {
+        b.iter(|| makecab::make_cab_to_writer(io::sink(), &input).expect("failed to create cab"))
+    });
+    // Compressing blocks independently goes through flate2 directly, rather
+    // than through the cab crate.
+    for level in [1, 6, 9] {
+        let options = makecab::CabOptions {
+            compression_level: Some(level),
+            ..makecab::CabOptions::default()
+        };
+        group.bench_function(format!("firefox.exe level {}", level), |b| {
+            b.iter(|| {
+                makecab::make_cab_to_writer_with_options(io::sink(), &input, &options)
+                    .expect("failed to create cab")
+            })
+        });
+    }
+    group.finish();
+}
+
+fn checksum(c: &mut Criterion) {
+    let data = include_bytes!("../testdata/firefox.exe");
+
+    let mut group = c.benchmark_group("checksum");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("firefox.exe", |b| {
+        b.iter(|| makecab::format::checksum(&data[..], 0))
+    });
+    // A data block's worth, since that's what gets checksummed at once.
+    let block = &data[..32 * 1024];
+    group.throughput(Throughput::Bytes(block.len() as u64));
+    group.bench_function("32K", |b| b.iter(|| makecab::format::checksum(block, 0)));
+    group.finish();
+}
+
+criterion_group!(benches, compress, checksum);
+criterion_main!(benches);
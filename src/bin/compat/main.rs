@@ -0,0 +1,281 @@
+//! Compare the cabinets this crate's `makecab` writes with the ones Windows'
+//! own `makecab.exe` writes for the same inputs.
+//!
+//! This is only built with the `compat` feature, and needs `makecab.exe`, so
+//! it's only useful on Windows:
+//!
+//! ```text
+//! cargo build --bin makecab
+//! cargo run --features compat --bin makecab-compat -- file.txt other.dll
+//! ```
+//!
+//! Every input is compressed by both tools with each set of flags in
+//! [`FLAGS`], and the two cabinets are compared header field by header
+//! field, folder by folder and block by block, and file by file, including
+//! the extracted contents. Compressed sizes are expected to differ, since
+//! the tools use different deflate implementations, so they're not
+//! compared. Any other difference is printed, and the exit code is 1 if
+//! there were any.
+
+use std::ffi::OsString;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::{env, fs};
+
+use anyhow::{bail, Result};
+use cab::Cabinet;
+use clap::{arg, App};
+use makecab::format::parse::parse_layout;
+use makecab::format::Layout;
+use tempfile::TempDir;
+
+/// The sets of flags both tools understand, each with a label for the
+/// report. Flags are written the way `makecab` takes them, and switched to
+/// `/` for `makecab.exe`.
+const FLAGS: &[(&str, &[&str])] = &[
+    ("default", &[]),
+    ("mszip", &["-D", "CompressionType=MSZIP"]),
+];
+
+fn main() {
+    let matches = App::new("makecab-compat")
+        .about("Compare the cabinet files makecab and makecab.exe write for the same inputs")
+        .arg(
+            arg!(--ours <PATH> "makecab to test [default: the makecab next to this program]")
+                .required(false)
+                .allow_invalid_utf8(true),
+        )
+        .arg(
+            arg!(--theirs <PATH> "makecab.exe to compare it with")
+                .required(false)
+                .allow_invalid_utf8(true)
+                .default_value("makecab.exe"),
+        )
+        .arg(arg!(<input>... "Files to compress").allow_invalid_utf8(true))
+        .get_matches();
+    let ours = match matches.value_of_os("ours") {
+        Some(ours) => PathBuf::from(ours),
+        None => {
+            let exe = env::current_exe().expect("couldn't find this program");
+            exe.with_file_name(format!("makecab{}", env::consts::EXE_SUFFIX))
+        }
+    };
+    let theirs = PathBuf::from(matches.value_of_os("theirs").unwrap());
+    let mut differences = 0;
+    for input in matches.values_of_os("input").unwrap() {
+        for (label, flags) in FLAGS {
+            println!("{} ({})", Path::new(input).display(), label);
+            match compare_tools(&ours, &theirs, Path::new(input), flags) {
+                Ok(diffs) => {
+                    for diff in &diffs {
+                        println!("  {}", diff);
+                    }
+                    differences += diffs.len();
+                }
+                Err(e) => {
+                    println!("  error: {}", e);
+                    differences += 1;
+                }
+            }
+        }
+    }
+    if differences > 0 {
+        println!("{} differences", differences);
+        process::exit(1);
+    }
+}
+
+/// Compress `input` with `flags` using both `ours` and `theirs`, and list
+/// the differences between the cabinets they write.
+fn compare_tools(ours: &Path, theirs: &Path, input: &Path, flags: &[&str]) -> Result<Vec<String>> {
+    if !cfg!(windows) {
+        bail!("makecab.exe only runs on Windows");
+    }
+    let input = fs::canonicalize(input)?;
+    let dir = TempDir::new()?;
+    let our_cab = run(
+        ours,
+        flags.iter().map(OsString::from),
+        &input,
+        dir.path(),
+        "ours.cab",
+    )?;
+    let their_flags = flags.iter().map(|flag| match flag.strip_prefix('-') {
+        Some(flag) => OsString::from(format!("/{}", flag)),
+        None => OsString::from(flag),
+    });
+    let their_cab = run(theirs, their_flags, &input, dir.path(), "theirs.cab")?;
+    compare_cabs(&our_cab, &their_cab)
+}
+
+/// Run `tool` in `dir` to compress `input` into `dest`, returning the path
+/// of the cabinet it wrote.
+fn run<I>(tool: &Path, flags: I, input: &Path, dir: &Path, dest: &str) -> Result<PathBuf>
+where
+    I: IntoIterator<Item = OsString>,
+{
+    let status = Command::new(tool)
+        .args(flags)
+        .arg(input)
+        .arg(dest)
+        .current_dir(dir)
+        .stdout(process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        bail!("{} failed: {}", tool.display(), status);
+    }
+    Ok(dir.join(dest))
+}
+
+/// List the differences between the cabinet files at `ours` and `theirs`,
+/// other than in their compressed sizes.
+fn compare_cabs(ours: &Path, theirs: &Path) -> Result<Vec<String>> {
+    let mut diffs = vec![];
+    let our_layout = parse_layout(BufReader::new(File::open(ours)?))?;
+    let their_layout = parse_layout(BufReader::new(File::open(theirs)?))?;
+    compare_layouts(&our_layout, &their_layout, &mut diffs);
+
+    let mut our_cab = Cabinet::new(File::open(ours)?)?;
+    let mut their_cab = Cabinet::new(File::open(theirs)?)?;
+    for file in &our_layout.files {
+        if !their_layout.files.iter().any(|f| f.name == file.name) {
+            continue;
+        }
+        let (mut our_data, mut their_data) = (vec![], vec![]);
+        our_cab.read_file(&file.name)?.read_to_end(&mut our_data)?;
+        their_cab
+            .read_file(&file.name)?
+            .read_to_end(&mut their_data)?;
+        if our_data != their_data {
+            diffs.push(format!("{}: extracted contents differ", file.name));
+        }
+    }
+    Ok(diffs)
+}
+
+/// Add the differences between the headers, folders, data blocks and file
+/// entries of `ours` and `theirs` to `diffs`.
+fn compare_layouts(ours: &Layout, theirs: &Layout, diffs: &mut Vec<String>) {
+    let (a, b) = (&ours.header, &theirs.header);
+    field(
+        diffs,
+        "header: versionMinor",
+        a.version_minor,
+        b.version_minor,
+    );
+    field(
+        diffs,
+        "header: versionMajor",
+        a.version_major,
+        b.version_major,
+    );
+    field(diffs, "header: cFolders", a.folder_count, b.folder_count);
+    field(diffs, "header: cFiles", a.file_count, b.file_count);
+    field(diffs, "header: flags", a.flags, b.flags);
+    field(diffs, "header: setID", a.set_id, b.set_id);
+    field(diffs, "header: iCabinet", a.cabinet_index, b.cabinet_index);
+    field(
+        diffs,
+        "header: cbCFHeader",
+        a.header_reserve.len(),
+        b.header_reserve.len(),
+    );
+    field(
+        diffs,
+        "header: cbCFFolder",
+        a.folder_reserve_size,
+        b.folder_reserve_size,
+    );
+    field(
+        diffs,
+        "header: cbCFData",
+        a.data_reserve_size,
+        b.data_reserve_size,
+    );
+    field(diffs, "header: coffFiles", a.files_offset, b.files_offset);
+
+    for (i, ((a, a_blocks), (b, b_blocks))) in ours.folders.iter().zip(&theirs.folders).enumerate()
+    {
+        let what = |field| format!("folder {}: {}", i, field);
+        field(diffs, &what("typeCompress"), a.compression, b.compression);
+        field(diffs, &what("cCFData"), a_blocks.len(), b_blocks.len());
+        let sizes = |blocks: &[makecab::format::DataBlock]| {
+            blocks
+                .iter()
+                .map(|block| block.uncompressed_size)
+                .collect::<Vec<_>>()
+        };
+        field(
+            diffs,
+            &what("block cbUncomp"),
+            sizes(a_blocks),
+            sizes(b_blocks),
+        );
+    }
+
+    for (a, b) in ours.files.iter().zip(&theirs.files) {
+        if a.name != b.name {
+            diffs.push(format!("file name: {:?} != {:?}", a.name, b.name));
+            continue;
+        }
+        let what = |field| format!("{}: {}", a.name, field);
+        field(diffs, &what("cbFile"), a.size, b.size);
+        field(
+            diffs,
+            &what("uoffFolderStart"),
+            a.folder_offset,
+            b.folder_offset,
+        );
+        field(diffs, &what("iFolder"), a.folder, b.folder);
+        field(diffs, &what("date"), a.date, b.date);
+        field(diffs, &what("time"), a.time, b.time);
+        field(diffs, &what("attribs"), a.attributes, b.attributes);
+    }
+}
+
+/// Add a difference to `diffs` if `ours` and `theirs` aren't equal.
+fn field<T: PartialEq + Debug>(diffs: &mut Vec<String>, what: &str, ours: T, theirs: T) {
+    if ours != theirs {
+        diffs.push(format!("{}: {:?} != {:?}", what, ours, theirs));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+
+    use self::tempdir::TempDir;
+    use makecab::{make_cab_from_files, make_cab_from_files_with_options, CabOptions};
+
+    use super::compare_cabs;
+
+    #[test]
+    fn compare() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let input = t.path().join("a.bin");
+        let data = (0..100_000)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(&input, &data).unwrap();
+        let (a, b) = (t.path().join("a.cab"), t.path().join("b.cab"));
+        make_cab_from_files(&a, &[&input]).expect("failed to create cab file");
+        make_cab_from_files(&b, &[&input]).expect("failed to create cab file");
+        assert_eq!(compare_cabs(&a, &b).unwrap(), Vec::<String>::new());
+
+        let options = CabOptions {
+            chunk_size: Some(16384),
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&b, &[&input], &options)
+            .expect("failed to create cab file");
+        let diffs = compare_cabs(&a, &b).unwrap();
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0], "folder 0: cCFData: 4 != 7");
+        assert!(diffs[1].starts_with("folder 0: block cbUncomp: "));
+    }
+}
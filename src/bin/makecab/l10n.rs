@@ -0,0 +1,130 @@
+//! Localized messages for the command-line tool.
+//!
+//! Messages are looked up in the [Fluent] bundle for the user's locale,
+//! falling back to English for locales and messages that haven't been
+//! translated. Microsoft's `makecab` is localized too, and some wrapper
+//! scripts match on its localized output.
+//!
+//! [Fluent]: https://projectfluent.org/
+
+use fluent::{FluentArgs, FluentBundle, FluentResource};
+use unic_langid::LanguageIdentifier;
+
+/// The translations, with the fallback first.
+const LOCALES: &[(&str, &str)] = &[
+    ("en-US", include_str!("locales/en-US.ftl")),
+    ("de", include_str!("locales/de.ftl")),
+    ("ja", include_str!("locales/ja.ftl")),
+];
+
+/// Looks up localized messages.
+pub struct Localizer {
+    /// The bundle for the user's locale, if there is one, then English.
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+impl Localizer {
+    /// Create a localizer for the current user's locale.
+    pub fn new() -> Localizer {
+        Localizer::for_locale(sys_locale::get_locale().as_deref())
+    }
+
+    /// Create a localizer for `locale`, a BCP 47 language tag like `de-DE`.
+    /// Only the language is used to pick a translation.
+    pub fn for_locale(locale: Option<&str>) -> Localizer {
+        let language = locale
+            .and_then(|locale| locale.parse::<LanguageIdentifier>().ok())
+            .map(|langid| langid.language);
+        let mut bundles = vec![];
+        for (i, (tag, source)) in LOCALES.iter().enumerate().rev() {
+            let langid = tag.parse::<LanguageIdentifier>().unwrap();
+            if i == 0 || Some(langid.language) == language {
+                bundles.push(bundle(langid, source));
+            }
+        }
+        Localizer { bundles }
+    }
+
+    /// Format the message `id` with `args`. Returns `id` itself if there's
+    /// no such message.
+    pub fn get(&self, id: &str, args: Option<&FluentArgs>) -> String {
+        for bundle in &self.bundles {
+            if let Some(pattern) = bundle.get_message(id).and_then(|msg| msg.value()) {
+                let mut errors = vec![];
+                return bundle
+                    .format_pattern(pattern, args, &mut errors)
+                    .into_owned();
+            }
+        }
+        id.to_owned()
+    }
+}
+
+fn bundle(langid: LanguageIdentifier, source: &str) -> FluentBundle<FluentResource> {
+    let resource =
+        FluentResource::try_new(source.to_owned()).unwrap_or_else(|(resource, _)| resource);
+    let mut bundle = FluentBundle::new(vec![langid]);
+    // Unicode isolation marks around arguments just get in the way in a
+    // terminal.
+    bundle.set_use_isolating(false);
+    let _ = bundle.add_resource(resource);
+    bundle
+}
+
+/// Format a localized message, like `t!(l10n, "id", name = value)`.
+macro_rules! t {
+    ($l10n:expr, $id:expr) => {
+        $l10n.get($id, None)
+    };
+    ($l10n:expr, $id:expr, $($key:ident = $value:expr),+ $(,)?) => {
+        $l10n.get($id, Some(&fluent::fluent_args![$(stringify!($key) => $value),+]))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Localizer, LOCALES};
+    use fluent::FluentResource;
+
+    fn ids(source: &str) -> Vec<&str> {
+        if let Err((_, errors)) = FluentResource::try_new(source.to_owned()) {
+            panic!("failed to parse: {:?}", errors);
+        }
+        let mut ids = source
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_alphabetic()))
+            .map(|line| line.split('=').next().unwrap().trim())
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn translations_complete() {
+        let english = ids(LOCALES[0].1);
+        for (tag, source) in &LOCALES[1..] {
+            assert_eq!(ids(source), english, "messages in {} differ", tag);
+        }
+    }
+
+    #[test]
+    fn locale_fallback() {
+        let l10n = Localizer::for_locale(Some("de-AT"));
+        assert_eq!(
+            t!(l10n, "lint-clean", cab = "\"a.cab\""),
+            "\"a.cab\": keine Probleme gefunden"
+        );
+        assert_eq!(
+            t!(l10n, "watch-built", source = "s", count = 1, dest = "d"),
+            "s (1 Datei) -> d"
+        );
+        for locale in &[None, Some("fr-FR"), Some("C"), Some("en-GB")] {
+            let l10n = Localizer::for_locale(*locale);
+            assert_eq!(
+                t!(l10n, "watch-built", source = "s", count = 2, dest = "d"),
+                "s (2 files) -> d"
+            );
+        }
+        assert_eq!(t!(l10n, "no-such-message"), "no-such-message");
+    }
+}
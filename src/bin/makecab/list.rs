@@ -0,0 +1,318 @@
+//! Output formats for the `list` subcommand and `extract --list-only`.
+
+use std::fmt::Write;
+
+use makecab::{Attribute, CabListing, FileListing, PlannedFile};
+
+use crate::l10n::Localizer;
+use crate::with_commas;
+
+/// Format `listing` as tables of folders and files for people to read.
+pub fn table(listing: &CabListing, l10n: &Localizer) -> String {
+    let folders = listing
+        .folders
+        .iter()
+        .enumerate()
+        .map(|(i, folder)| {
+            vec![
+                i.to_string(),
+                folder.compression.clone(),
+                with_commas(u64::from(folder.blocks)),
+                with_commas(folder.size),
+                with_commas(folder.compressed_size),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let files = listing
+        .files
+        .iter()
+        .map(|file| {
+            vec![
+                with_commas(u64::from(file.size)),
+                modified(file, "%Y-%m-%d %H:%M:%S"),
+                attribute_letters(&file.attributes),
+                file.folder.to_string(),
+                file.name.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let mut out = format_table(
+        &[
+            t!(l10n, "list-folder"),
+            t!(l10n, "list-compression"),
+            t!(l10n, "list-blocks"),
+            t!(l10n, "list-size"),
+            t!(l10n, "list-compressed"),
+        ],
+        &folders,
+        &[true, false, true, true, true],
+    );
+    out.push('\n');
+    out += &format_table(
+        &[
+            t!(l10n, "list-size"),
+            t!(l10n, "list-modified"),
+            t!(l10n, "list-attributes"),
+            t!(l10n, "list-folder"),
+            t!(l10n, "list-name"),
+        ],
+        &files,
+        &[true, false, false, true, false],
+    );
+    out
+}
+
+/// Format the files `extract --list-only` would write as a table for
+/// people to read.
+pub fn plan_table(files: &[PlannedFile], l10n: &Localizer) -> String {
+    let rows = files
+        .iter()
+        .map(|file| {
+            vec![
+                with_commas(file.size),
+                with_commas(file.compressed_size),
+                format!("{:.1}%", 100.0 * file.ratio),
+                file.folder.to_string(),
+                file.path.display().to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    format_table(
+        &[
+            t!(l10n, "list-size"),
+            t!(l10n, "list-compressed"),
+            t!(l10n, "list-ratio"),
+            t!(l10n, "list-folder"),
+            t!(l10n, "list-path"),
+        ],
+        &rows,
+        &[true, true, true, true, false],
+    )
+}
+
+/// Lay out `rows` in columns under `headers`, right-aligning the columns
+/// marked in `right`. The last column isn't padded.
+fn format_table(headers: &[String], rows: &[Vec<String>], right: &[bool]) -> String {
+    let widths = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(header.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let mut out = String::new();
+    for row in Some(headers)
+        .into_iter()
+        .chain(rows.iter().map(Vec::as_slice))
+    {
+        let mut line = String::new();
+        for (i, cell) in row.iter().enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            let pad = " ".repeat(widths[i] - cell.chars().count());
+            if right[i] {
+                line.push_str(&pad);
+                line.push_str(cell);
+            } else if i + 1 < row.len() {
+                line.push_str(cell);
+                line.push_str(&pad);
+            } else {
+                line.push_str(cell);
+            }
+        }
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Format `listing` as CSV, with a header row and then one row per file,
+/// including details of the file's folder.
+pub fn csv(listing: &CabListing) -> String {
+    let mut out = String::from(
+        "name,size,modified,attributes,folder,compression,folder_size,folder_compressed_size\n",
+    );
+    for file in &listing.files {
+        let folder = listing.folders.get(usize::from(file.folder));
+        let fields = [
+            file.name.clone(),
+            file.size.to_string(),
+            modified(file, "%Y-%m-%dT%H:%M:%S"),
+            attribute_names(&file.attributes).join(" "),
+            file.folder.to_string(),
+            folder.map_or(String::new(), |f| f.compression.clone()),
+            folder.map_or(String::new(), |f| f.size.to_string()),
+            folder.map_or(String::new(), |f| f.compressed_size.to_string()),
+        ];
+        let fields = fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Quote `field` for CSV if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Format `listing` as YAML.
+pub fn yaml(listing: &CabListing) -> String {
+    let mut out = String::new();
+    if listing.folders.is_empty() {
+        out.push_str("folders: []\n");
+    } else {
+        out.push_str("folders:\n");
+    }
+    for folder in &listing.folders {
+        let _ = writeln!(out, "  - compression: {}", folder.compression);
+        let _ = writeln!(out, "    blocks: {}", folder.blocks);
+        let _ = writeln!(out, "    size: {}", folder.size);
+        let _ = writeln!(out, "    compressed_size: {}", folder.compressed_size);
+    }
+    if listing.files.is_empty() {
+        out.push_str("files: []\n");
+    } else {
+        out.push_str("files:\n");
+    }
+    for file in &listing.files {
+        // JSON strings are valid YAML double-quoted scalars.
+        let quote = |s: &str| serde_json::to_string(s).unwrap();
+        let _ = writeln!(out, "  - name: {}", quote(&file.name));
+        let _ = writeln!(out, "    folder: {}", file.folder);
+        let _ = writeln!(out, "    size: {}", file.size);
+        match file.modified {
+            Some(_) => {
+                let modified = modified(file, "%Y-%m-%dT%H:%M:%S");
+                let _ = writeln!(out, "    modified: {}", quote(&modified));
+            }
+            None => out.push_str("    modified: null\n"),
+        }
+        let _ = writeln!(
+            out,
+            "    attributes: [{}]",
+            attribute_names(&file.attributes).join(", ")
+        );
+    }
+    out
+}
+
+/// The modification time of `file` in `format`, or an empty string if it
+/// isn't valid.
+fn modified(file: &FileListing, format: &str) -> String {
+    file.modified
+        .map_or(String::new(), |m| m.format(format).to_string())
+}
+
+/// The names of `attributes`, as written in manifests.
+fn attribute_names(attributes: &[Attribute]) -> Vec<String> {
+    attributes
+        .iter()
+        .map(|a| {
+            serde_json::to_value(a)
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .to_owned()
+        })
+        .collect()
+}
+
+/// `attributes` as letters in fixed columns, like `dir` and `attrib` show
+/// them, with `-` for attributes that aren't set.
+fn attribute_letters(attributes: &[Attribute]) -> String {
+    [
+        (Attribute::ReadOnly, 'R'),
+        (Attribute::Hidden, 'H'),
+        (Attribute::System, 'S'),
+        (Attribute::Archive, 'A'),
+        (Attribute::Exec, 'X'),
+    ]
+    .iter()
+    .map(|&(attribute, letter)| {
+        if attributes.contains(&attribute) {
+            letter
+        } else {
+            '-'
+        }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use makecab::{Attribute, CabListing, FileListing, FolderListing};
+
+    use super::{csv, format_table, yaml};
+
+    #[test]
+    fn formats() {
+        let listing = CabListing {
+            folders: vec![FolderListing {
+                compression: "MSZIP".to_owned(),
+                blocks: 1,
+                size: 13,
+                compressed_size: 21,
+            }],
+            files: vec![
+                FileListing {
+                    name: "a.txt".to_owned(),
+                    folder: 0,
+                    size: 10,
+                    modified: Some(NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8)),
+                    attributes: vec![Attribute::ReadOnly, Attribute::Archive],
+                },
+                FileListing {
+                    name: "b, \"c\".txt".to_owned(),
+                    folder: 0,
+                    size: 3,
+                    modified: None,
+                    attributes: vec![],
+                },
+            ],
+        };
+        assert_eq!(
+            csv(&listing),
+            "name,size,modified,attributes,folder,compression,folder_size,folder_compressed_size\n\
+             a.txt,10,2021-03-04T05:06:08,read-only archive,0,MSZIP,13,21\n\
+             \"b, \"\"c\"\".txt\",3,,,0,MSZIP,13,21\n"
+        );
+        assert_eq!(
+            yaml(&listing),
+            "folders:\n\
+             \x20 - compression: MSZIP\n\
+             \x20   blocks: 1\n\
+             \x20   size: 13\n\
+             \x20   compressed_size: 21\n\
+             files:\n\
+             \x20 - name: \"a.txt\"\n\
+             \x20   folder: 0\n\
+             \x20   size: 10\n\
+             \x20   modified: \"2021-03-04T05:06:08\"\n\
+             \x20   attributes: [read-only, archive]\n\
+             \x20 - name: \"b, \\\"c\\\".txt\"\n\
+             \x20   folder: 0\n\
+             \x20   size: 3\n\
+             \x20   modified: null\n\
+             \x20   attributes: []\n"
+        );
+        assert_eq!(
+            format_table(
+                &["Size".to_owned(), "Name".to_owned()],
+                &[vec!["1,000".to_owned(), "a.txt".to_owned()]],
+                &[true, false],
+            ),
+            " Size  Name\n1,000  a.txt\n"
+        );
+    }
+}
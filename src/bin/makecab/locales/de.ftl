@@ -0,0 +1,115 @@
+## Messages printed by the makecab command-line tool.
+##
+## Paths are passed in already quoted.
+
+error-directives = Fehler: Direktivendateien werden nicht unterstützt
+error-compression-type = Fehler: Nur '-D CompressionType=MSZIP', '-D ChunkSize=<Bytes>' und '-D CompressionLevel=<0-9>' werden unterstützt.
+error-chunk-size = Fehler: ChunkSize muss eine Anzahl von Bytes sein, nicht '{ $size }'
+error-compression-level = Fehler: CompressionLevel muss eine Zahl von 0 bis 9 sein, nicht '{ $level }'
+error-sha256-stdout = Fehler: --sha256 kann bei Ausgabe auf stdout nicht verwendet werden
+error-files-from-stdout = Fehler: --files-from kann bei Ausgabe auf stdout nicht verwendet werden
+error-name-by-hash-stdout = Fehler: --name-by-hash kann bei Ausgabe auf stdout nicht verwendet werden
+error-watch-stdout = Fehler: --watch kann bei Ausgabe auf stdout nicht verwendet werden
+error-driver-stdout = Fehler: --driver kann bei Ausgabe auf stdout nicht verwendet werden
+error-block-index-stdout = Fehler: --block-index kann bei Ausgabe auf stdout nicht verwendet werden
+error-max-size-stdout = Fehler: --max-size kann bei Ausgabe auf stdout nicht verwendet werden
+error-filter-not-dir = Fehler: --include und --exclude sind nur anwendbar, wenn die Quelle ein Verzeichnis ist
+error-datetime = Fehler: { $value } ist kein gültiges Datum mit Uhrzeit wie 2021-03-04T05:06:08
+error-size = Fehler: { $value } ist keine gültige Größe wie 650MB
+error-destination-name = Fehler: Aus { $source } lässt sich kein Zielname ableiten, bitte danach einen angeben
+bad-filter = Ungültiges Muster für --include oder --exclude: { $error }
+file-list-failed = Fehler beim Lesen der Dateiliste { $list }: { $error }
+response-file-failed = Fehler beim Lesen der Antwortdatei { $file }: { $error }
+writing = { $source } -> { $dest }
+write-failed = Fehler beim Schreiben der CAB-Datei: { $error }
+renamed = Umbenannt in { $cab }
+rename-failed = Fehler beim Umbenennen der CAB-Datei: { $error }
+wrote-manifest = { $manifest } geschrieben
+manifest-failed = Fehler beim Schreiben des SHA-256-Manifests: { $error }
+manifest-write-failed = Fehler beim Schreiben der CAB-Dateien aus { $manifest }: { $error }
+file-summary = { $name }: { $size } Bytes auf { $compressed } Bytes komprimiert
+block-summary = Block { $index } in Ordner { $folder }: { $size } Bytes auf { $compressed } Bytes komprimiert, bisher { $total_size } auf { $total_compressed } Bytes
+duplicate-renamed = Warnung: Unter { $name } war bereits eine andere Datei gespeichert, daher wurde diese als { $renamed } gespeichert
+summary = { $size } Bytes auf { $compressed } Bytes komprimiert ({ $savings } % Ersparnis) in { $seconds } Sekunden
+cat-failed = Fehler beim Lesen von { $member } aus der CAB-Datei: { $error }
+diff-matches = { $cab } stimmt mit { $dir } überein
+diff-failed = Fehler beim Vergleichen der CAB-Datei: { $error }
+verify-matches = { $cab } stimmt mit { $dir } überein
+verify-mismatches =
+    { $count ->
+        [one] { $count } Datei unterscheidet
+       *[other] { $count } Dateien unterscheiden
+    } sich zwischen { $cab } und { $dir }
+verify-failed = Fehler beim Prüfen der CAB-Datei gegen das Verzeichnis: { $error }
+verify-missing = - { $name }
+verify-extra = + { $name }
+verify-size = S { $name } ({ $cab } Bytes, { $disk } auf der Festplatte)
+verify-contents = M { $name }
+verify-timestamp = T { $name } ({ $seconds } s Abweichung von der Datei auf der Festplatte)
+lint-clean = { $cab }: keine Probleme gefunden
+read-failed = Fehler beim Lesen der CAB-Datei: { $error }
+rebuild-folder =
+    { $name }: { $blocks ->
+        [one] { $blocks } Block
+       *[other] { $blocks } Blöcke
+    } bei { $offset }, { $size } Bytes
+rebuild-failed = Fehler beim Wiederherstellen der CAB-Datei: { $error }
+watch-failed = Fehler beim Überwachen von { $dir }: { $error }
+watch-error = Fehler beim Überwachen von { $dir }: { $error }
+watch-built =
+    { $source } ({ $count ->
+        [one] { $count } Datei
+       *[other] { $count } Dateien
+    }) -> { $dest }
+list-folder = Ordner
+list-compression = Komprimierung
+list-blocks = Blöcke
+list-size = Größe
+list-compressed = Komprimiert
+list-modified = Geändert
+list-attributes = Attribute
+list-name = Name
+list-ratio = Verhältnis
+list-path = Pfad
+member-renamed = { $old } in { $new } umbenannt
+member-rename-failed = Eine Datei in { $cab } konnte nicht umbenannt werden: { $error }
+touched = Zeitstempel in { $cab } aktualisiert
+touch-failed = Zeitstempel in { $cab } konnten nicht aktualisiert werden: { $error }
+converted = { $input } in { $output } umgewandelt
+convert-failed = { $input } konnte nicht umgewandelt werden: { $error }
+joined =
+    { $count ->
+        [one] { $count } Cabinet
+       *[other] { $count } Cabinets
+    } zu { $cab } zusammengefügt
+join-failed = { $cab } konnte nicht geschrieben werden: { $error }
+split-cab = { $cab } geschrieben ({ $size } Bytes)
+split-failed = { $cab } konnte nicht aufgeteilt werden: { $error }
+test-ok = teste: { $name }  OK
+test-failed = teste: { $name }  FEHLER: { $error }
+test-clean = Keine Fehler in { $cab } gefunden
+test-errors =
+    { $count ->
+        [one] { $count } Datei
+       *[other] { $count } Dateien
+    } in { $cab } fehlerhaft
+extracted =
+    { $count ->
+        [one] { $count } Datei
+       *[other] { $count } Dateien
+    } nach { $dir } entpackt
+extract-failed = Dateien aus { $cab } konnten nicht entpackt werden: { $error }
+overwrite-prompt = { $path } überschreiben? [y/N]
+added =
+    { $count ->
+        [one] { $count } Datei
+       *[other] { $count } Dateien
+    } zu { $cab } hinzugefügt
+add-failed = Dateien konnten nicht zu { $cab } hinzugefügt werden: { $error }
+add-as-multiple = --as kann nur beim Hinzufügen einer einzelnen Datei verwendet werden
+removed =
+    { $count ->
+        [one] { $count } Datei
+       *[other] { $count } Dateien
+    } aus { $cab } entfernt
+remove-failed = Dateien konnten nicht aus { $cab } entfernt werden: { $error }
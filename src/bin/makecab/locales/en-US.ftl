@@ -0,0 +1,115 @@
+## Messages printed by the makecab command-line tool.
+##
+## Paths are passed in already quoted.
+
+error-directives = Error: directive files are not supported
+error-compression-type = Error: only '-D CompressionType=MSZIP', '-D ChunkSize=<bytes>' and '-D CompressionLevel=<0-9>' are supported.
+error-chunk-size = Error: ChunkSize must be a number of bytes, not '{ $size }'
+error-compression-level = Error: CompressionLevel must be a number from 0 to 9, not '{ $level }'
+error-sha256-stdout = Error: --sha256 can't be used when writing to stdout
+error-files-from-stdout = Error: --files-from can't be used when writing to stdout
+error-name-by-hash-stdout = Error: --name-by-hash can't be used when writing to stdout
+error-watch-stdout = Error: --watch can't be used when writing to stdout
+error-driver-stdout = Error: --driver can't be used when writing to stdout
+error-block-index-stdout = Error: --block-index can't be used when writing to stdout
+error-max-size-stdout = Error: --max-size can't be used when writing to stdout
+error-filter-not-dir = Error: --include and --exclude only apply when the source is a directory
+error-datetime = Error: { $value } isn't a valid date and time, like 2021-03-04T05:06:08
+error-size = Error: { $value } isn't a valid size, like 650MB
+error-destination-name = Error: can't work out a destination name from { $source }, so give one after it
+bad-filter = Bad --include or --exclude pattern: { $error }
+file-list-failed = Failed to read file list { $list }: { $error }
+response-file-failed = Failed to read response file { $file }: { $error }
+writing = { $source } -> { $dest }
+write-failed = Failed to write cab file: { $error }
+renamed = Renamed to { $cab }
+rename-failed = Failed to rename cab file: { $error }
+wrote-manifest = Wrote { $manifest }
+manifest-failed = Failed to write SHA-256 manifest: { $error }
+manifest-write-failed = Failed to write cab files from { $manifest }: { $error }
+file-summary = { $name }: { $size } bytes compressed to { $compressed } bytes
+block-summary = Block { $index } in folder { $folder }: { $size } bytes compressed to { $compressed } bytes, { $total_size } to { $total_compressed } bytes so far
+duplicate-renamed = Warning: another file was already stored as { $name }, so this one was stored as { $renamed }
+summary = { $size } bytes compressed to { $compressed } bytes ({ $savings }% savings) in { $seconds } seconds
+cat-failed = Failed to read { $member } from cab file: { $error }
+diff-matches = { $cab } matches { $dir }
+diff-failed = Failed to compare cab file: { $error }
+verify-matches = { $cab } matches { $dir }
+verify-mismatches =
+    { $count ->
+        [one] { $count } file differs
+       *[other] { $count } files differ
+    } between { $cab } and { $dir }
+verify-failed = Failed to verify cab file against directory: { $error }
+verify-missing = - { $name }
+verify-extra = + { $name }
+verify-size = S { $name } ({ $cab } bytes, { $disk } on disk)
+verify-contents = M { $name }
+verify-timestamp = T { $name } ({ $seconds }s from the file on disk)
+lint-clean = { $cab }: no problems found
+read-failed = Failed to read cab file: { $error }
+rebuild-folder =
+    { $name }: { $blocks ->
+        [one] { $blocks } block
+       *[other] { $blocks } blocks
+    } at { $offset }, { $size } bytes
+rebuild-failed = Failed to rebuild cab file: { $error }
+watch-failed = Failed to watch { $dir }: { $error }
+watch-error = Error watching { $dir }: { $error }
+watch-built =
+    { $source } ({ $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    }) -> { $dest }
+list-folder = Folder
+list-compression = Compression
+list-blocks = Blocks
+list-size = Size
+list-compressed = Compressed
+list-modified = Modified
+list-attributes = Attributes
+list-name = Name
+list-ratio = Ratio
+list-path = Path
+member-renamed = Renamed { $old } to { $new }
+member-rename-failed = Failed to rename a file in { $cab }: { $error }
+touched = Updated the timestamps in { $cab }
+touch-failed = Failed to update the timestamps in { $cab }: { $error }
+converted = Converted { $input } to { $output }
+convert-failed = Failed to convert { $input }: { $error }
+joined =
+    Joined { $count ->
+        [one] { $count } cabinet
+       *[other] { $count } cabinets
+    } into { $cab }
+join-failed = Failed to write { $cab }: { $error }
+split-cab = Wrote { $cab } ({ $size } bytes)
+split-failed = Failed to split { $cab }: { $error }
+test-ok = testing: { $name }  OK
+test-failed = testing: { $name }  FAILED: { $error }
+test-clean = No errors detected in { $cab }
+test-errors =
+    { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } failed in { $cab }
+extracted =
+    Extracted { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } to { $dir }
+extract-failed = Failed to extract files from { $cab }: { $error }
+overwrite-prompt = Overwrite { $path }? [y/N]
+added =
+    Added { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } to { $cab }
+add-failed = Failed to add files to { $cab }: { $error }
+add-as-multiple = --as can only be used when adding a single file
+removed =
+    Removed { $count ->
+        [one] { $count } file
+       *[other] { $count } files
+    } from { $cab }
+remove-failed = Failed to remove files from { $cab }: { $error }
@@ -0,0 +1,83 @@
+## Messages printed by the makecab command-line tool.
+##
+## Paths are passed in already quoted.
+
+error-directives = エラー: ディレクティブ ファイルはサポートされていません
+error-compression-type = エラー: '-D CompressionType=MSZIP'、'-D ChunkSize=<バイト数>'、'-D CompressionLevel=<0-9>' のみサポートされています。
+error-chunk-size = エラー: ChunkSize はバイト数で指定してください ('{ $size }' は無効です)
+error-compression-level = エラー: CompressionLevel は 0 から 9 の数値で指定してください ('{ $level }' は無効です)
+error-sha256-stdout = エラー: 標準出力に書き込む場合は --sha256 を使用できません
+error-files-from-stdout = エラー: 標準出力に書き込む場合は --files-from を使用できません
+error-name-by-hash-stdout = エラー: 標準出力に書き込む場合は --name-by-hash を使用できません
+error-watch-stdout = エラー: 標準出力に書き込む場合は --watch を使用できません
+error-driver-stdout = エラー: 標準出力に書き込む場合は --driver を使用できません
+error-block-index-stdout = エラー: 標準出力に書き込む場合は --block-index を使用できません
+error-max-size-stdout = エラー: 標準出力に書き込む場合は --max-size を使用できません
+error-filter-not-dir = エラー: --include と --exclude はソースがディレクトリの場合にのみ使用できます
+error-datetime = エラー: { $value } は有効な日時ではありません (例: 2021-03-04T05:06:08)
+error-size = エラー: { $value } は有効なサイズではありません (例: 650MB)
+error-destination-name = エラー: { $source } から出力先の名前を決められません。後ろに指定してください
+bad-filter = --include または --exclude のパターンが無効です: { $error }
+file-list-failed = ファイル リスト { $list } を読み込めませんでした: { $error }
+response-file-failed = 応答ファイル { $file } を読み込めませんでした: { $error }
+writing = { $source } -> { $dest }
+write-failed = CAB ファイルを書き込めませんでした: { $error }
+renamed = { $cab } に名前を変更しました
+rename-failed = CAB ファイルの名前を変更できませんでした: { $error }
+wrote-manifest = { $manifest } を書き込みました
+manifest-failed = SHA-256 マニフェストを書き込めませんでした: { $error }
+manifest-write-failed = { $manifest } から CAB ファイルを書き込めませんでした: { $error }
+file-summary = { $name }: { $size } バイトを { $compressed } バイトに圧縮しました
+block-summary = フォルダー { $folder } のブロック { $index }: { $size } バイトを { $compressed } バイトに圧縮しました (累計 { $total_size } バイトを { $total_compressed } バイトに)
+duplicate-renamed = 警告: { $name } には既に別のファイルが格納されているため、このファイルは { $renamed } として格納しました
+summary = { $size } バイトを { $compressed } バイトに圧縮しました (削減率 { $savings }%、{ $seconds } 秒)
+cat-failed = CAB ファイルから { $member } を読み取れませんでした: { $error }
+diff-matches = { $cab } は { $dir } と一致しています
+diff-failed = CAB ファイルを比較できませんでした: { $error }
+verify-matches = { $cab } は { $dir } と一致しています
+verify-mismatches = { $cab } と { $dir } で { $count } 個のファイルが異なります
+verify-failed = CAB ファイルをディレクトリと照合できませんでした: { $error }
+verify-missing = - { $name }
+verify-extra = + { $name }
+verify-size = S { $name } ({ $cab } バイト、ディスク上は { $disk } バイト)
+verify-contents = M { $name }
+verify-timestamp = T { $name } (ディスク上のファイルとの差 { $seconds } 秒)
+lint-clean = { $cab }: 問題は見つかりませんでした
+read-failed = CAB ファイルを読み取れませんでした: { $error }
+rebuild-folder = { $name }: { $offset } の { $blocks } ブロック、{ $size } バイト
+rebuild-failed = CAB ファイルを再構築できませんでした: { $error }
+watch-failed = { $dir } を監視できませんでした: { $error }
+watch-error = { $dir } の監視中にエラーが発生しました: { $error }
+watch-built = { $source } ({ $count } 個のファイル) -> { $dest }
+list-folder = フォルダー
+list-compression = 圧縮
+list-blocks = ブロック
+list-size = サイズ
+list-compressed = 圧縮後
+list-modified = 更新日時
+list-attributes = 属性
+list-name = 名前
+list-ratio = 圧縮率
+list-path = パス
+member-renamed = { $old } の名前を { $new } に変更しました
+member-rename-failed = { $cab } 内のファイルの名前を変更できませんでした: { $error }
+touched = { $cab } のタイムスタンプを更新しました
+touch-failed = { $cab } のタイムスタンプを更新できませんでした: { $error }
+converted = { $input } を { $output } に変換しました
+convert-failed = { $input } を変換できませんでした: { $error }
+joined = { $count } 個のキャビネットを { $cab } に結合しました
+join-failed = { $cab } を書き込めませんでした: { $error }
+split-cab = { $cab } を書き込みました ({ $size } バイト)
+split-failed = { $cab } を分割できませんでした: { $error }
+test-ok = テスト中: { $name }  OK
+test-failed = テスト中: { $name }  失敗: { $error }
+test-clean = { $cab } にエラーは見つかりませんでした
+test-errors = { $cab } の { $count } 個のファイルでエラーが発生しました
+extracted = { $count } 個のファイルを { $dir } に展開しました
+extract-failed = { $cab } からファイルを展開できませんでした: { $error }
+overwrite-prompt = { $path } を上書きしますか? [y/N]
+added = { $cab } に { $count } 個のファイルを追加しました
+add-failed = { $cab } にファイルを追加できませんでした: { $error }
+add-as-multiple = --as は 1 つのファイルを追加する場合にのみ使用できます
+removed = { $cab } から { $count } 個のファイルを削除しました
+remove-failed = { $cab } からファイルを削除できませんでした: { $error }
@@ -0,0 +1,1567 @@
+//! Create a cabinet file.
+
+use chrono::NaiveDateTime;
+use clap::{arg, App, ArgMatches};
+use notify::{RecursiveMode, Watcher};
+use std::borrow::Cow;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[macro_use]
+mod l10n;
+mod list;
+
+use l10n::Localizer;
+
+fn main() {
+    let l10n = Localizer::new();
+    let args = match expand_response_files(env::args_os()) {
+        Ok(args) => args,
+        Err((file, e)) => {
+            let file = format!("{:?}", file);
+            eprintln!(
+                "{}",
+                t!(
+                    l10n,
+                    "response-file-failed",
+                    file = file,
+                    error = e.to_string()
+                )
+            );
+            process::exit(1);
+        }
+    };
+    let matches = App::new("makecab")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("Ted Mielczarek <ted@mielczarek.org>")
+        .about("Cabinet Maker (less-fully-featured Rust port)")
+        .after_help("Arguments can also be read from a file, one per line, with @file.")
+        .args(&[
+            arg!(-F [directives]        "Not supported"),
+            clap::Arg::new("define")
+                .short('D')
+                .value_name("VAR=VAL")
+                .multiple_occurrences(true)
+                .help("Defines variable with specified value. Only CompressionType=MSZIP and ChunkSize=<bytes> are supported."),
+            arg!(-L [DIR]               "Location to place destination (default is current directory)")
+                .allow_invalid_utf8(true),
+            arg!(-V[n]                  "Verbosity level"),
+            arg!(--watch                "Treat <source> as a directory, put every file in it into the destination, and rebuild the destination whenever they change"),
+            arg!(--driver               "Treat <source> as a driver package directory and lay it out for submission to the Windows Hardware Dev Center, checking that its INF and catalog files are present")
+                .conflicts_with("watch"),
+            clap::Arg::new("name-by-hash")
+                .long("name-by-hash")
+                .value_name("TEMPLATE")
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value(makecab::HASH_NAME_TEMPLATE)
+                .help("Rename the destination after a SHA-256 digest of its contents, following TEMPLATE, where {stem} is the destination's name without extension and {hash} is the digest's first 16 hex digits [default: {stem}-{hash}.cab]"),
+            arg!(--"files-from" <FILE>  "Read the files to compress from FILE, or stdin if FILE is -, one per line. <source> is then the destination")
+                .required(false)
+                .allow_invalid_utf8(true)
+                .conflicts_with_all(&["destination", "watch", "driver"]),
+            clap::Arg::new("null")
+                .short('0')
+                .long("null")
+                .requires("files-from")
+                .help("Paths read with --files-from are separated by NULs instead of newlines, as find -print0 writes them"),
+            arg!(--include <GLOB>       "When <source> is a directory, only pack the files matching GLOB, a gitignore-style pattern like **/*.pdb. May be given more than once")
+                .required(false)
+                .multiple_occurrences(true)
+                .conflicts_with("driver"),
+            arg!(--exclude <GLOB>       "When <source> is a directory, don't pack the files matching GLOB, even if they match --include. May be given more than once")
+                .required(false)
+                .multiple_occurrences(true)
+                .conflicts_with("driver"),
+            arg!(--durable              "Flush the destination to disk before moving it into place, so it survives a crash or power cut once makecab exits")
+                .conflicts_with("driver"),
+            arg!(--preallocate          "Reserve disk space for the destination before compressing, enough for the worst case, so a full disk fails straight away")
+                .conflicts_with("driver"),
+            arg!(--threads <N>          "Compress on N threads, with separate threads reading the sources and writing the destination")
+                .required(false)
+                .validator(|n| n.parse::<usize>())
+                .conflicts_with("driver"),
+            arg!(--"block-index"        "Also write a <destination>.idx block index, so readers can jump to any part of a file in the destination without decompressing everything before it")
+                .conflicts_with("driver"),
+            arg!(--order <ORDER>        "The order to store the files in: as given, or sorted by name, size or extension")
+                .required(false)
+                .possible_values(["input", "name", "size", "extension"])
+                .default_value("input")
+                .conflicts_with("driver"),
+            arg!(--"name-codepage" <CODEPAGE> "Store file names in DOS code page 437 or 850 instead of UTF-8, for old extractors")
+                .required(false)
+                .possible_values(["437", "850"])
+                .conflicts_with("driver"),
+            arg!(--separator <SEPARATOR> "The separator to store between directories in file names: backslash, which Windows extractors expect, slash, or whichever the names have")
+                .required(false)
+                .possible_values(["backslash", "slash", "preserve"])
+                .default_value("backslash")
+                .conflicts_with("driver"),
+            arg!(--duplicates <POLICY>  "What to do when two files would be stored under the same name, ignoring case: fail, or store the later ones as name (2).ext and so on")
+                .required(false)
+                .possible_values(["error", "rename"])
+                .default_value("error")
+                .conflicts_with("driver"),
+            arg!(--timestamps <SOURCE>  "The timestamp to store for each file: when it was modified or created, now, or a fixed UTC time like 2021-03-04T05:06:08")
+                .required(false)
+                .default_value("modified")
+                .conflicts_with("driver"),
+            arg!(--"max-size" <SIZE>    "Split the destination into a linked set of cabinets no larger than SIZE, in bytes or with a K, M or G suffix, like 100MB, continuing files from one cabinet to the next")
+                .required(false)
+                .conflicts_with_all(&["driver", "watch", "block-index", "preallocate", "name-by-hash", "sha256"]),
+            arg!(--"name-template" <TEMPLATE> "With --max-size, the file name of each cabinet in the set, with %d replaced by its number, like pkg%d.cab [default: the destination's name with the number before the extension]")
+                .required(false)
+                .requires("max-size"),
+            arg!(--"disk-template" <TEMPLATE> "With --max-size, the disk label stored for each cabinet in the set, with %d replaced by its number [default: Disk %d]")
+                .required(false)
+                .requires("max-size"),
+            arg!(--sha256               "Also write a <destination>.sha256 manifest with the digests of each file and of the cabinet itself"),
+            arg!(<source>               "File to compress, or a directory to compress every file under")
+                .allow_invalid_utf8(true),
+            arg!([destination]          "File name to give compressed file. If omitted, the last character of the source file name is replaced with an underscore (_) and used as the destination. Use - to write to stdout.")
+                .allow_invalid_utf8(true),
+            ]
+        )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            App::new("cat")
+                .about("Write the contents of a file in a cabinet file to stdout")
+                .arg(arg!(<cab> "Cabinet file to read").allow_invalid_utf8(true))
+                .arg(arg!(<member> "Name of the file in the cabinet")),
+        )
+        .subcommand(
+            App::new("diff")
+                .about("Compare a cabinet file with another cabinet file or a directory")
+                .arg(arg!(<cab> "Cabinet file to compare").allow_invalid_utf8(true))
+                .arg(
+                    arg!(<other> "Cabinet file or directory to compare it with")
+                        .allow_invalid_utf8(true),
+                )
+                .arg(
+                    arg!(--format <FORMAT> "Output format")
+                        .required(false)
+                        .possible_values(["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            App::new("verify")
+                .about("Check that every file in a cabinet file matches the file of the same name in a directory")
+                .arg(arg!(<cab> "Cabinet file to verify").allow_invalid_utf8(true))
+                .arg(arg!(--against <DIR> "Directory to compare it with").allow_invalid_utf8(true))
+                .arg(
+                    arg!(--tolerance <SECONDS> "How far apart modification times can be")
+                        .required(false)
+                        .validator(|n| n.parse::<u64>())
+                        .default_value("2"),
+                )
+                .arg(
+                    arg!(--format <FORMAT> "Output format")
+                        .required(false)
+                        .possible_values(["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            App::new("info")
+                .about("Print the header, folders and files of a cabinet file as JSON")
+                .arg(arg!(<cab> "Cabinet file to read").allow_invalid_utf8(true)),
+        )
+        .subcommand(
+            App::new("lint")
+                .about("Check a cabinet file for violations of the cabinet format")
+                .arg(arg!(<cab> "Cabinet file to check").allow_invalid_utf8(true))
+                .arg(arg!(--json "Print the violations found as a JSON array")),
+        )
+        .subcommand(
+            App::new("list")
+                .about("List the folders and files in a cabinet file")
+                .arg(arg!(<cab> "Cabinet file to read").allow_invalid_utf8(true))
+                .arg(
+                    arg!(--format <FORMAT> "Output format")
+                        .required(false)
+                        .possible_values(["table", "json", "yaml", "csv"])
+                        .default_value("table"),
+                ),
+        )
+        .subcommand(
+            App::new("manifest")
+                .about("Write the cabinet files described by a JSON manifest")
+                .arg(arg!(<manifest> "Manifest file to read").allow_invalid_utf8(true))
+                .arg(arg!(-V[n] "Verbosity level")),
+        )
+        .subcommand(
+            App::new("extract")
+                .about("Extract files from a cabinet file")
+                .arg(arg!(<cab> "Cabinet file to extract from").allow_invalid_utf8(true))
+                .arg(arg!([member]... "Names of the files in the cabinet to extract, or every file if omitted"))
+                .arg(
+                    arg!(-d --dir <DIR> "Directory to extract into")
+                        .required(false)
+                        .allow_invalid_utf8(true)
+                        .default_value("."),
+                )
+                .arg(
+                    arg!(--overwrite <POLICY> "What to do about files that already exist")
+                        .required(false)
+                        .possible_values(["never", "always", "newer", "prompt"])
+                        .default_value("always"),
+                )
+                .arg(arg!(--"list-only" "List each file's size, compressed size, ratio, folder and the path it would be extracted to, without extracting anything")),
+        )
+        .subcommand(
+            App::new("expand")
+                .about("Expand single-file compressed files like foo.pd_, restoring their original names like expand -r")
+                .arg(arg!(<file>... "Compressed files to expand").allow_invalid_utf8(true))
+                .arg(
+                    arg!(-d --dir <DIR> "Directory to expand into")
+                        .required(false)
+                        .allow_invalid_utf8(true)
+                        .default_value("."),
+                ),
+        )
+        .subcommand(
+            App::new("add")
+                .about("Add files to an existing cabinet file")
+                .arg(arg!(<cab> "Cabinet file to change").allow_invalid_utf8(true))
+                .arg(arg!(<file>... "Files to add").allow_invalid_utf8(true))
+                .arg(
+                    arg!(--as <NAME> "Name to store the file under, if there's only one")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            App::new("remove")
+                .about("Rewrite a cabinet file without some of the files in it")
+                .arg(arg!(<cab> "Cabinet file to change").allow_invalid_utf8(true))
+                .arg(arg!(<member>... "Names of the files in the cabinet to remove")),
+        )
+        .subcommand(
+            App::new("rename")
+                .about("Rename a file in a cabinet file without recompressing it")
+                .arg(arg!(<cab> "Cabinet file to change").allow_invalid_utf8(true))
+                .arg(arg!(<old> "Name of the file in the cabinet"))
+                .arg(arg!(<new> "New name for the file")),
+        )
+        .subcommand(
+            App::new("touch")
+                .about("Set the modification time of files in a cabinet file without recompressing them")
+                .arg(arg!(<cab> "Cabinet file to change").allow_invalid_utf8(true))
+                .arg(arg!(--date <DATETIME> "Time to set, like 2021-03-04T05:06:08"))
+                .arg(arg!([member]... "Names of the files in the cabinet to change, or every file if omitted")),
+        )
+        .subcommand(
+            App::new("test")
+                .about("Decompress every file in a cabinet file without writing them out, checking their checksums and sizes")
+                .arg(arg!(<cab> "Cabinet file to test").allow_invalid_utf8(true)),
+        )
+        .subcommand(
+            App::new("rebuild")
+                .about("Rebuild a cabinet file with damaged headers from the data blocks that survive")
+                .arg(arg!(<broken> "Damaged cabinet file").allow_invalid_utf8(true))
+                .arg(arg!(<fixed> "Cabinet file to write").allow_invalid_utf8(true)),
+        )
+        .subcommand(
+            App::new("split")
+                .about("Split a cabinet file into a linked set of smaller cabinet files")
+                .arg(arg!(<cab> "Cabinet file to split").allow_invalid_utf8(true))
+                .arg(arg!(--"max-size" <SIZE> "Largest size for each cabinet, in bytes or with a K, M or G suffix, like 650MB"))
+                .arg(arg!(--"name-template" <TEMPLATE> "File name of each cabinet, with %d replaced by its number, like pkg%d.cab [default: the cabinet's name with the number before the extension]").required(false))
+                .arg(arg!(--"disk-template" <TEMPLATE> "Disk label stored for each cabinet, with %d replaced by its number [default: Disk %d]").required(false)),
+        )
+        .subcommand(
+            App::new("join")
+                .about("Join a linked set of cabinet files into a single cabinet file")
+                .arg(arg!(<cab> "First cabinet file in the set").allow_invalid_utf8(true))
+                .arg(arg!(-o --output <FILE> "Cabinet file to write").allow_invalid_utf8(true)),
+        )
+        .subcommand(
+            App::new("convert")
+                .about("Convert a cabinet file to a zip archive, or a zip archive to a cabinet file")
+                .arg(arg!(<input> "Cabinet file or zip archive to convert").allow_invalid_utf8(true))
+                .arg(arg!(<output> "Zip archive or cabinet file to write").allow_invalid_utf8(true))
+                .arg(arg!(--recompress "Compress files stored uncompressed in a zip archive")),
+        )
+        .get_matches_from(args);
+
+    match matches.subcommand() {
+        Some(("add", matches)) => process::exit(add(matches, &l10n)),
+        Some(("cat", matches)) => process::exit(cat(matches, &l10n)),
+        Some(("convert", matches)) => process::exit(convert(matches, &l10n)),
+        Some(("diff", matches)) => process::exit(diff(matches, &l10n)),
+        Some(("expand", matches)) => process::exit(expand(matches, &l10n)),
+        Some(("extract", matches)) => process::exit(extract(matches, &l10n)),
+        Some(("info", matches)) => process::exit(info(matches, &l10n)),
+        Some(("join", matches)) => process::exit(join(matches, &l10n)),
+        Some(("lint", matches)) => process::exit(lint(matches, &l10n)),
+        Some(("list", matches)) => process::exit(list(matches, &l10n)),
+        Some(("manifest", matches)) => process::exit(manifest(matches, &l10n)),
+        Some(("rebuild", matches)) => process::exit(rebuild(matches, &l10n)),
+        Some(("remove", matches)) => process::exit(remove(matches, &l10n)),
+        Some(("rename", matches)) => process::exit(rename(matches, &l10n)),
+        Some(("split", matches)) => process::exit(split(matches, &l10n)),
+        Some(("test", matches)) => process::exit(test(matches, &l10n)),
+        Some(("touch", matches)) => process::exit(touch(matches, &l10n)),
+        Some(("verify", matches)) => process::exit(verify(matches, &l10n)),
+        _ => {}
+    }
+
+    // Check for unsupported options.
+    if matches.is_present("directives") {
+        println!("{}", t!(l10n, "error-directives"));
+        process::exit(1);
+    }
+    let mut chunk_size = None;
+    let mut compression_level = None;
+    for define in matches.values_of("define").into_iter().flatten() {
+        match define.split_once('=') {
+            Some(("CompressionType", "MSZIP")) => {}
+            Some(("ChunkSize", size)) => match size.parse() {
+                Ok(size) => chunk_size = Some(size),
+                Err(_) => {
+                    println!("{}", t!(l10n, "error-chunk-size", size = size));
+                    process::exit(1);
+                }
+            },
+            Some(("CompressionLevel", level)) => match level.parse() {
+                Ok(level) if level <= 9 => compression_level = Some(level),
+                _ => {
+                    println!("{}", t!(l10n, "error-compression-level", level = level));
+                    process::exit(1);
+                }
+            },
+            _ => {
+                println!("{}", t!(l10n, "error-compression-type"));
+                process::exit(1);
+            }
+        }
+    }
+
+    let source = matches.value_of_os("source").unwrap();
+    let files_from = matches.value_of_os("files-from");
+    if files_from.is_some() && source == "-" {
+        eprintln!("{}", t!(l10n, "error-files-from-stdout"));
+        process::exit(1);
+    }
+    let to_stdout = matches.value_of_os("destination") == Some(OsStr::new("-"));
+    if to_stdout {
+        for (option, error) in [
+            ("name-by-hash", "error-name-by-hash-stdout"),
+            ("sha256", "error-sha256-stdout"),
+            ("watch", "error-watch-stdout"),
+            ("driver", "error-driver-stdout"),
+            ("block-index", "error-block-index-stdout"),
+            ("max-size", "error-max-size-stdout"),
+        ] {
+            if matches.is_present(option) {
+                eprintln!("{}", t!(l10n, error));
+                process::exit(1);
+            }
+        }
+    }
+    let filter = match makecab::PathFilter::new(
+        &matches
+            .values_of("include")
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>(),
+        &matches
+            .values_of("exclude")
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>(),
+    ) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{}", t!(l10n, "bad-filter", error = e.to_string()));
+            process::exit(1);
+        }
+    };
+    let timestamps = match matches.value_of("timestamps") {
+        Some("created") => makecab::TimestampSource::Created,
+        Some("now") => makecab::TimestampSource::Now,
+        Some("modified") | None => makecab::TimestampSource::Modified,
+        Some(fixed) => match fixed.parse::<NaiveDateTime>() {
+            Ok(datetime) => makecab::TimestampSource::Fixed(datetime),
+            Err(_) => {
+                println!("{}", t!(l10n, "error-datetime", value = fixed));
+                process::exit(1);
+            }
+        },
+    };
+    let split = matches
+        .value_of("max-size")
+        .map(|max_size| match parse_size(max_size) {
+            Some(max_size) => makecab::SplitOptions {
+                max_size,
+                name_template: matches.value_of("name-template").map(String::from),
+                disk_template: matches.value_of("disk-template").map(String::from),
+            },
+            None => {
+                println!("{}", t!(l10n, "error-size", value = max_size));
+                process::exit(1);
+            }
+        });
+    let options = makecab::CabOptions {
+        durable: matches.is_present("durable"),
+        preallocate: matches.is_present("preallocate"),
+        threads: matches
+            .value_of("threads")
+            .map_or(0, |n| n.parse().unwrap()),
+        chunk_size,
+        compression_level,
+        block_index: matches.is_present("block-index"),
+        order: match matches.value_of("order") {
+            Some("name") => makecab::MemberOrder::Name,
+            Some("size") => makecab::MemberOrder::Size,
+            Some("extension") => makecab::MemberOrder::Extension,
+            _ => makecab::MemberOrder::Input,
+        },
+        name_codepage: matches
+            .value_of("name-codepage")
+            .and_then(|number| makecab::Codepage::from_number(number.parse().ok()?)),
+        separator: match matches.value_of("separator") {
+            Some("slash") => makecab::PathSeparator::Slash,
+            Some("preserve") => makecab::PathSeparator::Preserve,
+            _ => makecab::PathSeparator::Backslash,
+        },
+        duplicate_names: match matches.value_of("duplicates") {
+            Some("rename") => makecab::DuplicateNames::Rename,
+            _ => makecab::DuplicateNames::Error,
+        },
+        timestamps,
+        split,
+        ..makecab::CabOptions::default()
+    };
+    let is_dir = files_from.is_none() && Path::new(source).is_dir();
+    if (matches.is_present("include") || matches.is_present("exclude"))
+        && !is_dir
+        && !matches.is_present("watch")
+    {
+        eprintln!("{}", t!(l10n, "error-filter-not-dir"));
+        process::exit(1);
+    }
+    if to_stdout {
+        let stdout = io::stdout();
+        let result = if is_dir {
+            makecab::make_cab_from_dir_to_writer(stdout.lock(), source, &filter, &options)
+        } else {
+            makecab::make_cab_to_writer_with_options(stdout.lock(), source, &options)
+        };
+        if let Err(e) = result {
+            eprintln!("{}", t!(l10n, "write-failed", error = e.to_string()));
+            process::exit(1);
+        }
+        return;
+    }
+    let dest_name = match matches
+        .value_of_os("destination")
+        .or_else(|| files_from.map(|_| source))
+    {
+        Some(dest) => Cow::Borrowed(dest),
+        None => match default_destination(Path::new(source)) {
+            Some(dest) => Cow::Owned(dest),
+            None => {
+                eprintln!(
+                    "{}",
+                    t!(
+                        l10n,
+                        "error-destination-name",
+                        source = format!("{:?}", source)
+                    )
+                );
+                process::exit(1);
+            }
+        },
+    };
+    let mut dest = matches
+        .value_of_os("DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::current_dir().unwrap())
+        .join(dest_name);
+    if matches.is_present("watch") {
+        process::exit(watch(Path::new(source), &dest, &filter, &options, &l10n));
+    }
+    let files = files_from.map(
+        |list| match read_file_list(list, matches.is_present("null")) {
+            Ok(files) => files,
+            Err(e) => {
+                let list = format!("{:?}", list);
+                println!(
+                    "{}",
+                    t!(l10n, "file-list-failed", list = list, error = e.to_string())
+                );
+                process::exit(1);
+            }
+        },
+    );
+    println!(
+        "{}",
+        t!(
+            l10n,
+            "writing",
+            source = format!("{:?}", files_from.unwrap_or(source)),
+            dest = format!("{:?}", dest)
+        )
+    );
+    let result = if let Some(files) = &files {
+        makecab::make_cab_from_files_with_options(&dest, files, &options)
+    } else if matches.is_present("driver") {
+        makecab::make_driver_cab(&dest, source)
+    } else if is_dir {
+        makecab::make_cab_from_dir(&dest, source, &filter, &options)
+    } else {
+        makecab::make_cab_from_files_with_options(&dest, &[source], &options)
+    };
+    match result {
+        Ok(summary) => {
+            for file in &summary.files {
+                if let Some(original) = &file.renamed_from {
+                    eprintln!(
+                        "{}",
+                        t!(
+                            l10n,
+                            "duplicate-renamed",
+                            name = original.as_str(),
+                            renamed = file.name.as_str()
+                        )
+                    );
+                }
+            }
+            print_summary(&summary, verbosity(&matches), &l10n);
+            print_set(&summary.cabinets, &l10n);
+        }
+        Err(e) => {
+            println!("{}", t!(l10n, "write-failed", error = e.to_string()));
+            ::std::process::exit(1);
+        }
+    }
+    if let Some(template) = matches.value_of("name-by-hash") {
+        match makecab::rename_by_hash(&dest, template) {
+            Ok(renamed) => {
+                println!("{}", t!(l10n, "renamed", cab = format!("{:?}", renamed)));
+                dest = renamed;
+            }
+            Err(e) => {
+                println!("{}", t!(l10n, "rename-failed", error = e.to_string()));
+                ::std::process::exit(1);
+            }
+        }
+    }
+    if matches.is_present("sha256") {
+        match makecab::write_sha256_manifest(&dest) {
+            Ok(manifest) => println!(
+                "{}",
+                t!(l10n, "wrote-manifest", manifest = format!("{:?}", manifest))
+            ),
+            Err(e) => {
+                println!("{}", t!(l10n, "manifest-failed", error = e.to_string()));
+                ::std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Replace every `@file` argument with the lines of `file`, one argument per
+/// line, like Microsoft's tools do, to get around the limit on the length of
+/// command lines on Windows. Blank lines are skipped and arguments may be
+/// wrapped in double quotes. On failure, returns the response file that
+/// couldn't be read.
+fn expand_response_files<I>(args: I) -> Result<Vec<OsString>, (PathBuf, io::Error)>
+where
+    I: IntoIterator<Item = OsString>,
+{
+    let mut expanded = vec![];
+    for arg in args {
+        let file = match arg.to_str().and_then(|arg| arg.strip_prefix('@')) {
+            Some(file) if !file.is_empty() => PathBuf::from(file),
+            _ => {
+                expanded.push(arg);
+                continue;
+            }
+        };
+        let contents = fs::read_to_string(&file).map_err(|e| (file, e))?;
+        for line in contents.lines() {
+            let line = line.trim();
+            let line = line
+                .strip_prefix('"')
+                .and_then(|line| line.strip_suffix('"'))
+                .unwrap_or(line);
+            if !line.is_empty() {
+                expanded.push(OsString::from(line));
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// Read the list of files at `list`, or on stdin if `list` is `-`, with
+/// one path per line, or separated by NULs if `nul` is set.
+fn read_file_list(list: &OsStr, nul: bool) -> io::Result<Vec<PathBuf>> {
+    let bytes = if list == "-" {
+        let mut bytes = vec![];
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        bytes
+    } else {
+        fs::read(list)?
+    };
+    parse_file_list(bytes, nul)
+}
+
+/// Parse a size in bytes, optionally followed by a `K`, `M` or `G` suffix
+/// for powers of 1024, with or without a trailing `B`.
+fn parse_size(size: &str) -> Option<u64> {
+    let upper = size.trim().to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (digits, multiplier) = match digits.char_indices().last() {
+        Some((i, 'K')) => (&digits[..i], 1 << 10),
+        Some((i, 'M')) => (&digits[..i], 1 << 20),
+        Some((i, 'G')) => (&digits[..i], 1 << 30),
+        _ => (digits, 1),
+    };
+    digits.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Split `bytes` into paths separated by newlines, or by NULs if `nul` is
+/// set, skipping empty ones.
+fn parse_file_list(bytes: Vec<u8>, nul: bool) -> io::Result<Vec<PathBuf>> {
+    let separator = if nul { b'\0' } else { b'\n' };
+    bytes
+        .split(|&b| b == separator)
+        .map(|path| match path {
+            [path @ .., b'\r'] if !nul => path,
+            path => path,
+        })
+        .filter(|path| !path.is_empty())
+        .map(|path| path_from_bytes(path.to_vec()))
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Get the verbosity level from `-V`, which is 1 if no level is given.
+fn verbosity(matches: &ArgMatches) -> u32 {
+    if !matches.is_present("n") {
+        return 0;
+    }
+    matches
+        .value_of("n")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Print how well the files in a cabinet compressed, like Microsoft's
+/// `makecab` does, if `verbosity` is at least 1. At 2 and above every file
+/// gets its own line, and at 3 and above so does every block.
+fn print_summary(summary: &makecab::CabSummary, verbosity: u32, l10n: &Localizer) {
+    if verbosity >= 3 {
+        for (i, block) in summary.blocks.iter().enumerate() {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "block-summary",
+                    index = i,
+                    folder = block.folder,
+                    size = with_commas(u64::from(block.uncompressed_size)),
+                    compressed = with_commas(u64::from(block.compressed_size)),
+                    total_size = with_commas(block.total_uncompressed),
+                    total_compressed = with_commas(block.total_compressed)
+                )
+            );
+        }
+    }
+    if verbosity >= 2 {
+        for file in &summary.files {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "file-summary",
+                    name = file.name.as_str(),
+                    size = with_commas(file.size),
+                    compressed = with_commas(file.compressed_size)
+                )
+            );
+        }
+    }
+    if verbosity >= 1 {
+        let size = summary.uncompressed_size();
+        let percent = if size == 0 {
+            0.0
+        } else {
+            100.0 * (1.0 - summary.cab_size as f64 / size as f64)
+        };
+        println!(
+            "{}",
+            t!(
+                l10n,
+                "summary",
+                size = with_commas(size),
+                compressed = with_commas(summary.cab_size),
+                savings = format!("{:.2}", percent),
+                seconds = format!("{:.2}", summary.elapsed.as_secs_f64())
+            )
+        );
+    }
+}
+
+/// Format `n` with commas between each group of three digits.
+fn with_commas(n: u64) -> String {
+    let digits = n.to_string();
+    digits
+        .as_bytes()
+        .rchunks(3)
+        .rev()
+        .map(|group| std::str::from_utf8(group).unwrap())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Run the `cat` subcommand, returning the exit code.
+fn cat(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let member = matches.value_of("member").unwrap();
+    let stdout = io::stdout();
+    match makecab::extract_file_to(cab, member, stdout.lock()) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!(
+                "{}",
+                t!(l10n, "cat-failed", member = member, error = e.to_string())
+            );
+            1
+        }
+    }
+}
+
+/// Run the `diff` subcommand, returning the exit code.
+fn diff(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let other = matches.value_of_os("other").unwrap();
+    let json = matches.value_of("format") == Some("json");
+    if !Path::new(other).is_dir() {
+        return diff_cabs(cab, other, json, l10n);
+    }
+    match makecab::diff_cab_dir(cab, other) {
+        Ok(diff) => {
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+            } else {
+                for name in &diff.added {
+                    println!("+ {}", name);
+                }
+                for name in &diff.removed {
+                    println!("- {}", name);
+                }
+                for name in &diff.changed {
+                    println!("M {}", name);
+                }
+                if diff.is_empty() {
+                    println!(
+                        "{}",
+                        t!(
+                            l10n,
+                            "diff-matches",
+                            cab = format!("{:?}", cab),
+                            dir = format!("{:?}", other)
+                        )
+                    );
+                }
+            }
+            if diff.is_empty() {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            println!("{}", t!(l10n, "diff-failed", error = e.to_string()));
+            1
+        }
+    }
+}
+
+/// Run the `verify` subcommand, returning the exit code.
+fn verify(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let dir = matches.value_of_os("against").unwrap();
+    let tolerance = Duration::from_secs(matches.value_of_t_or_exit("tolerance"));
+    let mismatches = match makecab::verify_against_dir(cab, dir, tolerance) {
+        Ok(mismatches) => mismatches,
+        Err(e) => {
+            println!("{}", t!(l10n, "verify-failed", error = e.to_string()));
+            return 1;
+        }
+    };
+    if matches.value_of("format") == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&mismatches).unwrap());
+    } else {
+        for mismatch in &mismatches {
+            let name = &mismatch.name;
+            let line = match mismatch.kind {
+                makecab::MismatchKind::Missing => t!(l10n, "verify-missing", name = name),
+                makecab::MismatchKind::Extra => t!(l10n, "verify-extra", name = name),
+                makecab::MismatchKind::Size { cab, disk } => {
+                    t!(l10n, "verify-size", name = name, cab = cab, disk = disk)
+                }
+                makecab::MismatchKind::Contents => t!(l10n, "verify-contents", name = name),
+                makecab::MismatchKind::Timestamp { cab, disk } => {
+                    t!(l10n, "verify-timestamp", name = name, seconds = cab - disk)
+                }
+            };
+            println!("{}", line);
+        }
+        let (cab, dir) = (format!("{:?}", cab), format!("{:?}", dir));
+        if mismatches.is_empty() {
+            println!("{}", t!(l10n, "verify-matches", cab = cab, dir = dir));
+        } else {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "verify-mismatches",
+                    count = mismatches.len(),
+                    cab = cab,
+                    dir = dir
+                )
+            );
+        }
+    }
+    if mismatches.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// Compare two cabinet files for the `diff` subcommand, returning the exit
+/// code.
+fn diff_cabs(old: &OsStr, new: &OsStr, json: bool, l10n: &Localizer) -> i32 {
+    let diff = match makecab::diff_cabs(old, new) {
+        Ok(diff) => diff,
+        Err(e) => {
+            println!("{}", t!(l10n, "diff-failed", error = e.to_string()));
+            return 1;
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+    } else {
+        for name in &diff.added {
+            println!("+ {}", name);
+        }
+        for name in &diff.removed {
+            println!("- {}", name);
+        }
+        for name in &diff.changed {
+            println!("M {}", name);
+        }
+        for (tag, changes) in [
+            ("R", &diff.renamed),
+            ("T", &diff.retimed),
+            ("C", &diff.recompressed),
+        ] {
+            for change in changes {
+                println!("{} {}: {} -> {}", tag, change.name, change.old, change.new);
+            }
+        }
+        if diff.is_empty() {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "diff-matches",
+                    cab = format!("{:?}", old),
+                    dir = format!("{:?}", new)
+                )
+            );
+        }
+    }
+    if diff.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// Run the `expand` subcommand, returning the exit code.
+fn expand(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let dir = matches.value_of_os("dir").unwrap();
+    let mut count = 0;
+    for file in matches.values_of_os("file").unwrap() {
+        match makecab::expand_file(file, dir) {
+            Ok(paths) => count += paths.len(),
+            Err(e) => {
+                println!(
+                    "{}",
+                    t!(
+                        l10n,
+                        "extract-failed",
+                        cab = format!("{:?}", file),
+                        error = e.to_string()
+                    )
+                );
+                return 1;
+            }
+        }
+    }
+    println!(
+        "{}",
+        t!(l10n, "extracted", count = count, dir = format!("{:?}", dir))
+    );
+    0
+}
+
+/// Run the `extract` subcommand, returning the exit code.
+fn extract(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let members = matches
+        .values_of("member")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let dir = matches.value_of_os("dir").unwrap();
+    if matches.is_present("list-only") {
+        return match makecab::plan_extraction(cab, &members, dir) {
+            Ok(files) => {
+                print!("{}", list::plan_table(&files, l10n));
+                0
+            }
+            Err(e) => {
+                println!(
+                    "{}",
+                    t!(
+                        l10n,
+                        "extract-failed",
+                        cab = format!("{:?}", cab),
+                        error = e.to_string()
+                    )
+                );
+                1
+            }
+        };
+    }
+    let overwrite = match matches.value_of("overwrite") {
+        Some("never") => makecab::Overwrite::Never,
+        Some("newer") => makecab::Overwrite::Newer,
+        Some("prompt") => makecab::Overwrite::Prompt,
+        _ => makecab::Overwrite::Always,
+    };
+    let confirm = |path: &Path| {
+        eprint!(
+            "{} ",
+            t!(l10n, "overwrite-prompt", path = format!("{:?}", path))
+        );
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).is_ok() && answer.trim().to_lowercase().starts_with('y')
+    };
+    match makecab::extract_files_with_overwrite(cab, &members, dir, overwrite, confirm) {
+        Ok(paths) => {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "extracted",
+                    count = paths.len(),
+                    dir = format!("{:?}", dir)
+                )
+            );
+            0
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "extract-failed",
+                    cab = format!("{:?}", cab),
+                    error = e.to_string()
+                )
+            );
+            1
+        }
+    }
+}
+
+/// Run the `info` subcommand, returning the exit code.
+fn info(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    match makecab::format::parse::parse_cab(cab) {
+        Ok(parsed) => {
+            println!("{}", serde_json::to_string_pretty(&parsed).unwrap());
+            0
+        }
+        Err(e) => {
+            println!("{}", t!(l10n, "read-failed", error = e.to_string()));
+            1
+        }
+    }
+}
+
+/// Run the `lint` subcommand, returning the exit code.
+fn lint(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    match makecab::lint_cab(cab) {
+        Ok(violations) if matches.is_present("json") => {
+            println!("{}", serde_json::to_string_pretty(&violations).unwrap());
+            if violations.is_empty() {
+                0
+            } else {
+                1
+            }
+        }
+        Ok(violations) if violations.is_empty() => {
+            println!("{}", t!(l10n, "lint-clean", cab = format!("{:?}", cab)));
+            0
+        }
+        Ok(violations) => {
+            for violation in &violations {
+                println!("{:?}: {}", cab, violation);
+            }
+            1
+        }
+        Err(e) => {
+            println!("{}", t!(l10n, "read-failed", error = e.to_string()));
+            1
+        }
+    }
+}
+
+/// Run the `list` subcommand, returning the exit code.
+fn list(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let listing = match makecab::list_cab(cab) {
+        Ok(listing) => listing,
+        Err(e) => {
+            println!("{}", t!(l10n, "read-failed", error = e.to_string()));
+            return 1;
+        }
+    };
+    match matches.value_of("format") {
+        Some("json") => println!("{}", serde_json::to_string_pretty(&listing).unwrap()),
+        Some("yaml") => print!("{}", list::yaml(&listing)),
+        Some("csv") => print!("{}", list::csv(&listing)),
+        _ => print!("{}", list::table(&listing, l10n)),
+    }
+    0
+}
+
+/// Run the `manifest` subcommand, returning the exit code.
+fn manifest(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let manifest = matches.value_of_os("manifest").unwrap();
+    match makecab::make_cabs_from_manifest(manifest) {
+        Ok(summaries) => {
+            for summary in &summaries {
+                println!(
+                    "{}",
+                    t!(
+                        l10n,
+                        "writing",
+                        source = format!("{:?}", manifest),
+                        dest = format!("{:?}", summary.path)
+                    )
+                );
+                print_summary(summary, verbosity(matches), l10n);
+            }
+            0
+        }
+        Err(e) => {
+            let manifest = format!("{:?}", manifest);
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "manifest-write-failed",
+                    manifest = manifest,
+                    error = e.to_string()
+                )
+            );
+            1
+        }
+    }
+}
+
+/// Run the `rebuild` subcommand, returning the exit code.
+fn rebuild(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let broken = matches.value_of_os("broken").unwrap();
+    let fixed = matches.value_of_os("fixed").unwrap();
+    match makecab::rebuild_cab(broken, fixed) {
+        Ok(folders) => {
+            for folder in &folders {
+                println!(
+                    "{}",
+                    t!(
+                        l10n,
+                        "rebuild-folder",
+                        name = folder.name.as_str(),
+                        blocks = folder.blocks,
+                        offset = format!("0x{:x}", folder.offset),
+                        size = with_commas(folder.size)
+                    )
+                );
+            }
+            0
+        }
+        Err(e) => {
+            println!("{}", t!(l10n, "rebuild-failed", error = e.to_string()));
+            1
+        }
+    }
+}
+
+/// The name `makecab.exe` gives the destination when only `source` is
+/// given: its file name with the last character replaced by `_`, like
+/// `file.pd_` for `file.pdb`. This is the last component of UNC paths like
+/// `\\server\share\file.pdb` and device paths like `\\?\C:\dir\file.pdb`
+/// too, and a directory like `.` is named after the directory it refers to.
+fn default_destination(source: &Path) -> Option<OsString> {
+    let canonical;
+    let name = match source.file_name() {
+        Some(name) => name,
+        None => {
+            canonical = fs::canonicalize(source).ok()?;
+            canonical.file_name()?
+        }
+    };
+    let mut chars = name.to_str()?.chars();
+    chars.next_back();
+    Some(OsString::from(format!("{}_", chars.as_str())))
+}
+
+/// Run the `add` subcommand, returning the exit code.
+fn add(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let files = matches
+        .values_of_os("file")
+        .unwrap()
+        .map(Path::new)
+        .collect::<Vec<_>>();
+    let names = match matches.value_of("as") {
+        Some(_) if files.len() > 1 => {
+            println!("{}", t!(l10n, "add-as-multiple"));
+            return 1;
+        }
+        Some(name) => vec![Cow::from(name)],
+        None => files
+            .iter()
+            .map(|file| file.file_name().unwrap_or_default().to_string_lossy())
+            .collect(),
+    };
+    let inputs = files.iter().zip(&names).collect::<Vec<_>>();
+    let cab_name = format!("{:?}", cab);
+    match makecab::append_files(cab, &inputs) {
+        Ok(()) => {
+            println!("{}", t!(l10n, "added", cab = cab_name, count = files.len()));
+            0
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                t!(l10n, "add-failed", cab = cab_name, error = e.to_string())
+            );
+            1
+        }
+    }
+}
+
+/// Run the `remove` subcommand, returning the exit code.
+fn remove(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let members = matches.values_of("member").unwrap().collect::<Vec<_>>();
+    let cab_name = format!("{:?}", cab);
+    match makecab::remove_files(cab, &members) {
+        Ok(()) => {
+            println!(
+                "{}",
+                t!(l10n, "removed", cab = cab_name, count = members.len())
+            );
+            0
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                t!(l10n, "remove-failed", cab = cab_name, error = e.to_string())
+            );
+            1
+        }
+    }
+}
+
+/// Run the `rename` subcommand, returning the exit code.
+fn rename(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let old = matches.value_of("old").unwrap();
+    let new = matches.value_of("new").unwrap();
+    let cab_name = format!("{:?}", cab);
+    match makecab::rename_file(cab, old, new) {
+        Ok(()) => {
+            println!("{}", t!(l10n, "member-renamed", old = old, new = new));
+            0
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "member-rename-failed",
+                    cab = cab_name,
+                    error = e.to_string()
+                )
+            );
+            1
+        }
+    }
+}
+
+/// Run the `touch` subcommand, returning the exit code.
+fn touch(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let date = matches.value_of("date").unwrap();
+    let datetime = match date.parse::<NaiveDateTime>() {
+        Ok(datetime) => datetime,
+        Err(_) => {
+            println!("{}", t!(l10n, "error-datetime", value = date));
+            return 1;
+        }
+    };
+    let members = matches
+        .values_of("member")
+        .map_or(vec![], |members| members.collect());
+    let cab_name = format!("{:?}", cab);
+    match makecab::set_file_datetimes(cab, &members, datetime) {
+        Ok(()) => {
+            println!("{}", t!(l10n, "touched", cab = cab_name));
+            0
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                t!(l10n, "touch-failed", cab = cab_name, error = e.to_string())
+            );
+            1
+        }
+    }
+}
+
+/// Run the `convert` subcommand, returning the exit code.
+fn convert(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let input = matches.value_of_os("input").unwrap();
+    let output = matches.value_of_os("output").unwrap();
+    let (input_name, output_name) = (format!("{:?}", input), format!("{:?}", output));
+    let from_zip = Path::new(input)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    let result = if from_zip {
+        makecab::zip_to_cab(input, output, matches.is_present("recompress"))
+    } else {
+        makecab::cab_to_zip(input, output)
+    };
+    match result {
+        Ok(()) => {
+            println!(
+                "{}",
+                t!(l10n, "converted", input = input_name, output = output_name)
+            );
+            0
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "convert-failed",
+                    input = input_name,
+                    error = e.to_string()
+                )
+            );
+            1
+        }
+    }
+}
+
+/// Run the `join` subcommand, returning the exit code.
+fn join(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let output = matches.value_of_os("output").unwrap();
+    let out_name = format!("{:?}", output);
+    match makecab::join_cabs(cab, output) {
+        Ok(paths) => {
+            println!(
+                "{}",
+                t!(l10n, "joined", cab = out_name, count = paths.len())
+            );
+            0
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                t!(l10n, "join-failed", cab = out_name, error = e.to_string())
+            );
+            1
+        }
+    }
+}
+
+/// Run the `split` subcommand, returning the exit code.
+fn split(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let max_size = matches.value_of("max-size").unwrap();
+    let max_size = match parse_size(max_size) {
+        Some(max_size) => max_size,
+        None => {
+            println!("{}", t!(l10n, "error-size", value = max_size));
+            return 1;
+        }
+    };
+    let options = makecab::SplitOptions {
+        max_size,
+        name_template: matches.value_of("name-template").map(String::from),
+        disk_template: matches.value_of("disk-template").map(String::from),
+    };
+    match makecab::split_cab_with_options(cab, &options) {
+        Ok(paths) => {
+            print_set(&paths, l10n);
+            0
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                t!(
+                    l10n,
+                    "split-failed",
+                    cab = format!("{:?}", cab),
+                    error = e.to_string()
+                )
+            );
+            1
+        }
+    }
+}
+
+/// Print the path and size of each cabinet in a set written by `split` or
+/// `--max-size`.
+fn print_set(paths: &[PathBuf], l10n: &Localizer) {
+    for path in paths {
+        let size = fs::metadata(path).map_or(0, |meta| meta.len());
+        println!(
+            "{}",
+            t!(
+                l10n,
+                "split-cab",
+                cab = format!("{:?}", path),
+                size = with_commas(size)
+            )
+        );
+    }
+}
+
+/// Run the `test` subcommand, returning the exit code.
+fn test(matches: &ArgMatches, l10n: &Localizer) -> i32 {
+    let cab = matches.value_of_os("cab").unwrap();
+    let report = match makecab::verify_cab(cab) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{}", t!(l10n, "read-failed", error = e.to_string()));
+            return 1;
+        }
+    };
+    for file in &report.files {
+        match &file.error {
+            None => println!("{}", t!(l10n, "test-ok", name = file.name.as_str())),
+            Some(error) => println!(
+                "{}",
+                t!(
+                    l10n,
+                    "test-failed",
+                    name = file.name.as_str(),
+                    error = error.as_str()
+                )
+            ),
+        }
+    }
+    let failed = report.files.iter().filter(|file| !file.is_ok()).count();
+    let cab = format!("{:?}", cab);
+    if failed == 0 {
+        println!("{}", t!(l10n, "test-clean", cab = cab));
+        0
+    } else {
+        println!("{}", t!(l10n, "test-errors", cab = cab, count = failed));
+        1
+    }
+}
+
+/// Put every file in the directory tree `source` that `filter` keeps into
+/// the cabinet `dest`, as [`makecab::make_cab_from_dir`] does, and rebuild
+/// it whenever any of them change. Only
+/// returns, with the exit code, if watching fails.
+fn watch(
+    source: &Path,
+    dest: &Path,
+    filter: &makecab::PathFilter,
+    options: &makecab::CabOptions,
+    l10n: &Localizer,
+) -> i32 {
+    let watch_failed = |e: notify::Error| {
+        let dir = format!("{:?}", source);
+        println!(
+            "{}",
+            t!(l10n, "watch-failed", dir = dir, error = e.to_string())
+        );
+        1
+    };
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(tx) {
+        Ok(watcher) => watcher,
+        Err(e) => return watch_failed(e),
+    };
+    if let Err(e) = watcher.watch(source, RecursiveMode::Recursive) {
+        return watch_failed(e);
+    }
+    loop {
+        match makecab::make_cab_from_dir(dest, source, filter, options) {
+            Ok(summary) => println!(
+                "{}",
+                t!(
+                    l10n,
+                    "watch-built",
+                    source = format!("{:?}", source),
+                    count = summary.files.len(),
+                    dest = format!("{:?}", dest)
+                )
+            ),
+            Err(e) => println!("{}", t!(l10n, "write-failed", error = e.to_string())),
+        }
+        // Wait for something other than the cabinet itself, or the
+        // temporary file it's written to, to change, then give editors and
+        // build tools a moment to finish writing.
+        loop {
+            match rx.recv() {
+                Ok(Ok(event))
+                    if !event.kind.is_access()
+                        && event.paths.iter().any(|p| !is_own_file(p, dest)) =>
+                {
+                    break
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => {
+                    let dir = format!("{:?}", source);
+                    println!(
+                        "{}",
+                        t!(l10n, "watch-error", dir = dir, error = e.to_string())
+                    )
+                }
+                Err(_) => return 1,
+            }
+        }
+        while rx.recv_timeout(Duration::from_millis(200)).is_ok() {}
+    }
+}
+
+/// Whether `path` is the cabinet `dest` or the temporary file it's written
+/// to.
+fn is_own_file(path: &Path, dest: &Path) -> bool {
+    path.file_name() == dest.file_name()
+        || path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(makecab::TEMP_FILE_PREFIX))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use self::tempdir::TempDir;
+    use super::{default_destination, expand_response_files, parse_file_list, parse_size};
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn response_files() {
+        let td = TempDir::new("makecab").unwrap();
+        let rsp = td.path().join("args.rsp");
+        fs::write(&rsp, "-V2\r\n\n  \"dir with spaces\\a.txt\"  \r\nout.cab\n").unwrap();
+        let args = vec![
+            OsString::from("makecab"),
+            OsString::from(format!("@{}", rsp.display())),
+            OsString::from("@"),
+        ];
+        assert_eq!(
+            expand_response_files(args).unwrap(),
+            vec!["makecab", "-V2", "dir with spaces\\a.txt", "out.cab", "@"]
+        );
+
+        let missing = td.path().join("missing.rsp");
+        let (file, _) =
+            expand_response_files(vec![OsString::from(format!("@{}", missing.display()))])
+                .unwrap_err();
+        assert_eq!(file, missing);
+    }
+
+    #[test]
+    fn file_lists() {
+        let paths = |list: &[u8], nul| parse_file_list(list.to_vec(), nul).unwrap();
+        assert_eq!(
+            paths(b"a.txt\r\ndir/b c.txt\n\nd.txt", false),
+            vec![
+                PathBuf::from("a.txt"),
+                PathBuf::from("dir/b c.txt"),
+                PathBuf::from("d.txt")
+            ]
+        );
+        assert_eq!(
+            paths(b"a\nb.txt\0c.txt\r\0", true),
+            vec![PathBuf::from("a\nb.txt"), PathBuf::from("c.txt\r")]
+        );
+    }
+
+    #[test]
+    fn default_destinations() {
+        let dest = |source: &str| default_destination(Path::new(source)).unwrap();
+        assert_eq!(dest("file.pdb"), "file.pd_");
+        assert_eq!(dest("dir/größe.txt"), "größe.tx_");
+        assert_eq!(dest("名前"), "名_");
+        let cwd = std::env::current_dir().unwrap();
+        let cwd_name = cwd.file_name().unwrap().to_str().unwrap();
+        assert_eq!(
+            dest("."),
+            OsString::from(format!("{}_", &cwd_name[..cwd_name.len() - 1]))
+        );
+        if cfg!(windows) {
+            assert_eq!(dest(r"\\server\share\file.pdb"), "file.pd_");
+            assert_eq!(dest(r"\\?\C:\very\long\path\file.dll"), "file.dl_");
+            assert_eq!(dest(r"\\?\UNC\server\share\file.exe"), "file.ex_");
+            assert_eq!(default_destination(Path::new(r"\\server\share\")), None);
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1000"), Some(1000));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("650MB"), Some(650 * 1024 * 1024));
+        assert_eq!(parse_size("2 gb"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("MB"), None);
+        assert_eq!(parse_size("12X"), None);
+    }
+}
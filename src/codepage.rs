@@ -0,0 +1,160 @@
+//! Encoding stored names in DOS code pages.
+//!
+//! Names not marked as UTF-8 are in whatever code page the extractor
+//! assumes, which for old DOS and Windows tools is the OEM code page rather
+//! than UTF-8.
+
+/// The upper half of code page 437, the original IBM PC character set.
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕',
+    '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐',
+    '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', '≡', '±',
+    '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{00a0}',
+];
+
+/// The upper half of code page 850, DOS's Western European code page.
+const CP850: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©',
+    '╣', '║', '╗', '╝', '¢', '¥', '┐', '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '¤', 'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì',
+    '▀', 'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´',
+    '\u{00ad}', '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{00a0}',
+];
+
+/// A DOS code page to store names in, set through
+/// [`CabOptions::name_codepage`].
+///
+/// [`CabOptions::name_codepage`]: crate::CabOptions::name_codepage
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codepage {
+    /// Code page 437, used by US DOS.
+    Cp437,
+    /// Code page 850, used by Western European DOS.
+    Cp850,
+}
+
+impl Codepage {
+    /// The code page's number, like 437.
+    pub fn number(self) -> u16 {
+        match self {
+            Codepage::Cp437 => 437,
+            Codepage::Cp850 => 850,
+        }
+    }
+
+    /// The code page with the number `number`, if it's one of the supported
+    /// ones.
+    pub fn from_number(number: u16) -> Option<Codepage> {
+        match number {
+            437 => Some(Codepage::Cp437),
+            850 => Some(Codepage::Cp850),
+            _ => None,
+        }
+    }
+
+    fn upper_half(self) -> &'static [char; 128] {
+        match self {
+            Codepage::Cp437 => &CP437,
+            Codepage::Cp850 => &CP850,
+        }
+    }
+
+    /// Encode `name` in this code page, or return the first character that
+    /// the code page doesn't have.
+    pub fn encode(self, name: &str) -> Result<Vec<u8>, char> {
+        name.chars()
+            .map(|c| {
+                if c.is_ascii() {
+                    Ok(c as u8)
+                } else {
+                    self.upper_half()
+                        .iter()
+                        .position(|&upper| upper == c)
+                        .map(|i| 0x80 + i as u8)
+                        .ok_or(c)
+                }
+            })
+            .collect()
+    }
+
+    /// Decode `bytes` from this code page.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map(|&b| {
+                if b.is_ascii() {
+                    b as char
+                } else {
+                    self.upper_half()[usize::from(b - 0x80)]
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+
+    use self::tempdir::TempDir;
+
+    use super::Codepage;
+    use crate::format::parse::parse_cab;
+    use crate::{lint_cab, make_cab_from_files_with_options, CabOptions};
+
+    #[test]
+    fn encode() {
+        assert_eq!(
+            Codepage::Cp437.encode("readme.txt"),
+            Ok(b"readme.txt".to_vec())
+        );
+        assert_eq!(
+            Codepage::Cp437.encode("Caf\u{e9}\\\u{fc}ber.txt"),
+            Ok(b"Caf\x82\\\x81ber.txt".to_vec())
+        );
+        // 437 has no À, but 850 does.
+        assert_eq!(Codepage::Cp437.encode("\u{c0}.txt"), Err('\u{c0}'));
+        assert_eq!(
+            Codepage::Cp850.encode("\u{c0}.txt"),
+            Ok(b"\xb7.txt".to_vec())
+        );
+        assert_eq!(Codepage::Cp850.encode("\u{65e5}.txt"), Err('\u{65e5}'));
+        for codepage in [Codepage::Cp437, Codepage::Cp850] {
+            let all = (0..=255).collect::<Vec<u8>>();
+            let decoded = codepage.decode(&all);
+            assert_eq!(decoded.chars().count(), 256);
+            assert_eq!(codepage.encode(&decoded), Ok(all));
+            assert_eq!(Codepage::from_number(codepage.number()), Some(codepage));
+        }
+    }
+
+    #[test]
+    fn stored_names() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("caf\u{e9}.txt");
+        fs::write(&path, "coffee").unwrap();
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            name_codepage: Some(Codepage::Cp437),
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &[&path], &options)
+            .expect("failed to create cab file");
+        assert_eq!(lint_cab(&cab).expect("failed to lint cab file"), vec![]);
+        let parsed = parse_cab(&cab).expect("failed to parse cab file");
+        assert!(!parsed.files[0].is_name_utf());
+        let data = fs::read(&cab).unwrap();
+        assert!(data.windows(9).any(|w| w == b"caf\x82.txt\0"));
+
+        let path = t.path().join("\u{c0}.txt");
+        fs::write(&path, "a").unwrap();
+        assert!(make_cab_from_files_with_options(&cab, &[&path], &options).is_err());
+    }
+}
@@ -0,0 +1,362 @@
+//! Compressing the data blocks in a folder.
+
+use std::fmt::Debug;
+use std::io;
+
+use anyhow::{bail, Result};
+use flate2::Compression;
+
+use crate::format::{tcompTYPE_MSZIP, tcompTYPE_NONE, MAX_COMPRESSED_CHUNK};
+use crate::pipeline::compress_block;
+use crate::pool::CompressorPool;
+use crate::{CFDATA_SIZE, MAX_CHUNK};
+
+/// A codec for the data blocks in a folder, used when writing cabinets with
+/// [`CabOptions::compressor`].
+///
+/// Each block holds up to 32768 bytes of data and is compressed on its own,
+/// possibly on several threads at once, so codecs that need state carried
+/// from one block to the next, like LZX and Quantum, don't fit. Extractors
+/// only understand the compression types in the cabinet format, so a codec
+/// with a type of its own makes cabinets only readers that know about it
+/// can extract.
+///
+/// [`CabOptions::compressor`]: crate::CabOptions::compressor
+pub trait FolderCompressor: Debug + Send + Sync {
+    /// The folder's `typeCompress` field, one of the `tcompTYPE_*`
+    /// constants plus any settings the type has.
+    fn compression_type(&self) -> u16;
+
+    /// Compress `chunk` into the data of a block. The data can be at most
+    /// 38912 bytes, 6144 more than a full chunk, as the format allows.
+    ///
+    /// `chunk` is never empty. Empty files take up no data, so a folder
+    /// whose files are all empty gets no blocks, and this isn't called.
+    fn compress_block(&self, chunk: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// Compresses blocks with MSZIP, reusing deflate streams from a
+/// [`CompressorPool`].
+#[derive(Debug)]
+pub struct MsZipCompressor {
+    pool: CompressorPool,
+}
+
+impl MsZipCompressor {
+    /// Compress blocks at the deflate compression `level`.
+    pub fn new(level: Compression) -> MsZipCompressor {
+        MsZipCompressor {
+            pool: CompressorPool::new(level),
+        }
+    }
+}
+
+impl FolderCompressor for MsZipCompressor {
+    fn compression_type(&self) -> u16 {
+        tcompTYPE_MSZIP
+    }
+
+    fn compress_block(&self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        self.pool.get().compress_block(chunk)
+    }
+}
+
+/// Stores blocks without compressing them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoneCompressor;
+
+impl FolderCompressor for NoneCompressor {
+    fn compression_type(&self) -> u16 {
+        tcompTYPE_NONE
+    }
+
+    fn compress_block(&self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(chunk.to_vec())
+    }
+}
+
+/// Compresses a stream of data into CFDATA records one block at a time,
+/// without doing any I/O or starting any threads of its own, so event
+/// loops can drive compression themselves.
+///
+/// Data goes in through [`BlockEncoder::push_input`], which takes as much
+/// as fits in the block being filled, and finished blocks come out of
+/// [`BlockEncoder::pull_block`]. Once a block is full no more input is
+/// taken until it's been pulled. [`BlockEncoder::finish`] marks the end of
+/// the input, so the last, partly filled block can be pulled too. Blocks
+/// are never empty, so input with no data makes no blocks.
+///
+/// The records have no reserve, and follow each other in a folder of the
+/// encoder's [`FolderCompressor::compression_type`].
+#[derive(Debug)]
+pub struct BlockEncoder<C = MsZipCompressor> {
+    compressor: C,
+    chunk: Vec<u8>,
+    chunk_size: usize,
+    finished: bool,
+}
+
+impl BlockEncoder {
+    /// Compress blocks of 32768 bytes with MSZIP at the deflate compression
+    /// `level`.
+    pub fn mszip(level: Compression) -> BlockEncoder {
+        BlockEncoder {
+            compressor: MsZipCompressor::new(level),
+            chunk: Vec::with_capacity(MAX_CHUNK),
+            chunk_size: MAX_CHUNK,
+            finished: false,
+        }
+    }
+}
+
+impl<C: FolderCompressor> BlockEncoder<C> {
+    /// The most bytes [`BlockEncoder::pull_block`] writes, and so the
+    /// smallest buffer it takes: a CFDATA header and the most compressed
+    /// data a block can hold.
+    pub const MAX_BLOCK_SIZE: usize = CFDATA_SIZE as usize + MAX_COMPRESSED_CHUNK;
+
+    /// Compress blocks of `chunk_size` bytes, from 1 to 32768, with
+    /// `compressor`.
+    pub fn new(compressor: C, chunk_size: usize) -> Result<BlockEncoder<C>> {
+        if !(1..=MAX_CHUNK).contains(&chunk_size) {
+            bail!(
+                "Chunk size must be from 1 to {} bytes, not {}",
+                MAX_CHUNK,
+                chunk_size
+            );
+        }
+        Ok(BlockEncoder {
+            compressor,
+            chunk: Vec::with_capacity(chunk_size),
+            chunk_size,
+            finished: false,
+        })
+    }
+
+    /// Add as much of `input` as fits in the block being filled, returning
+    /// how many bytes were taken. This is 0 when the block is full and
+    /// waiting to be pulled, and after [`BlockEncoder::finish`].
+    pub fn push_input(&mut self, input: &[u8]) -> usize {
+        if self.finished {
+            return 0;
+        }
+        let len = input.len().min(self.chunk_size - self.chunk.len());
+        self.chunk.extend_from_slice(&input[..len]);
+        len
+    }
+
+    /// Mark the end of the input, so the last block can be pulled even if
+    /// it isn't full.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Whether the input has ended and every block has been pulled.
+    pub fn is_done(&self) -> bool {
+        self.finished && self.chunk.is_empty()
+    }
+
+    /// Compress the block being filled into a CFDATA record in `buf`, which
+    /// must be at least [`BlockEncoder::MAX_BLOCK_SIZE`] bytes, returning
+    /// the record's size. Returns `None` if the block isn't full yet, or
+    /// once everything has been pulled.
+    pub fn pull_block(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        if buf.len() < Self::MAX_BLOCK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Buffer is {} bytes, less than the {} a block can take",
+                    buf.len(),
+                    Self::MAX_BLOCK_SIZE
+                ),
+            ));
+        }
+        let full = self.chunk.len() == self.chunk_size;
+        if self.chunk.is_empty() || !(full || self.finished) {
+            return Ok(None);
+        }
+        let block = compress_block(&self.chunk, &self.compressor)?;
+        let size = block.size() as usize;
+        block
+            .write_to(&mut buf[..size])
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.chunk.clear();
+        Ok(Some(size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::{self, File};
+    use std::io::{self, Read};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use self::tempdir::TempDir;
+    use cab::{Cabinet, CompressionType};
+    use chrono::NaiveDate;
+    use flate2::Compression;
+
+    use super::{BlockEncoder, FolderCompressor, MsZipCompressor, NoneCompressor};
+    use crate::format::parse::read_data_block;
+    use crate::format::{tcompTYPE_MSZIP, tcompTYPE_NONE, FileEntry, Folder, Layout};
+    use crate::{make_cab_from_files_with_options, verify, CabOptions, MAX_CHUNK};
+
+    /// Stores blocks uncompressed, counting them.
+    #[derive(Debug, Default)]
+    struct Counting(AtomicUsize);
+
+    impl FolderCompressor for Counting {
+        fn compression_type(&self) -> u16 {
+            tcompTYPE_NONE
+        }
+
+        fn compress_block(&self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(chunk.to_vec())
+        }
+    }
+
+    /// Produces blocks too large for the format.
+    #[derive(Debug)]
+    struct Bloated;
+
+    impl FolderCompressor for Bloated {
+        fn compression_type(&self) -> u16 {
+            tcompTYPE_NONE
+        }
+
+        fn compress_block(&self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(chunk.repeat(2))
+        }
+    }
+
+    #[test]
+    fn poll_encoder() {
+        let data = (0..MAX_CHUNK * 2 + 500)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut encoder = BlockEncoder::mszip(Compression::default());
+        let mut buf = vec![0; BlockEncoder::<MsZipCompressor>::MAX_BLOCK_SIZE];
+        let mut records = vec![];
+        let mut input = &data[..];
+        while !encoder.is_done() {
+            let taken = encoder.push_input(&input[..input.len().min(1000)]);
+            input = &input[taken..];
+            if input.is_empty() {
+                encoder.finish();
+            }
+            if let Some(size) = encoder.pull_block(&mut buf).unwrap() {
+                records.extend_from_slice(&buf[..size]);
+            } else {
+                assert!(taken > 0, "encoder stalled");
+            }
+        }
+        assert_eq!(encoder.push_input(b"more"), 0);
+        assert_eq!(encoder.pull_block(&mut buf).unwrap(), None);
+
+        let mut reader = &records[..];
+        let mut blocks = vec![];
+        while !reader.is_empty() {
+            let (csum, block) = read_data_block(&mut reader, 0).unwrap();
+            assert_eq!(csum, block.checksum());
+            blocks.push(block);
+        }
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|block| block.uncompressed_size)
+                .collect::<Vec<_>>(),
+            [32768, 32768, 500]
+        );
+        let datetime = NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8);
+        let layout = Layout {
+            folders: vec![(
+                Folder {
+                    compression: tcompTYPE_MSZIP,
+                    ..Folder::default()
+                },
+                blocks,
+            )],
+            files: vec![FileEntry::new(
+                "data.bin",
+                data.len() as u32,
+                0,
+                0,
+                datetime,
+            )],
+            ..Layout::default()
+        };
+        let bytes = layout.to_bytes().unwrap();
+        let mut cabinet = Cabinet::new(io::Cursor::new(bytes)).unwrap();
+        let mut read = vec![];
+        cabinet
+            .read_file("data.bin")
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+
+        // No input, no blocks.
+        let mut encoder = BlockEncoder::new(NoneCompressor, 10).unwrap();
+        assert_eq!(encoder.pull_block(&mut buf).unwrap(), None);
+        assert!(!encoder.is_done());
+        encoder.finish();
+        assert_eq!(encoder.pull_block(&mut buf).unwrap(), None);
+        assert!(encoder.is_done());
+
+        assert!(BlockEncoder::new(NoneCompressor, 0).is_err());
+        let mut encoder = BlockEncoder::new(NoneCompressor, 10).unwrap();
+        assert_eq!(encoder.push_input(b"hello world"), 10);
+        assert!(encoder.pull_block(&mut [0; 100]).is_err());
+        assert_eq!(encoder.pull_block(&mut buf).unwrap(), Some(18));
+    }
+
+    #[test]
+    fn pluggable() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("data.bin");
+        let data = (0..MAX_CHUNK * 3 + 10)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(&path, &data).unwrap();
+        let cab = t.path().join("test.cab");
+
+        let counting = Arc::new(Counting::default());
+        let options = CabOptions {
+            compressor: Some(counting.clone()),
+            threads: 2,
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &[&path], &options)
+            .expect("failed to create cab file");
+        assert_eq!(counting.0.load(Ordering::Relaxed), 4);
+        verify(&cab, &path).expect("cab file didn't verify");
+        let mut cabinet = Cabinet::new(File::open(&cab).unwrap()).unwrap();
+        let folder = cabinet.folder_entries().next().unwrap();
+        assert_eq!(folder.compression_type(), CompressionType::None);
+        let mut read = vec![];
+        cabinet
+            .read_file("data.bin")
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+
+        let options = CabOptions {
+            compressor: Some(Arc::new(NoneCompressor)),
+            ..CabOptions::default()
+        };
+        let summary = make_cab_from_files_with_options(&cab, &[&path], &options)
+            .expect("failed to create cab file");
+        assert!(summary.cab_size > data.len() as u64);
+
+        let options = CabOptions {
+            compressor: Some(Arc::new(Bloated)),
+            ..CabOptions::default()
+        };
+        assert!(make_cab_from_files_with_options(&cab, &[&path], &options).is_err());
+    }
+}
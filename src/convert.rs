@@ -0,0 +1,263 @@
+//! Converting cabinets to and from zip archives.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use cab::{Cabinet, CabinetBuilder, CompressionType};
+use chrono::{Datelike, NaiveDate, Timelike};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+use crate::temp_cab_file;
+
+/// Write the files in the cabinet file at `cab_path` to a zip archive at
+/// `zip_path`, for tools that can't read cabinets.
+///
+/// Each file is decompressed and deflated straight into the archive, one
+/// at a time, keeping its name, with `\` separators turned into `/`, and
+/// its modification time. Read-only files are marked read-only in the
+/// archive too.
+pub fn cab_to_zip<T: AsRef<Path>, U: AsRef<Path>>(cab_path: T, zip_path: U) -> Result<()> {
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let files = cabinet
+        .folder_entries()
+        .flat_map(|folder| folder.file_entries())
+        .map(|file| {
+            let mut options =
+                FileOptions::default().compression_method(CompressionMethod::Deflated);
+            if let Some(datetime) = file.datetime() {
+                if let Ok(datetime) = DateTime::from_date_and_time(
+                    datetime.year() as u16,
+                    datetime.month() as u8,
+                    datetime.day() as u8,
+                    datetime.hour() as u8,
+                    datetime.minute() as u8,
+                    datetime.second() as u8,
+                ) {
+                    options = options.last_modified_time(datetime);
+                }
+            }
+            if file.is_read_only() {
+                options = options.unix_permissions(0o444);
+            }
+            (file.name().to_owned(), options)
+        })
+        .collect::<Vec<_>>();
+
+    let zip_path = zip_path.as_ref();
+    let mut zip = ZipWriter::new(BufWriter::new(temp_cab_file(zip_path)?));
+    for (name, options) in files {
+        zip.start_file(name.replace('\\', "/"), options)?;
+        io::copy(&mut cabinet.read_file(&name)?, &mut zip)?;
+    }
+    let mut writer = zip.finish()?;
+    writer.flush()?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .persist(zip_path)?;
+    Ok(())
+}
+
+/// Write the files in the zip archive at `zip_path` to a cabinet file at
+/// `cab_path`, to repackage cross-platform artifacts for Windows tooling.
+///
+/// Files keep their order, names, with `/` separators turned into `\\`, and
+/// modification times, and directory entries are skipped. Files that are
+/// deflated in the archive are decompressed and recompressed with MSZIP,
+/// since MSZIP needs a block boundary every 32 KiB of output, which deflate
+/// streams don't have. Stored files are stored uncompressed in the cabinet
+/// too, unless `recompress` is set, in which case everything is compressed
+/// with MSZIP.
+pub fn zip_to_cab<T: AsRef<Path>, U: AsRef<Path>>(
+    zip_path: T,
+    cab_path: U,
+    recompress: bool,
+) -> Result<()> {
+    let mut zip = ZipArchive::new(File::open(zip_path)?)?;
+    let mut members = vec![];
+    let mut names = HashSet::new();
+    for i in 0..zip.len() {
+        let file = zip.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().replace('/', "\\");
+        if !names.insert(name.clone()) {
+            bail!("Zip archive has more than one file named '{}'", name);
+        }
+        let compression = match file.compression() {
+            CompressionMethod::Stored if !recompress => CompressionType::None,
+            _ => CompressionType::MsZip,
+        };
+        let modified = file.last_modified();
+        let datetime = NaiveDate::from_ymd_opt(
+            modified.year().into(),
+            modified.month().into(),
+            modified.day().into(),
+        )
+        .and_then(|date| {
+            date.and_hms_opt(
+                modified.hour().into(),
+                modified.minute().into(),
+                modified.second().into(),
+            )
+        });
+        let read_only = file.unix_mode().is_some_and(|mode| mode & 0o222 == 0);
+        members.push((i, name, compression, datetime, read_only));
+    }
+
+    let mut cab_builder = CabinetBuilder::new();
+    // Runs of files with the same compression share a folder, so the files
+    // stay in the same order.
+    for run in members.chunk_by(|a, b| a.2 == b.2) {
+        let folder = cab_builder.add_folder(run[0].2);
+        for (_, name, _, datetime, read_only) in run {
+            let new_file = folder.add_file(name.as_str());
+            if let Some(datetime) = datetime {
+                new_file.set_datetime(*datetime);
+            }
+            new_file.set_is_read_only(*read_only);
+        }
+    }
+
+    let cab_path = cab_path.as_ref();
+    let mut cab_writer = cab_builder.build(temp_cab_file(cab_path)?)?;
+    let mut indices = members.iter().map(|member| member.0);
+    while let (Some(mut writer), Some(i)) = (cab_writer.next_file()?, indices.next()) {
+        io::copy(&mut zip.by_index(i)?, &mut writer)?;
+    }
+    cab_writer.finish()?.persist(cab_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    use self::tempdir::TempDir;
+    use cab::{Cabinet, CompressionType};
+    use chrono::NaiveDate;
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+    use super::{cab_to_zip, zip_to_cab};
+    use crate::{make_cabs_from_manifest, verify_cab};
+
+    #[test]
+    fn to_zip() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        fs::write(t.path().join("a.txt"), "a".repeat(100_000)).unwrap();
+        fs::write(t.path().join("b.txt"), b"bbb").unwrap();
+        let manifest = t.path().join("build.json");
+        fs::write(
+            &manifest,
+            r#"{
+                "cab": "out.cab",
+                "entries": [
+                    {"source": "a.txt", "timestamp": "2021-03-04T05:06:08"},
+                    {"source": "b.txt", "name": "dir\\b.txt", "compression": "none",
+                     "attributes": ["read-only"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        make_cabs_from_manifest(&manifest).expect("failed to create cab file");
+        let zip_path = t.path().join("out.zip");
+        cab_to_zip(t.path().join("out.cab"), &zip_path).expect("failed to convert cab file");
+
+        let mut zip = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        assert_eq!(zip.len(), 2);
+        let mut a = zip.by_name("a.txt").unwrap();
+        let modified = a.last_modified();
+        assert_eq!(
+            (modified.year(), modified.month(), modified.day()),
+            (2021, 3, 4)
+        );
+        assert_eq!(
+            (modified.hour(), modified.minute(), modified.second()),
+            (5, 6, 8)
+        );
+        let mut data = String::new();
+        a.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "a".repeat(100_000));
+        drop(a);
+        let b = zip.by_name("dir/b.txt").unwrap();
+        assert_eq!(b.size(), 3);
+        assert_eq!(b.unix_mode().map(|mode| mode & 0o777), Some(0o444));
+    }
+
+    #[test]
+    fn from_zip() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let zip_path = t.path().join("in.zip");
+        let mut zip = ZipWriter::new(File::create(&zip_path).unwrap());
+        let deflated = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(DateTime::from_date_and_time(2021, 3, 4, 5, 6, 8).unwrap());
+        let stored = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .unix_permissions(0o444);
+        zip.start_file("a.txt", deflated).unwrap();
+        zip.write_all("a".repeat(100_000).as_bytes()).unwrap();
+        zip.add_directory("dir/", FileOptions::default()).unwrap();
+        zip.start_file("dir/b.txt", stored).unwrap();
+        zip.write_all(b"bbb").unwrap();
+        zip.start_file("c.txt", deflated).unwrap();
+        zip.write_all(b"ccc").unwrap();
+        zip.finish().unwrap();
+
+        let cab = t.path().join("out.cab");
+        zip_to_cab(&zip_path, &cab, false).expect("failed to convert zip archive");
+        assert!(verify_cab(&cab).expect("cab file didn't verify").is_ok());
+        let mut cabinet = Cabinet::new(File::open(&cab).unwrap()).unwrap();
+        let folders = cabinet
+            .folder_entries()
+            .map(|folder| {
+                let names = folder
+                    .file_entries()
+                    .map(|file| file.name().to_owned())
+                    .collect::<Vec<_>>();
+                (folder.compression_type(), names)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            folders,
+            vec![
+                (CompressionType::MsZip, vec!["a.txt".to_owned()]),
+                (CompressionType::None, vec!["dir\\b.txt".to_owned()]),
+                (CompressionType::MsZip, vec!["c.txt".to_owned()]),
+            ]
+        );
+        let a = cabinet.get_file_entry("a.txt").unwrap();
+        assert_eq!(
+            a.datetime(),
+            NaiveDate::from_ymd_opt(2021, 3, 4)
+                .unwrap()
+                .and_hms_opt(5, 6, 8)
+        );
+        assert!(cabinet.get_file_entry("dir\\b.txt").unwrap().is_read_only());
+        let mut data = String::new();
+        cabinet
+            .read_file("a.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "a".repeat(100_000));
+
+        // Recompressing puts everything in one MSZIP folder.
+        zip_to_cab(&zip_path, &cab, true).expect("failed to convert zip archive");
+        let cabinet = Cabinet::new(File::open(&cab).unwrap()).unwrap();
+        let compression = cabinet
+            .folder_entries()
+            .map(|folder| folder.compression_type())
+            .collect::<Vec<_>>();
+        assert_eq!(compression, vec![CompressionType::MsZip]);
+    }
+}
@@ -0,0 +1,659 @@
+//! Comparing cabinets with other sets of files.
+
+#![allow(non_upper_case_globals)]
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use cab::Cabinet;
+use filetime::FileTime;
+use serde::Serialize;
+
+use crate::extract::entry_mtime;
+use crate::format::parse::parse;
+use crate::format::{
+    from_dos_datetime, tcompTYPE_LZX, tcompTYPE_MASK, tcompTYPE_MSZIP, tcompTYPE_NONE,
+    tcompTYPE_QUANTUM,
+};
+use crate::reserve::read_reserve;
+use crate::{make_cab_from_files, member_digests, sha256_hex, stored_name};
+
+/// The differences between the files in a cabinet and another set of files,
+/// matched up by name and compared by SHA-256 digest.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CabDiff {
+    /// Files that aren't in the cabinet.
+    pub added: Vec<String>,
+    /// Files that are only in the cabinet.
+    pub removed: Vec<String>,
+    /// Files whose contents differ from those in the cabinet.
+    pub changed: Vec<String>,
+    /// Files whose contents are the same as those in the cabinet.
+    pub unchanged: Vec<String>,
+}
+
+impl CabDiff {
+    /// Returns `true` if there are no added, removed or changed files.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The differences between two cabinets, as found by [`diff_cabs`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CabsDiff {
+    /// Files that are only in the new cabinet.
+    pub added: Vec<String>,
+    /// Files that are only in the old cabinet.
+    pub removed: Vec<String>,
+    /// Files in both cabinets whose contents differ.
+    pub changed: Vec<String>,
+    /// Files stored under a different name in the new cabinet, with the
+    /// same contents.
+    pub renamed: Vec<MemberChange>,
+    /// Files in both cabinets whose modification times differ.
+    pub retimed: Vec<MemberChange>,
+    /// Files in both cabinets whose folders are compressed differently.
+    pub recompressed: Vec<MemberChange>,
+}
+
+impl CabsDiff {
+    /// Returns `true` if the cabinets hold the same files, stored the same
+    /// way.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.renamed.is_empty()
+            && self.retimed.is_empty()
+            && self.recompressed.is_empty()
+    }
+}
+
+/// Something about a file that differs between two cabinets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MemberChange {
+    /// The name of the file in the old cabinet.
+    pub name: String,
+    /// The old value: a name, a timestamp like `2021-03-04T05:06:08`, or
+    /// compression settings like `MSZIP` or `LZX:21`.
+    pub old: String,
+    /// The new value.
+    pub new: String,
+}
+
+/// Write a cabinet file at `cab_path` containing only the files in
+/// `input_paths` that were added or changed relative to the cabinet at
+/// `baseline_path`, for building incremental update packages.
+///
+/// Input files are matched with files in the baseline by the name they would
+/// be stored under, and compared by the SHA-256 digest of their contents.
+/// The returned diff also lists the files that are in the baseline but not
+/// in `input_paths`, which an update would need to remove.
+pub fn make_diff_cab<T, U, V>(cab_path: T, baseline_path: U, input_paths: &[V]) -> Result<CabDiff>
+where
+    T: AsRef<Path>,
+    U: AsRef<Path>,
+    V: AsRef<Path>,
+{
+    let baseline = member_digests(baseline_path.as_ref())?;
+    let mut remaining = baseline.iter().cloned().collect::<HashMap<_, _>>();
+    let mut diff = CabDiff::default();
+    let mut to_write = vec![];
+    for input_path in input_paths {
+        let input_path = input_path.as_ref();
+        let name = stored_name(input_path)?.to_owned();
+        match remaining.remove(&name) {
+            None => {
+                diff.added.push(name);
+                to_write.push(input_path);
+            }
+            Some(digest) if digest != sha256_hex(File::open(input_path)?)? => {
+                diff.changed.push(name);
+                to_write.push(input_path);
+            }
+            Some(_) => diff.unchanged.push(name),
+        }
+    }
+    diff.removed = baseline
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| remaining.contains_key(name))
+        .collect();
+    make_cab_from_files(cab_path, &to_write)?;
+    Ok(diff)
+}
+
+/// Compare the files in the cabinet at `cab_path` with the files in the
+/// directory `dir`, to check that a packaging step captured everything.
+///
+/// Files in subdirectories of `dir` are matched with files stored under
+/// names containing `\`. Files whose sizes match the sizes recorded in
+/// the cabinet are compared by the SHA-256 digest of their contents. Files
+/// that are only in `dir` are reported as added, and files that are only in
+/// the cabinet as removed.
+pub fn diff_cab_dir<T: AsRef<Path>, U: AsRef<Path>>(cab_path: T, dir: U) -> Result<CabDiff> {
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let members = cabinet
+        .folder_entries()
+        .flat_map(|folder| {
+            folder
+                .file_entries()
+                .map(|file| (file.name().to_owned(), u64::from(file.uncompressed_size())))
+        })
+        .collect::<Vec<_>>();
+    let mut remaining = members.iter().cloned().collect::<HashMap<_, _>>();
+    let mut diff = CabDiff::default();
+    for (name, path) in dir_files(dir.as_ref())? {
+        match remaining.remove(&name) {
+            None => diff.added.push(name),
+            Some(size) if size != fs::metadata(&path)?.len() => diff.changed.push(name),
+            Some(_) => {
+                let digest = sha256_hex(cabinet.read_file(&name)?)?;
+                if digest == sha256_hex(File::open(&path)?)? {
+                    diff.unchanged.push(name);
+                } else {
+                    diff.changed.push(name);
+                }
+            }
+        }
+    }
+    diff.removed = members
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| remaining.contains_key(name))
+        .collect();
+    Ok(diff)
+}
+
+/// A way a file differs between a cabinet and a directory, as found by
+/// [`verify_against_dir`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Mismatch {
+    /// The name of the file as stored in the cabinet.
+    pub name: String,
+    /// What differs.
+    #[serde(flatten)]
+    pub kind: MismatchKind,
+}
+
+/// What differs about a file in a [`Mismatch`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MismatchKind {
+    /// The file is in the cabinet but not in the directory.
+    Missing,
+    /// The file is in the directory but not in the cabinet.
+    Extra,
+    /// The file's size in the cabinet and on disk.
+    Size { cab: u64, disk: u64 },
+    /// The sizes match but the contents don't.
+    Contents,
+    /// The file's modification time in the cabinet and on disk, in seconds
+    /// since the Unix epoch.
+    Timestamp { cab: i64, disk: i64 },
+}
+
+/// Check every file in the cabinet at `cab_path` against the file of the
+/// same name under `dir`, for signing off on a release.
+///
+/// Files are compared by size, then by the SHA-256 digest of their
+/// contents, and their modification times must be within `tolerance` of
+/// each other. DOS timestamps only have two-second precision, so a
+/// tolerance below two seconds can report files that were stored
+/// faithfully. Files in only one of the two places are reported too, so an
+/// empty list means the cabinet matches the directory exactly.
+pub fn verify_against_dir<T: AsRef<Path>, U: AsRef<Path>>(
+    cab_path: T,
+    dir: U,
+    tolerance: Duration,
+) -> Result<Vec<Mismatch>> {
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let extras = read_reserve(&cabinet);
+    let members = cabinet
+        .folder_entries()
+        .flat_map(|folder| {
+            folder.file_entries().map(|file| {
+                let mtime = extras
+                    .get(file.name())
+                    .and_then(|extra| extra.timestamps)
+                    .map(|t| t.modified)
+                    .or_else(|| entry_mtime(file));
+                (
+                    file.name().to_owned(),
+                    u64::from(file.uncompressed_size()),
+                    mtime,
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut on_disk = dir_files(dir.as_ref())?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut mismatches = vec![];
+    let mut mismatch = |name: &str, kind| {
+        mismatches.push(Mismatch {
+            name: name.to_owned(),
+            kind,
+        })
+    };
+    for (name, size, mtime) in &members {
+        let path = match on_disk.remove(name) {
+            Some(path) => path,
+            None => {
+                mismatch(name, MismatchKind::Missing);
+                continue;
+            }
+        };
+        let meta = fs::metadata(&path)?;
+        if meta.len() != *size {
+            mismatch(
+                name,
+                MismatchKind::Size {
+                    cab: *size,
+                    disk: meta.len(),
+                },
+            );
+        } else if sha256_hex(cabinet.read_file(name)?)? != sha256_hex(File::open(&path)?)? {
+            mismatch(name, MismatchKind::Contents);
+        }
+        let disk = FileTime::from_last_modification_time(&meta).unix_seconds();
+        if let Some(cab) = mtime.map(|mtime| mtime.unix_seconds()) {
+            if cab.abs_diff(disk) > tolerance.as_secs() {
+                mismatch(name, MismatchKind::Timestamp { cab, disk });
+            }
+        }
+    }
+    let mut extra = on_disk.into_keys().collect::<Vec<_>>();
+    extra.sort();
+    for name in extra {
+        mismatch(&name, MismatchKind::Extra);
+    }
+    Ok(mismatches)
+}
+
+/// Compare the cabinet at `old_path` with the cabinet at `new_path`, to
+/// review what changed between two builds of a package.
+///
+/// Files are matched up by name and compared by the SHA-256 digest of
+/// their contents. A file that's only in the old cabinet with the same
+/// contents as one that's only in the new cabinet is reported as renamed,
+/// rather than removed and added. Files in both cabinets are also checked
+/// for changed timestamps and compression settings, including the LZX
+/// window size and Quantum level.
+pub fn diff_cabs<T: AsRef<Path>, U: AsRef<Path>>(old_path: T, new_path: U) -> Result<CabsDiff> {
+    let (old_path, new_path) = (old_path.as_ref(), new_path.as_ref());
+    let old = member_details(old_path)?;
+    let new = member_details(new_path)?;
+    let mut remaining = new
+        .iter()
+        .map(|member| (member.name.as_str(), member))
+        .collect::<HashMap<_, _>>();
+    let mut diff = CabsDiff::default();
+    let mut removed = vec![];
+    for old_member in &old {
+        let new_member = match remaining.remove(old_member.name.as_str()) {
+            Some(new_member) => new_member,
+            None => {
+                removed.push(old_member);
+                continue;
+            }
+        };
+        if old_member.digest != new_member.digest {
+            diff.changed.push(old_member.name.clone());
+        }
+        for (list, old_value, new_value) in [
+            (
+                &mut diff.retimed,
+                &old_member.modified,
+                &new_member.modified,
+            ),
+            (
+                &mut diff.recompressed,
+                &old_member.compression,
+                &new_member.compression,
+            ),
+        ] {
+            if old_value != new_value {
+                list.push(MemberChange {
+                    name: old_member.name.clone(),
+                    old: old_value.clone(),
+                    new: new_value.clone(),
+                });
+            }
+        }
+    }
+    let mut added = new
+        .iter()
+        .filter(|member| remaining.contains_key(member.name.as_str()))
+        .collect::<Vec<_>>();
+    for old_member in removed {
+        match added
+            .iter()
+            .position(|member| member.digest == old_member.digest)
+        {
+            Some(i) => diff.renamed.push(MemberChange {
+                name: old_member.name.clone(),
+                old: old_member.name.clone(),
+                new: added.remove(i).name.clone(),
+            }),
+            None => diff.removed.push(old_member.name.clone()),
+        }
+    }
+    diff.added = added
+        .into_iter()
+        .map(|member| member.name.clone())
+        .collect();
+    Ok(diff)
+}
+
+/// What [`diff_cabs`] compares about a file in a cabinet.
+struct MemberDetails {
+    name: String,
+    digest: String,
+    modified: String,
+    compression: String,
+}
+
+/// Read the name, digest, timestamp and compression settings of every file
+/// in the cabinet at `cab_path`, in the order they're stored.
+fn member_details(cab_path: &Path) -> Result<Vec<MemberDetails>> {
+    let parsed = parse(BufReader::new(File::open(cab_path)?))?;
+    let mut digests = member_digests(cab_path)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let folders = parsed.folders;
+    parsed
+        .files
+        .into_iter()
+        .map(|file| {
+            let digest = match digests.remove(&file.name) {
+                Some(digest) => digest,
+                None => bail!("Couldn't read '{}' from {:?}", file.name, cab_path),
+            };
+            let modified = match from_dos_datetime(file.date, file.time) {
+                Some(datetime) => datetime.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                None => "invalid".to_owned(),
+            };
+            let compression = match folders.get(usize::from(file.folder)) {
+                Some(folder) => compression_settings(folder.compression),
+                // Continued from or to another cabinet.
+                None => "unknown".to_owned(),
+            };
+            Ok(MemberDetails {
+                name: file.name,
+                digest,
+                modified,
+                compression,
+            })
+        })
+        .collect()
+}
+
+/// Describe a folder's `typeCompress` field, including the LZX window size
+/// and the Quantum level and window size, which are packed into the bits
+/// above the compression type.
+fn compression_settings(compression: u16) -> String {
+    let window = (compression >> 8) & 0x1f;
+    match compression & tcompTYPE_MASK {
+        tcompTYPE_NONE => "None".to_owned(),
+        tcompTYPE_MSZIP => "MSZIP".to_owned(),
+        tcompTYPE_QUANTUM => format!("Quantum:{}:{}", (compression >> 4) & 0xf, window),
+        tcompTYPE_LZX => format!("LZX:{}", window),
+        other => format!("Unknown:{}", other),
+    }
+}
+
+/// List every file under `dir`, sorted by path, along with the name it
+/// would be stored under in a cabinet that mirrors `dir`'s layout.
+pub(crate) fn dir_files(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut files = vec![];
+    let mut dirs = vec![(String::new(), dir.to_path_buf())];
+    while let Some((prefix, dir)) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) => format!("{}{}", prefix, name),
+                Err(name) => bail!("Bad filename: '{}'", name.to_string_lossy()),
+            };
+            if entry.file_type()?.is_dir() {
+                dirs.push((format!("{}\\", name), entry.path()));
+            } else {
+                files.push((name, entry.path()));
+            }
+        }
+    }
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::time::Duration;
+
+    use self::tempdir::TempDir;
+    use filetime::FileTime;
+
+    use super::{
+        compression_settings, diff_cab_dir, diff_cabs, make_diff_cab, verify_against_dir, CabDiff,
+        MemberChange, Mismatch, MismatchKind,
+    };
+    use crate::{make_cab_from_files, make_cabs_from_manifest, verify_cab};
+
+    #[test]
+    fn diff_cab() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let old = t.path().join("old");
+        let new = t.path().join("new");
+        fs::create_dir(&old).unwrap();
+        fs::create_dir(&new).unwrap();
+        for (name, data) in &[("a.txt", "same"), ("b.txt", "old"), ("c.txt", "gone")] {
+            fs::write(old.join(name), data).unwrap();
+        }
+        for (name, data) in &[("a.txt", "same"), ("b.txt", "new"), ("d.txt", "added")] {
+            fs::write(new.join(name), data).unwrap();
+        }
+        let baseline = t.path().join("baseline.cab");
+        make_cab_from_files(
+            &baseline,
+            &[old.join("a.txt"), old.join("b.txt"), old.join("c.txt")],
+        )
+        .expect("failed to create cab file");
+
+        let cab = t.path().join("update.cab");
+        let diff = make_diff_cab(
+            &cab,
+            &baseline,
+            &[new.join("a.txt"), new.join("b.txt"), new.join("d.txt")],
+        )
+        .expect("failed to create diff cab file");
+        assert_eq!(
+            diff,
+            CabDiff {
+                added: vec!["d.txt".to_owned()],
+                removed: vec!["c.txt".to_owned()],
+                changed: vec!["b.txt".to_owned()],
+                unchanged: vec!["a.txt".to_owned()],
+            }
+        );
+        let names = verify_cab(&cab)
+            .expect("failed to read cab file")
+            .files
+            .into_iter()
+            .map(|f| f.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["b.txt", "d.txt"]);
+    }
+
+    #[test]
+    fn diff_dir() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let dir = t.path().join("dir");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for (name, data) in &[("a.txt", "same"), ("b.txt", "old"), ("c.txt", "gone")] {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        let cab = t.path().join("test.cab");
+        make_cab_from_files(
+            &cab,
+            &[dir.join("a.txt"), dir.join("b.txt"), dir.join("c.txt")],
+        )
+        .expect("failed to create cab file");
+        assert!(diff_cab_dir(&cab, &dir)
+            .expect("failed to diff cab file")
+            .is_empty());
+
+        fs::remove_file(dir.join("c.txt")).unwrap();
+        // Same size, different contents.
+        fs::write(dir.join("b.txt"), "new").unwrap();
+        fs::write(dir.join("sub").join("d.txt"), "added").unwrap();
+        let diff = diff_cab_dir(&cab, &dir).expect("failed to diff cab file");
+        assert_eq!(
+            diff,
+            CabDiff {
+                added: vec!["sub\\d.txt".to_owned()],
+                removed: vec!["c.txt".to_owned()],
+                changed: vec!["b.txt".to_owned()],
+                unchanged: vec!["a.txt".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn verify_dir() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let dir = t.path().join("dir");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let mtime = FileTime::from_unix_time(1_600_000_000, 0);
+        let names = ["a.txt", "b.txt", "c.txt", "d.txt"];
+        for name in &names {
+            fs::write(dir.join(name), name).unwrap();
+            filetime::set_file_mtime(dir.join(name), mtime).unwrap();
+        }
+        let cab = t.path().join("test.cab");
+        make_cab_from_files(&cab, &names.map(|name| dir.join(name)))
+            .expect("failed to create cab file");
+        let tolerance = Duration::from_secs(2);
+        assert_eq!(
+            verify_against_dir(&cab, &dir, tolerance).expect("failed to verify cab file"),
+            vec![]
+        );
+
+        filetime::set_file_mtime(
+            dir.join("a.txt"),
+            FileTime::from_unix_time(1_600_000_010, 0),
+        )
+        .unwrap();
+        fs::write(dir.join("b.txt"), "B.TXT").unwrap();
+        fs::write(dir.join("c.txt"), "longer").unwrap();
+        fs::remove_file(dir.join("d.txt")).unwrap();
+        fs::write(dir.join("sub").join("e.txt"), "extra").unwrap();
+        for name in &["b.txt", "c.txt", "sub/e.txt"] {
+            filetime::set_file_mtime(dir.join(name), mtime).unwrap();
+        }
+        let mismatch = |name: &str, kind| Mismatch {
+            name: name.to_owned(),
+            kind,
+        };
+        assert_eq!(
+            verify_against_dir(&cab, &dir, tolerance).expect("failed to verify cab file"),
+            vec![
+                mismatch(
+                    "a.txt",
+                    MismatchKind::Timestamp {
+                        cab: 1_600_000_000,
+                        disk: 1_600_000_010,
+                    }
+                ),
+                mismatch("b.txt", MismatchKind::Contents),
+                mismatch("c.txt", MismatchKind::Size { cab: 5, disk: 6 }),
+                mismatch("d.txt", MismatchKind::Missing),
+                mismatch("sub\\e.txt", MismatchKind::Extra),
+            ]
+        );
+        assert_eq!(
+            verify_against_dir(&cab, &dir, Duration::from_secs(60))
+                .expect("failed to verify cab file")
+                .len(),
+            4
+        );
+    }
+
+    #[test]
+    fn diff_two_cabs() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        for (name, data) in &[
+            ("a.txt", "same"),
+            ("b.txt", "old"),
+            ("b2.txt", "new"),
+            ("c.txt", "gone"),
+            ("d.txt", "added"),
+            ("e.txt", "moved"),
+        ] {
+            fs::write(t.path().join(name), data).unwrap();
+        }
+        let manifest = t.path().join("build.json");
+        fs::write(
+            &manifest,
+            r#"{
+                "entries": [
+                    {"source": "a.txt", "cab": "old.cab", "timestamp": "2021-03-04T05:06:08"},
+                    {"source": "b.txt", "cab": "old.cab", "timestamp": "2021-03-04T05:06:08"},
+                    {"source": "c.txt", "cab": "old.cab"},
+                    {"source": "e.txt", "cab": "old.cab", "name": "old\\e.txt"},
+                    {"source": "a.txt", "cab": "new.cab", "timestamp": "2021-03-04T05:06:10",
+                     "compression": "none"},
+                    {"source": "b2.txt", "cab": "new.cab", "name": "b.txt",
+                     "timestamp": "2021-03-04T05:06:08"},
+                    {"source": "d.txt", "cab": "new.cab"},
+                    {"source": "e.txt", "cab": "new.cab", "name": "new\\e.txt"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        make_cabs_from_manifest(&manifest).expect("failed to create cab files");
+        let old = t.path().join("old.cab");
+        let new = t.path().join("new.cab");
+        assert!(diff_cabs(&old, &old)
+            .expect("failed to diff cab files")
+            .is_empty());
+
+        let diff = diff_cabs(&old, &new).expect("failed to diff cab files");
+        assert_eq!(diff.added, vec!["d.txt"]);
+        assert_eq!(diff.removed, vec!["c.txt"]);
+        assert_eq!(diff.changed, vec!["b.txt"]);
+        let change = |name: &str, old: &str, new: &str| MemberChange {
+            name: name.to_owned(),
+            old: old.to_owned(),
+            new: new.to_owned(),
+        };
+        assert_eq!(
+            diff.renamed,
+            vec![change("old\\e.txt", "old\\e.txt", "new\\e.txt")]
+        );
+        assert_eq!(
+            diff.retimed,
+            vec![change(
+                "a.txt",
+                "2021-03-04T05:06:08",
+                "2021-03-04T05:06:10"
+            )]
+        );
+        assert_eq!(diff.recompressed, vec![change("a.txt", "MSZIP", "None")]);
+    }
+
+    #[test]
+    fn compression_names() {
+        assert_eq!(compression_settings(0x0000), "None");
+        assert_eq!(compression_settings(0x0001), "MSZIP");
+        assert_eq!(compression_settings(0x1503), "LZX:21");
+        assert_eq!(compression_settings(0x1472), "Quantum:7:20");
+    }
+}
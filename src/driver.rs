@@ -0,0 +1,223 @@
+//! Writing driver packages for submission to the Windows Hardware Dev
+//! Center.
+//!
+//! Partner Center expects a submission cabinet to hold each driver package
+//! in its own top-level folder, with the INF, catalog and binaries of the
+//! package inside it, optionally split into per-architecture subfolders.
+//! A cabinet with the files at the root, or a package without its INF or
+//! catalog, is rejected only after uploading, so this checks for those
+//! mistakes up front.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+
+use crate::diff::dir_files;
+use crate::{write_cab_file, CabOptions, CabSummary, Input};
+
+/// The names of per-architecture subfolders of a driver package.
+const ARCHITECTURES: &[&str] = &["x86", "amd64", "arm", "arm64", "ia64"];
+
+/// Write a submission cabinet at `cab_path` for the driver package in the
+/// directory `driver_dir`.
+///
+/// Every file under `driver_dir` is stored under a top-level folder named
+/// after `driver_dir`, keeping its path relative to `driver_dir`, so
+/// `echo\amd64\echo.sys` stays `echo\amd64\echo.sys` in the cabinet.
+///
+/// There must be at least one INF, every folder holding an INF must also
+/// hold a catalog, and every catalog named by an INF's `CatalogFile`
+/// directives must be next to it. If `driver_dir` has per-architecture
+/// subfolders like `amd64` and no INF of its own, each of those must hold an
+/// INF.
+pub fn make_driver_cab<T: AsRef<Path>, U: AsRef<Path>>(
+    cab_path: T,
+    driver_dir: U,
+) -> Result<CabSummary> {
+    let start = Instant::now();
+    let driver_dir = driver_dir.as_ref();
+    let package = match driver_dir.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => bail!("Bad driver package directory {:?}", driver_dir),
+    };
+    let files = dir_files(driver_dir)?;
+    check_package(driver_dir, &files)?;
+    let names = files
+        .iter()
+        .map(|(name, _)| format!("{}\\{}", package, name))
+        .collect::<Vec<_>>();
+    let inputs = files
+        .iter()
+        .zip(&names)
+        .map(|((_, path), name)| Input::with_name(path, name))
+        .collect::<Result<Vec<_>>>()?;
+    write_cab_file(cab_path.as_ref(), &inputs, &CabOptions::default(), start)
+}
+
+/// Check the layout of the driver package in `driver_dir`, which holds
+/// `files`.
+fn check_package(driver_dir: &Path, files: &[(String, PathBuf)]) -> Result<()> {
+    // The files in each folder of the package.
+    let mut folders = BTreeMap::<&str, Vec<(&str, &Path)>>::new();
+    for (name, path) in files {
+        let (folder, file) = name.rsplit_once('\\').unwrap_or(("", name));
+        folders.entry(folder).or_default().push((file, path));
+    }
+    let is_inf = |file: &str| file.to_ascii_lowercase().ends_with(".inf");
+    let has_inf = |entries: &[(&str, &Path)]| entries.iter().any(|(file, _)| is_inf(file));
+    if !folders.values().any(|entries| has_inf(entries)) {
+        bail!("No INF file in driver package {:?}", driver_dir);
+    }
+    if !folders.get("").is_some_and(|entries| has_inf(entries)) {
+        for arch in ARCHITECTURES {
+            let mut arch_folders = folders
+                .iter()
+                .filter(|(folder, _)| folder.split('\\').next() == Some(*arch))
+                .peekable();
+            if arch_folders.peek().is_some() && !arch_folders.any(|(_, entries)| has_inf(entries)) {
+                bail!(
+                    "No INF file for {} in driver package {:?}",
+                    arch,
+                    driver_dir
+                );
+            }
+        }
+    }
+    for (folder, entries) in &folders {
+        let folder = if folder.is_empty() { "." } else { folder };
+        let contains = |name: &str| {
+            entries
+                .iter()
+                .any(|(file, _)| file.eq_ignore_ascii_case(name))
+        };
+        for (inf, path) in entries.iter().filter(|(file, _)| is_inf(file)) {
+            if !entries
+                .iter()
+                .any(|(file, _)| file.to_ascii_lowercase().ends_with(".cat"))
+            {
+                bail!("No catalog file next to {}\\{}", folder, inf);
+            }
+            for catalog in catalog_files(&fs::read(path)?) {
+                if !contains(&catalog) {
+                    bail!(
+                        "{}\\{} names catalog file {} but it isn't in the package",
+                        folder,
+                        inf,
+                        catalog
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Get the catalog files named by the `CatalogFile` directives in the INF
+/// file `inf`, which may be UTF-16.
+fn catalog_files(inf: &[u8]) -> Vec<String> {
+    let text = match inf {
+        [0xff, 0xfe, rest @ ..] => String::from_utf16_lossy(
+            &rest
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        _ => String::from_utf8_lossy(inf).into_owned(),
+    };
+    text.lines()
+        .filter_map(|line| {
+            let line = line.split(';').next().unwrap_or("");
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim().to_ascii_lowercase();
+            if key == "catalogfile" || key.starts_with("catalogfile.") {
+                Some(value.trim().trim_matches('"').to_owned())
+            } else {
+                None
+            }
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::path::Path;
+
+    use self::tempdir::TempDir;
+    use super::{catalog_files, make_driver_cab};
+    use crate::verify_cab;
+
+    fn write(dir: &Path, name: &str, contents: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn catalog_directives() {
+        let inf = "[Version]\r\nSignature=\"$WINDOWS NT$\"\r\nCatalogFile = echo.cat ; comment\r\nCatalogFile.NTamd64=\"echo64.cat\"\r\n";
+        assert_eq!(
+            catalog_files(inf.as_bytes()),
+            vec!["echo.cat", "echo64.cat"]
+        );
+        let utf16 = std::iter::once(0xfeff)
+            .chain("CatalogFile=a.cat".encode_utf16())
+            .flat_map(|c: u16| c.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(catalog_files(&utf16), vec!["a.cat"]);
+    }
+
+    #[test]
+    fn driver_package() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let driver = t.path().join("echo");
+        write(&driver, "amd64/echo.inf", "CatalogFile=echo.cat\r\n");
+        write(&driver, "amd64/echo.cat", "cat");
+        write(&driver, "amd64/echo.sys", "sys");
+        write(&driver, "x86/echo.inf", "CatalogFile=echo.cat\r\n");
+        write(&driver, "x86/ECHO.CAT", "cat");
+        write(&driver, "x86/echo.sys", "sys");
+        let cab = t.path().join("echo.cab");
+        make_driver_cab(&cab, &driver).expect("failed to create cab file");
+        let mut names = verify_cab(&cab)
+            .expect("failed to read cab file")
+            .files
+            .into_iter()
+            .map(|f| f.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "echo\\amd64\\echo.cat",
+                "echo\\amd64\\echo.inf",
+                "echo\\amd64\\echo.sys",
+                "echo\\x86\\ECHO.CAT",
+                "echo\\x86\\echo.inf",
+                "echo\\x86\\echo.sys",
+            ]
+        );
+
+        // An architecture without an INF.
+        write(&driver, "arm64/echo.sys", "sys");
+        assert!(make_driver_cab(&cab, &driver).is_err());
+        fs::remove_dir_all(driver.join("arm64")).unwrap();
+
+        // A missing catalog.
+        fs::remove_file(driver.join("x86/ECHO.CAT")).unwrap();
+        write(&driver, "x86/other.cat", "cat");
+        assert!(make_driver_cab(&cab, &driver).is_err());
+        fs::remove_file(driver.join("x86/other.cat")).unwrap();
+        assert!(make_driver_cab(&cab, &driver).is_err());
+
+        let empty = t.path().join("empty");
+        write(&empty, "readme.txt", "nothing here");
+        assert!(make_driver_cab(&cab, &empty).is_err());
+    }
+}
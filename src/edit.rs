@@ -0,0 +1,381 @@
+//! Changing existing cabinets without recompressing the data in them.
+//!
+//! The cabinet is read into a [`Layout`] with its data blocks exactly as
+//! they are, the records are changed, and the layout is written back over
+//! the original, so these changes are cheap even for large cabinets.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDateTime;
+use filetime::FileTime;
+use flate2::{Compression, CrcReader};
+
+use crate::compressor::MsZipCompressor;
+use crate::format::parse::parse_layout;
+use crate::format::{
+    dos_datetime, ifoldCONTINUED_FROM_PREV, tcompTYPE_MSZIP, FileEntry, Folder, Layout, _A_ARCH,
+    _A_NAME_IS_UTF,
+};
+use crate::pipeline::compress_block;
+use crate::reserve::{read_reserve_tables, FileExtras, Reserve, Timestamps};
+use crate::{index, temp_cab_file, MAX_CHUNK};
+
+/// Read the cabinet file at `cab_path`, let `edit` change it, and replace
+/// the file with the result. The cabinet is left alone if anything fails.
+fn edit_cab<F: FnOnce(&mut Layout) -> Result<()>>(cab_path: &Path, edit: F) -> Result<()> {
+    let mut layout = parse_layout(BufReader::new(File::open(cab_path)?))?;
+    edit(&mut layout)?;
+    let mut writer = BufWriter::new(temp_cab_file(cab_path)?);
+    layout.write_to(&mut writer)?;
+    let temp = writer.into_inner().map_err(|e| e.into_error())?;
+    temp.persist(cab_path)?;
+    index::refresh_block_index(cab_path)?;
+    Ok(())
+}
+
+/// Add the files at the paths in `inputs` to the cabinet file at
+/// `cab_path`, each stored under the name paired with it, to patch a
+/// cabinet without building it again.
+///
+/// The new files go in a new MSZIP folder after the existing ones, whose
+/// data is copied across without being recompressed. If the cabinet stores
+/// timestamps or CRC-32s for [`CabOptions`], they're stored for the new
+/// files too. It's an error for any of the names to be in the cabinet
+/// already, or for the cabinet to be part of a set, in which case it's left
+/// alone.
+///
+/// [`CabOptions`]: crate::CabOptions
+pub fn append_files<T: AsRef<Path>, U: AsRef<Path>, S: AsRef<str>>(
+    cab_path: T,
+    inputs: &[(U, S)],
+) -> Result<()> {
+    edit_cab(cab_path.as_ref(), |layout| {
+        if layout.header.prev.is_some() || layout.header.next.is_some() {
+            bail!("Can't add files to a cabinet that's part of a set");
+        }
+        let mut names = layout
+            .files
+            .iter()
+            .map(|file| file.name.clone())
+            .collect::<HashSet<_>>();
+        for (_, name) in inputs {
+            if !names.insert(name.as_ref().to_owned()) {
+                bail!(
+                    "There's already a file named '{}' in cabinet",
+                    name.as_ref()
+                );
+            }
+        }
+        let extras = read_reserve_tables(&layout.header.header_reserve, layout.files.len());
+
+        let folder_index = layout.folders.len() as u16;
+        let data_reserve = vec![0; usize::from(layout.header.data_reserve_size)];
+        let mut blocks = vec![];
+        let mut chunk = Vec::with_capacity(MAX_CHUNK);
+        let compressor = MsZipCompressor::new(Compression::default());
+        let mut folder_offset = 0u64;
+        let mut new_extras = vec![];
+        for (path, name) in inputs {
+            let path = path.as_ref();
+            let meta = fs::metadata(path)?;
+            let mut reader = CrcReader::new(File::open(path)?);
+            // Counted here rather than by the CRC, whose count wraps at 4GB.
+            let mut size = 0u64;
+            loop {
+                let limit = (MAX_CHUNK - chunk.len()) as u64;
+                let read = (&mut reader).take(limit).read_to_end(&mut chunk)?;
+                size += read as u64;
+                if chunk.len() == MAX_CHUNK {
+                    blocks.push(compress_block(&chunk, &compressor)?);
+                    chunk.clear();
+                }
+                if read == 0 {
+                    break;
+                }
+            }
+            if folder_offset + size > u64::from(u32::MAX) {
+                bail!("File '{}' is too large", path.display());
+            }
+            let mtime = FileTime::from_last_modification_time(&meta);
+            let datetime = NaiveDateTime::from_timestamp(mtime.unix_seconds(), mtime.nanoseconds());
+            let mut file = FileEntry::new(
+                name.as_ref(),
+                size as u32,
+                folder_index,
+                folder_offset as u32,
+                datetime,
+            );
+            file.attributes |= _A_ARCH;
+            layout.files.push(file);
+            new_extras.push(FileExtras {
+                timestamps: Some(Timestamps {
+                    modified: mtime,
+                    created: FileTime::from_creation_time(&meta),
+                }),
+                crc32: Some(reader.crc().sum()),
+            });
+            folder_offset += size;
+        }
+        if !chunk.is_empty() {
+            blocks.push(compress_block(&chunk, &compressor)?);
+        }
+        for block in &mut blocks {
+            block.reserve = data_reserve.clone();
+        }
+        let folder = Folder {
+            compression: tcompTYPE_MSZIP,
+            reserve: vec![0; usize::from(layout.header.folder_reserve_size)],
+            ..Folder::default()
+        };
+        layout.folders.push((folder, blocks));
+
+        if let Some(mut extras) = extras {
+            extras.extend(new_extras);
+            layout.header.header_reserve = Reserve {
+                timestamps: extras.iter().map(|extra| extra.timestamps).collect(),
+                crc32s: extras.iter().map(|extra| extra.crc32).collect(),
+                ..Reserve::default()
+            }
+            .to_bytes()?;
+        }
+        Ok(())
+    })
+}
+
+/// Rename the file stored as `old_name` in the cabinet file at `cab_path`
+/// to `new_name`, for when the names an installer expects turn up late.
+///
+/// Only the file's CFFILE record changes, so nothing is recompressed. It's
+/// an error for `old_name` to be missing, for `new_name` to be taken, or
+/// for the file to be continued from or into another cabinet in a set, in
+/// which case the cabinet is left alone.
+pub fn rename_file<T: AsRef<Path>>(cab_path: T, old_name: &str, new_name: &str) -> Result<()> {
+    edit_cab(cab_path.as_ref(), |layout| {
+        if layout.files.iter().any(|file| file.name == new_name) {
+            bail!("There's already a file named '{}' in cabinet", new_name);
+        }
+        let file = match layout.files.iter_mut().find(|file| file.name == old_name) {
+            Some(file) => file,
+            None => bail!("No file named '{}' in cabinet", old_name),
+        };
+        if file.folder >= ifoldCONTINUED_FROM_PREV {
+            bail!("Can't rename '{}', which spans cabinets", old_name);
+        }
+        file.name = new_name.to_owned();
+        if new_name.is_ascii() {
+            file.attributes &= !_A_NAME_IS_UTF;
+        } else {
+            file.attributes |= _A_NAME_IS_UTF;
+        }
+        Ok(())
+    })
+}
+
+/// Set the modification time of the files stored as `names` in the
+/// cabinet file at `cab_path` to `datetime`, or of every file if `names` is
+/// empty, for example to normalize them all to a fixed build date.
+///
+/// Only the files' CFFILE records change, along with the high-resolution
+/// timestamps if the cabinet stores them, so nothing is recompressed. Times
+/// before 1980 can't be stored and are clamped to the start of 1980. It's
+/// an error for any of `names` to be missing from the cabinet, in which
+/// case it's left alone.
+pub fn set_file_datetimes<T: AsRef<Path>, S: AsRef<str>>(
+    cab_path: T,
+    names: &[S],
+    datetime: NaiveDateTime,
+) -> Result<()> {
+    edit_cab(cab_path.as_ref(), |layout| {
+        let selected = names.iter().map(AsRef::as_ref).collect::<HashSet<_>>();
+        for name in &selected {
+            if !layout.files.iter().any(|file| file.name == *name) {
+                bail!("No file named '{}' in cabinet", name);
+            }
+        }
+        let mut extras = read_reserve_tables(&layout.header.header_reserve, layout.files.len());
+        let (date, time) = dos_datetime(datetime);
+        let modified =
+            FileTime::from_unix_time(datetime.timestamp(), datetime.timestamp_subsec_nanos());
+        for (index, file) in layout.files.iter_mut().enumerate() {
+            if !selected.is_empty() && !selected.contains(file.name.as_str()) {
+                continue;
+            }
+            file.date = date;
+            file.time = time;
+            if let Some(timestamps) = extras
+                .as_mut()
+                .and_then(|extras| extras[index].timestamps.as_mut())
+            {
+                timestamps.modified = modified;
+            }
+        }
+
+        if let Some(extras) = extras {
+            layout.header.header_reserve = Reserve {
+                timestamps: extras.iter().map(|extra| extra.timestamps).collect(),
+                crc32s: extras.iter().map(|extra| extra.crc32).collect(),
+                ..Reserve::default()
+            }
+            .to_bytes()?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use self::tempdir::TempDir;
+    use cab::Cabinet;
+    use chrono::NaiveDate;
+
+    use super::{append_files, rename_file, set_file_datetimes};
+    use crate::{
+        extract_cab, lint_cab, make_cab_from_files_with_options, read_file_range, verify_cab,
+        CabOptions,
+    };
+
+    #[test]
+    fn append() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let a = t.path().join("a.txt");
+        fs::write(&a, "a".repeat(100_000)).unwrap();
+        let new = t.path().join("new.dll");
+        let new_data = (0..100_000u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(&new, &new_data).unwrap();
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            crc32: true,
+            high_res_timestamps: true,
+            block_index: true,
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &[&a], &options).expect("failed to create cab file");
+        assert!(append_files(&cab, &[(&new, "a.txt")]).is_err());
+
+        append_files(&cab, &[(&new, "bin\\new.dll")]).expect("failed to add file");
+        assert_eq!(lint_cab(&cab).expect("failed to lint cab file"), vec![]);
+        // The CRC-32 table covers the new file too.
+        let report = verify_cab(&cab).expect("cab file didn't verify");
+        assert!(report.is_ok());
+        let mut cabinet = Cabinet::new(File::open(&cab).unwrap()).unwrap();
+        assert_eq!(cabinet.folder_entries().count(), 2);
+        let entry = cabinet.get_file_entry("bin\\new.dll").unwrap();
+        assert!(entry.is_archive());
+        let mut data = vec![];
+        cabinet
+            .read_file("bin\\new.dll")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, new_data);
+        // The block index is brought up to date.
+        assert_eq!(
+            read_file_range(&cab, "bin\\new.dll", 70_000, 10).unwrap(),
+            &new_data[70_000..70_010]
+        );
+    }
+
+    #[test]
+    fn rename() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let paths = ["a.txt", "b.txt"]
+            .iter()
+            .map(|name| {
+                let path = t.path().join(name);
+                fs::write(&path, name.repeat(10_000)).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            crc32: true,
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &paths, &options)
+            .expect("failed to create cab file");
+        let before = fs::read(&cab).unwrap();
+        assert!(rename_file(&cab, "missing.txt", "c.txt").is_err());
+        assert!(rename_file(&cab, "a.txt", "b.txt").is_err());
+        assert!(rename_file(&cab, "a.txt", "").is_err());
+        assert_eq!(fs::read(&cab).unwrap(), before);
+
+        rename_file(&cab, "a.txt", "bin\\\u{e9}.txt").expect("failed to rename file");
+        assert_eq!(lint_cab(&cab).expect("failed to lint cab file"), vec![]);
+        assert!(verify_cab(&cab).expect("cab file didn't verify").is_ok());
+        let mut cabinet = Cabinet::new(File::open(&cab).unwrap()).unwrap();
+        assert!(cabinet.get_file_entry("a.txt").is_none());
+        let mut data = vec![];
+        cabinet
+            .read_file("bin\\\u{e9}.txt")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, "a.txt".repeat(10_000).as_bytes());
+        // The data blocks are copied as they are.
+        let after = fs::read(&cab).unwrap();
+        assert_eq!(
+            after.len() - before.len(),
+            "bin\\\u{e9}.txt".len() - "a.txt".len()
+        );
+        assert!(before.ends_with(&after[after.len() - 200..]));
+    }
+
+    #[test]
+    fn set_datetimes() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let paths = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|name| {
+                let path = t.path().join(name);
+                fs::write(&path, name.repeat(10_000)).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            high_res_timestamps: true,
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &paths, &options)
+            .expect("failed to create cab file");
+        let datetime = NaiveDate::from_ymd(2020, 2, 29).and_hms(12, 34, 56);
+        assert!(set_file_datetimes(&cab, &["a.txt", "missing.txt"], datetime).is_err());
+
+        let datetimes = |cab: &std::path::Path| {
+            let cabinet = Cabinet::new(File::open(cab).unwrap()).unwrap();
+            ["a.txt", "b.txt", "c.txt"]
+                .iter()
+                .map(|name| cabinet.get_file_entry(name).unwrap().datetime())
+                .collect::<Vec<_>>()
+        };
+        let before = datetimes(&cab);
+        set_file_datetimes(&cab, &["a.txt", "c.txt"], datetime).expect("failed to set times");
+        assert_eq!(
+            datetimes(&cab),
+            vec![Some(datetime), before[1], Some(datetime)]
+        );
+        assert_eq!(lint_cab(&cab).expect("failed to lint cab file"), vec![]);
+        // Extracting uses the high-resolution timestamp, which is changed too.
+        let out = t.path().join("out");
+        extract_cab(&cab, &out).expect("failed to extract cab file");
+        let mtime = fs::metadata(out.join("a.txt")).unwrap().modified().unwrap();
+        let expected =
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(datetime.timestamp() as u64);
+        assert_eq!(mtime, expected);
+
+        let no_names: &[&str] = &[];
+        set_file_datetimes(&cab, no_names, datetime).expect("failed to set times");
+        assert_eq!(datetimes(&cab), vec![Some(datetime); 3]);
+    }
+}
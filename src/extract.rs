@@ -0,0 +1,988 @@
+//! Extracting files from cabinets.
+//!
+//! Cabinets may contain any number of folders, each compressed with its own
+//! compression type. Each CFFILE entry belongs to exactly one folder, so
+//! extracting a subset of the files only decompresses the folders holding
+//! them, and folders can be decompressed in parallel.
+//!
+//! A cabinet can also be one of a linked set, with folders and files
+//! carried on from one cabinet to the next. Like FDI, extracting from any
+//! cabinet in a set finds the others and extracts from the whole set, so
+//! continued files come out whole.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Result};
+use cab::{Cabinet, FileEntry};
+use filetime::FileTime;
+use flate2::CrcReader;
+use rayon::prelude::*;
+use serde::Serialize;
+use tempfile::NamedTempFile;
+
+use crate::format::parse::{parse, read_block_sizes, read_header};
+use crate::format::CabinetLink;
+use crate::reserve::{read_reserve, set_file_created, FileExtras};
+use crate::split::{first_in_set, join_set};
+use crate::{CabReport, FileReport, TEMP_FILE_PREFIX};
+
+/// Extract every file in the cabinet at `cab_path` into the directory
+/// `out_dir`, returning the paths of the extracted files.
+///
+/// Stored names containing `\` or `/` are extracted into subdirectories of
+/// `out_dir`, which are created as needed.
+///
+/// If the cabinet is part of a linked set, the other cabinets in the set
+/// are looked for next to it, and every file in the set is extracted, with
+/// files continued from one cabinet to the next put back together. Use
+/// [`extract_files_with_locator`] to find cabinets kept elsewhere.
+pub fn extract_cab<T: AsRef<Path>, U: AsRef<Path>>(
+    cab_path: T,
+    out_dir: U,
+) -> Result<Vec<PathBuf>> {
+    extract_files_with_locator(cab_path, &[] as &[&str], out_dir, |_| None)
+}
+
+/// Extract the files stored as `names` in the cabinet at `cab_path` into the
+/// directory `out_dir`, returning the paths of the extracted files.
+///
+/// Only the folders containing the requested files are decompressed. It is
+/// an error for any of `names` to be missing from the cabinet. Names can
+/// have either `\\` or `/` between components, whichever the cabinet stores.
+/// Like [`extract_cab`], if the cabinet is part of a linked set, the names
+/// are looked for in the whole set.
+pub fn extract_files<T, U, S>(cab_path: T, names: &[S], out_dir: U) -> Result<Vec<PathBuf>>
+where
+    T: AsRef<Path>,
+    U: AsRef<Path>,
+    S: AsRef<str>,
+{
+    let source = Source::open(cab_path.as_ref(), &mut |_| None)?;
+    let names = names
+        .iter()
+        .map(|name| member_name(&source.cabinet, name.as_ref()))
+        .collect::<Vec<_>>();
+    extract_names(&source.path, &source.cabinet, &names, out_dir.as_ref())
+}
+
+/// Like [`extract_files`], but when the cabinet is part of a linked set,
+/// calling `locate` with the link to each cabinet in the set that isn't
+/// next to the cabinet linking to it, which returns where that cabinet is,
+/// or `None` if it can't be found. This is where an installer would ask
+/// for the next disk. An empty `names` extracts every file in the set.
+pub fn extract_files_with_locator<T, U, S, F>(
+    cab_path: T,
+    names: &[S],
+    out_dir: U,
+    mut locate: F,
+) -> Result<Vec<PathBuf>>
+where
+    T: AsRef<Path>,
+    U: AsRef<Path>,
+    S: AsRef<str>,
+    F: FnMut(&CabinetLink) -> Option<PathBuf>,
+{
+    let source = Source::open(cab_path.as_ref(), &mut locate)?;
+    let names = if names.is_empty() {
+        all_names(&source.cabinet)
+    } else {
+        names
+            .iter()
+            .map(|name| member_name(&source.cabinet, name.as_ref()))
+            .collect::<Vec<_>>()
+    };
+    extract_names(&source.path, &source.cabinet, &names, out_dir.as_ref())
+}
+
+/// What to do when a file being extracted already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Replace it, like `expand` does.
+    #[default]
+    Always,
+    /// Leave it alone.
+    Never,
+    /// Replace it only if the file in the cabinet was modified more recently.
+    Newer,
+    /// Ask a callback whether to replace it.
+    Prompt,
+}
+
+/// Like [`extract_files`], but with `overwrite` saying what to do about
+/// files that already exist in `out_dir`, returning the paths of only the
+/// files that were extracted. An empty `names` extracts every file.
+///
+/// With [`Overwrite::Prompt`], `confirm` is called with the path of each
+/// existing file and it's only replaced if that returns `true`. `confirm`
+/// isn't called otherwise.
+pub fn extract_files_with_overwrite<T, U, S, F>(
+    cab_path: T,
+    names: &[S],
+    out_dir: U,
+    overwrite: Overwrite,
+    mut confirm: F,
+) -> Result<Vec<PathBuf>>
+where
+    T: AsRef<Path>,
+    U: AsRef<Path>,
+    S: AsRef<str>,
+    F: FnMut(&Path) -> bool,
+{
+    let source = Source::open(cab_path.as_ref(), &mut |_| None)?;
+    let cabinet = &source.cabinet;
+    let names = if names.is_empty() {
+        all_names(cabinet)
+    } else {
+        names
+            .iter()
+            .map(|name| member_name(cabinet, name.as_ref()))
+            .collect::<Vec<_>>()
+    };
+    let out_dir = out_dir.as_ref();
+    let extras = read_reserve(cabinet);
+    // Decide what to replace up front, so `confirm` is called in order
+    // before extracting on several threads.
+    let mut to_extract = vec![];
+    for name in names {
+        let path = output_path(out_dir, &name)?;
+        let replace = match fs::symlink_metadata(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+            Err(e) => return Err(e.into()),
+            Ok(meta) => match overwrite {
+                Overwrite::Always => true,
+                Overwrite::Never => false,
+                Overwrite::Newer => {
+                    let entry = match cabinet.get_file_entry(&name) {
+                        Some(entry) => entry,
+                        None => bail!("No file named '{}' in cabinet", name),
+                    };
+                    let mtime = extras
+                        .get(&name)
+                        .and_then(|extra| extra.timestamps)
+                        .map(|t| t.modified)
+                        .or_else(|| entry_mtime(entry));
+                    mtime.is_some_and(|mtime| mtime > FileTime::from_last_modification_time(&meta))
+                }
+                Overwrite::Prompt => confirm(&path),
+            },
+        };
+        if replace {
+            to_extract.push(name);
+        }
+    }
+    extract_names(&source.path, cabinet, &to_extract, out_dir)
+}
+
+/// A file that extracting from a cabinet would write, as listed by
+/// [`plan_extraction`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlannedFile {
+    /// The name the file is stored under.
+    pub name: String,
+    /// The index of the folder holding the file, or one of the
+    /// `ifoldCONTINUED_*` constants.
+    pub folder: u16,
+    /// The size of the file.
+    pub size: u64,
+    /// Roughly how much compressed data the file takes up. Blocks holding
+    /// data from more than one file are shared between them in proportion
+    /// to how much of each they hold. Files continued from or to another
+    /// cabinet count as 0.
+    pub compressed_size: u64,
+    /// The compressed size as a fraction of the size, or 1 for empty files.
+    pub ratio: f64,
+    /// The path the file would be extracted to.
+    pub path: PathBuf,
+}
+
+/// List what extracting the files stored as `names` in the cabinet at
+/// `cab_path` into `out_dir` would write, without decompressing anything.
+/// An empty `names` lists every file.
+///
+/// Like [`extract_files`], names can have either `\\` or `/` between
+/// components, and it's an error for any of them to be missing from the
+/// cabinet or to have a name that isn't safe to extract.
+pub fn plan_extraction<T, U, S>(cab_path: T, names: &[S], out_dir: U) -> Result<Vec<PlannedFile>>
+where
+    T: AsRef<Path>,
+    U: AsRef<Path>,
+    S: AsRef<str>,
+{
+    let source = Source::open(cab_path.as_ref(), &mut |_| None)?;
+    let mut reader = BufReader::new(File::open(&source.path)?);
+    let cabinet = parse(&mut reader)?;
+    let blocks = cabinet
+        .folders
+        .iter()
+        .map(|folder| read_block_sizes(&mut reader, folder, cabinet.header.data_reserve_size))
+        .collect::<io::Result<Vec<_>>>()?;
+    let files = if names.is_empty() {
+        cabinet.files.iter().collect::<Vec<_>>()
+    } else {
+        let key = |name: &str| name.replace('/', "\\");
+        names
+            .iter()
+            .map(|name| {
+                let name = name.as_ref();
+                match cabinet.files.iter().find(|file| file.name == name) {
+                    Some(file) => Ok(file),
+                    None => match cabinet
+                        .files
+                        .iter()
+                        .find(|file| key(&file.name) == key(name))
+                    {
+                        Some(file) => Ok(file),
+                        None => bail!("No file named '{}' in cabinet", name),
+                    },
+                }
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+    files
+        .into_iter()
+        .map(|file| {
+            let size = u64::from(file.size);
+            let (start, end) = (
+                u64::from(file.folder_offset),
+                u64::from(file.folder_offset) + size,
+            );
+            let mut compressed = 0.0;
+            let mut block_start = 0;
+            for &(data_size, block_size) in
+                blocks.get(usize::from(file.folder)).into_iter().flatten()
+            {
+                let block_end = block_start + u64::from(block_size);
+                let overlap = end.min(block_end).saturating_sub(start.max(block_start));
+                if overlap > 0 {
+                    compressed += overlap as f64 * f64::from(data_size) / f64::from(block_size);
+                }
+                block_start = block_end;
+            }
+            let compressed_size = compressed.round() as u64;
+            Ok(PlannedFile {
+                name: file.name.clone(),
+                folder: file.folder,
+                size,
+                compressed_size,
+                ratio: if size == 0 {
+                    1.0
+                } else {
+                    compressed_size as f64 / size as f64
+                },
+                path: output_path(out_dir.as_ref(), &file.name)?,
+            })
+        })
+        .collect()
+}
+
+/// Write the contents of the file stored as `name` in the cabinet at
+/// `cab_path` to `writer`, returning the writer once it's all been written.
+///
+/// This is handy for piping a single file from a cabinet into something
+/// else without a temporary file.
+pub fn extract_file_to<T: AsRef<Path>, W: Write>(
+    cab_path: T,
+    name: &str,
+    mut writer: W,
+) -> Result<W> {
+    let source = Source::open(cab_path.as_ref(), &mut |_| None)?;
+    let mut cabinet = source.cabinet;
+    let name = member_name(&cabinet, name);
+    let size = match cabinet.get_file_entry(&name) {
+        Some(entry) => u64::from(entry.uncompressed_size()),
+        None => bail!("No file named '{}' in cabinet", name),
+    };
+    let written = io::copy(&mut cabinet.read_file(&name)?, &mut writer)?;
+    if written != size {
+        bail!("expected {} bytes but decompressed {}", size, written);
+    }
+    writer.flush()?;
+    Ok(writer)
+}
+
+/// Extract everything that can be recovered from the possibly damaged
+/// cabinet at `cab_path` into the directory `out_dir`.
+///
+/// Unlike [`extract_cab`], a file that can't be extracted doesn't stop the
+/// rest from being extracted. A CFDATA block with a bad checksum or
+/// truncated data only damages the files with data in or after that block
+/// in the same folder; every file before it, and every file in other
+/// folders, is still recovered. Nothing is left on disk for damaged files,
+/// and the returned report says which files those were and why. An error is
+/// only returned if the cabinet's headers can't be read at all.
+pub fn salvage_cab<T: AsRef<Path>, U: AsRef<Path>>(cab_path: T, out_dir: U) -> Result<CabReport> {
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let entries = cabinet
+        .folder_entries()
+        .enumerate()
+        .flat_map(|(index, folder)| {
+            folder
+                .file_entries()
+                .map(move |file| (index, file.name().to_owned(), file.uncompressed_size()))
+        })
+        .collect::<Vec<_>>();
+    let extras = read_reserve(&cabinet);
+    let mut report = CabReport::default();
+    for (folder, name, size) in entries {
+        let error = match extract_one(&mut cabinet, &extras, &name, out_dir.as_ref()) {
+            Ok(_) => None,
+            Err(e) => {
+                if let Ok(path) = output_path(out_dir.as_ref(), &name) {
+                    let _ = fs::remove_file(path);
+                }
+                Some(e.to_string())
+            }
+        };
+        report.files.push(FileReport {
+            name,
+            folder,
+            size: u64::from(size),
+            error,
+        });
+    }
+    Ok(report)
+}
+
+/// The signature at the start of files compressed by `compress.exe`.
+const SZDD_SIGNATURE: &[u8] = b"SZDD\x88\xf0\x27\x33";
+
+/// Expand the single-file compressed file at `path`, like `foo.pd_`, into
+/// `out_dir` under its original name, the way `expand -r` does, returning
+/// the paths of the expanded files.
+///
+/// Both forms such files come in are understood: cabinets written by
+/// `makecab`, whose files are extracted under the names stored in them, and
+/// the older SZDD format written by `compress.exe`, which records the
+/// character its trailing `_` replaced.
+pub fn expand_file<T: AsRef<Path>, U: AsRef<Path>>(path: T, out_dir: U) -> Result<Vec<PathBuf>> {
+    let (path, out_dir) = (path.as_ref(), out_dir.as_ref());
+    let data = fs::read(path)?;
+    if !data.starts_with(SZDD_SIGNATURE) {
+        return extract_cab(path, out_dir);
+    }
+    if data.len() < 14 || data[8] != b'A' {
+        bail!("{:?} isn't a supported SZDD file", path);
+    }
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => match (name.strip_suffix('_'), data[9]) {
+            (Some(stem), c) if c != 0 => format!("{}{}", stem, char::from(c)),
+            _ => name.to_owned(),
+        },
+        None => bail!("Bad filename: {:?}", path),
+    };
+    let size = u32::from_le_bytes([data[10], data[11], data[12], data[13]]);
+    let out_path = output_path(out_dir, &name)?;
+    fs::create_dir_all(out_dir)?;
+    fs::write(&out_path, expand_szdd(&data[14..], size as usize)?)?;
+    Ok(vec![out_path])
+}
+
+/// Decompress the LZSS data in an SZDD file, which should expand to `size`
+/// bytes.
+///
+/// Each control byte says, from its lowest bit up, whether each of the next
+/// eight items is a literal byte or a two-byte reference to up to 18 bytes
+/// in a 4096-byte window, which starts out filled with spaces.
+fn expand_szdd(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut window = [b' '; 4096];
+    let mut pos = 4096 - 16;
+    let mut out = Vec::with_capacity(size);
+    let mut input = data.iter().copied();
+    while let Some(control) = input.next() {
+        for bit in 0..8 {
+            if control & (1 << bit) != 0 {
+                let b = match input.next() {
+                    Some(b) => b,
+                    None => break,
+                };
+                out.push(b);
+                window[pos] = b;
+                pos = (pos + 1) % window.len();
+            } else {
+                let (lo, hi) = match (input.next(), input.next()) {
+                    (Some(lo), Some(hi)) => (usize::from(lo), usize::from(hi)),
+                    _ => break,
+                };
+                let offset = lo | ((hi & 0xf0) << 4);
+                for i in 0..(hi & 0x0f) + 3 {
+                    let b = window[(offset + i) % window.len()];
+                    out.push(b);
+                    window[pos] = b;
+                    pos = (pos + 1) % window.len();
+                }
+            }
+        }
+    }
+    if out.len() != size {
+        bail!("expected {} bytes but decompressed {}", size, out.len());
+    }
+    Ok(out)
+}
+
+/// A cabinet opened to extract files from.
+struct Source {
+    cabinet: Cabinet<File>,
+    /// Where to open the cabinet again.
+    path: PathBuf,
+    /// The temporary file holding the whole set the cabinet is in, joined
+    /// into one cabinet, if it's part of one.
+    _joined: Option<NamedTempFile>,
+}
+
+impl Source {
+    /// Open the cabinet file at `cab_path` to extract files from.
+    ///
+    /// If it's part of a linked set, the cabinets in the set are found, next
+    /// to each other or wherever `locate` says, and joined into a temporary
+    /// cabinet the way [`join_cabs`](crate::join_cabs) joins them, which is
+    /// opened instead.
+    fn open(
+        cab_path: &Path,
+        locate: &mut dyn FnMut(&CabinetLink) -> Option<PathBuf>,
+    ) -> Result<Source> {
+        let in_set = match read_header(&mut BufReader::new(File::open(cab_path)?)) {
+            Ok(header) => header.prev.is_some() || header.next.is_some(),
+            // Leave it to the `cab` crate to say what's wrong.
+            Err(_) => false,
+        };
+        if !in_set {
+            return Ok(Source {
+                cabinet: Cabinet::new(File::open(cab_path)?)?,
+                path: cab_path.to_path_buf(),
+                _joined: None,
+            });
+        }
+        let first = first_in_set(cab_path, locate)?;
+        let (joined, _) = join_set(&first, locate)?;
+        let mut temp = tempfile::Builder::new()
+            .prefix(TEMP_FILE_PREFIX)
+            .tempfile()?;
+        let mut writer = BufWriter::new(&mut temp);
+        joined.write_to(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        Ok(Source {
+            cabinet: Cabinet::new(temp.reopen()?)?,
+            path: temp.path().to_path_buf(),
+            _joined: Some(temp),
+        })
+    }
+}
+
+/// The names of every file in `cabinet`.
+fn all_names(cabinet: &Cabinet<File>) -> Vec<String> {
+    cabinet
+        .folder_entries()
+        .flat_map(|folder| folder.file_entries().map(|file| file.name().to_owned()))
+        .collect()
+}
+
+/// Extract the files stored as `names` in `cabinet`, which was opened from
+/// `cab_path`, into `out_dir`, returning the paths they were extracted to
+/// in the same order.
+///
+/// Each folder has to be decompressed from the start, but folders are
+/// independent of each other, so when the files are spread over several
+/// folders, each folder is extracted on its own thread through its own
+/// handle on the cabinet file.
+fn extract_names(
+    cab_path: &Path,
+    cabinet: &Cabinet<File>,
+    names: &[String],
+    out_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let folders = cabinet
+        .folder_entries()
+        .enumerate()
+        .flat_map(|(index, folder)| folder.file_entries().map(move |file| (file.name(), index)))
+        .collect::<HashMap<_, _>>();
+    let mut by_folder = BTreeMap::<_, Vec<_>>::new();
+    for (i, name) in names.iter().enumerate() {
+        match folders.get(name.as_str()) {
+            Some(&folder) => by_folder.entry(folder).or_default().push((i, name)),
+            None => bail!("No file named '{}' in cabinet", name),
+        }
+    }
+    let extras = read_reserve(cabinet);
+    let mut extracted = by_folder
+        .into_par_iter()
+        .map(|(_, names)| {
+            let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+            names
+                .into_iter()
+                .map(|(i, name)| Ok((i, extract_one(&mut cabinet, &extras, name, out_dir)?)))
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    extracted.sort_by_key(|&(i, _)| i);
+    Ok(extracted.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Extract the file stored as `name` in `cabinet` into `out_dir`, returning
+/// the path it was extracted to. If `extras` has timestamps for the file
+/// they're restored instead of those in its CFFILE entry, and if it has a
+/// CRC-32 it's checked.
+fn extract_one<R: io::Read + io::Seek>(
+    cabinet: &mut Cabinet<R>,
+    extras: &HashMap<String, FileExtras>,
+    name: &str,
+    out_dir: &Path,
+) -> Result<PathBuf> {
+    let (size, mtime) = match cabinet.get_file_entry(name) {
+        Some(entry) => (u64::from(entry.uncompressed_size()), entry_mtime(entry)),
+        None => bail!("No file named '{}' in cabinet", name),
+    };
+    let extra = extras.get(name).copied().unwrap_or_default();
+    let mtime = extra.timestamps.map(|t| t.modified).or(mtime);
+    let path = output_path(out_dir, name)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut reader = CrcReader::new(cabinet.read_file(name)?);
+    let written = io::copy(&mut reader, &mut File::create(&path)?)?;
+    if written != size {
+        bail!("expected {} bytes but decompressed {}", size, written);
+    }
+    match extra.crc32 {
+        Some(expected) if expected != reader.crc().sum() => bail!(
+            "expected CRC-32 {:08x} but decompressed data has {:08x}",
+            expected,
+            reader.crc().sum()
+        ),
+        _ => {}
+    }
+    if let Some(mtime) = mtime {
+        filetime::set_file_mtime(&path, mtime)?;
+    }
+    if let Some(created) = extra.timestamps.and_then(|t| t.created) {
+        set_file_created(&path, created)?;
+    }
+    Ok(path)
+}
+
+/// Get the modification time stored for `entry`, if it has a valid one.
+pub(crate) fn entry_mtime(entry: &FileEntry) -> Option<FileTime> {
+    entry
+        .datetime()
+        .map(|dt| FileTime::from_unix_time(dt.timestamp(), dt.timestamp_subsec_nanos()))
+}
+
+/// The name of the file in `cabinet` that `name` refers to, which may have
+/// `/` where the stored name has `\\` or the other way around. If there's no
+/// such file, this is just `name`.
+fn member_name<R: io::Read + io::Seek>(cabinet: &Cabinet<R>, name: &str) -> String {
+    if cabinet.get_file_entry(name).is_some() {
+        return name.to_owned();
+    }
+    let key = |name: &str| name.replace('/', "\\");
+    cabinet
+        .folder_entries()
+        .flat_map(|folder| folder.file_entries())
+        .find(|file| key(file.name()) == key(name))
+        .map_or_else(|| name.to_owned(), |file| file.name().to_owned())
+}
+
+/// Get the path under `out_dir` that the file stored as `name` should be
+/// extracted to, refusing names that would escape `out_dir`.
+fn output_path(out_dir: &Path, name: &str) -> Result<PathBuf> {
+    let mut path = out_dir.to_path_buf();
+    for part in name.split(['\\', '/']) {
+        match Path::new(part).components().next() {
+            None | Some(Component::CurDir) => {}
+            Some(Component::Normal(_)) if !part.contains(':') => path.push(part),
+            _ => bail!("Refusing to extract file with unsafe name '{}'", name),
+        }
+    }
+    if path == out_dir {
+        bail!("Refusing to extract file with empty name '{}'", name);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::fs::File;
+    use std::path::Path;
+
+    use self::tempdir::TempDir;
+    use super::{
+        expand_file, extract_cab, extract_file_to, extract_files, extract_files_with_locator,
+        extract_files_with_overwrite, output_path, plan_extraction, salvage_cab, Overwrite,
+        SZDD_SIGNATURE,
+    };
+    use crate::{make_cab_from_files, make_cab_from_files_with_options, CabOptions, SplitOptions};
+    use cab::{CabinetBuilder, CompressionType};
+    use chrono::NaiveDate;
+    use filetime::FileTime;
+
+    const FILES: &[(&str, &[u8])] = &[
+        ("a.txt", b"hello"),
+        ("dir\\b.txt", b"world"),
+        ("c.bin", &[0xff; 100_000]),
+    ];
+
+    /// Write a cabinet with the first two of `FILES` in an MSZIP folder and
+    /// the last in an uncompressed folder.
+    fn multi_folder_cab(path: &Path) {
+        let mut builder = CabinetBuilder::new();
+        let datetime = NaiveDate::from_ymd(2020, 2, 29).and_hms(12, 34, 56);
+        let folder = builder.add_folder(CompressionType::MsZip);
+        for (name, _) in &FILES[..2] {
+            folder.add_file(*name).set_datetime(datetime);
+        }
+        let folder = builder.add_folder(CompressionType::None);
+        folder.add_file(FILES[2].0).set_datetime(datetime);
+
+        let mut writer = builder
+            .build(File::create(path).expect("failed to create cab file"))
+            .expect("failed to build cab file");
+        let mut files = FILES.iter();
+        while let Some(mut w) = writer.next_file().expect("failed to write cab file") {
+            std::io::Write::write_all(&mut w, files.next().unwrap().1)
+                .expect("failed to write cab file");
+        }
+        writer.finish().expect("failed to finish cab file");
+    }
+
+    #[test]
+    fn extract_all() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        multi_folder_cab(&cab);
+        let out = t.path().join("out");
+
+        let paths = extract_cab(&cab, &out).expect("failed to extract cab file");
+        assert_eq!(
+            paths,
+            vec![
+                out.join("a.txt"),
+                out.join("dir").join("b.txt"),
+                out.join("c.bin")
+            ]
+        );
+        for ((_, data), path) in FILES.iter().zip(&paths) {
+            assert_eq!(&fs::read(path).expect("failed to read file")[..], *data);
+        }
+    }
+
+    #[test]
+    fn extract_some() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        multi_folder_cab(&cab);
+        let out = t.path().join("out");
+
+        let paths = extract_files(&cab, &["c.bin"], &out).expect("failed to extract cab file");
+        assert_eq!(paths, vec![out.join("c.bin")]);
+        assert_eq!(
+            fs::read(&paths[0]).expect("failed to read file"),
+            FILES[2].1
+        );
+        assert!(!out.join("a.txt").exists());
+
+        assert!(extract_files(&cab, &["missing"], &out).is_err());
+
+        // Files from different folders come back in the order asked for.
+        let paths =
+            extract_files(&cab, &["c.bin", "a.txt"], &out).expect("failed to extract cab file");
+        assert_eq!(paths, vec![out.join("c.bin"), out.join("a.txt")]);
+
+        // Either separator finds dir\\b.txt.
+        let paths = extract_files(&cab, &["dir/b.txt"], &out).expect("failed to extract cab file");
+        assert_eq!(paths, vec![out.join("dir").join("b.txt")]);
+        let data = extract_file_to(&cab, "dir/b.txt", vec![]).expect("failed to read cab file");
+        assert_eq!(data, FILES[1].1);
+    }
+
+    #[test]
+    fn plan() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        multi_folder_cab(&cab);
+        let out = t.path().join("out");
+
+        let planned = plan_extraction::<_, _, &str>(&cab, &[], &out).expect("failed to read cab");
+        assert!(!out.exists());
+        let listed = planned
+            .iter()
+            .map(|file| {
+                (
+                    file.name.as_str(),
+                    file.folder,
+                    file.size,
+                    file.path.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listed,
+            vec![
+                ("a.txt", 0, 5, out.join("a.txt")),
+                ("dir\\b.txt", 0, 5, out.join("dir").join("b.txt")),
+                ("c.bin", 1, 100_000, out.join("c.bin")),
+            ]
+        );
+        // The two small files share a block, and the uncompressed folder's
+        // data is as big as the file.
+        let listing = crate::list_cab(&cab).unwrap();
+        let block_data = listing.folders[0].compressed_size - crate::CFDATA_SIZE;
+        assert_eq!(
+            planned[0].compressed_size + planned[1].compressed_size,
+            block_data
+        );
+        assert_eq!(planned[2].compressed_size, 100_000);
+        assert_eq!(planned[2].ratio, 1.0);
+
+        let planned = plan_extraction(&cab, &["dir/b.txt"], &out).expect("failed to read cab");
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].name, "dir\\b.txt");
+        assert!(plan_extraction(&cab, &["missing"], &out).is_err());
+    }
+
+    #[test]
+    fn expand() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let out = t.path().join("out");
+
+        // "abcabcabc" as three literals and a six-byte reference back to
+        // them, compressed from file.txt.
+        let mut szdd = SZDD_SIGNATURE.to_vec();
+        szdd.extend_from_slice(b"At");
+        szdd.extend_from_slice(&9u32.to_le_bytes());
+        szdd.extend_from_slice(&[0x07, b'a', b'b', b'c', 0xf0, 0xf3]);
+        let compressed = t.path().join("file.tx_");
+        fs::write(&compressed, &szdd).unwrap();
+        let paths = expand_file(&compressed, &out).expect("failed to expand file");
+        assert_eq!(paths, vec![out.join("file.txt")]);
+        assert_eq!(fs::read(&paths[0]).unwrap(), b"abcabcabc");
+
+        // The wrong size is an error.
+        szdd[10] = 10;
+        fs::write(&compressed, &szdd).unwrap();
+        assert!(expand_file(&compressed, &out).is_err());
+
+        let input = t.path().join("symbols.pdb");
+        fs::write(&input, "not really a pdb").unwrap();
+        let compressed = t.path().join("symbols.pd_");
+        make_cab_from_files(&compressed, &[&input]).expect("failed to create cab file");
+        let paths = expand_file(&compressed, &out).expect("failed to expand file");
+        assert_eq!(paths, vec![out.join("symbols.pdb")]);
+        assert_eq!(fs::read(&paths[0]).unwrap(), b"not really a pdb");
+    }
+
+    #[test]
+    fn extract_many_folders() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        let mut builder = CabinetBuilder::new();
+        let names = (0..16)
+            .map(|i| format!("dir{}\\file{}.bin", i % 3, i))
+            .collect::<Vec<_>>();
+        for pair in names.chunks(2) {
+            let folder = builder.add_folder(CompressionType::MsZip);
+            for name in pair {
+                folder.add_file(name.as_str());
+            }
+        }
+        let mut writer = builder
+            .build(File::create(&cab).expect("failed to create cab file"))
+            .expect("failed to build cab file");
+        let mut i = 0;
+        while let Some(mut w) = writer.next_file().expect("failed to write cab file") {
+            std::io::Write::write_all(&mut w, &vec![i as u8; 50_000 + i])
+                .expect("failed to write cab file");
+            i += 1;
+        }
+        writer.finish().expect("failed to finish cab file");
+
+        let out = t.path().join("out");
+        let paths = extract_cab(&cab, &out).expect("failed to extract cab file");
+        assert_eq!(paths.len(), 16);
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(
+                *path,
+                out.join(format!("dir{}", i % 3))
+                    .join(format!("file{}.bin", i))
+            );
+            assert_eq!(fs::read(path).unwrap(), vec![i as u8; 50_000 + i]);
+        }
+    }
+
+    #[test]
+    fn overwrite() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        multi_folder_cab(&cab);
+        let out = t.path().join("out");
+        fs::create_dir(&out).unwrap();
+        // Older and newer than the 2020-02-29 timestamps in the cabinet.
+        let (old, new) = (
+            FileTime::from_unix_time(1_500_000_000, 0),
+            FileTime::from_unix_time(1_700_000_000, 0),
+        );
+        let reset = || {
+            fs::write(out.join("a.txt"), "old a").unwrap();
+            filetime::set_file_mtime(out.join("a.txt"), old).unwrap();
+            fs::write(out.join("c.bin"), "new c").unwrap();
+            filetime::set_file_mtime(out.join("c.bin"), new).unwrap();
+            let _ = fs::remove_dir_all(out.join("dir"));
+        };
+        let none = &[] as &[&str];
+        let never_asked = |path: &Path| -> bool { panic!("asked about {:?}", path) };
+
+        reset();
+        let paths = extract_files_with_overwrite(&cab, none, &out, Overwrite::Never, never_asked)
+            .expect("failed to extract cab file");
+        assert_eq!(paths, vec![out.join("dir").join("b.txt")]);
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old a");
+
+        reset();
+        let paths = extract_files_with_overwrite(&cab, none, &out, Overwrite::Newer, never_asked)
+            .expect("failed to extract cab file");
+        assert_eq!(
+            paths,
+            vec![out.join("a.txt"), out.join("dir").join("b.txt")]
+        );
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), FILES[0].1);
+        assert_eq!(fs::read(out.join("c.bin")).unwrap(), b"new c");
+
+        reset();
+        let mut asked = vec![];
+        let paths = extract_files_with_overwrite(&cab, none, &out, Overwrite::Prompt, |path| {
+            asked.push(path.to_path_buf());
+            path.ends_with("c.bin")
+        })
+        .expect("failed to extract cab file");
+        assert_eq!(asked, vec![out.join("a.txt"), out.join("c.bin")]);
+        assert_eq!(
+            paths,
+            vec![out.join("dir").join("b.txt"), out.join("c.bin")]
+        );
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old a");
+        assert_eq!(fs::read(out.join("c.bin")).unwrap(), FILES[2].1);
+
+        reset();
+        let paths =
+            extract_files_with_overwrite(&cab, &["a.txt"], &out, Overwrite::Always, never_asked)
+                .expect("failed to extract cab file");
+        assert_eq!(paths, vec![out.join("a.txt")]);
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), FILES[0].1);
+    }
+
+    #[test]
+    fn extract_to_writer() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        multi_folder_cab(&cab);
+
+        let data = extract_file_to(&cab, "dir\\b.txt", vec![]).expect("failed to extract file");
+        assert_eq!(data, FILES[1].1);
+        assert!(extract_file_to(&cab, "missing", vec![]).is_err());
+    }
+
+    #[test]
+    fn salvage() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        multi_folder_cab(&cab);
+        // Damage the last data block, which only holds part of `c.bin`.
+        let mut bytes = fs::read(&cab).expect("failed to read cab file");
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        fs::write(&cab, &bytes).expect("failed to write cab file");
+        let out = t.path().join("out");
+
+        assert!(extract_cab(&cab, &out).is_err());
+        let report = salvage_cab(&cab, &out).expect("failed to salvage cab file");
+        assert!(!report.is_ok());
+        let damaged = report
+            .files
+            .iter()
+            .filter(|f| !f.is_ok())
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(damaged, vec!["c.bin"]);
+        assert_eq!(fs::read(out.join("a.txt")).unwrap(), FILES[0].1);
+        assert_eq!(fs::read(out.join("dir").join("b.txt")).unwrap(), FILES[1].1);
+        assert!(!out.join("c.bin").exists());
+    }
+
+    #[test]
+    fn unsafe_names() {
+        let out = Path::new("out");
+        assert_eq!(
+            output_path(out, ".\\a\\b").unwrap(),
+            out.join("a").join("b")
+        );
+        assert!(output_path(out, "..\\evil").is_err());
+        assert!(output_path(out, "a/../../evil").is_err());
+        assert!(output_path(out, "C:\\evil").is_err());
+        assert!(output_path(out, "\\").is_err());
+    }
+
+    #[test]
+    fn extract_set() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        // Noise doesn't compress, so the big file runs over several cabinets.
+        let mut seed = 1u32;
+        let big = (0..100_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+        let inputs = [t.path().join("small.txt"), t.path().join("big.bin")];
+        fs::write(&inputs[0], b"hello").unwrap();
+        fs::write(&inputs[1], &big).unwrap();
+        let options = CabOptions {
+            split: Some(SplitOptions::new(40_000)),
+            ..CabOptions::default()
+        };
+        let summary = make_cab_from_files_with_options(t.path().join("set.cab"), &inputs, &options)
+            .expect("failed to create cab set");
+        assert_eq!(summary.cabinets.len(), 3);
+
+        // Any cabinet in the set gets every file in the set.
+        for cab in &summary.cabinets[..2] {
+            let out = t.path().join("out");
+            let extracted = extract_cab(cab, &out).expect("failed to extract cab set");
+            assert_eq!(extracted.len(), 2);
+            assert_eq!(fs::read(out.join("small.txt")).unwrap(), b"hello");
+            assert_eq!(fs::read(out.join("big.bin")).unwrap(), big);
+            fs::remove_dir_all(&out).unwrap();
+        }
+        let out = t.path().join("one");
+        extract_files(&summary.cabinets[2], &["big.bin"], &out)
+            .expect("failed to extract continued file");
+        assert_eq!(fs::read(out.join("big.bin")).unwrap(), big);
+        assert!(!out.join("small.txt").exists());
+
+        // Cabinets that have been moved away are asked for.
+        let disk2 = t.path().join("disk2");
+        fs::create_dir(&disk2).unwrap();
+        for cab in &summary.cabinets[1..] {
+            fs::rename(cab, disk2.join(cab.file_name().unwrap())).unwrap();
+        }
+        let out = t.path().join("located");
+        assert!(extract_cab(&summary.cabinets[0], &out).is_err());
+        let mut asked = vec![];
+        extract_files_with_locator(&summary.cabinets[0], &[] as &[&str], &out, |link| {
+            asked.push(link.cabinet.clone());
+            Some(disk2.join(&link.cabinet))
+        })
+        .expect("failed to extract cab set");
+        // Only the first moved cabinet has to be asked for, the last one is
+        // next to it.
+        assert_eq!(asked, ["set2.cab"]);
+        assert_eq!(fs::read(out.join("big.bin")).unwrap(), big);
+    }
+}
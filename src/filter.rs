@@ -0,0 +1,119 @@
+//! Choosing which files in a directory tree to pack, with gitignore-style
+//! glob patterns.
+
+use anyhow::{bail, Result};
+
+/// Include and exclude patterns for the files in a directory tree.
+///
+/// Patterns are matched against paths relative to the root of the tree,
+/// with `/` between components, as in `.gitignore` files: `*` matches
+/// anything but a `/`, `?` matches any single character but a `/`, and
+/// `**` matches any number of directories, so `**/*.pdb` matches a `.pdb`
+/// file anywhere. A pattern without a `/` matches a file name at any depth,
+/// and a leading `/` anchors a pattern to the root. Matching is
+/// case-insensitive, like Windows file systems.
+///
+/// A file is kept if it matches any include pattern, or there are none,
+/// and doesn't match any exclude pattern.
+#[derive(Clone, Debug, Default)]
+pub struct PathFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl PathFilter {
+    /// Create a filter keeping files that match one of `include` (or any
+    /// file if `include` is empty) but none of `exclude`.
+    pub fn new<S: AsRef<str>, T: AsRef<str>>(include: &[S], exclude: &[T]) -> Result<PathFilter> {
+        Ok(PathFilter {
+            include: include
+                .iter()
+                .map(|p| normalize(p.as_ref()))
+                .collect::<Result<_>>()?,
+            exclude: exclude
+                .iter()
+                .map(|p| normalize(p.as_ref()))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Whether the file at `path`, relative to the root of the tree, should
+    /// be kept. Either `/` or `\` can separate components.
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.replace('\\', "/").to_lowercase();
+        let matches = |pattern: &String| glob_match(pattern.as_bytes(), path.as_bytes());
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Turn `pattern` into one that's matched against the whole path.
+fn normalize(pattern: &str) -> Result<String> {
+    let pattern = pattern.replace('\\', "/").to_lowercase();
+    if pattern.is_empty() || pattern == "/" {
+        bail!("Empty pattern");
+    }
+    Ok(match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_owned(),
+        None if pattern.contains('/') => pattern,
+        None => format!("**/{}", pattern),
+    })
+}
+
+/// Match the whole of `text` against the glob `pattern`.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*'] => true,
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob_match(rest, text)
+                || text
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &c)| c == b'/')
+                    .any(|(i, _)| glob_match(rest, &text[i + 1..]))
+        }
+        [b'*', rest @ ..] => {
+            let segment = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=segment).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => match text {
+            [c, text @ ..] if *c != b'/' => glob_match(rest, text),
+            _ => false,
+        },
+        [p, rest @ ..] => match text {
+            [c, text @ ..] if c == p => glob_match(rest, text),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathFilter;
+
+    #[test]
+    fn filters() {
+        let filter = PathFilter::new(&["**/*.pdb", "/bin/*.exe"], &["**/obj/**", "*.tmp.*"])
+            .expect("failed to create filter");
+        assert!(filter.matches("a.pdb"));
+        assert!(filter.matches("x64/release/A.PDB"));
+        assert!(filter.matches("bin\\app.exe"));
+        assert!(!filter.matches("bin/sub/app.exe"));
+        assert!(!filter.matches("src/bin/app.exe"));
+        assert!(!filter.matches("a.obj"));
+        assert!(!filter.matches("x64/obj/a.pdb"));
+        assert!(!filter.matches("x64/a.tmp.pdb"));
+
+        let exclude_only = PathFilter::new::<&str, _>(&[], &["*.obj", "build/"]).unwrap();
+        assert!(exclude_only.matches("a.c"));
+        assert!(exclude_only.matches("dir/a.c"));
+        assert!(!exclude_only.matches("dir/a.obj"));
+        assert!(PathFilter::new::<&str, _>(&[], &[""]).is_err());
+
+        let question = PathFilter::new::<_, &str>(&["file?.txt"], &[]).unwrap();
+        assert!(question.matches("dir/file1.txt"));
+        assert!(!question.matches("file12.txt"));
+        assert!(!question.matches("file/.txt"));
+    }
+}
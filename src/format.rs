@@ -0,0 +1,816 @@
+//! The records of the cabinet file format, for building cabinets by hand.
+//!
+//! [`make_cab`](crate::make_cab) and friends only produce one shape of
+//! cabinet. The types here serialize each record of the [cabinet format]
+//! byte for byte, so unusual-but-valid cabinets (custom flags, reserves,
+//! cabinet set links, odd layouts) can be built without reimplementing the
+//! spec. [`Layout`] puts whole cabinets together and fills in the sizes,
+//! counts, offsets and checksums. The [`parse`] module reads them back.
+//!
+//! The constants use the names from the spec.
+//!
+//! [cabinet format]: https://docs.microsoft.com/en-us/previous-versions/bb417343(v=msdn.10)
+
+#![allow(non_upper_case_globals)]
+
+use std::io::{self, Read, Write};
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    LimitExceeded, CFDATA_SIZE, CFFILE_SIZE, CFFOLDER_SIZE, CFHEADER_SIZE, MAX_CHUNK,
+    MAX_HEADER_RESERVE,
+};
+
+pub mod parse;
+
+/// The cabinet is continued from a previous one.
+pub const cfhdrPREV_CABINET: u16 = 0x0001;
+/// The cabinet is continued in a next one.
+pub const cfhdrNEXT_CABINET: u16 = 0x0002;
+/// The header has reserve sizes and a header reserve.
+pub const cfhdrRESERVE_PRESENT: u16 = 0x0004;
+
+/// The file is continued from the previous cabinet.
+pub const ifoldCONTINUED_FROM_PREV: u16 = 0xfffd;
+/// The file is continued in the next cabinet.
+pub const ifoldCONTINUED_TO_NEXT: u16 = 0xfffe;
+/// The file is continued from the previous cabinet and in the next one.
+pub const ifoldCONTINUED_PREV_AND_NEXT: u16 = 0xffff;
+
+/// Read-only file attribute.
+pub const _A_RDONLY: u16 = 0x01;
+/// Hidden file attribute.
+pub const _A_HIDDEN: u16 = 0x02;
+/// System file attribute.
+pub const _A_SYSTEM: u16 = 0x04;
+/// Archive file attribute.
+pub const _A_ARCH: u16 = 0x20;
+/// Run the file after extraction.
+pub const _A_EXEC: u16 = 0x40;
+/// The file name is UTF-8.
+pub const _A_NAME_IS_UTF: u16 = 0x80;
+
+/// The bits of `typeCompress` holding the compression type.
+pub const tcompTYPE_MASK: u16 = 0x000f;
+/// No compression.
+pub const tcompTYPE_NONE: u16 = 0x0000;
+/// MSZIP compression.
+pub const tcompTYPE_MSZIP: u16 = 0x0001;
+/// Quantum compression.
+pub const tcompTYPE_QUANTUM: u16 = 0x0002;
+/// LZX compression.
+pub const tcompTYPE_LZX: u16 = 0x0003;
+
+/// The longest string (including the terminating NUL) allowed in a header.
+pub(crate) const MAX_STRING: usize = 256;
+/// The most compressed data a CFDATA block may hold.
+pub(crate) const MAX_COMPRESSED_CHUNK: usize = MAX_CHUNK + 6144;
+
+/// A link to the previous or next cabinet in a set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CabinetLink {
+    /// The file name of the cabinet.
+    pub cabinet: String,
+    /// The name of the disk the cabinet is on.
+    pub disk: String,
+}
+
+/// A CFHEADER record.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    /// `cbCabinet`, the size of the whole cabinet file.
+    pub cabinet_size: u32,
+    /// `coffFiles`, the offset of the first CFFILE record.
+    pub files_offset: u32,
+    /// `versionMinor`, 3 by default.
+    pub version_minor: u8,
+    /// `versionMajor`, 1 by default.
+    pub version_major: u8,
+    /// `cFolders`.
+    pub folder_count: u16,
+    /// `cFiles`.
+    pub file_count: u16,
+    /// `flags`. The flags for the optional fields below that are present
+    /// are added when the header is written.
+    pub flags: u16,
+    /// `setID`, shared by every cabinet in a set.
+    pub set_id: u16,
+    /// `iCabinet`, the index of this cabinet in its set.
+    pub cabinet_index: u16,
+    /// `abReserve`, the header reserve.
+    pub header_reserve: Vec<u8>,
+    /// `cbCFFolder`, the size of each folder's reserve.
+    pub folder_reserve_size: u8,
+    /// `cbCFData`, the size of each data block's reserve.
+    pub data_reserve_size: u8,
+    /// `szCabinetPrev` and `szDiskPrev`.
+    pub prev: Option<CabinetLink>,
+    /// `szCabinetNext` and `szDiskNext`.
+    pub next: Option<CabinetLink>,
+}
+
+impl Default for Header {
+    fn default() -> Header {
+        Header {
+            cabinet_size: 0,
+            files_offset: 0,
+            version_minor: 3,
+            version_major: 1,
+            folder_count: 0,
+            file_count: 0,
+            flags: 0,
+            set_id: 0,
+            cabinet_index: 0,
+            header_reserve: vec![],
+            folder_reserve_size: 0,
+            data_reserve_size: 0,
+            prev: None,
+            next: None,
+        }
+    }
+}
+
+impl Header {
+    /// The flags that will be written, including the ones implied by the
+    /// optional fields.
+    pub fn effective_flags(&self) -> u16 {
+        let mut flags = self.flags;
+        if !self.header_reserve.is_empty()
+            || self.folder_reserve_size != 0
+            || self.data_reserve_size != 0
+        {
+            flags |= cfhdrRESERVE_PRESENT;
+        }
+        if self.prev.is_some() {
+            flags |= cfhdrPREV_CABINET;
+        }
+        if self.next.is_some() {
+            flags |= cfhdrNEXT_CABINET;
+        }
+        flags
+    }
+
+    /// The size of the serialized header.
+    pub fn size(&self) -> u64 {
+        let flags = self.effective_flags();
+        let mut size = CFHEADER_SIZE;
+        if flags & cfhdrRESERVE_PRESENT != 0 {
+            size += 4 + self.header_reserve.len() as u64;
+        }
+        for link in self.prev.iter().chain(self.next.iter()) {
+            size += link.cabinet.len() as u64 + link.disk.len() as u64 + 2;
+        }
+        size
+    }
+
+    /// Serialize the header to `w`.
+    ///
+    /// This fails if the header reserve is too large, if a link's names are
+    /// too long or contain NULs, or if `flags` says a cabinet is linked but
+    /// the link is missing.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<()> {
+        if self.header_reserve.len() > MAX_HEADER_RESERVE as usize {
+            bail!(
+                "Header reserve is {} bytes, more than the maximum of {}",
+                self.header_reserve.len(),
+                MAX_HEADER_RESERVE
+            );
+        }
+        if self.flags & cfhdrPREV_CABINET != 0 && self.prev.is_none() {
+            bail!("cfhdrPREV_CABINET is set but there is no previous cabinet");
+        }
+        if self.flags & cfhdrNEXT_CABINET != 0 && self.next.is_none() {
+            bail!("cfhdrNEXT_CABINET is set but there is no next cabinet");
+        }
+        let flags = self.effective_flags();
+        w.write_all(b"MSCF")?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&self.cabinet_size.to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&self.files_offset.to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&[self.version_minor, self.version_major])?;
+        w.write_all(&self.folder_count.to_le_bytes())?;
+        w.write_all(&self.file_count.to_le_bytes())?;
+        w.write_all(&flags.to_le_bytes())?;
+        w.write_all(&self.set_id.to_le_bytes())?;
+        w.write_all(&self.cabinet_index.to_le_bytes())?;
+        if flags & cfhdrRESERVE_PRESENT != 0 {
+            w.write_all(&(self.header_reserve.len() as u16).to_le_bytes())?;
+            w.write_all(&[self.folder_reserve_size, self.data_reserve_size])?;
+            w.write_all(&self.header_reserve)?;
+        }
+        for link in self.prev.iter().chain(self.next.iter()) {
+            write_cstring(&mut w, &link.cabinet)?;
+            write_cstring(&mut w, &link.disk)?;
+        }
+        Ok(())
+    }
+}
+
+/// A CFFOLDER record.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Folder {
+    /// `coffCabStart`, the offset of the folder's first CFDATA block.
+    pub data_offset: u32,
+    /// `cCFData`, the number of CFDATA blocks in the folder.
+    pub data_count: u16,
+    /// `typeCompress`, one of the `tcompTYPE_*` constants plus any
+    /// parameters for the compression type.
+    pub compression: u16,
+    /// `abReserve`, which must be `cbCFFolder` bytes long.
+    pub reserve: Vec<u8>,
+}
+
+impl Folder {
+    /// The size of the serialized folder.
+    pub fn size(&self) -> u64 {
+        CFFOLDER_SIZE + self.reserve.len() as u64
+    }
+
+    /// Serialize the folder to `w`.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<()> {
+        w.write_all(&self.data_offset.to_le_bytes())?;
+        w.write_all(&self.data_count.to_le_bytes())?;
+        w.write_all(&self.compression.to_le_bytes())?;
+        w.write_all(&self.reserve)?;
+        Ok(())
+    }
+}
+
+/// A CFFILE record.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// `cbFile`, the uncompressed size of the file.
+    pub size: u32,
+    /// `uoffFolderStart`, the offset of the file in its folder's
+    /// uncompressed data.
+    pub folder_offset: u32,
+    /// `iFolder`, the index of the file's folder, or one of the
+    /// `ifoldCONTINUED_*` constants.
+    pub folder: u16,
+    /// `date`, in MS-DOS format.
+    pub date: u16,
+    /// `time`, in MS-DOS format.
+    pub time: u16,
+    /// `attribs`, a combination of the `_A_*` constants.
+    pub attributes: u16,
+    /// `szName`.
+    pub name: String,
+}
+
+impl FileEntry {
+    /// Create a file entry for a file `size` bytes long at `folder_offset`
+    /// in `folder`, modified at `datetime`. The name is marked as UTF-8 if
+    /// it isn't ASCII.
+    pub fn new<S: Into<String>>(
+        name: S,
+        size: u32,
+        folder: u16,
+        folder_offset: u32,
+        datetime: NaiveDateTime,
+    ) -> FileEntry {
+        let name = name.into();
+        let (date, time) = dos_datetime(datetime);
+        let attributes = if name.is_ascii() { 0 } else { _A_NAME_IS_UTF };
+        FileEntry {
+            size,
+            folder_offset,
+            folder,
+            date,
+            time,
+            attributes,
+            name,
+        }
+    }
+
+    /// Whether the name is marked as UTF-8.
+    pub fn is_name_utf(&self) -> bool {
+        self.attributes & _A_NAME_IS_UTF != 0
+    }
+
+    /// The size of the serialized file entry.
+    pub fn size(&self) -> u64 {
+        CFFILE_SIZE + self.name.len() as u64 + 1
+    }
+
+    /// Serialize the file entry to `w`. This fails if the name is empty,
+    /// too long or contains a NUL.
+    pub fn write_to<W: Write>(&self, w: W) -> Result<()> {
+        self.write_with_name(w, self.name.as_bytes())
+    }
+
+    /// Like [`FileEntry::write_to`], but storing `name` instead of the
+    /// entry's name, for names encoded in something other than UTF-8.
+    pub fn write_with_name<W: Write>(&self, mut w: W, name: &[u8]) -> Result<()> {
+        if name.is_empty() {
+            bail!("File name is empty");
+        }
+        w.write_all(&self.size.to_le_bytes())?;
+        w.write_all(&self.folder_offset.to_le_bytes())?;
+        w.write_all(&self.folder.to_le_bytes())?;
+        w.write_all(&self.date.to_le_bytes())?;
+        w.write_all(&self.time.to_le_bytes())?;
+        w.write_all(&self.attributes.to_le_bytes())?;
+        write_cstring_bytes(&mut w, name, &self.name)
+    }
+}
+
+/// A CFDATA record.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DataBlock {
+    /// `abReserve`, which must be `cbCFData` bytes long.
+    pub reserve: Vec<u8>,
+    /// `ab`, the compressed data.
+    pub data: Vec<u8>,
+    /// `cbUncomp`, the size of the data once decompressed.
+    pub uncompressed_size: u16,
+}
+
+impl DataBlock {
+    /// Create an uncompressed block holding `data`, for a folder using
+    /// `tcompTYPE_NONE`.
+    pub fn uncompressed(data: Vec<u8>) -> DataBlock {
+        DataBlock {
+            reserve: vec![],
+            uncompressed_size: data.len() as u16,
+            data,
+        }
+    }
+
+    /// The `csum` field that will be written for the block.
+    pub fn checksum(&self) -> u32 {
+        let mut header = (self.data.len() as u16).to_le_bytes().to_vec();
+        header.extend_from_slice(&self.uncompressed_size.to_le_bytes());
+        header.extend_from_slice(&self.reserve);
+        checksum(&header, checksum(&self.data, 0))
+    }
+
+    /// The size of the serialized block.
+    pub fn size(&self) -> u64 {
+        CFDATA_SIZE + self.reserve.len() as u64 + self.data.len() as u64
+    }
+
+    /// Serialize the block to `w`, with its checksum. This fails if the
+    /// data is more than 64K.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<()> {
+        if self.data.len() > u16::MAX as usize {
+            bail!("Data block is {} bytes, too large", self.data.len());
+        }
+        w.write_all(&self.checksum().to_le_bytes())?;
+        w.write_all(&(self.data.len() as u16).to_le_bytes())?;
+        w.write_all(&self.uncompressed_size.to_le_bytes())?;
+        w.write_all(&self.reserve)?;
+        w.write_all(&self.data)?;
+        Ok(())
+    }
+}
+
+/// A whole cabinet: a header, folders with their data blocks, and files.
+///
+/// [`Layout::write_to`] fills in the header's sizes and counts and each
+/// folder's data offset and block count, and writes the records in the
+/// usual order: header, folders, files, then each folder's data.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Layout {
+    /// The header.
+    pub header: Header,
+    /// The folders, each with its data blocks.
+    pub folders: Vec<(Folder, Vec<DataBlock>)>,
+    /// The files.
+    pub files: Vec<FileEntry>,
+}
+
+impl Layout {
+    /// The header and folders as they will be written.
+    pub fn resolve(&self) -> Result<(Header, Vec<Folder>)> {
+        let mut header = self.header.clone();
+        if self.files.len() > u16::MAX as usize {
+            return Err(LimitExceeded::TooManyFiles(self.files.len()).into());
+        }
+        if self.folders.len() > u16::MAX as usize {
+            bail!("Too many folders");
+        }
+        header.folder_count = self.folders.len() as u16;
+        header.file_count = self.files.len() as u16;
+        let mut offset = header.size();
+        for (index, (folder, blocks)) in self.folders.iter().enumerate() {
+            if folder.reserve.len() != header.folder_reserve_size as usize {
+                bail!(
+                    "Folder {} has a {} byte reserve but cbCFFolder is {}",
+                    index,
+                    folder.reserve.len(),
+                    header.folder_reserve_size
+                );
+            }
+            if blocks.len() > u16::MAX as usize {
+                bail!("Folder {} has too many data blocks", index);
+            }
+            for block in blocks {
+                if block.reserve.len() != header.data_reserve_size as usize {
+                    bail!(
+                        "A data block in folder {} has a {} byte reserve but cbCFData is {}",
+                        index,
+                        block.reserve.len(),
+                        header.data_reserve_size
+                    );
+                }
+            }
+            offset += folder.size();
+        }
+        header.files_offset = to_u32(offset)?;
+        offset += self.files.iter().map(FileEntry::size).sum::<u64>();
+        let mut folders = Vec::with_capacity(self.folders.len());
+        for (folder, blocks) in &self.folders {
+            let mut folder = folder.clone();
+            folder.data_offset = to_u32(offset)?;
+            folder.data_count = blocks.len() as u16;
+            offset += blocks.iter().map(DataBlock::size).sum::<u64>();
+            folders.push(folder);
+        }
+        header.cabinet_size = to_u32(offset)?;
+        Ok((header, folders))
+    }
+
+    /// Serialize the whole cabinet to `w`.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<()> {
+        let (header, folders) = self.resolve()?;
+        header.write_to(&mut w)?;
+        for folder in &folders {
+            folder.write_to(&mut w)?;
+        }
+        for file in &self.files {
+            file.write_to(&mut w)?;
+        }
+        for (_, blocks) in &self.folders {
+            for block in blocks {
+                block.write_to(&mut w)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Serialize the whole cabinet.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// Convert `datetime` to MS-DOS date and time fields. Dates before 1980
+/// can't be represented and are clamped to the start of 1980.
+pub fn dos_datetime(datetime: NaiveDateTime) -> (u16, u16) {
+    if datetime.year() < 1980 {
+        return ((1 << 5) | 1, 0);
+    }
+    let date = (((datetime.year() - 1980) as u16) << 9)
+        | ((datetime.month() as u16) << 5)
+        | datetime.day() as u16;
+    let time = ((datetime.hour() as u16) << 11)
+        | ((datetime.minute() as u16) << 5)
+        | (datetime.second() as u16 / 2);
+    (date, time)
+}
+
+/// Decode an MS-DOS date and time, as stored in CFFILE records, returning
+/// `None` if they aren't valid.
+pub fn from_dos_datetime(date: u16, time: u16) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(
+        1980 + i32::from(date >> 9),
+        u32::from((date >> 5) & 0xf),
+        u32::from(date & 0x1f),
+    )?
+    .and_hms_opt(
+        u32::from(time >> 11),
+        u32::from((time >> 5) & 0x3f),
+        2 * u32::from(time & 0x1f),
+    )
+}
+
+fn to_u32(offset: u64) -> Result<u32> {
+    if offset > u64::from(u32::MAX) {
+        bail!("Cabinet is larger than 4GB");
+    }
+    Ok(offset as u32)
+}
+
+fn write_cstring<W: Write>(w: &mut W, s: &str) -> Result<()> {
+    write_cstring_bytes(w, s.as_bytes(), s)
+}
+
+/// Write `bytes` followed by a NUL, naming it `display` in errors.
+fn write_cstring_bytes<W: Write>(w: &mut W, bytes: &[u8], display: &str) -> Result<()> {
+    if bytes.contains(&0) {
+        bail!("{:?} contains a NUL", display);
+    }
+    if bytes.len() + 1 > MAX_STRING {
+        bail!(
+            "{:?} is longer than the maximum of {} bytes",
+            display,
+            MAX_STRING - 1
+        );
+    }
+    w.write_all(bytes)?;
+    w.write_all(&[0])?;
+    Ok(())
+}
+
+/// Compute the cabinet checksum of `data`, starting from `seed`.
+///
+/// The checksum XORs together the little-endian 32-bit words of `data`, so
+/// all but the last few bytes are done 16 or 32 bytes at a time, with AVX2
+/// or SSE2 on x86-64 (depending on what the CPU supports) and NEON on
+/// AArch64.
+pub fn checksum(data: &[u8], seed: u32) -> u32 {
+    let (words, rest) = data.split_at(data.len() / 4 * 4);
+    // Leftover bytes are combined in the opposite order to the rest.
+    let ul = rest.iter().fold(0, |ul, &b| (ul << 8) | u32::from(b));
+    seed ^ xor_words(words) ^ ul
+}
+
+/// XOR together the little-endian 32-bit words of `words`, whose length
+/// must be a multiple of 4.
+fn xor_words(words: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { xor_words_avx2(words) };
+        }
+        // Every x86-64 CPU has SSE2.
+        unsafe { xor_words_sse2(words) }
+    }
+    #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+    {
+        // Every AArch64 CPU has NEON.
+        unsafe { xor_words_neon(words) }
+    }
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_endian = "little")
+    )))]
+    {
+        xor_words_scalar(words)
+    }
+}
+
+/// [`xor_words`] a word at a time.
+fn xor_words_scalar(words: &[u8]) -> u32 {
+    words.chunks_exact(4).fold(0, |csum, w| {
+        csum ^ u32::from_le_bytes([w[0], w[1], w[2], w[3]])
+    })
+}
+
+/// [`xor_words`] 16 bytes at a time.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn xor_words_sse2(words: &[u8]) -> u32 {
+    use std::arch::x86_64::*;
+
+    let mut acc = _mm_setzero_si128();
+    let mut chunks = words.chunks_exact(16);
+    for chunk in &mut chunks {
+        acc = _mm_xor_si128(acc, _mm_loadu_si128(chunk.as_ptr() as *const __m128i));
+    }
+    let mut lanes = [0u32; 4];
+    _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, acc);
+    lanes
+        .iter()
+        .fold(xor_words_scalar(chunks.remainder()), |csum, &lane| {
+            csum ^ lane
+        })
+}
+
+/// [`xor_words`] 32 bytes at a time.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn xor_words_avx2(words: &[u8]) -> u32 {
+    use std::arch::x86_64::*;
+
+    let mut acc = _mm256_setzero_si256();
+    let mut chunks = words.chunks_exact(32);
+    for chunk in &mut chunks {
+        acc = _mm256_xor_si256(acc, _mm256_loadu_si256(chunk.as_ptr() as *const __m256i));
+    }
+    let mut lanes = [0u32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc);
+    lanes
+        .iter()
+        .fold(xor_words_sse2(chunks.remainder()), |csum, &lane| {
+            csum ^ lane
+        })
+}
+
+/// [`xor_words`] 16 bytes at a time.
+#[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+#[target_feature(enable = "neon")]
+unsafe fn xor_words_neon(words: &[u8]) -> u32 {
+    use std::arch::aarch64::*;
+
+    let mut acc = vdupq_n_u32(0);
+    let mut chunks = words.chunks_exact(16);
+    for chunk in &mut chunks {
+        acc = veorq_u32(acc, vreinterpretq_u32_u8(vld1q_u8(chunk.as_ptr())));
+    }
+    let mut lanes = [0u32; 4];
+    vst1q_u32(lanes.as_mut_ptr(), acc);
+    lanes
+        .iter()
+        .fold(xor_words_scalar(chunks.remainder()), |csum, &lane| {
+            csum ^ lane
+        })
+}
+
+pub(crate) fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Read a NUL-terminated string, not including the NUL.
+pub(crate) fn read_cstring<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut s = vec![];
+    loop {
+        match read_u8(r)? {
+            0 => return Ok(s),
+            b => s.push(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::io::Read;
+
+    use self::tempdir::TempDir;
+    use super::*;
+    use crate::lint_cab;
+    use cab::Cabinet;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(checksum(&[], 0), 0);
+        assert_eq!(checksum(&[1, 2, 3, 4], 0), 0x04030201);
+        assert_eq!(checksum(&[1, 2, 3, 4, 5, 6, 7], 0), 0x04030201 ^ 0x050607);
+        assert_eq!(checksum(&[1, 2], 0x0100), 0x0102 ^ 0x0100);
+
+        // The vectorized versions agree with a word at a time, whatever the
+        // length and alignment.
+        let data = (0..300u32)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect::<Vec<_>>();
+        for start in 0..4 {
+            for end in start..data.len() {
+                let words = &data[start..start + (end - start) / 4 * 4];
+                assert_eq!(xor_words(words), xor_words_scalar(words));
+                #[cfg(target_arch = "x86_64")]
+                assert_eq!(unsafe { xor_words_sse2(words) }, xor_words_scalar(words));
+            }
+        }
+    }
+
+    #[test]
+    fn records() {
+        let header = Header {
+            cabinet_size: 0x1234,
+            files_offset: 0x2c,
+            folder_count: 1,
+            file_count: 2,
+            set_id: 7,
+            folder_reserve_size: 1,
+            header_reserve: vec![0xaa, 0xbb],
+            next: Some(CabinetLink {
+                cabinet: "b.cab".to_owned(),
+                disk: "2".to_owned(),
+            }),
+            ..Header::default()
+        };
+        let mut bytes = vec![];
+        header.write_to(&mut bytes).unwrap();
+        let mut expected = b"MSCF".to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 0, 0x34, 0x12, 0, 0, 0, 0, 0, 0, 0x2c, 0, 0, 0]);
+        expected.extend_from_slice(&[0, 0, 0, 0, 3, 1, 1, 0, 2, 0, 0x06, 0, 7, 0, 0, 0]);
+        expected.extend_from_slice(&[2, 0, 1, 0, 0xaa, 0xbb]);
+        expected.extend_from_slice(b"b.cab\x002\x00");
+        assert_eq!(bytes, expected);
+        assert_eq!(header.size(), bytes.len() as u64);
+
+        let file = FileEntry::new(
+            "d\u{e9}j\u{e0}.txt",
+            5,
+            0,
+            0,
+            NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8),
+        );
+        let mut bytes = vec![];
+        file.write_to(&mut bytes).unwrap();
+        assert_eq!(
+            &bytes[..16],
+            &[5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x64, 0x52, 0xc4, 0x28, 0x80, 0]
+        );
+        assert_eq!(&bytes[16..], "d\u{e9}j\u{e0}.txt\0".as_bytes());
+        assert_eq!(file.size(), bytes.len() as u64);
+
+        let block = DataBlock::uncompressed(b"hello".to_vec());
+        let mut bytes = vec![];
+        block.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[4..], b"\x05\x00\x05\x00hello");
+        assert_eq!(
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            block.checksum()
+        );
+
+        let bad = Header {
+            flags: cfhdrPREV_CABINET,
+            ..Header::default()
+        };
+        assert!(bad.write_to(&mut vec![]).is_err());
+        let bad = FileEntry {
+            name: "a\0b".to_owned(),
+            ..FileEntry::default()
+        };
+        assert!(bad.write_to(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn layout() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let datetime = NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8);
+        let layout = Layout {
+            header: Header {
+                set_id: 0x1234,
+                header_reserve: vec![1, 2, 3],
+                folder_reserve_size: 2,
+                ..Header::default()
+            },
+            folders: vec![(
+                Folder {
+                    compression: tcompTYPE_NONE,
+                    reserve: vec![4, 5],
+                    ..Folder::default()
+                },
+                vec![
+                    DataBlock::uncompressed(b"hello ".to_vec()),
+                    DataBlock::uncompressed(b"world".to_vec()),
+                ],
+            )],
+            files: vec![
+                FileEntry::new("a.txt", 8, 0, 0, datetime),
+                FileEntry::new("b.txt", 3, 0, 8, datetime),
+            ],
+        };
+        let bytes = layout.to_bytes().expect("failed to serialize cabinet");
+        let (header, folders) = layout.resolve().unwrap();
+        assert_eq!(header.cabinet_size as usize, bytes.len());
+        assert_eq!(folders[0].data_count, 2);
+
+        let path = t.path().join("test.cab");
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(lint_cab(&path).expect("failed to lint cab file"), vec![]);
+        // The records survive a round trip through JSON.
+        let parsed = parse::parse_cab(&path).expect("failed to parse cab file");
+        let json = serde_json::to_string(&parsed).unwrap();
+        assert_eq!(
+            serde_json::from_str::<parse::ParsedCabinet>(&json).unwrap(),
+            parsed
+        );
+        let mut cabinet = Cabinet::new(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(cabinet.cabinet_set_id(), 0x1234);
+        assert_eq!(cabinet.reserve_data(), &[1, 2, 3]);
+        assert_eq!(
+            cabinet.folder_entries().next().unwrap().reserve_data(),
+            &[4, 5]
+        );
+        let mut contents = String::new();
+        cabinet
+            .read_file("b.txt")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "rld");
+
+        // Reserves must match the sizes in the header.
+        let mut bad = layout;
+        bad.folders[0].0.reserve.clear();
+        assert!(bad.to_bytes().is_err());
+    }
+}
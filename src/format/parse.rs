@@ -0,0 +1,327 @@
+//! Reading the records of a cabinet file.
+//!
+//! This is the other half of [`format`](crate::format): it reads records
+//! into the same owned types, without checking that they make sense
+//! together. [`lint_cab`](crate::lint_cab) does the checking.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    cfhdrNEXT_CABINET, cfhdrPREV_CABINET, cfhdrRESERVE_PRESENT, read_cstring, read_u16, read_u32,
+    read_u8, CabinetLink, DataBlock, FileEntry, Folder, Header, Layout,
+};
+use crate::CFDATA_SIZE;
+
+/// The header, folders and files of a cabinet, as read by [`parse_cab`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParsedCabinet {
+    /// The header.
+    pub header: Header,
+    /// The folders.
+    pub folders: Vec<Folder>,
+    /// The files.
+    pub files: Vec<FileEntry>,
+}
+
+/// Read the header, folders and files of the cabinet file at `cab_path`.
+pub fn parse_cab<T: AsRef<Path>>(cab_path: T) -> Result<ParsedCabinet> {
+    parse(BufReader::new(File::open(cab_path)?))
+}
+
+/// Read the header, folders and files of the cabinet in `reader`, which
+/// should be positioned at its start.
+pub fn parse<R: Read + Seek>(mut reader: R) -> Result<ParsedCabinet> {
+    let start = reader.stream_position()?;
+    let header = read_header(&mut reader)?;
+    let folders = (0..header.folder_count)
+        .map(|_| read_folder(&mut reader, header.folder_reserve_size))
+        .collect::<io::Result<Vec<_>>>()?;
+    reader.seek(SeekFrom::Start(start + u64::from(header.files_offset)))?;
+    let files = (0..header.file_count)
+        .map(|_| read_file_entry(&mut reader))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(ParsedCabinet {
+        header,
+        folders,
+        files,
+    })
+}
+
+/// Read the whole cabinet in `reader`, which should be positioned at its
+/// start, into a [`Layout`] that can be changed and written back.
+///
+/// Unlike [`parse`], this reads every data block, and fails if a block's
+/// checksum is wrong or a file's name isn't UTF-8, since writing the layout
+/// back would hide the damage.
+pub fn parse_layout<R: Read + Seek>(mut reader: R) -> Result<Layout> {
+    let start = reader.stream_position()?;
+    let header = read_header(&mut reader)?;
+    let folders = (0..header.folder_count)
+        .map(|_| read_folder(&mut reader, header.folder_reserve_size))
+        .collect::<io::Result<Vec<_>>>()?;
+    reader.seek(SeekFrom::Start(start + u64::from(header.files_offset)))?;
+    let mut files = Vec::with_capacity(usize::from(header.file_count));
+    for _ in 0..header.file_count {
+        let (file, raw_name) = read_file_entry_raw(&mut reader)?;
+        if file.name.as_bytes() != raw_name.as_slice() {
+            bail!("File name '{}' isn't UTF-8", file.name);
+        }
+        files.push(file);
+    }
+    let mut folders_with_blocks = Vec::with_capacity(folders.len());
+    for folder in folders {
+        reader.seek(SeekFrom::Start(start + u64::from(folder.data_offset)))?;
+        let mut blocks = Vec::with_capacity(usize::from(folder.data_count));
+        for _ in 0..folder.data_count {
+            let (csum, block) = read_data_block(&mut reader, header.data_reserve_size)?;
+            if csum != 0 && csum != block.checksum() {
+                bail!(
+                    "Bad checksum in data block at offset {}",
+                    reader.stream_position()? - start
+                );
+            }
+            blocks.push(block);
+        }
+        folders_with_blocks.push((folder, blocks));
+    }
+    Ok(Layout {
+        header,
+        folders: folders_with_blocks,
+        files,
+    })
+}
+
+/// Read a CFHEADER record, including the reserve and links that follow it.
+/// Names that aren't UTF-8 are converted lossily.
+pub fn read_header<R: Read>(r: &mut R) -> io::Result<Header> {
+    let mut signature = [0; 4];
+    r.read_exact(&mut signature)?;
+    if &signature != b"MSCF" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a cabinet file",
+        ));
+    }
+    let _reserved1 = read_u32(r)?;
+    let cabinet_size = read_u32(r)?;
+    let _reserved2 = read_u32(r)?;
+    let files_offset = read_u32(r)?;
+    let _reserved3 = read_u32(r)?;
+    let version_minor = read_u8(r)?;
+    let version_major = read_u8(r)?;
+    let folder_count = read_u16(r)?;
+    let file_count = read_u16(r)?;
+    let flags = read_u16(r)?;
+    let set_id = read_u16(r)?;
+    let cabinet_index = read_u16(r)?;
+    let (mut header_reserve, mut folder_reserve_size, mut data_reserve_size) = (vec![], 0, 0);
+    if flags & cfhdrRESERVE_PRESENT != 0 {
+        header_reserve = vec![0; usize::from(read_u16(r)?)];
+        folder_reserve_size = read_u8(r)?;
+        data_reserve_size = read_u8(r)?;
+        r.read_exact(&mut header_reserve)?;
+    }
+    let mut read_link = |flag| -> io::Result<Option<CabinetLink>> {
+        if flags & flag == 0 {
+            return Ok(None);
+        }
+        Ok(Some(CabinetLink {
+            cabinet: String::from_utf8_lossy(&read_cstring(r)?).into_owned(),
+            disk: String::from_utf8_lossy(&read_cstring(r)?).into_owned(),
+        }))
+    };
+    let prev = read_link(cfhdrPREV_CABINET)?;
+    let next = read_link(cfhdrNEXT_CABINET)?;
+    Ok(Header {
+        cabinet_size,
+        files_offset,
+        version_minor,
+        version_major,
+        folder_count,
+        file_count,
+        flags,
+        set_id,
+        cabinet_index,
+        header_reserve,
+        folder_reserve_size,
+        data_reserve_size,
+        prev,
+        next,
+    })
+}
+
+/// Read a CFFOLDER record with a `reserve_size` byte reserve.
+pub fn read_folder<R: Read>(r: &mut R, reserve_size: u8) -> io::Result<Folder> {
+    let data_offset = read_u32(r)?;
+    let data_count = read_u16(r)?;
+    let compression = read_u16(r)?;
+    let mut reserve = vec![0; usize::from(reserve_size)];
+    r.read_exact(&mut reserve)?;
+    Ok(Folder {
+        data_offset,
+        data_count,
+        compression,
+        reserve,
+    })
+}
+
+/// Read the `cbData` and `cbUncomp` fields of each of `folder`'s CFDATA
+/// records, skipping over their data, in a cabinet whose data blocks have
+/// `data_reserve_size` byte reserves.
+pub(crate) fn read_block_sizes<R: Read + Seek>(
+    r: &mut R,
+    folder: &Folder,
+    data_reserve_size: u8,
+) -> io::Result<Vec<(u16, u16)>> {
+    let mut offset = u64::from(folder.data_offset);
+    (0..folder.data_count)
+        .map(|_| {
+            // Skip the checksum.
+            r.seek(SeekFrom::Start(offset + 4))?;
+            let data_size = read_u16(r)?;
+            let uncompressed_size = read_u16(r)?;
+            offset += CFDATA_SIZE + u64::from(data_reserve_size) + u64::from(data_size);
+            Ok((data_size, uncompressed_size))
+        })
+        .collect()
+}
+
+/// Read the CFDATA record at the reader's position, with a `reserve_size`
+/// byte reserve, returning its `csum` field and the block.
+pub fn read_data_block<R: Read>(r: &mut R, reserve_size: u8) -> io::Result<(u32, DataBlock)> {
+    let csum = read_u32(r)?;
+    let data_size = read_u16(r)?;
+    let uncompressed_size = read_u16(r)?;
+    let mut reserve = vec![0; usize::from(reserve_size)];
+    r.read_exact(&mut reserve)?;
+    let mut data = vec![0; usize::from(data_size)];
+    r.read_exact(&mut data)?;
+    Ok((
+        csum,
+        DataBlock {
+            reserve,
+            data,
+            uncompressed_size,
+        },
+    ))
+}
+
+/// Read a CFFILE record. A name that isn't UTF-8 is converted lossily.
+pub fn read_file_entry<R: Read>(r: &mut R) -> io::Result<FileEntry> {
+    read_file_entry_raw(r).map(|(entry, _)| entry)
+}
+
+/// Read a CFFILE record, also returning the name exactly as stored.
+pub(crate) fn read_file_entry_raw<R: Read>(r: &mut R) -> io::Result<(FileEntry, Vec<u8>)> {
+    let size = read_u32(r)?;
+    let folder_offset = read_u32(r)?;
+    let folder = read_u16(r)?;
+    let date = read_u16(r)?;
+    let time = read_u16(r)?;
+    let attributes = read_u16(r)?;
+    let raw_name = read_cstring(r)?;
+    let name = String::from_utf8_lossy(&raw_name).into_owned();
+    let entry = FileEntry {
+        size,
+        folder_offset,
+        folder,
+        date,
+        time,
+        attributes,
+        name,
+    };
+    Ok((entry, raw_name))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::io::Cursor;
+
+    use self::tempdir::TempDir;
+    use super::{parse, parse_cab, parse_layout};
+    use crate::format::{
+        tcompTYPE_MSZIP, tcompTYPE_NONE, CabinetLink, DataBlock, FileEntry, Folder, Header, Layout,
+    };
+    use crate::make_cab_from_files;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn round_trip() {
+        let datetime = NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8);
+        let layout = Layout {
+            header: Header {
+                cabinet_index: 2,
+                header_reserve: vec![1, 2, 3],
+                folder_reserve_size: 1,
+                prev: Some(CabinetLink {
+                    cabinet: "a.cab".to_owned(),
+                    disk: "1".to_owned(),
+                }),
+                ..Header::default()
+            },
+            folders: vec![(
+                Folder {
+                    compression: tcompTYPE_NONE,
+                    reserve: vec![9],
+                    ..Folder::default()
+                },
+                vec![DataBlock::uncompressed(b"hello".to_vec())],
+            )],
+            files: vec![FileEntry::new("\u{e9}.txt", 5, 0, 0, datetime)],
+        };
+        let (header, folders) = layout.resolve().unwrap();
+        let parsed = parse(Cursor::new(layout.to_bytes().unwrap())).unwrap();
+        assert_eq!(
+            parsed.header,
+            Header {
+                flags: header.effective_flags(),
+                ..header
+            }
+        );
+        assert_eq!(parsed.folders, folders);
+        assert_eq!(parsed.files, layout.files);
+        assert!(parsed.files[0].is_name_utf());
+        // Reading the whole layout back gives the same bytes, but a bad
+        // checksum is caught.
+        let mut bytes = layout.to_bytes().unwrap();
+        let reparsed = parse_layout(Cursor::new(&bytes)).unwrap();
+        assert_eq!(reparsed.to_bytes().unwrap(), bytes);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(parse_layout(Cursor::new(&bytes)).is_err());
+
+        assert!(parse(Cursor::new(b"not a cab".to_vec())).is_err());
+    }
+
+    #[test]
+    fn parse_made_cab() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let a = t.path().join("a.txt");
+        let b = t.path().join("b.txt");
+        fs::write(&a, vec![b'a'; 50_000]).unwrap();
+        fs::write(&b, b"b").unwrap();
+        let cab = t.path().join("test.cab");
+        make_cab_from_files(&cab, &[&a, &b]).expect("failed to create cab file");
+        let parsed = parse_cab(&cab).expect("failed to parse cab file");
+        assert_eq!(
+            parsed.header.cabinet_size as u64,
+            fs::metadata(&cab).unwrap().len()
+        );
+        assert_eq!(parsed.folders.len(), 1);
+        assert_eq!(parsed.folders[0].compression, tcompTYPE_MSZIP);
+        let files = parsed
+            .files
+            .iter()
+            .map(|f| (f.name.as_str(), f.size, f.folder_offset))
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec![("a.txt", 50_000, 0), ("b.txt", 1, 50_000)]);
+    }
+}
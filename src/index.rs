@@ -0,0 +1,390 @@
+//! Indexing the data blocks of a cabinet for random access.
+//!
+//! CFDATA blocks only record their own sizes, so reading from the middle of
+//! a file in a cabinet normally means decompressing its folder from the
+//! start. A block index, written next to the cabinet as `<cab>.idx`, lists
+//! where each block starts in the cabinet and how much uncompressed data
+//! comes before it, so that a reader can jump straight to the blocks it
+//! needs. Indexes are only written for cabinets whose blocks are compressed
+//! independently of each other, so each block can be decompressed on its
+//! own.
+//!
+//! An index starts with the tag `MCBI`, a two byte version, a two byte
+//! folder count, the four byte size of the cabinet and the CRC-32 of its
+//! header, folder and file records, so an index that's out of date is
+//! ignored. Then for each folder there's a two byte block count followed by
+//! a pair of four byte values per block: the offset of its CFDATA record in
+//! the cabinet, and the offset of its first byte in the folder's
+//! uncompressed data.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use cab::Cabinet;
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+
+use crate::format::parse::{parse, read_block_sizes, read_data_block, ParsedCabinet};
+use crate::format::{read_u16, read_u32, tcompTYPE_MASK, tcompTYPE_MSZIP, tcompTYPE_NONE};
+use crate::CFDATA_SIZE;
+
+const INDEX_TAG: &[u8; 4] = b"MCBI";
+const INDEX_VERSION: u16 = 1;
+
+/// Where a data block is, in the cabinet and in its folder's data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BlockEntry {
+    /// The offset of the block's CFDATA record in the cabinet.
+    offset: u32,
+    /// The offset of the block's first byte in the folder's uncompressed
+    /// data.
+    folder_offset: u32,
+}
+
+/// The blocks of every folder in a cabinet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BlockIndex {
+    cab_size: u32,
+    records_crc32: u32,
+    folders: Vec<Vec<BlockEntry>>,
+}
+
+impl BlockIndex {
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = INDEX_TAG.to_vec();
+        bytes.extend_from_slice(&INDEX_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.folders.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.cab_size.to_le_bytes());
+        bytes.extend_from_slice(&self.records_crc32.to_le_bytes());
+        for blocks in &self.folders {
+            if blocks.len() > usize::from(u16::MAX) {
+                bail!("Too many blocks in folder");
+            }
+            bytes.extend_from_slice(&(blocks.len() as u16).to_le_bytes());
+            for block in blocks {
+                bytes.extend_from_slice(&block.offset.to_le_bytes());
+                bytes.extend_from_slice(&block.folder_offset.to_le_bytes());
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Read an index, returning `None` if it's not one this version
+    /// understands.
+    fn from_bytes(bytes: &[u8]) -> Option<BlockIndex> {
+        let mut r = bytes;
+        let mut tag = [0; 4];
+        r.read_exact(&mut tag).ok()?;
+        if &tag != INDEX_TAG || read_u16(&mut r).ok()? != INDEX_VERSION {
+            return None;
+        }
+        let folder_count = read_u16(&mut r).ok()?;
+        let cab_size = read_u32(&mut r).ok()?;
+        let records_crc32 = read_u32(&mut r).ok()?;
+        let folders = (0..folder_count)
+            .map(|_| {
+                let count = read_u16(&mut r)?;
+                (0..count)
+                    .map(|_| {
+                        Ok(BlockEntry {
+                            offset: read_u32(&mut r)?,
+                            folder_offset: read_u32(&mut r)?,
+                        })
+                    })
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .ok()?;
+        Some(BlockIndex {
+            cab_size,
+            records_crc32,
+            folders,
+        })
+    }
+}
+
+/// The path of the block index for the cabinet at `cab_path`.
+fn index_path(cab_path: &Path) -> PathBuf {
+    let mut path = OsString::from(cab_path);
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+/// The CRC-32 of the header, folder and file records of `cabinet`, which
+/// come before the first data block.
+fn records_crc32<R: Read + Seek>(reader: &mut R, cabinet: &ParsedCabinet) -> io::Result<u32> {
+    let end = cabinet
+        .folders
+        .iter()
+        .map(|folder| folder.data_offset)
+        .min()
+        .unwrap_or(cabinet.header.cabinet_size);
+    reader.seek(SeekFrom::Start(0))?;
+    let mut records = vec![];
+    reader.take(u64::from(end)).read_to_end(&mut records)?;
+    let mut crc = Crc::new();
+    crc.update(&records);
+    Ok(crc.sum())
+}
+
+/// Write a block index for the cabinet file at `cab_path` next to it,
+/// returning the path of the index.
+///
+/// The blocks of the cabinet must have been compressed independently of
+/// each other, which isn't checked.
+pub(crate) fn write_block_index(cab_path: &Path) -> Result<PathBuf> {
+    let mut reader = BufReader::new(File::open(cab_path)?);
+    let cabinet = parse(&mut reader)?;
+    let mut folders = vec![];
+    for folder in &cabinet.folders {
+        let sizes = read_block_sizes(&mut reader, folder, cabinet.header.data_reserve_size)?;
+        let mut offset = u64::from(folder.data_offset);
+        let mut folder_offset = 0u32;
+        let mut blocks = vec![];
+        for (data_size, uncompressed_size) in sizes {
+            blocks.push(BlockEntry {
+                offset: offset as u32,
+                folder_offset,
+            });
+            offset +=
+                CFDATA_SIZE + u64::from(cabinet.header.data_reserve_size) + u64::from(data_size);
+            folder_offset += u32::from(uncompressed_size);
+        }
+        folders.push(blocks);
+    }
+    let index = BlockIndex {
+        cab_size: cabinet.header.cabinet_size,
+        records_crc32: records_crc32(&mut reader, &cabinet)?,
+        folders,
+    };
+    let path = index_path(cab_path);
+    fs::write(&path, index.to_bytes()?)?;
+    Ok(path)
+}
+
+/// Move the block index for the cabinet file at `from`, if there is one,
+/// to go with the cabinet's new path `to`.
+pub(crate) fn rename_block_index(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(index_path(from), index_path(to)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Write the block index for the cabinet file at `cab_path` again after
+/// the cabinet has been changed, if it has one.
+pub(crate) fn refresh_block_index(cab_path: &Path) -> Result<()> {
+    if index_path(cab_path).exists() {
+        write_block_index(cab_path)?;
+    }
+    Ok(())
+}
+
+/// Read up to `len` bytes starting `offset` bytes into the file stored as
+/// `name` in the cabinet file at `cab_path`.
+///
+/// If the cabinet was written with [`CabOptions::block_index`], only the
+/// blocks holding the bytes asked for are read and decompressed. Otherwise
+/// the file is decompressed from the start of its folder. Fewer than `len`
+/// bytes are returned if the file ends first.
+///
+/// [`CabOptions::block_index`]: crate::CabOptions::block_index
+pub fn read_file_range<T: AsRef<Path>>(
+    cab_path: T,
+    name: &str,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>> {
+    let cab_path = cab_path.as_ref();
+    if let Some(data) = read_indexed_range(cab_path, name, offset, len)? {
+        return Ok(data);
+    }
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    if cabinet.get_file_entry(name).is_none() {
+        bail!("No file named '{}' in cabinet", name);
+    }
+    let mut reader = cabinet.read_file(name)?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![];
+    reader.take(len as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Like [`read_file_range`], but using the cabinet's block index, returning
+/// `None` if there isn't an up to date index that covers the file.
+fn read_indexed_range(
+    cab_path: &Path,
+    name: &str,
+    offset: u64,
+    len: usize,
+) -> Result<Option<Vec<u8>>> {
+    let index = match fs::read(index_path(cab_path)) {
+        Ok(bytes) => BlockIndex::from_bytes(&bytes),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let index = match index {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let mut reader = BufReader::new(File::open(cab_path)?);
+    let cabinet = parse(&mut reader)?;
+    if index.cab_size != cabinet.header.cabinet_size
+        || index.folders.len() != cabinet.folders.len()
+        || index.records_crc32 != records_crc32(&mut reader, &cabinet)?
+    {
+        return Ok(None);
+    }
+    let file = match cabinet.files.iter().find(|file| file.name == name) {
+        Some(file) => file,
+        None => bail!("No file named '{}' in cabinet", name),
+    };
+    let (folder, blocks) = match (
+        cabinet.folders.get(usize::from(file.folder)),
+        index.folders.get(usize::from(file.folder)),
+    ) {
+        (Some(folder), Some(blocks)) => (folder, blocks),
+        _ => return Ok(None),
+    };
+    let compression = folder.compression & tcompTYPE_MASK;
+    if compression != tcompTYPE_MSZIP && compression != tcompTYPE_NONE {
+        return Ok(None);
+    }
+
+    let file_end = u64::from(file.folder_offset) + u64::from(file.size);
+    let start = (u64::from(file.folder_offset) + offset).min(file_end);
+    let end = start.saturating_add(len as u64).min(file_end);
+    if start == end {
+        return Ok(Some(vec![]));
+    }
+    // The last block starting at or before `start`.
+    let first = blocks
+        .partition_point(|block| u64::from(block.folder_offset) <= start)
+        .saturating_sub(1);
+    let data_start = match blocks.get(first) {
+        Some(block) => u64::from(block.folder_offset),
+        None => bail!("Block index doesn't match the cabinet's data"),
+    };
+    let mut data = vec![];
+    for block in &blocks[first..] {
+        if u64::from(block.folder_offset) >= end {
+            break;
+        }
+        reader.seek(SeekFrom::Start(u64::from(block.offset)))?;
+        let (csum, block) = read_data_block(&mut reader, cabinet.header.data_reserve_size)?;
+        if csum != 0 && csum != block.checksum() {
+            bail!(
+                "Bad checksum in data block at offset {}",
+                reader.stream_position()?
+            );
+        }
+        let decompressed = if compression == tcompTYPE_MSZIP {
+            if !block.data.starts_with(b"CK") {
+                bail!("MSZIP block signature missing");
+            }
+            let mut decompressed = Vec::with_capacity(usize::from(block.uncompressed_size));
+            DeflateDecoder::new(&block.data[2..]).read_to_end(&mut decompressed)?;
+            decompressed
+        } else {
+            block.data
+        };
+        if decompressed.len() != usize::from(block.uncompressed_size) {
+            bail!(
+                "Expected {} bytes in data block but decompressed {}",
+                block.uncompressed_size,
+                decompressed.len()
+            );
+        }
+        data.extend_from_slice(&decompressed);
+    }
+    let from = (start - data_start.min(start)) as usize;
+    let to = (end - data_start.min(end)) as usize;
+    if data_start > start || to > data.len() {
+        bail!("Block index doesn't match the cabinet's data");
+    }
+    data.truncate(to);
+    data.drain(..from);
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+
+    use self::tempdir::TempDir;
+    use super::{index_path, read_file_range, read_indexed_range};
+    use crate::{make_cab_from_files_with_options, CabOptions};
+
+    #[test]
+    fn block_index() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let contents = [
+            (0..10_000u32)
+                .map(|i| (i * 7 % 251) as u8)
+                .collect::<Vec<_>>(),
+            vec![],
+            (0..5_000u32).map(|i| (i % 13) as u8).collect::<Vec<_>>(),
+        ];
+        let paths = contents
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                let path = t.path().join(format!("{}.bin", i));
+                fs::write(&path, data).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            block_index: true,
+            chunk_size: Some(1000),
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &paths, &options)
+            .expect("failed to create cab file");
+        assert!(index_path(&cab).exists());
+
+        let ranges = [
+            (0, 0),
+            (0, 10),
+            (999, 2),
+            (1500, 3000),
+            (9990, 100),
+            (20_000, 5),
+        ];
+        for (data, path) in contents.iter().zip(&paths) {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            for &(offset, len) in &ranges {
+                let expected = data
+                    .iter()
+                    .skip(offset)
+                    .take(len)
+                    .copied()
+                    .collect::<Vec<_>>();
+                let indexed = read_indexed_range(&cab, name, offset as u64, len)
+                    .expect("failed to read range")
+                    .expect("block index wasn't used");
+                assert_eq!(indexed, expected);
+                let read =
+                    read_file_range(&cab, name, offset as u64, len).expect("failed to read range");
+                assert_eq!(read, expected);
+            }
+        }
+        assert!(read_file_range(&cab, "missing.bin", 0, 1).is_err());
+
+        // An index for a different cabinet is ignored.
+        make_cab_from_files_with_options(&cab, &paths[..1], &CabOptions::default())
+            .expect("failed to create cab file");
+        assert!(index_path(&cab).exists());
+        assert_eq!(read_indexed_range(&cab, "0.bin", 0, 10).unwrap(), None);
+        assert_eq!(
+            read_file_range(&cab, "0.bin", 5000, 10).unwrap(),
+            &contents[0][5000..5010]
+        );
+    }
+}
@@ -1,66 +1,1862 @@
 //! A simple Microsoft cabinet compressor.
 //!
-//! Only supports writing a single file to a single folder.
-//! Cabinet format structures derived from the [Microsoft Cabinet File Format]
-//! documentation.
+//! [`make_cab_from_files_with_options`] and friends write files to a single
+//! folder, compressed with MSZIP by default or with any
+//! [`FolderCompressor`], such as [`NoneCompressor`] for uncompressed
+//! folders. [`CabOptions::split`] spreads the data over a linked set of
+//! cabinets instead, which [`join_cabs`] puts back together. Cabinets of any
+//! other shape, such as one with several folders compressed different ways,
+//! can be put together record by record with [`format::Layout`].
+//!
+//! Any cabinet the `cab` crate understands can be read back and extracted,
+//! along with linked sets of them, and diffed, edited or checked against
+//! the files it was made from. Cabinet format structures are derived from
+//! the [Microsoft Cabinet File Format] documentation.
 //!
 //! [Microsoft Cabinet File Format]: https://msdn.microsoft.com/en-us/library/bb417343.aspx#cabinet_format
 
 #![allow(non_camel_case_types, non_snake_case)]
 
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt::Write as _;
 use std::fs::File;
 use std::io;
-use std::path::Path;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Result};
-use cab::{CabinetBuilder, CompressionType};
-use chrono::NaiveDateTime;
+use cab::{Cabinet, CabinetBuilder, CompressionType};
+use chrono::{NaiveDateTime, Utc};
 use filetime::FileTime;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, CrcReader};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+mod codepage;
+mod compressor;
+mod convert;
+mod diff;
+mod driver;
+mod edit;
+mod extract;
+mod filter;
+pub mod format;
+mod index;
+mod lint;
+mod list;
+mod manifest;
+mod msi;
+mod pipeline;
+mod pool;
+mod rebuild;
+mod remove;
+mod reserve;
+mod retry;
+mod split;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+mod writer;
+
+pub use codepage::Codepage;
+pub use compressor::{BlockEncoder, FolderCompressor, MsZipCompressor, NoneCompressor};
+pub use convert::{cab_to_zip, zip_to_cab};
+pub use diff::{
+    diff_cab_dir, diff_cabs, make_diff_cab, verify_against_dir, CabDiff, CabsDiff, MemberChange,
+    Mismatch, MismatchKind,
+};
+pub use driver::make_driver_cab;
+pub use edit::{append_files, rename_file, set_file_datetimes};
+pub use extract::{
+    expand_file, extract_cab, extract_file_to, extract_files, extract_files_with_locator,
+    extract_files_with_overwrite, plan_extraction, salvage_cab, Overwrite, PlannedFile,
+};
+pub use filter::PathFilter;
+pub use index::read_file_range;
+pub use lint::{lint_cab, Violation};
+pub use list::{list_cab, CabListing, FileListing, FolderListing};
+pub use manifest::{make_cabs_from_manifest, Attribute, EntryCompression, Manifest, ManifestEntry};
+pub use msi::{make_msi_media_cab, MsiFile};
+pub use pool::{CompressorPool, PooledCompressor};
+pub use rebuild::{rebuild_cab, RecoveredFolder};
+pub use remove::remove_files;
+pub use reserve::{
+    read_data_reserves, read_reserve_data, write_folder_reserve, write_header_reserve, DataReserve,
+    ReserveData,
+};
+pub use retry::RetryPolicy;
+pub use split::{join_cabs, split_cab, split_cab_with_options, SplitOptions};
+pub use writer::CabWriter;
+
+use reserve::{read_reserve, Reserve, Timestamps, HEADER_RESERVE_OFFSET};
+use retry::RetryingInput;
+
+/// The maximum amount of uncompressed data in a single CFDATA block.
+const MAX_CHUNK: usize = 32 * 1024;
+
+/// Sizes of the fixed-length parts of the cabinet structures.
+const CFHEADER_SIZE: u64 = 36;
+const CFFOLDER_SIZE: u64 = 8;
+const CFFILE_SIZE: u64 = 16;
+const CFDATA_SIZE: u64 = 8;
+/// The largest header reserve the specification allows.
+const MAX_HEADER_RESERVE: u16 = 60_000;
+
+/// The start of the names of the temporary files cabinets are written to
+/// before being moved into place.
+pub const TEMP_FILE_PREFIX: &str = ".makecab";
 
 /// Write a cabinet file at `cab_path` containing the single file `input_path`.
-pub fn make_cab<T: AsRef<Path>, U: AsRef<Path>>(cab_path: T, input_path: U) -> Result<()> {
-    let mut input = File::open(input_path.as_ref())?;
-    let input_filename = match input_path.as_ref().file_name().and_then(|n| n.to_str()) {
-        Some(name) => name,
-        None => bail!("Bad filename: '{}'", input_path.as_ref().to_string_lossy()),
+///
+/// An empty file is stored as a 0-byte file, which takes up no compressed
+/// data. A folder holding nothing but empty files has no data blocks at
+/// all, rather than an empty one, which is what extractors expect.
+pub fn make_cab<T: AsRef<Path>, U: AsRef<Path>>(cab_path: T, input_path: U) -> Result<CabSummary> {
+    make_cab_from_files(cab_path, &[input_path])
+}
+
+/// Write a cabinet file at `cab_path` containing the files `input_paths`.
+///
+/// The files are stored in a single MSZIP folder, in the order given, each
+/// under its own file name.
+pub fn make_cab_from_files<T: AsRef<Path>, U: AsRef<Path>>(
+    cab_path: T,
+    input_paths: &[U],
+) -> Result<CabSummary> {
+    make_cab_from_files_with_options(cab_path, input_paths, &CabOptions::default())
+}
+
+/// Like [`make_cab_from_files`], but with `options` controlling how the
+/// cabinet is written.
+pub fn make_cab_from_files_with_options<T: AsRef<Path>, U: AsRef<Path>>(
+    cab_path: T,
+    input_paths: &[U],
+    options: &CabOptions,
+) -> Result<CabSummary> {
+    let start = Instant::now();
+    let files = input_paths
+        .iter()
+        .map(|path| Ok((stored_name(path.as_ref())?, path.as_ref())))
+        .collect::<Result<Vec<_>>>()?;
+    write_named_files(cab_path.as_ref(), &files, options, start)
+}
+
+/// Write a cabinet file at `cab_path` containing the files in the directory
+/// tree `dir` that `filter` keeps, with `options` controlling how it's
+/// written.
+///
+/// Each file is stored under its path relative to `dir`, with `\` between
+/// components, so `bin\app.pdb` stays `bin\app.pdb` in the cabinet. If
+/// `cab_path` is inside `dir`, it isn't packed into itself.
+pub fn make_cab_from_dir<T: AsRef<Path>, U: AsRef<Path>>(
+    cab_path: T,
+    dir: U,
+    filter: &PathFilter,
+    options: &CabOptions,
+) -> Result<CabSummary> {
+    let start = Instant::now();
+    let cab_path = cab_path.as_ref();
+    let files = filtered_dir_files(dir.as_ref(), filter, Some(cab_path))?;
+    let files = files
+        .iter()
+        .map(|(name, path)| (name.as_str(), path.as_path()))
+        .collect::<Vec<_>>();
+    write_named_files(cab_path, &files, options, start)
+}
+
+/// The files in the directory tree `dir` that `filter` keeps, as
+/// `(name, path)` with names relative to `dir`, leaving out `own_path`, the
+/// cabinet being written, if it's inside `dir`.
+fn filtered_dir_files(
+    dir: &Path,
+    filter: &PathFilter,
+    own_path: Option<&Path>,
+) -> Result<Vec<(String, PathBuf)>> {
+    let own_path = own_path.and_then(|path| path.canonicalize().ok());
+    let files = diff::dir_files(dir)?
+        .into_iter()
+        .filter(|(name, path)| {
+            filter.matches(name) && (own_path.is_none() || path.canonicalize().ok() != own_path)
+        })
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        bail!("No files to pack in {:?}", dir);
+    }
+    Ok(files)
+}
+
+/// Write a cabinet file at `cab_path` containing the files in `files`, each
+/// a name to store it under and a path to read it from, as
+/// [`with_named_inputs`] does.
+fn write_named_files(
+    cab_path: &Path,
+    files: &[(&str, &Path)],
+    options: &CabOptions,
+    start: Instant,
+) -> Result<CabSummary> {
+    let (mut summary, renamed) = with_named_inputs(files, options, |inputs| {
+        write_cab_file(cab_path, inputs, options, start)
+    })?;
+    for file in &mut summary.files {
+        if let Some(original) = renamed.get(&file.name) {
+            file.renamed_from = Some(original.clone());
+        }
+    }
+    Ok(summary)
+}
+
+/// Pass `write` the inputs for `files`, each a name to store it under and a
+/// path to read it from, with separators as [`CabOptions::separator`] says
+/// and handling names that would collide as [`CabOptions::duplicate_names`]
+/// says. Returns what `write` does, along with the original names of any
+/// renamed files, keyed by their new names.
+fn with_named_inputs<T, F>(
+    files: &[(&str, &Path)],
+    options: &CabOptions,
+    write: F,
+) -> Result<(T, HashMap<String, String>)>
+where
+    F: FnOnce(&[Input]) -> Result<T>,
+{
+    let names = files
+        .iter()
+        .map(|(name, _)| options.separator.apply(name))
+        .collect::<Vec<_>>();
+    let files = names
+        .iter()
+        .zip(files)
+        .map(|(name, &(_, path))| (&**name, path))
+        .collect::<Vec<_>>();
+    let renamed = options.duplicate_names.resolve(&files)?;
+    let inputs = files
+        .iter()
+        .enumerate()
+        .map(|(i, &(name, path))| match renamed.get(&i) {
+            Some(new_name) => Input::with_name(path, new_name),
+            None => Input::with_name(path, name),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let written = write(&inputs)?;
+    let original_names = renamed
+        .iter()
+        .map(|(&i, new_name)| (new_name.clone(), files[i].0.to_owned()))
+        .collect();
+    Ok((written, original_names))
+}
+
+/// Write one cabinet file per `(cab_path, input_path)` pair in `jobs`, as
+/// [`make_cab`] would.
+///
+/// The cabinets are compressed in parallel on rayon's global thread pool,
+/// which makes packaging thousands of small files (like symbol files for a
+/// symbol server) scale across cores. A failure in one job doesn't stop the
+/// others; the result of each job is returned in the same order as `jobs`.
+pub fn make_cabs<T, U>(jobs: &[(T, U)]) -> Vec<Result<CabSummary>>
+where
+    T: AsRef<Path> + Sync,
+    U: AsRef<Path> + Sync,
+{
+    jobs.par_iter()
+        .map(|(cab_path, input_path)| make_cab(cab_path, input_path))
+        .collect()
+}
+
+/// What was written by [`make_cab`] and [`make_cab_from_files`].
+#[derive(Clone, Debug)]
+pub struct CabSummary {
+    /// The path of the cabinet file.
+    pub path: PathBuf,
+    /// The size of the cabinet file, or of every cabinet in the set with
+    /// [`CabOptions::split`].
+    pub cab_size: u64,
+    /// The cabinets written with [`CabOptions::split`], in order, in which
+    /// case `path` is only what they're named after. Empty if the cabinet
+    /// wasn't split.
+    pub cabinets: Vec<PathBuf>,
+    /// One summary per file in the cabinet, in the order they're stored.
+    pub files: Vec<FileSummary>,
+    /// One summary per block of compressed data, in the order they're
+    /// stored, counting the totals through the whole set with
+    /// [`CabOptions::split`].
+    pub blocks: Vec<BlockSummary>,
+    /// How long writing the cabinet took.
+    pub elapsed: Duration,
+}
+
+impl CabSummary {
+    /// The total size of the files in the cabinet before compression.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+/// What was written for a single CFDATA block in a cabinet, for logging
+/// or for adjusting how later cabinets are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockSummary {
+    /// The index of the folder the block is in.
+    pub folder: u16,
+    /// The size of the block's data before compression.
+    pub uncompressed_size: u16,
+    /// The size of the block's compressed data, not counting its CFDATA
+    /// header.
+    pub compressed_size: u16,
+    /// The total size before compression of this block and every block
+    /// before it in the cabinet.
+    pub total_uncompressed: u64,
+    /// The total size of the compressed data in this block and every block
+    /// before it in the cabinet.
+    pub total_compressed: u64,
+}
+
+/// Read the sizes of every block in the cabinet file at `cab_path`.
+fn block_summaries(cab_path: &Path) -> Result<Vec<BlockSummary>> {
+    let mut reader = io::BufReader::new(File::open(cab_path)?);
+    let cabinet = format::parse::parse(&mut reader)?;
+    let (mut total_uncompressed, mut total_compressed) = (0, 0);
+    let mut blocks = vec![];
+    for (i, folder) in cabinet.folders.iter().enumerate() {
+        let sizes =
+            format::parse::read_block_sizes(&mut reader, folder, cabinet.header.data_reserve_size)?;
+        for (compressed_size, uncompressed_size) in sizes {
+            total_uncompressed += u64::from(uncompressed_size);
+            total_compressed += u64::from(compressed_size);
+            blocks.push(BlockSummary {
+                folder: i as u16,
+                uncompressed_size,
+                compressed_size,
+                total_uncompressed,
+                total_compressed,
+            });
+        }
+    }
+    Ok(blocks)
+}
+
+/// What was written for a single file in a cabinet.
+#[derive(Clone, Debug)]
+pub struct FileSummary {
+    /// The name the file is stored under.
+    pub name: String,
+    /// The size of the file before compression.
+    pub size: u64,
+    /// The amount of compressed data written while compressing the file.
+    /// Compressed blocks can hold data from more than one file, so this is
+    /// only approximately the space the file takes up, but the sizes of all
+    /// the files add up to the size of all the compressed data.
+    pub compressed_size: u64,
+    /// The name the file would have been stored under, if it was renamed
+    /// because another file already had it.
+    pub renamed_from: Option<String>,
+}
+
+/// Options for writing cabinet files.
+#[derive(Clone, Debug, Default)]
+pub struct CabOptions {
+    /// The most memory, in bytes, to spend buffering data while writing a
+    /// cabinet. With [`CabOptions::threads`], fewer compressor threads and
+    /// shorter queues between them are used to stay within it, down to a
+    /// single thread with one block in flight. A cabinet being written to a
+    /// non-seekable output is only built in memory if it's sure to fit,
+    /// leaving the rest for compressing, and otherwise spooled to a
+    /// temporary file. `None` means no limit.
+    pub memory_limit: Option<u64>,
+    /// On Windows, if an input file can't be opened normally, try again
+    /// with `FILE_FLAG_BACKUP_SEMANTICS`. Run from an account holding the
+    /// backup privilege, this reads files whose permissions would otherwise
+    /// deny access, like logs and DLLs held open by services. Inputs are
+    /// always opened sharing read, write and delete access, so files other
+    /// processes have open for writing can be read either way. Ignored on
+    /// other platforms.
+    pub open_locked_files: bool,
+    /// Also store each file's modification and creation time at full
+    /// precision in the cabinet's header reserve, which extracting with this
+    /// crate restores. Other extractors ignore them and use the usual DOS
+    /// timestamps, which have a two second resolution. This only has room
+    /// for a few thousand files.
+    pub high_res_timestamps: bool,
+    /// Also store the CRC-32 of each file's contents in the cabinet's header
+    /// reserve, which [`verify_cab`] and extracting with this crate check.
+    /// The CFDATA checksums only cover each block of compressed data, so
+    /// this also catches data that was corrupted before it was compressed.
+    pub crc32: bool,
+    /// Bytes to store in the cabinet's header reserve, which can be read
+    /// back with [`read_reserve_data`] and overwritten with
+    /// [`write_header_reserve`], for things like signatures or indexes. Can't
+    /// be combined with `high_res_timestamps` or `crc32`, which store their
+    /// own data there.
+    pub header_reserve: Vec<u8>,
+    /// Bytes to store in the folder's reserve, which can be read back with
+    /// [`read_reserve_data`] and overwritten with [`write_folder_reserve`].
+    pub folder_reserve: Vec<u8>,
+    /// Fills in a reserve of the same size in every CFDATA block as the
+    /// blocks are compressed, which can be read back with
+    /// [`read_data_reserves`]. Blocks are then compressed independently of
+    /// each other, like with `threads`.
+    pub data_reserve: Option<Arc<dyn DataReserve>>,
+    /// A flag that another thread can set to cancel writing the cabinet.
+    /// It's checked every time more data is read from the input files, and
+    /// once it's set writing fails with [`Cancelled`].
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// How to retry opening and reading input files after transient errors,
+    /// like a network share briefly dropping out. `None` means any error
+    /// fails writing the cabinet straight away.
+    pub retry: Option<RetryPolicy>,
+    /// Flush the cabinet file to disk before moving it into place, and the
+    /// directory holding it afterwards, so that once writing it returns the
+    /// cabinet survives a crash or power cut. With `split`, every cabinet
+    /// in the set is flushed. Ignored when writing to a [`Write`] rather
+    /// than a path.
+    pub durable: bool,
+    /// Reserve disk space for the cabinet file before writing it, enough for
+    /// the worst case where nothing compresses, so that a disk that's too
+    /// full fails straight away rather than part way through, and the file
+    /// is less fragmented. Space that turns out not to be needed is released
+    /// once the cabinet is written. Only supported on Linux and Windows, and
+    /// ignored when writing to a [`Write`] rather than a path.
+    pub preallocate: bool,
+    /// Compress on this many threads, with another thread reading the input
+    /// files and the calling thread writing the cabinet, so that reading,
+    /// compressing and writing all overlap even for a single large input.
+    /// Blocks are compressed independently of each other, which costs a
+    /// little compression. 0, the default, compresses on the calling
+    /// thread.
+    pub threads: usize,
+    /// The most data to put in each compressed block, from 1 to 32768
+    /// bytes. Smaller blocks compress less well, but take less time to
+    /// compress and decompress on their own, so readers seeking to a
+    /// position in a file have less to decompress first. Blocks are then
+    /// compressed independently of each other, like with `threads`. `None`
+    /// means the maximum of 32768 bytes.
+    pub chunk_size: Option<usize>,
+    /// Also write a block index next to the cabinet, as `<cab>.idx`, which
+    /// [`read_file_range`] uses to read part of a file without
+    /// decompressing everything before it. Blocks are then compressed
+    /// independently of each other, like with `threads`. Ignored when
+    /// writing to a [`Write`] rather than a path.
+    pub block_index: bool,
+    /// The order to store the files in.
+    pub order: MemberOrder,
+    /// The deflate compression level to compress MSZIP blocks at, from 0,
+    /// which stores the data without compressing it, to 9, which compresses
+    /// best but slowest. Blocks are then compressed independently of each
+    /// other, like with `threads`. `None` means flate2's default level, 6.
+    pub compression_level: Option<u32>,
+    /// The codec to compress the folder's blocks with, instead of MSZIP
+    /// at `compression_level`. Blocks are then compressed independently of
+    /// each other, like with `threads`.
+    pub compressor: Option<Arc<dyn FolderCompressor>>,
+    /// Store names in this DOS code page instead of UTF-8, for old
+    /// extractors that read names in the OEM code page. Writing fails if a
+    /// name has a character the code page doesn't. Blocks are then
+    /// compressed independently of each other, like with `threads`.
+    pub name_codepage: Option<Codepage>,
+    /// What to do when two files would be stored under the same name.
+    pub duplicate_names: DuplicateNames,
+    /// The separator to store between the components of names with paths.
+    pub separator: PathSeparator,
+    /// Where the timestamp stored for each file comes from.
+    pub timestamps: TimestampSource,
+    /// Spread the cabinet over a linked set of cabinets, named as
+    /// [`split_cab_with_options`] names them, so files too large for one
+    /// cabinet continue from one to the next. The set is written instead of
+    /// the cabinet, and listed in [`CabSummary::cabinets`]. Each cabinet is
+    /// started as soon as the one before it is full, with a folder of its
+    /// own, so only each cabinet, rather than all the data, has to fit in a
+    /// folder. Blocks are then compressed independently of each other, like
+    /// with `threads`. Can't be used with `block_index` or `preallocate`,
+    /// or when writing to a [`Write`] rather than a path.
+    pub split: Option<SplitOptions>,
+}
+
+/// The order to store files in a cabinet, set through
+/// [`CabOptions::order`].
+///
+/// Files that sort the same are ordered by name, so apart from `Input`,
+/// the order doesn't depend on the order the files were given in, which
+/// can vary between runs when they come from a glob or a directory listing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemberOrder {
+    /// The order the files were given in.
+    #[default]
+    Input,
+    /// By the name each file is stored under, comparing bytes.
+    Name,
+    /// By size, smallest first.
+    Size,
+    /// By extension, ignoring ASCII case, so similar files are compressed
+    /// next to each other. Files without an extension come first.
+    Extension,
+}
+
+impl MemberOrder {
+    /// Sort `inputs` into this order.
+    fn sort(self, inputs: &mut [&Input]) {
+        match self {
+            MemberOrder::Input => {}
+            MemberOrder::Name => inputs.sort_by_key(|input| input.name),
+            MemberOrder::Size => inputs.sort_by_key(|input| (input.size, input.name)),
+            MemberOrder::Extension => {
+                inputs.sort_by_cached_key(|input| (extension(input.name), input.name))
+            }
+        }
+    }
+}
+
+/// Where the timestamps stored for files come from, set through
+/// [`CabOptions::timestamps`].
+///
+/// Files in a fresh checkout are all modified when they were checked out,
+/// which says nothing about the files themselves, so `Now` or `Fixed` can
+/// be more useful, and `Fixed` makes cabinets that don't change from one
+/// build to the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// When each file was last modified.
+    #[default]
+    Modified,
+    /// When each file was created, or when it was last modified on
+    /// platforms and file systems that don't record that.
+    Created,
+    /// When the cabinet is written, the same for every file.
+    Now,
+    /// The given time, in UTC, for every file.
+    Fixed(NaiveDateTime),
+}
+
+impl TimestampSource {
+    /// The timestamp to store for `input` in a cabinet written at `now`.
+    fn timestamp(self, input: &Input, now: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            TimestampSource::Modified => input.mtime,
+            TimestampSource::Created => input.created.map_or(input.mtime, |created| {
+                NaiveDateTime::from_timestamp(created.unix_seconds(), created.nanoseconds())
+            }),
+            TimestampSource::Now => now,
+            TimestampSource::Fixed(datetime) => datetime,
+        }
+    }
+}
+
+/// The separator to store between the directories and file name in a
+/// stored name like `bin\\app.pdb`, set through [`CabOptions::separator`].
+///
+/// Extracting with this crate accepts either.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathSeparator {
+    /// `\\`, which is what Windows extractors expect, replacing any `/`.
+    #[default]
+    Backslash,
+    /// `/`, replacing any `\\`, for extractors that only split names on
+    /// `/`.
+    Slash,
+    /// Whichever separators the names were given with.
+    Preserve,
+}
+
+impl PathSeparator {
+    /// The stored name `name` with this separator.
+    fn apply(self, name: &str) -> Cow<'_, str> {
+        let (from, to) = match self {
+            PathSeparator::Backslash => ('/', "\\"),
+            PathSeparator::Slash => ('\\', "/"),
+            PathSeparator::Preserve => return Cow::Borrowed(name),
+        };
+        if name.contains(from) {
+            Cow::Owned(name.replace(from, to))
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+}
+
+/// What to do when two files would be stored under the same name in a
+/// cabinet, set through [`CabOptions::duplicate_names`].
+///
+/// Names are compared ignoring case, with `/` the same as `\\`, since that's
+/// how Windows compares paths when extracting. Otherwise one of the files
+/// would silently replace the other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateNames {
+    /// Fail, naming the files that collide.
+    #[default]
+    Error,
+    /// Store the later files under a numbered name like `a (2).txt`,
+    /// recording the name they would have had in
+    /// [`FileSummary::renamed_from`].
+    Rename,
+}
+
+impl DuplicateNames {
+    /// Check the names `files` would be stored under, returning new names
+    /// for the files that need renaming, by their index in `files`.
+    fn resolve(self, files: &[(&str, &Path)]) -> Result<HashMap<usize, String>> {
+        let key = |name: &str| name.replace('/', "\\").to_lowercase();
+        let mut taken = files
+            .iter()
+            .map(|(name, _)| key(name))
+            .collect::<HashSet<_>>();
+        let mut first = HashMap::new();
+        let mut renamed = HashMap::new();
+        for (i, &(name, path)) in files.iter().enumerate() {
+            let other = match first.entry(key(name)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(path);
+                    continue;
+                }
+                Entry::Occupied(entry) => *entry.get(),
+            };
+            if self == DuplicateNames::Error {
+                bail!(
+                    "{:?} and {:?} would both be stored as '{}'",
+                    other,
+                    path,
+                    name
+                );
+            }
+            let new_name = (2..)
+                .map(|n| numbered_name(name, n))
+                .find(|new_name| !taken.contains(&key(new_name)))
+                .unwrap();
+            taken.insert(key(&new_name));
+            renamed.insert(i, new_name);
+        }
+        Ok(renamed)
+    }
+}
+
+/// The stored name `name` with ` (n)` added before its extension.
+fn numbered_name(name: &str, n: usize) -> String {
+    let file_start = name.rfind(['\\', '/']).map_or(0, |i| i + 1);
+    match name[file_start..].rfind('.') {
+        Some(i) if i > 0 => {
+            let (stem, ext) = name.split_at(file_start + i);
+            format!("{} ({}){}", stem, n, ext)
+        }
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+/// The extension of the stored name `name`, in lowercase, or an empty
+/// string if it doesn't have one.
+fn extension(name: &str) -> String {
+    let file_name = name.rsplit('\\').next().unwrap_or(name);
+    match file_name.rfind('.') {
+        Some(i) if i > 0 => file_name[i + 1..].to_ascii_lowercase(),
+        _ => String::new(),
+    }
+}
+
+impl CabOptions {
+    /// The size of the blocks to compress, checking that it's one the
+    /// format allows.
+    fn chunk_size(&self) -> Result<usize> {
+        match self.chunk_size {
+            None => Ok(MAX_CHUNK),
+            Some(size) if (1..=MAX_CHUNK).contains(&size) => Ok(size),
+            Some(size) => bail!(
+                "Chunk size must be from 1 to {} bytes, not {}",
+                MAX_CHUNK,
+                size
+            ),
+        }
+    }
+
+    /// The codec to compress blocks with.
+    fn folder_compressor(&self) -> Result<Arc<dyn FolderCompressor>> {
+        Ok(match &self.compressor {
+            Some(compressor) => compressor.clone(),
+            None => Arc::new(MsZipCompressor::new(self.compression()?)),
+        })
+    }
+
+    /// The level to compress blocks at, checking that it's one deflate
+    /// supports.
+    fn compression(&self) -> Result<Compression> {
+        match self.compression_level {
+            None => Ok(Compression::default()),
+            Some(level) if level <= 9 => Ok(Compression::new(level)),
+            Some(level) => bail!("Compression level must be from 0 to 9, not {}", level),
+        }
+    }
+}
+
+/// The error writing a cabinet fails with when it's cancelled through
+/// [`CabOptions::cancel`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// The error writing a cabinet fails with when what would be written
+/// doesn't fit in the cabinet format, which is checked before anything is
+/// compressed. Otherwise the counts and sizes in the cabinet's 16-bit
+/// fields would wrap around, and extractors would misread it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// More files than the 65535 a cabinet can hold.
+    TooManyFiles(usize),
+    /// A stored name longer than the 255 bytes a cabinet can hold.
+    NameTooLong(String),
+    /// More bytes of data than the 65535 blocks in a folder can hold.
+    FolderTooLarge(u64),
+    /// A header reserve larger than the 60000 bytes a cabinet can hold,
+    /// counting the tables stored for [`CabOptions::high_res_timestamps`]
+    /// and [`CabOptions::crc32`].
+    HeaderReserveTooLarge(usize),
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LimitExceeded::TooManyFiles(count) => write!(
+                f,
+                "{} files won't fit in a cabinet, which holds at most {}; split them between \
+                 several cabinets, for example with a manifest",
+                count,
+                u16::MAX
+            ),
+            LimitExceeded::NameTooLong(name) => write!(
+                f,
+                "{:?} is longer than the {} bytes a cabinet can store as a name",
+                name,
+                format::MAX_STRING - 1
+            ),
+            LimitExceeded::FolderTooLarge(size) => write!(
+                f,
+                "{} bytes of data won't fit in a cabinet folder, which holds at most {} blocks; \
+                 use larger blocks, or split the files between several cabinets, for example \
+                 with a manifest",
+                size,
+                u16::MAX
+            ),
+            LimitExceeded::HeaderReserveTooLarge(size) => write!(
+                f,
+                "The cabinet header's reserve would be {} bytes, more than the {} allowed; store \
+                 fewer files in each cabinet, for example with a manifest, or don't store high \
+                 resolution timestamps or CRC-32s",
+                size, MAX_HEADER_RESERVE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Check that files stored as `names`, with `size` bytes of data between
+/// them if that's known yet, fit in a cabinet with a single folder whose
+/// blocks hold `chunk_size` bytes.
+fn check_limits<'a, I>(names: I, size: Option<u64>, chunk_size: usize) -> Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut count = 0;
+    for name in names {
+        if name.len() + 1 > format::MAX_STRING {
+            return Err(LimitExceeded::NameTooLong(name.to_owned()).into());
+        }
+        count += 1;
+    }
+    if count > usize::from(u16::MAX) {
+        return Err(LimitExceeded::TooManyFiles(count).into());
+    }
+    match size {
+        Some(size) if size.div_ceil(chunk_size as u64) > u64::from(u16::MAX) => {
+            Err(LimitExceeded::FolderTooLarge(size).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A reader that fails with [`Cancelled`] once `cancel` is set.
+struct CancelReader<'a, R> {
+    inner: R,
+    cancel: &'a AtomicBool,
+}
+
+impl<'a, R: Read> Read for CancelReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::other(Cancelled));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Write a cabinet containing the single file `input_path` to `writer`,
+/// returning the writer once the cabinet is complete.
+///
+/// Unlike [`make_cab`], `writer` doesn't need to be seekable, so this can
+/// write to pipes, sockets, or streaming uploads. The cabinet header records
+/// sizes and offsets that are only known once everything has been
+/// compressed, so the whole cabinet is built in memory before being written.
+pub fn make_cab_to_writer<W: Write, U: AsRef<Path>>(writer: W, input_path: U) -> Result<W> {
+    make_cab_to_writer_with_options(writer, input_path, &CabOptions::default())
+}
+
+/// Like [`make_cab_to_writer`], but with `options` controlling how the
+/// cabinet is written. If the cabinet might not fit in
+/// [`CabOptions::memory_limit`] it is built in a temporary file instead of in
+/// memory.
+pub fn make_cab_to_writer_with_options<W: Write, U: AsRef<Path>>(
+    writer: W,
+    input_path: U,
+    options: &CabOptions,
+) -> Result<W> {
+    make_cab_from_files_to_writer(writer, &[input_path], options)
+}
+
+/// Like [`make_cab_from_files_with_options`], but writing the cabinet to
+/// `writer` as [`make_cab_to_writer_with_options`] does.
+pub fn make_cab_from_files_to_writer<W: Write, U: AsRef<Path>>(
+    writer: W,
+    input_paths: &[U],
+    options: &CabOptions,
+) -> Result<W> {
+    let files = input_paths
+        .iter()
+        .map(|path| Ok((stored_name(path.as_ref())?, path.as_ref())))
+        .collect::<Result<Vec<_>>>()?;
+    let (writer, _) = with_named_inputs(&files, options, |inputs| {
+        write_cab_to_writer(writer, inputs, options)
+    })?;
+    Ok(writer)
+}
+
+/// Like [`make_cab_from_dir`], but writing the cabinet to `writer` as
+/// [`make_cab_to_writer_with_options`] does.
+pub fn make_cab_from_dir_to_writer<W: Write, U: AsRef<Path>>(
+    writer: W,
+    dir: U,
+    filter: &PathFilter,
+    options: &CabOptions,
+) -> Result<W> {
+    let files = filtered_dir_files(dir.as_ref(), filter, None)?;
+    let files = files
+        .iter()
+        .map(|(name, path)| (name.as_str(), path.as_path()))
+        .collect::<Vec<_>>();
+    let (writer, _) = with_named_inputs(&files, options, |inputs| {
+        write_cab_to_writer(writer, inputs, options)
+    })?;
+    Ok(writer)
+}
+
+/// Write a cabinet containing `inputs` to `writer`, which doesn't need to be
+/// seekable, building it in memory or, if it might not fit in
+/// [`CabOptions::memory_limit`], in a temporary file.
+fn write_cab_to_writer<W: Write>(
+    mut writer: W,
+    inputs: &[Input],
+    options: &CabOptions,
+) -> Result<W> {
+    if options.split.is_some() {
+        bail!("A cabinet written to a stream can't be split into a set");
+    }
+    let max_size = max_cab_size(inputs, options.chunk_size()?);
+    match options.memory_limit {
+        Some(limit) if max_size > limit => {
+            let (mut spool, _) = write_cab(tempfile::tempfile()?, inputs, options)?;
+            spool.seek(io::SeekFrom::Start(0))?;
+            io::copy(&mut spool, &mut writer)?;
+        }
+        limit => {
+            let options = CabOptions {
+                memory_limit: limit.map(|limit| limit - max_size),
+                ..options.clone()
+            };
+            let (cab, _) = write_cab(io::Cursor::new(vec![]), inputs, &options)?;
+            writer.write_all(cab.get_ref())?;
+        }
+    }
+    writer.flush()?;
+    Ok(writer)
+}
+
+/// An upper bound on the size of a cabinet containing `inputs`, compressed
+/// in blocks of `chunk_size` bytes.
+fn max_cab_size(inputs: &[Input], chunk_size: usize) -> u64 {
+    let size = inputs.iter().map(|input| input.size).sum::<u64>();
+    let names = inputs
+        .iter()
+        .map(|input| input.name.len() as u64 + 1)
+        .sum::<u64>();
+    let blocks = size.div_ceil(chunk_size as u64);
+    // Incompressible data is stored in deflate blocks with a small header,
+    // plus the two byte MSZIP signature.
+    let block_overhead = CFDATA_SIZE + 2 + 8;
+    CFHEADER_SIZE
+        + CFFOLDER_SIZE
+        + CFFILE_SIZE * inputs.len() as u64
+        + names
+        + size
+        + blocks * block_overhead
+}
+
+/// A file to be stored in a cabinet.
+struct Input<'a> {
+    /// Where to read the file's contents from.
+    path: &'a Path,
+    /// The name to store the file under.
+    name: &'a str,
+    /// The size of the file.
+    size: u64,
+    /// The modification time to store for the file.
+    mtime: NaiveDateTime,
+    /// The creation time of the file, if the platform keeps track of it.
+    created: Option<FileTime>,
+}
+
+impl<'a> Input<'a> {
+    /// Gather everything needed to store the file at `path` in a cabinet
+    /// as `name`, without opening it.
+    fn with_name(path: &'a Path, name: &'a str) -> Result<Input<'a>> {
+        let meta = std::fs::metadata(path)?;
+        let mtime = FileTime::from_last_modification_time(&meta);
+        Ok(Input {
+            path,
+            name,
+            size: meta.len(),
+            mtime: NaiveDateTime::from_timestamp(mtime.unix_seconds(), mtime.nanoseconds()),
+            created: FileTime::from_creation_time(&meta),
+        })
+    }
+}
+
+/// A file to be stored in a cabinet, whose contents come from a function
+/// that is only called once the cabinet writer reaches the file.
+///
+/// Only one input is open at a time, and each is dropped before the next is
+/// opened, so cabinets can hold tens of thousands of files without running
+/// into file descriptor limits.
+pub struct LazyInput<'a> {
+    name: String,
+    mtime: NaiveDateTime,
+    open: Box<dyn FnOnce() -> io::Result<Box<dyn Read + 'a>> + 'a>,
+}
+
+impl<'a> LazyInput<'a> {
+    /// Store the contents read from whatever `open` returns as `name`, with
+    /// the modification time `mtime`.
+    pub fn new<S, F, R>(name: S, mtime: NaiveDateTime, open: F) -> LazyInput<'a>
+    where
+        S: Into<String>,
+        F: FnOnce() -> io::Result<R> + 'a,
+        R: Read + 'a,
+    {
+        LazyInput {
+            name: name.into(),
+            mtime,
+            open: Box::new(move || Ok(Box::new(open()?) as Box<dyn Read + 'a>)),
+        }
+    }
+
+    /// Store the concatenation of `chunks` as `name`, with the modification
+    /// time `mtime`, for data produced by generators or async tasks that
+    /// doesn't come from a `Read`.
+    ///
+    /// The chunks are only pulled from the iterator as the cabinet writer
+    /// needs them, and an error from it fails writing the cabinet.
+    pub fn from_chunks<S, I, B>(name: S, mtime: NaiveDateTime, chunks: I) -> LazyInput<'a>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = io::Result<B>>,
+        I::IntoIter: 'a,
+        B: AsRef<[u8]> + 'a,
+    {
+        let chunks = chunks.into_iter();
+        LazyInput::new(name, mtime, move || {
+            Ok(ChunkReader {
+                chunks,
+                chunk: None,
+                pos: 0,
+            })
+        })
+    }
+}
+
+/// Reads the concatenation of the chunks an iterator yields.
+struct ChunkReader<I, B> {
+    chunks: I,
+    chunk: Option<B>,
+    pos: usize,
+}
+
+impl<I, B> Read for ChunkReader<I, B>
+where
+    I: Iterator<Item = io::Result<B>>,
+    B: AsRef<[u8]>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(chunk) = &self.chunk {
+                let rest = &chunk.as_ref()[self.pos..];
+                if !rest.is_empty() {
+                    let len = buf.len().min(rest.len());
+                    buf[..len].copy_from_slice(&rest[..len]);
+                    self.pos += len;
+                    return Ok(len);
+                }
+            }
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.chunk = Some(chunk?);
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Write a cabinet file at `cab_path` containing `inputs`, opening each one
+/// only when it is time to compress it.
+///
+/// The files are stored in a single MSZIP folder, in the order given, with
+/// any `/` in their names stored as `\\`. Like [`make_cab_from_files`], the
+/// cabinet only replaces `cab_path` once it's complete.
+pub fn make_cab_from_lazy_inputs<T: AsRef<Path>>(
+    cab_path: T,
+    inputs: Vec<LazyInput>,
+) -> Result<()> {
+    let cab_path = cab_path.as_ref();
+    write_lazy_inputs(temp_cab_file(cab_path)?, inputs)?.persist(cab_path)?;
+    Ok(())
+}
+
+/// Build a cabinet containing `inputs` in memory, as
+/// [`make_cab_from_lazy_inputs`] would write it, and return its bytes.
+///
+/// This is for small cabinets that are about to be embedded in something
+/// else, hashed or uploaded, so there's no need to write them to a file.
+/// With inputs whose contents are also in memory, nothing touches the file
+/// system at all.
+pub fn make_cab_to_vec(inputs: Vec<LazyInput>) -> Result<Vec<u8>> {
+    let cab = write_lazy_inputs(io::Cursor::new(vec![]), inputs)?;
+    Ok(cab.into_inner())
+}
+
+/// Write a cabinet containing `inputs` to `writer`, returning the writer.
+fn write_lazy_inputs<W: Write + Seek>(writer: W, inputs: Vec<LazyInput>) -> Result<W> {
+    let (names, opens): (Vec<_>, Vec<_>) = inputs
+        .into_iter()
+        .map(|input| ((input.name, input.mtime), input.open))
+        .unzip();
+    let names = names
+        .iter()
+        .map(|(name, mtime)| (PathSeparator::default().apply(name), *mtime))
+        .collect::<Vec<_>>();
+    let entries = names
+        .iter()
+        .map(|(name, mtime)| (&**name, *mtime))
+        .collect::<Vec<_>>();
+    check_limits(entries.iter().map(|&(name, _)| name), None, MAX_CHUNK)?;
+    let (writer, _) = write_entries(writer, &entries, Reserve::default(), &[], opens)?;
+    Ok(writer)
+}
+
+/// The directory the cabinet file at `cab_path` is in.
+fn cab_dir(cab_path: &Path) -> &Path {
+    match cab_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Create a temporary file next to `cab_path` to write a cabinet to, before
+/// it replaces `cab_path`.
+fn temp_cab_file(cab_path: &Path) -> io::Result<tempfile::NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(TEMP_FILE_PREFIX);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // Like `File::create`, rather than only readable by the owner.
+        builder.permissions(std::fs::Permissions::from_mode(0o666));
+    }
+    builder.tempfile_in(cab_dir(cab_path))
+}
+
+/// Write a cabinet file at `cab_path` containing `inputs`, as `options` say
+/// to, returning a summary of it with the time taken since `start`.
+///
+/// The cabinet is written to a temporary file next to `cab_path`, which
+/// only replaces `cab_path` once it's complete, so if writing fails or is
+/// cancelled there's no partial cabinet left behind.
+fn write_cab_file(
+    cab_path: &Path,
+    inputs: &[Input],
+    options: &CabOptions,
+    start: Instant,
+) -> Result<CabSummary> {
+    if let Some(split) = &options.split {
+        return write_cab_set(cab_path, inputs, options, split, start);
+    }
+    let temp = temp_cab_file(cab_path)?;
+    if options.preallocate {
+        let reserves = options.header_reserve.len() + options.folder_reserve.len();
+        let max_size = max_cab_size(inputs, options.chunk_size()?);
+        preallocate(temp.as_file(), max_size + reserves as u64)?;
+    }
+    let (temp, files) = write_cab(temp, inputs, options)?;
+    if options.preallocate {
+        // Release whatever was reserved past the end of the cabinet.
+        let len = temp.as_file().metadata()?.len();
+        temp.as_file().set_len(len)?;
+    }
+    if options.durable {
+        temp.as_file().sync_all()?;
+    }
+    let cab_file = temp.persist(cab_path)?;
+    if options.block_index {
+        index::write_block_index(cab_path)?;
+    }
+    // The rename only survives a crash once the directory is synced too.
+    if options.durable {
+        sync_dir(cab_dir(cab_path))?;
+    }
+    Ok(CabSummary {
+        path: cab_path.to_path_buf(),
+        cab_size: cab_file.metadata()?.len(),
+        cabinets: vec![],
+        files,
+        blocks: block_summaries(cab_path)?,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Write a linked set of cabinets containing `inputs`, named after
+/// `cab_path` as `split` says, returning a summary of the whole set with
+/// the time taken since `start`.
+///
+/// The cabinets are written as the blocks are compressed, so each cabinet,
+/// rather than all the data, has to fit in a folder, and none of them is
+/// moved into place until they're all complete.
+fn write_cab_set(
+    cab_path: &Path,
+    inputs: &[Input],
+    options: &CabOptions,
+    split: &SplitOptions,
+    start: Instant,
+) -> Result<CabSummary> {
+    if options.block_index {
+        bail!("A block index can't be written for a cabinet that's split into a set");
+    }
+    if options.preallocate {
+        bail!("Disk space can't be reserved for a cabinet that's split into a set");
+    }
+    let (inputs, entries, reserve) = cab_entries(inputs, options)?;
+    let never = AtomicBool::new(false);
+    let cancel = options.cancel.as_deref().unwrap_or(&never);
+    let never_retry = RetryPolicy::never();
+    let policy = options.retry.as_ref().unwrap_or(&never_retry);
+    let opens = inputs.iter().map(|input| {
+        move || {
+            RetryingInput::open(input.path, options, policy)
+                .map(|inner| CancelReader { inner, cancel })
+        }
+    });
+    let result = pipeline::write_set_pipelined(
+        cab_path,
+        split,
+        options.durable,
+        &entries,
+        reserve,
+        &options.folder_reserve,
+        options.data_reserve.as_deref(),
+        opens,
+        options.threads,
+        options.memory_limit,
+        options.chunk_size()?,
+        &*options.folder_compressor()?,
+        options.name_codepage,
+    );
+    let (cabinets, files) = check_cancelled(result, cancel)?;
+    let mut summary = CabSummary {
+        path: cab_path.to_path_buf(),
+        cab_size: 0,
+        cabinets: vec![],
+        files,
+        blocks: vec![],
+        elapsed: Duration::default(),
     };
-    let meta = input.metadata()?;
-    let mtime = FileTime::from_last_modification_time(&meta);
-    let mtime = NaiveDateTime::from_timestamp(mtime.unix_seconds(), mtime.nanoseconds());
+    for path in cabinets {
+        summary.cab_size += std::fs::metadata(&path)?.len();
+        let (uncompressed, compressed) = summary.blocks.last().map_or((0, 0), |last| {
+            (last.total_uncompressed, last.total_compressed)
+        });
+        summary.blocks.extend(
+            block_summaries(&path)?
+                .into_iter()
+                .map(|block| BlockSummary {
+                    total_uncompressed: uncompressed + block.total_uncompressed,
+                    total_compressed: compressed + block.total_compressed,
+                    ..block
+                }),
+        );
+        summary.cabinets.push(path);
+    }
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+/// Flush the directory `dir` to disk, so that files just moved into it
+/// survive a crash. Windows can't open directories as files, but NTFS
+/// journals renames, so there's nothing to do there.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Sort `inputs` into the order `options` says to store them in and check
+/// they fit in a cabinet, returning them along with the name and timestamp
+/// to store for each and the header reserve to store with them. Unless the
+/// cabinet is split into a set, all their data has to fit in one folder.
+#[allow(clippy::type_complexity)]
+fn cab_entries<'a, 'b>(
+    inputs: &'b [Input<'a>],
+    options: &CabOptions,
+) -> Result<(Vec<&'b Input<'a>>, Vec<(&'a str, NaiveDateTime)>, Reserve)> {
+    let mut inputs = inputs.iter().collect::<Vec<_>>();
+    options.order.sort(&mut inputs);
+    let size = match options.split {
+        Some(_) => None,
+        None => Some(inputs.iter().map(|input| input.size).sum()),
+    };
+    check_limits(
+        inputs.iter().map(|input| input.name),
+        size,
+        options.chunk_size()?,
+    )?;
+    let now = Utc::now().naive_utc();
+    let entries = inputs
+        .iter()
+        .map(|input| (input.name, options.timestamps.timestamp(input, now)))
+        .collect::<Vec<_>>();
+    let mut reserve = Reserve {
+        header: options.header_reserve.clone(),
+        ..Reserve::default()
+    };
+    if options.high_res_timestamps {
+        let timestamps = inputs
+            .iter()
+            .zip(&entries)
+            .map(|(input, (_, mtime))| Timestamps {
+                modified: FileTime::from_unix_time(
+                    mtime.timestamp(),
+                    mtime.timestamp_subsec_nanos(),
+                ),
+                created: input.created,
+            })
+            .collect();
+        reserve.timestamps = Some(timestamps);
+    }
+    if options.crc32 {
+        reserve.crc32s = Some(vec![]);
+    }
+    Ok((inputs, entries, reserve))
+}
+
+/// Turn the error `result` has, if writing failed because `cancel` was set,
+/// into [`Cancelled`].
+fn check_cancelled<T>(result: Result<T>, cancel: &AtomicBool) -> Result<T> {
+    result.map_err(|e| {
+        if cancel.load(Ordering::Relaxed) {
+            Cancelled.into()
+        } else {
+            e
+        }
+    })
+}
+
+/// Write a cabinet containing `inputs` in a single MSZIP folder to `writer`,
+/// in the order `options` says to, returning the writer and a summary of
+/// each file.
+fn write_cab<W: Write + Seek>(
+    writer: W,
+    inputs: &[Input],
+    options: &CabOptions,
+) -> Result<(W, Vec<FileSummary>)> {
+    let (inputs, entries, reserve) = cab_entries(inputs, options)?;
+    let never = AtomicBool::new(false);
+    let cancel = options.cancel.as_deref().unwrap_or(&never);
+    let never_retry = RetryPolicy::never();
+    let policy = options.retry.as_ref().unwrap_or(&never_retry);
+    let opens = inputs.iter().map(|input| {
+        move || {
+            RetryingInput::open(input.path, options, policy)
+                .map(|inner| CancelReader { inner, cancel })
+        }
+    });
+    let result = if options.threads > 0
+        || options.chunk_size.is_some()
+        || options.block_index
+        || options.compression_level.is_some()
+        || options.compressor.is_some()
+        || options.data_reserve.is_some()
+        || options.name_codepage.is_some()
+    {
+        pipeline::write_entries_pipelined(
+            writer,
+            &entries,
+            reserve,
+            &options.folder_reserve,
+            options.data_reserve.as_deref(),
+            opens,
+            options.threads,
+            options.memory_limit,
+            options.chunk_size()?,
+            &*options.folder_compressor()?,
+            options.name_codepage,
+        )
+    } else {
+        write_entries(writer, &entries, reserve, &options.folder_reserve, opens)
+    };
+    check_cancelled(result, cancel)
+}
+
+/// Open the input file at `path` for reading, as `options` say to.
+#[cfg(windows)]
+fn open_input(path: &Path, options: &CabOptions) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_SHARE_READ: u32 = 0x1;
+    const FILE_SHARE_WRITE: u32 = 0x2;
+    const FILE_SHARE_DELETE: u32 = 0x4;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    // Inputs are read from start to end, so tell the cache manager to read
+    // ahead aggressively.
+    const FILE_FLAG_SEQUENTIAL_SCAN: u32 = 0x0800_0000;
+
+    match std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_SEQUENTIAL_SCAN)
+        .open(path)
+    {
+        Err(e) if options.open_locked_files => std::fs::OpenOptions::new()
+            .read(true)
+            .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_SEQUENTIAL_SCAN)
+            .open(path)
+            .map_err(|_| e),
+        result => result,
+    }
+}
+
+/// Open the input file at `path` for reading, as `options` say to.
+#[cfg(not(windows))]
+fn open_input(path: &Path, _options: &CabOptions) -> io::Result<File> {
+    let file = File::open(path)?;
+    // Inputs are read from start to end, so ask the kernel to read ahead
+    // aggressively. This is only a hint, so failing doesn't matter.
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL);
+        }
+    }
+    Ok(file)
+}
+
+/// Reserve `len` bytes of disk space for `file`, without changing its size.
+#[cfg(target_os = "linux")]
+fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::convert::TryFrom;
+    use std::os::unix::io::AsRawFd;
+
+    let len =
+        libc::off_t::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    if unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) } == 0 {
+        return Ok(());
+    }
+    match io::Error::last_os_error() {
+        // Not every file system can preallocate, which is fine.
+        e if e.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Reserve `len` bytes of disk space for `file`, without changing its size.
+#[cfg(windows)]
+fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::convert::TryFrom;
+    use std::ffi::c_void;
+    use std::os::windows::io::AsRawHandle;
+
+    #[repr(C)]
+    struct FILE_ALLOCATION_INFO {
+        AllocationSize: i64,
+    }
+    const FileAllocationInfo: i32 = 5;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetFileInformationByHandle(
+            file: *mut c_void,
+            class: i32,
+            info: *const c_void,
+            size: u32,
+        ) -> i32;
+    }
+
+    let info = FILE_ALLOCATION_INFO {
+        AllocationSize: i64::try_from(len)
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+    };
+    let ok = unsafe {
+        SetFileInformationByHandle(
+            file.as_raw_handle() as *mut c_void,
+            FileAllocationInfo,
+            &info as *const FILE_ALLOCATION_INFO as *const c_void,
+            std::mem::size_of::<FILE_ALLOCATION_INFO>() as u32,
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reserve `len` bytes of disk space for `file`, which this platform
+/// doesn't support.
+#[cfg(not(any(target_os = "linux", windows)))]
+fn preallocate(_file: &File, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/// Write a cabinet to `writer` with a single MSZIP folder holding a file for
+/// each `(name, mtime)` in `entries`, whose contents are read from what the
+/// corresponding function in `opens` returns. Any tables in `reserve` are
+/// stored in the cabinet's header reserve; if it asks for CRC-32s they're
+/// computed as the files are compressed. If `folder_reserve` isn't empty
+/// it's stored in the folder's reserve. Returns the writer and a summary of
+/// each file.
+fn write_entries<W, I, F, R>(
+    writer: W,
+    entries: &[(&str, NaiveDateTime)],
+    mut reserve: Reserve,
+    folder_reserve: &[u8],
+    opens: I,
+) -> Result<(W, Vec<FileSummary>)>
+where
+    W: Write + Seek,
+    I: IntoIterator<Item = F>,
+    F: FnOnce() -> io::Result<R>,
+    R: Read,
+{
     let mut cab_builder = CabinetBuilder::new();
-    let folder = cab_builder.add_folder(CompressionType::MsZip);
-    let file = folder.add_file(input_filename);
-    file.set_datetime(mtime);
+    let crc32 = reserve.crc32s.is_some();
+    if crc32 {
+        // Leave room for the CRC-32s, which are filled in at the end.
+        reserve.crc32s = Some(vec![0; entries.len()]);
+    }
+    let header_reserve = reserve.to_bytes()?;
+    if !header_reserve.is_empty() {
+        cab_builder.set_reserve_data(header_reserve);
+    }
+    if !entries.is_empty() {
+        let folder = cab_builder.add_folder(CompressionType::MsZip);
+        if !folder_reserve.is_empty() {
+            folder.set_reserve_data(folder_reserve.to_vec());
+        }
+        for &(name, mtime) in entries {
+            let file = folder.add_file(name);
+            file.set_datetime(mtime);
+        }
+    }
 
-    let cab_file = File::create(cab_path)?;
-    let mut cab_writer = cab_builder.build(cab_file)?;
-    while let Some(mut writer) = cab_writer.next_file()? {
-        io::copy(&mut input, &mut writer)?;
+    let end = Rc::new(Cell::new(0));
+    let writer = EndTracker {
+        inner: writer,
+        pos: 0,
+        end: end.clone(),
+    };
+    let mut cab_writer = cab_builder.build(writer)?;
+    let mut opens = opens.into_iter();
+    let mut crc32s = vec![];
+    let mut files = vec![];
+    let mut last_end = end.get();
+    while let (Some(mut writer), Some(open)) = (cab_writer.next_file()?, opens.next()) {
+        // Inputs are only opened once the writer gets to them, so writing
+        // lots of files doesn't need lots of file descriptors.
+        let mut reader = CrcReader::new(open()?);
+        let size = io::copy(&mut reader, &mut writer)?;
+        crc32s.push(reader.crc().sum());
+        files.push(FileSummary {
+            name: entries[files.len()].0.to_owned(),
+            size,
+            compressed_size: end.get() - last_end,
+            renamed_from: None,
+        });
+        last_end = end.get();
+    }
+    let mut writer = cab_writer.finish()?.inner;
+    // The last block of compressed data is only written once there are no
+    // more files.
+    if let Some(last) = files.last_mut() {
+        last.compressed_size += end.get() - last_end;
+    }
+    if crc32 {
+        reserve.crc32s = Some(crc32s);
+        writer.seek(io::SeekFrom::Start(HEADER_RESERVE_OFFSET))?;
+        writer.write_all(&reserve.to_bytes()?)?;
+        writer.seek(io::SeekFrom::End(0))?;
+    }
+    Ok((writer, files))
+}
+
+/// A writer that keeps track of the end of what has been written to it.
+struct EndTracker<W> {
+    inner: W,
+    pos: u64,
+    end: Rc<Cell<u64>>,
+}
+
+impl<W: Write> Write for EndTracker<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.pos += n as u64;
+        self.end.set(self.end.get().max(self.pos));
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for EndTracker<W> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Options controlling how [`estimate_cab_size`] samples its inputs.
+#[derive(Clone, Debug)]
+pub struct EstimateOptions {
+    /// The number of blocks to compress from each input. Inputs that don't
+    /// have more blocks than this are compressed in full.
+    pub sample_blocks: usize,
+}
+
+impl Default for EstimateOptions {
+    fn default() -> EstimateOptions {
+        EstimateOptions { sample_blocks: 8 }
+    }
+}
+
+/// Estimate the size of a cabinet containing the files in `inputs` without
+/// actually building it.
+///
+/// A handful of blocks spread evenly through each input are compressed, and
+/// the compression ratio they achieve is extrapolated to the rest of the
+/// input. This is much faster than compressing everything for large inputs,
+/// at the cost of accuracy for inputs whose contents vary a lot.
+pub fn estimate_cab_size<P: AsRef<Path>>(inputs: &[P], options: &EstimateOptions) -> Result<u64> {
+    let mut header_size = CFHEADER_SIZE + CFFOLDER_SIZE;
+    let mut total_size = 0;
+    let mut compressed_size = 0;
+    for input_path in inputs {
+        let input_path = input_path.as_ref();
+        header_size += CFFILE_SIZE + stored_name(input_path)?.len() as u64 + 1;
+        let mut input = File::open(input_path)?;
+        let size = input.metadata()?.len();
+        total_size += size;
+        compressed_size += estimate_compressed_size(&mut input, size, options.sample_blocks)?;
+    }
+    let blocks = total_size.div_ceil(MAX_CHUNK as u64);
+    Ok(header_size + blocks * CFDATA_SIZE + compressed_size)
+}
+
+/// Estimate the size of the MSZIP blocks for the `size` bytes of `input` by
+/// compressing at most `sample_blocks` blocks of it.
+fn estimate_compressed_size(input: &mut File, size: u64, sample_blocks: usize) -> Result<u64> {
+    let blocks = size.div_ceil(MAX_CHUNK as u64);
+    let samples = blocks.min(sample_blocks.max(1) as u64);
+    let mut buf = Vec::with_capacity(MAX_CHUNK);
+    let mut sampled = 0;
+    let mut compressed = 0;
+    for i in 0..samples {
+        input.seek(io::SeekFrom::Start(i * blocks / samples * MAX_CHUNK as u64))?;
+        buf.clear();
+        let len = Read::by_ref(input)
+            .take(MAX_CHUNK as u64)
+            .read_to_end(&mut buf)?;
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&buf)?;
+        // Each MSZIP block starts with a two byte signature.
+        compressed += 2 + encoder.finish()?.len() as u64;
+        sampled += len as u64;
+    }
+    if sampled == 0 {
+        return Ok(0);
+    }
+    Ok(compressed * size / sampled)
+}
+
+/// Check that the cabinet file at `cab_path` contains the file `input_path`
+/// with identical contents, by reading it back with a pure-Rust cabinet reader.
+pub fn verify<T: AsRef<Path>, U: AsRef<Path>>(cab_path: T, input_path: U) -> Result<()> {
+    let input_filename = stored_name(input_path.as_ref())?;
+    let mut expected = vec![];
+    File::open(input_path.as_ref())?.read_to_end(&mut expected)?;
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let mut actual = vec![];
+    cabinet
+        .read_file(input_filename)?
+        .read_to_end(&mut actual)?;
+    if actual != expected {
+        bail!(
+            "Contents of '{}' in cabinet do not match the original ({} bytes vs. {} bytes)",
+            input_filename,
+            actual.len(),
+            expected.len()
+        );
     }
-    cab_writer.finish()?;
     Ok(())
 }
 
-// If I ever add support for extracting files from cabinets, I could
-// add round-trip tests for that here, but for now we'll live with
-// just testing creating cabinet files and then extracting them with
-// `expand`. The API Microsoft exposes for working with cabinet files
-// is horrendously complex, so rather than try to wrap that with Rust
-// FFI we'll just shell out to `expand`.
-#[cfg(all(test, windows))]
+/// The outcome of checking a single file in a cabinet with [`verify_cab`]
+/// or [`salvage_cab`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileReport {
+    /// The name of the file as stored in the cabinet.
+    pub name: String,
+    /// The index of the folder containing the file.
+    pub folder: usize,
+    /// The uncompressed size recorded in the file's header entry.
+    pub size: u64,
+    /// A description of what went wrong decompressing the file, if anything.
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    /// Returns `true` if the file decompressed cleanly to its recorded size.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The outcome of checking every file in a cabinet with [`verify_cab`] or
+/// [`salvage_cab`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CabReport {
+    /// One report per file, in the order they appear in the cabinet.
+    pub files: Vec<FileReport>,
+}
+
+impl CabReport {
+    /// Returns `true` if every file in the cabinet decompressed cleanly.
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(FileReport::is_ok)
+    }
+}
+
+/// Check the integrity of the cabinet file at `cab_path`.
+///
+/// Every file in every folder is decompressed, which checks the checksum of
+/// each CFDATA block along the way, and the amount of data produced is
+/// compared against the size recorded for the file, as well as against the
+/// CRC-32 recorded with [`CabOptions::crc32`] if there is one. Problems with individual
+/// files are recorded in the returned report; an error is only returned if
+/// the cabinet itself can't be opened or its headers can't be parsed.
+pub fn verify_cab<T: AsRef<Path>>(cab_path: T) -> Result<CabReport> {
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let entries = cabinet
+        .folder_entries()
+        .enumerate()
+        .flat_map(|(index, folder)| {
+            folder
+                .file_entries()
+                .map(move |file| (index, file.name().to_owned(), file.uncompressed_size()))
+        })
+        .collect::<Vec<_>>();
+    let extras = read_reserve(&cabinet);
+    let mut report = CabReport::default();
+    for (folder, name, size) in entries {
+        let size = u64::from(size);
+        let expected_crc32 = extras.get(&name).and_then(|extra| extra.crc32);
+        let error = match cabinet.read_file(&name).and_then(|reader| {
+            let mut reader = CrcReader::new(reader);
+            let n = io::copy(&mut reader, &mut io::sink())?;
+            Ok((n, reader.crc().sum()))
+        }) {
+            Ok((n, _)) if n != size => {
+                Some(format!("expected {} bytes but decompressed {}", size, n))
+            }
+            Ok((_, crc32)) => match expected_crc32 {
+                Some(expected) if expected != crc32 => Some(format!(
+                    "expected CRC-32 {:08x} but decompressed data has {:08x}",
+                    expected, crc32
+                )),
+                _ => None,
+            },
+            Err(e) => Some(e.to_string()),
+        };
+        report.files.push(FileReport {
+            name,
+            folder,
+            size,
+            error,
+        });
+    }
+    Ok(report)
+}
+
+/// Write a `<cab>.sha256` manifest next to the cabinet file at `cab_path`.
+///
+/// The manifest contains one line per file in the cabinet with the SHA-256
+/// digest of its uncompressed contents, followed by a line with the digest
+/// of the cabinet file itself, in the same format `sha256sum` produces.
+/// Returns the path of the manifest.
+pub fn write_sha256_manifest<T: AsRef<Path>>(cab_path: T) -> Result<PathBuf> {
+    let cab_path = cab_path.as_ref();
+    let mut manifest = String::new();
+    for (name, digest) in member_digests(cab_path)? {
+        writeln!(manifest, "{}  {}", digest, name)?;
+    }
+    let digest = sha256_hex(File::open(cab_path)?)?;
+    let cab_name = match cab_path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => bail!("Bad filename: '{}'", cab_path.to_string_lossy()),
+    };
+    writeln!(manifest, "{}  {}", digest, cab_name)?;
+
+    let mut manifest_path = OsString::from(cab_path);
+    manifest_path.push(".sha256");
+    let manifest_path = PathBuf::from(manifest_path);
+    std::fs::write(&manifest_path, manifest)?;
+    Ok(manifest_path)
+}
+
+/// The default template for [`rename_by_hash`].
+pub const HASH_NAME_TEMPLATE: &str = "{stem}-{hash}.cab";
+
+/// Rename the cabinet file at `cab_path` after the SHA-256 digest of its
+/// contents, so that artifact stores and CDNs can deduplicate it and
+/// caches never serve a stale copy. Returns the new path.
+///
+/// The new file name is `template` with `{stem}` replaced by the current
+/// file name without its extension and `{hash}` by the first 16 hex digits
+/// of the digest, like [`HASH_NAME_TEMPLATE`]. The template must contain
+/// `{hash}`. A block index written with [`CabOptions::block_index`] is
+/// renamed to match.
+pub fn rename_by_hash<T: AsRef<Path>>(cab_path: T, template: &str) -> Result<PathBuf> {
+    let cab_path = cab_path.as_ref();
+    if !template.contains("{hash}") {
+        bail!("Name template '{}' doesn't contain {{hash}}", template);
+    }
+    if template.contains(['/', '\\']) {
+        bail!("Name template '{}' isn't a plain file name", template);
+    }
+    let stem = match cab_path.file_stem() {
+        Some(stem) => stem.to_string_lossy(),
+        None => bail!("Bad filename: '{}'", cab_path.to_string_lossy()),
+    };
+    let digest = sha256_hex(File::open(cab_path)?)?;
+    let name = template
+        .replace("{stem}", &stem)
+        .replace("{hash}", &digest[..16]);
+    let new_path = cab_path.with_file_name(name);
+    std::fs::rename(cab_path, &new_path)?;
+    index::rename_block_index(cab_path, &new_path)?;
+    Ok(new_path)
+}
+
+/// Get the name and SHA-256 digest of the contents of every file in the
+/// cabinet at `cab_path`, in the order they appear in the cabinet.
+fn member_digests(cab_path: &Path) -> Result<Vec<(String, String)>> {
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let names = cabinet
+        .folder_entries()
+        .flat_map(|folder| folder.file_entries().map(|file| file.name().to_owned()))
+        .collect::<Vec<_>>();
+    names
+        .into_iter()
+        .map(|name| {
+            let digest = sha256_hex(cabinet.read_file(&name)?)?;
+            Ok((name, digest))
+        })
+        .collect()
+}
+
+/// Compute the SHA-256 digest of everything read from `reader` as a hex string.
+fn sha256_hex<R: Read>(mut reader: R) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Get the name under which `path` is stored in a cabinet.
+fn stored_name(path: &Path) -> Result<&str> {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => Ok(name),
+        None => bail!("Bad filename: '{}'", path.to_string_lossy()),
+    }
+}
+
+// Every test reads the cabinet back with `verify`, which uses the `cab`
+// crate's reader and so runs on every platform. On Windows we additionally
+// extract the file with `expand`, to make sure Microsoft's own tools agree.
+// The API Microsoft exposes for working with cabinet files is horrendously
+// complex, so rather than try to wrap that with Rust FFI we'll just shell
+// out to `expand`.
+#[cfg(test)]
 mod tests {
     extern crate tempdir;
 
+    use std::cell::{Cell, RefCell};
     use std::fs::File;
     use std::io;
     use std::io::prelude::*;
+    #[cfg(windows)]
+    use std::path::Path;
+    #[cfg(windows)]
     use std::process::Command;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
 
     use self::tempdir::TempDir;
-    use super::make_cab;
+    use super::{
+        estimate_cab_size, extract_cab, lint_cab, list_cab, make_cab, make_cab_from_dir,
+        make_cab_from_dir_to_writer, make_cab_from_files, make_cab_from_files_with_options,
+        make_cab_from_lazy_inputs, make_cab_to_vec, make_cab_to_writer,
+        make_cab_to_writer_with_options, make_cabs, read_file_range, rename_by_hash, sha256_hex,
+        verify, verify_cab, write_sha256_manifest, CabOptions, CabWriter, Cancelled,
+        DuplicateNames, EstimateOptions, LazyInput, LimitExceeded, MemberOrder, PathFilter,
+        PathSeparator, TimestampSource, CFDATA_SIZE, CFFILE_SIZE, CFFOLDER_SIZE, CFHEADER_SIZE,
+        HASH_NAME_TEMPLATE, MAX_CHUNK,
+    };
+    use chrono::{NaiveDate, NaiveDateTime, Utc};
+    use filetime::FileTime;
 
     // Write `data` to a file, create a cabinet file from it, and then
-    // extract the file using `expand` and verify that the data is the same.
+    // read the file back and verify that the data is the same.
     fn roundtrip(data: &[u8]) {
         let t = TempDir::new("makecab").expect("failed to create temp dir");
         let in_path = t.path().join("original");
@@ -70,17 +1866,25 @@ mod tests {
         }
         let cab = t.path().join("test.cab");
         make_cab(&cab, &in_path).expect("failed to create cab file");
+        verify(&cab, &in_path).expect("failed to verify cab file");
+
+        #[cfg(windows)]
+        expand(&cab, &t.path().join("extracted"), data);
+    }
 
-        let out_path = t.path().join("extracted");
+    // Extract the file in `cab` to `out_path` using `expand` and verify that
+    // the data is the same as `data`.
+    #[cfg(windows)]
+    fn expand(cab: &Path, out_path: &Path, data: &[u8]) {
         let output = Command::new("expand")
-            .arg(&cab)
-            .arg(&out_path)
+            .arg(cab)
+            .arg(out_path)
             .output()
             .expect("failed to run expand");
         if output.status.success() {
             let mut buf = vec![];
             {
-                File::open(&out_path)
+                File::open(out_path)
                     .expect("failed to open output file")
                     .read_to_end(&mut buf)
                     .expect("failed to read output file");
@@ -108,7 +1912,883 @@ Its stderr was:
         }
     }
 
+    #[test]
+    fn verify_cab_reports_files() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original.txt");
+        let data = test_data(MAX_CHUNK * 3);
+        File::create(&in_path)
+            .and_then(|mut f| f.write_all(&data))
+            .expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        make_cab(&cab, &in_path).expect("failed to create cab file");
+
+        let report = verify_cab(&cab).expect("failed to read cab file");
+        assert!(report.is_ok());
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].name, "original.txt");
+        assert_eq!(report.files[0].size, data.len() as u64);
+
+        // Flip a bit in the last data block so its checksum no longer matches.
+        let mut bytes = std::fs::read(&cab).expect("failed to read cab file");
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        std::fs::write(&cab, &bytes).expect("failed to write cab file");
+        let report = verify_cab(&cab).expect("failed to read cab file");
+        assert!(!report.is_ok());
+        assert!(report.files[0].error.is_some());
+    }
+
+    #[test]
+    fn open_locked_files() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("service.log");
+        let mut log = File::create(&in_path).expect("failed to create test file");
+        log.write_all(&test_data(MAX_CHUNK + 1))
+            .expect("failed to write test data");
+        // Keep the file open for writing while it's read.
+        let options = CabOptions {
+            open_locked_files: true,
+            ..CabOptions::default()
+        };
+        let cab = t.path().join("test.cab");
+        make_cab_from_files_with_options(&cab, &[&in_path], &options)
+            .expect("failed to create cab file");
+        verify(&cab, &in_path).expect("failed to verify cab file");
+        drop(log);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn device_and_unc_paths() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        // `canonicalize` returns a device path like `\\?\C:\...`, which can
+        // reach a directory deeper than MAX_PATH allows.
+        let mut dir = std::fs::canonicalize(t.path()).unwrap();
+        assert!(dir.to_str().unwrap().starts_with(r"\\?\"));
+        for _ in 0..20 {
+            dir.push("a_long_directory_name");
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        let in_path = dir.join("file.pdb");
+        std::fs::write(&in_path, test_data(MAX_CHUNK + 1)).unwrap();
+        let cab = dir.join("file.pd_");
+        make_cab_from_files(&cab, &[&in_path]).expect("failed to create cab file");
+        verify(&cab, &in_path).expect("failed to verify cab file");
+        assert_eq!(verify_cab(&cab).unwrap().files[0].name, "file.pdb");
+
+        // The same directory through the administrative share, as a UNC
+        // path like `\\localhost\C$\...`, if it's shared.
+        let local = std::fs::canonicalize(t.path()).unwrap();
+        let local = local.to_str().unwrap().trim_start_matches(r"\\?\");
+        let unc = Path::new(r"\\localhost").join(local.replacen(':', "$", 1));
+        if unc.exists() {
+            let in_path = unc.join("file.dll");
+            std::fs::write(&in_path, test_data(1000)).unwrap();
+            let cab = unc.join("file.dl_");
+            make_cab_from_files(&cab, &[&in_path]).expect("failed to create cab file");
+            verify(&cab, &in_path).expect("failed to verify cab file");
+            assert_eq!(verify_cab(&cab).unwrap().files[0].name, "file.dll");
+        }
+    }
+
+    #[test]
+    fn lazy_inputs() {
+        // Counts how many inputs are open at once.
+        struct Counted<R>(R, Rc<Cell<usize>>);
+        impl<R: Read> Read for Counted<R> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl<R> Drop for Counted<R> {
+            fn drop(&mut self) {
+                self.1.set(self.1.get() - 1);
+            }
+        }
+
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let open_count = Rc::new(Cell::new(0));
+        let opened = Rc::new(RefCell::new(vec![]));
+        let mtime = NaiveDate::from_ymd(2020, 2, 29).and_hms(12, 34, 56);
+        let inputs = (0..100)
+            .map(|i| {
+                let (open_count, opened) = (open_count.clone(), opened.clone());
+                LazyInput::new(format!("file{}.txt", i), mtime, move || {
+                    assert_eq!(open_count.get(), 0, "more than one input open at once");
+                    open_count.set(1);
+                    opened.borrow_mut().push(i);
+                    Ok(Counted(
+                        std::io::Cursor::new(test_data(i * 100)),
+                        open_count,
+                    ))
+                })
+            })
+            .collect::<Vec<_>>();
+        assert!(opened.borrow().is_empty());
+
+        let cab = t.path().join("test.cab");
+        make_cab_from_lazy_inputs(&cab, inputs).expect("failed to create cab file");
+        assert_eq!(*opened.borrow(), (0..100).collect::<Vec<_>>());
+        let report = verify_cab(&cab).expect("failed to read cab file");
+        assert!(report.is_ok());
+        assert_eq!(report.files.len(), 100);
+        assert_eq!(report.files[99].name, "file99.txt");
+        assert_eq!(report.files[99].size, 9900);
+
+        // A cabinet that fails part way through leaves what was there alone.
+        let before = std::fs::read(&cab).unwrap();
+        let inputs = vec![LazyInput::new("gone.txt", mtime, || {
+            Err::<io::Cursor<Vec<u8>>, _>(io::Error::other("gone"))
+        })];
+        assert!(make_cab_from_lazy_inputs(&cab, inputs).is_err());
+        assert_eq!(std::fs::read(&cab).unwrap(), before);
+        assert_eq!(std::fs::read_dir(t.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn to_vec() {
+        let mtime = NaiveDate::from_ymd(2020, 2, 29).and_hms(12, 34, 56);
+        let data = test_data(MAX_CHUNK + 100);
+        let inputs = vec![
+            LazyInput::from_chunks("a.bin", mtime, vec![io::Result::Ok(data.clone())]),
+            LazyInput::new("dir/b.txt", mtime, || Ok(io::Cursor::new(b"b".to_vec()))),
+        ];
+        let cab = make_cab_to_vec(inputs).expect("failed to create cab");
+        let mut cabinet = cab::Cabinet::new(io::Cursor::new(&cab)).unwrap();
+        let mut read = vec![];
+        cabinet
+            .read_file("a.bin")
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+        assert!(cabinet.get_file_entry("dir\\b.txt").is_some());
+
+        // The same bytes as writing a file.
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("test.cab");
+        let inputs = vec![LazyInput::new("a.bin", mtime, || {
+            Ok(io::Cursor::new(test_data(10)))
+        })];
+        make_cab_from_lazy_inputs(&path, inputs).expect("failed to create cab file");
+        let inputs = vec![LazyInput::new("a.bin", mtime, || {
+            Ok(io::Cursor::new(test_data(10)))
+        })];
+        assert_eq!(
+            make_cab_to_vec(inputs).unwrap(),
+            std::fs::read(&path).unwrap()
+        );
+    }
+
+    #[test]
+    fn block_sizes() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("data.bin");
+        let data = test_data(MAX_CHUNK * 3 + 10);
+        std::fs::write(&path, &data).unwrap();
+        let cab = t.path().join("test.cab");
+        for chunk_size in [None, Some(10_000)] {
+            let options = CabOptions {
+                chunk_size,
+                ..CabOptions::default()
+            };
+            let summary = make_cab_from_files_with_options(&cab, &[&path], &options)
+                .expect("failed to create cab file");
+            let chunk = chunk_size.unwrap_or(MAX_CHUNK);
+            let sizes = summary
+                .blocks
+                .iter()
+                .map(|block| usize::from(block.uncompressed_size))
+                .collect::<Vec<_>>();
+            let mut expected = vec![chunk; data.len() / chunk];
+            expected.push(data.len() % chunk);
+            assert_eq!(sizes, expected);
+            let last = summary.blocks.last().unwrap();
+            assert_eq!(last.total_uncompressed, data.len() as u64);
+            assert_eq!(
+                last.total_compressed,
+                summary.files[0].compressed_size - CFDATA_SIZE * summary.blocks.len() as u64
+            );
+            assert!(summary.blocks.iter().all(|block| block.folder == 0));
+        }
+    }
+
+    #[test]
+    fn empty_inputs() {
+        // Every way of writing a cabinet stores a 0-byte source as a 0-byte
+        // file that verifies and extracts, like the empty PDB stubs some
+        // builds produce.
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let empty = t.path().join("empty.pdb");
+        std::fs::write(&empty, b"").unwrap();
+        let other = t.path().join("other.txt");
+        std::fs::write(&other, "other").unwrap();
+        let cab = t.path().join("test.cab");
+        let pipelined = CabOptions {
+            threads: 2,
+            block_index: true,
+            ..CabOptions::default()
+        };
+        for options in [CabOptions::default(), pipelined] {
+            let summary = make_cab_from_files_with_options(&cab, &[&empty], &options)
+                .expect("failed to create cab file");
+            assert_eq!(summary.uncompressed_size(), 0);
+            verify(&cab, &empty).expect("cab file didn't verify");
+            let report = verify_cab(&cab).unwrap();
+            assert!(report.is_ok());
+            assert_eq!(report.files[0].size, 0);
+            let out = t.path().join("out");
+            extract_cab(&cab, &out).expect("failed to extract cab file");
+            assert_eq!(std::fs::read(out.join("empty.pdb")).unwrap(), b"");
+            assert_eq!(
+                read_file_range(&cab, "empty.pdb", 0, 10).unwrap(),
+                Vec::<u8>::new()
+            );
+            if options.threads > 0 {
+                // A folder of empty files has no data blocks at all, so
+                // compressors are never handed an empty chunk.
+                assert!(summary.blocks.is_empty());
+                assert_eq!(summary.files[0].compressed_size, 0);
+            }
+
+            let summary = make_cab_from_files_with_options(&cab, &[&empty, &other], &options)
+                .expect("failed to create cab file");
+            assert_eq!(summary.files[0].compressed_size, 0);
+            verify(&cab, &empty).expect("cab file didn't verify");
+            verify(&cab, &other).expect("cab file didn't verify");
+        }
+
+        let mtime = NaiveDate::from_ymd(2020, 2, 29).and_hms(12, 34, 56);
+        let inputs = vec![LazyInput::new("empty.pdb", mtime, || Ok(io::empty()))];
+        let data = make_cab_to_vec(inputs).expect("failed to create cab");
+        std::fs::write(&cab, data).unwrap();
+        verify(&cab, &empty).expect("cab file didn't verify");
+
+        CabWriter::create(&cab, "empty.pdb")
+            .expect("failed to create cab")
+            .finish()
+            .expect("failed to finish cab");
+        verify(&cab, &empty).expect("cab file didn't verify");
+    }
+
+    #[test]
+    fn limits() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        fn limit<T: std::fmt::Debug>(result: anyhow::Result<T>) -> Option<LimitExceeded> {
+            let e = result.expect_err("cab file was written");
+            e.downcast_ref::<LimitExceeded>().cloned()
+        }
+
+        let mtime = NaiveDate::from_ymd(2020, 2, 29).and_hms(12, 34, 56);
+        let opened = Rc::new(Cell::new(false));
+        let inputs = (0..=u16::MAX as usize)
+            .map(|i| {
+                let opened = opened.clone();
+                LazyInput::new(format!("{}.txt", i), mtime, move || {
+                    opened.set(true);
+                    Ok(io::empty())
+                })
+            })
+            .collect();
+        assert_eq!(
+            limit(make_cab_from_lazy_inputs(&cab, inputs)),
+            Some(LimitExceeded::TooManyFiles(65536))
+        );
+        assert!(!opened.get(), "inputs were read before checking limits");
+
+        let name = "x".repeat(256);
+        let inputs = vec![LazyInput::new(name.clone(), mtime, || Ok(io::empty()))];
+        assert_eq!(
+            limit(make_cab_from_lazy_inputs(&cab, inputs)),
+            Some(LimitExceeded::NameTooLong(name))
+        );
+
+        let path = t.path().join("data.bin");
+        std::fs::write(&path, test_data(usize::from(u16::MAX) + 1)).unwrap();
+        let options = CabOptions {
+            chunk_size: Some(1),
+            ..CabOptions::default()
+        };
+        assert_eq!(
+            limit(make_cab_from_files_with_options(&cab, &[&path], &options)),
+            Some(LimitExceeded::FolderTooLarge(65536))
+        );
+        let options = CabOptions {
+            header_reserve: vec![0; 60_001],
+            ..CabOptions::default()
+        };
+        assert_eq!(
+            limit(make_cab_from_files_with_options(&cab, &[&path], &options)),
+            Some(LimitExceeded::HeaderReserveTooLarge(60_001))
+        );
+    }
+
+    #[test]
+    fn lazy_chunks() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let mtime = NaiveDate::from_ymd(2020, 2, 29).and_hms(12, 34, 56);
+        let data = test_data(MAX_CHUNK * 2 + 100);
+        let pulled = Rc::new(Cell::new(0));
+        let counted = pulled.clone();
+        let chunks = data
+            .chunks(1000)
+            .chain(std::iter::once(&[][..]))
+            .inspect(move |_| counted.set(counted.get() + 1))
+            .map(Ok);
+        let inputs = vec![
+            LazyInput::from_chunks("chunks.bin", mtime, chunks),
+            LazyInput::from_chunks("empty.bin", mtime, Vec::<io::Result<Vec<u8>>>::new()),
+        ];
+        assert_eq!(pulled.get(), 0);
+        let cab = t.path().join("test.cab");
+        make_cab_from_lazy_inputs(&cab, inputs).expect("failed to create cab file");
+        assert_eq!(pulled.get(), data.len().div_ceil(1000) + 1);
+        let mut cabinet = cab::Cabinet::new(File::open(&cab).unwrap()).unwrap();
+        let mut read = vec![];
+        cabinet
+            .read_file("chunks.bin")
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+        assert_eq!(
+            cabinet
+                .get_file_entry("empty.bin")
+                .unwrap()
+                .uncompressed_size(),
+            0
+        );
+
+        let failing = vec![
+            Ok(b"some data".to_vec()),
+            Err(io::Error::other("generator failed")),
+        ];
+        let inputs = vec![LazyInput::from_chunks("failing.bin", mtime, failing)];
+        assert!(make_cab_from_lazy_inputs(&cab, inputs).is_err());
+    }
+
+    #[test]
+    fn summary() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let inputs = [("a.bin", MAX_CHUNK * 3), ("b.bin", 1000)]
+            .iter()
+            .map(|(name, size)| {
+                let in_path = t.path().join(name);
+                File::create(&in_path)
+                    .and_then(|mut f| f.write_all(&test_data(*size)))
+                    .expect("failed to write test data");
+                in_path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("test.cab");
+        let summary = make_cab_from_files(&cab, &inputs).expect("failed to create cab file");
+        assert_eq!(summary.path, cab);
+        assert_eq!(
+            summary.cab_size,
+            std::fs::metadata(&cab)
+                .expect("failed to read metadata")
+                .len()
+        );
+        assert_eq!(summary.uncompressed_size(), MAX_CHUNK as u64 * 3 + 1000);
+        let names = summary
+            .files
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.bin", "b.bin"]);
+        assert!(summary.files.iter().all(|file| file.compressed_size > 0));
+        let headers = CFHEADER_SIZE + CFFOLDER_SIZE + 2 * (CFFILE_SIZE + 6);
+        let compressed = summary
+            .files
+            .iter()
+            .map(|file| file.compressed_size)
+            .sum::<u64>();
+        assert_eq!(headers + compressed, summary.cab_size);
+    }
+
+    #[test]
+    fn batch() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let jobs = (0..8)
+            .map(|i| {
+                let in_path = t.path().join(format!("file{}.pdb", i));
+                File::create(&in_path)
+                    .and_then(|mut f| f.write_all(&test_data(i * 1000)))
+                    .expect("failed to write test data");
+                (t.path().join(format!("file{}.pd_", i)), in_path)
+            })
+            .chain(Some((
+                t.path().join("missing.cab"),
+                t.path().join("missing"),
+            )))
+            .collect::<Vec<_>>();
+
+        let results = make_cabs(&jobs);
+        assert_eq!(results.len(), jobs.len());
+        for ((cab, in_path), result) in jobs.iter().zip(&results).take(8) {
+            assert!(result.is_ok());
+            verify(cab, in_path).expect("failed to verify cab file");
+        }
+        assert!(results[8].is_err());
+    }
+
+    #[test]
+    fn non_seekable_writer() {
+        // A writer that only implements `Write`, like a pipe.
+        struct Pipe(Vec<u8>);
+        impl Write for Pipe {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original");
+        File::create(&in_path)
+            .and_then(|mut f| f.write_all(&test_data(MAX_CHUNK * 2)))
+            .expect("failed to write test data");
+        let Pipe(bytes) =
+            make_cab_to_writer(Pipe(vec![]), &in_path).expect("failed to create cab file");
+
+        let cab = t.path().join("test.cab");
+        let mut expected = vec![];
+        make_cab(&cab, &in_path).expect("failed to create cab file");
+        File::open(&cab)
+            .and_then(|mut f| f.read_to_end(&mut expected))
+            .expect("failed to read cab file");
+        assert_eq!(bytes, expected);
+
+        // With no memory to spare the cabinet is spooled to disk instead.
+        let options = CabOptions {
+            memory_limit: Some(0),
+            ..CabOptions::default()
+        };
+        let Pipe(bytes) = make_cab_to_writer_with_options(Pipe(vec![]), &in_path, &options)
+            .expect("failed to create cab file");
+        assert_eq!(bytes, expected);
+
+        // Options are applied just as they are when writing to a file.
+        let datetime = NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8);
+        for options in &[
+            CabOptions {
+                threads: 2,
+                ..CabOptions::default()
+            },
+            CabOptions {
+                chunk_size: Some(1000),
+                ..CabOptions::default()
+            },
+            CabOptions {
+                compression_level: Some(0),
+                ..CabOptions::default()
+            },
+            CabOptions {
+                timestamps: TimestampSource::Fixed(datetime),
+                ..CabOptions::default()
+            },
+        ] {
+            let Pipe(bytes) = make_cab_to_writer_with_options(Pipe(vec![]), &in_path, options)
+                .expect("failed to create cab file");
+            make_cab_from_files_with_options(&cab, &[&in_path], options)
+                .expect("failed to create cab file");
+            let expected = std::fs::read(&cab).unwrap();
+            assert_eq!(bytes, expected);
+        }
+
+        // Directory trees are written the same way as to a file, with the
+        // same options.
+        let dir = t.path().join("dir");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "hello").unwrap();
+        std::fs::write(dir.join("sub").join("b.txt"), test_data(MAX_CHUNK)).unwrap();
+        let options = CabOptions {
+            chunk_size: Some(1000),
+            order: MemberOrder::Size,
+            ..CabOptions::default()
+        };
+        let filter = PathFilter::default();
+        let Pipe(bytes) = make_cab_from_dir_to_writer(Pipe(vec![]), &dir, &filter, &options)
+            .expect("failed to create cab file");
+        make_cab_from_dir(&cab, &dir, &filter, &options).expect("failed to create cab file");
+        assert_eq!(bytes, std::fs::read(&cab).unwrap());
+        assert_eq!(
+            list_cab(&cab)
+                .unwrap()
+                .files
+                .iter()
+                .map(|file| file.name.as_str())
+                .collect::<Vec<_>>(),
+            ["a.txt", "sub\\b.txt"]
+        );
+    }
+
+    #[test]
+    fn estimate_size() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("firefox.exe");
+        File::create(&in_path)
+            .and_then(|mut f| f.write_all(include_bytes!("../testdata/firefox.exe")))
+            .expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        make_cab(&cab, &in_path).expect("failed to create cab file");
+        let actual = std::fs::metadata(&cab).unwrap().len() as f64;
+
+        let estimate = estimate_cab_size(&[&in_path], &EstimateOptions::default())
+            .expect("failed to estimate cab size") as f64;
+        assert!(
+            (estimate - actual).abs() / actual < 0.25,
+            "estimate {} too far from actual size {}",
+            estimate,
+            actual
+        );
+    }
+
+    #[test]
+    fn sha256_manifest() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original.txt");
+        File::create(&in_path)
+            .and_then(|mut f| f.write_all(b"hello world"))
+            .expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        make_cab(&cab, &in_path).expect("failed to create cab file");
+
+        let manifest_path = write_sha256_manifest(&cab).expect("failed to write manifest");
+        assert_eq!(manifest_path, t.path().join("test.cab.sha256"));
+        let manifest = std::fs::read_to_string(&manifest_path).expect("failed to read manifest");
+        let cab_digest = sha256_hex(File::open(&cab).unwrap()).unwrap();
+        assert_eq!(
+            manifest,
+            format!(
+                "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  original.txt\n\
+                 {}  test.cab\n",
+                cab_digest
+            )
+        );
+    }
+
+    #[test]
+    fn name_by_hash() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original.txt");
+        std::fs::write(&in_path, b"hello world").expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        make_cab(&cab, &in_path).expect("failed to create cab file");
+        let digest = sha256_hex(File::open(&cab).unwrap()).unwrap();
+
+        let renamed = rename_by_hash(&cab, HASH_NAME_TEMPLATE).expect("failed to rename cab file");
+        assert_eq!(
+            renamed,
+            t.path().join(format!("test-{}.cab", &digest[..16]))
+        );
+        assert!(!cab.exists());
+        let renamed = rename_by_hash(&renamed, "{hash}.cab").expect("failed to rename cab file");
+        assert_eq!(renamed, t.path().join(format!("{}.cab", &digest[..16])));
+
+        assert!(rename_by_hash(&renamed, "{stem}.cab").is_err());
+        assert!(rename_by_hash(&renamed, "out/{hash}.cab").is_err());
+    }
+
+    #[test]
+    fn cancel() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("test.txt");
+        std::fs::write(&in_path, test_data(100_000)).expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        std::fs::write(&cab, b"previous").unwrap();
+        let options = CabOptions {
+            cancel: Some(Arc::new(AtomicBool::new(true))),
+            ..CabOptions::default()
+        };
+        let e = make_cab_from_files_with_options(&cab, &[&in_path], &options).unwrap_err();
+        assert!(e.is::<Cancelled>());
+        // The previous cabinet is untouched and the temporary file is gone.
+        assert_eq!(std::fs::read(&cab).unwrap(), b"previous");
+        assert_eq!(std::fs::read_dir(t.path()).unwrap().count(), 2);
+
+        options
+            .cancel
+            .as_ref()
+            .unwrap()
+            .store(false, Ordering::Relaxed);
+        make_cab_from_files_with_options(&cab, &[&in_path], &options)
+            .expect("failed to create cab file");
+        verify(&cab, &in_path).expect("cab file didn't verify");
+    }
+
+    #[test]
+    fn preallocate() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("test.txt");
+        std::fs::write(&in_path, vec![b'a'; 1_000_000]).expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            preallocate: true,
+            ..CabOptions::default()
+        };
+        let summary = make_cab_from_files_with_options(&cab, &[&in_path], &options)
+            .expect("failed to create cab file");
+        verify(&cab, &in_path).expect("cab file didn't verify");
+        assert_eq!(std::fs::metadata(&cab).unwrap().len(), summary.cab_size);
+        #[cfg(unix)]
+        {
+            // The space reserved for the worst case was given back.
+            use std::os::unix::fs::MetadataExt;
+            assert!(std::fs::metadata(&cab).unwrap().blocks() * 512 < 100_000);
+        }
+    }
+
+    #[test]
+    fn chunk_size() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("test.txt");
+        std::fs::write(&in_path, test_data(10_000)).expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        let options = |chunk_size| CabOptions {
+            chunk_size: Some(chunk_size),
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &[&in_path], &options(1000))
+            .expect("failed to create cab file");
+        verify(&cab, &in_path).expect("cab file didn't verify");
+        let parsed = crate::format::parse::parse_cab(&cab).unwrap();
+        assert_eq!(parsed.folders[0].data_count, 10);
+        assert_eq!(lint_cab(&cab).expect("failed to lint cab file"), vec![]);
+
+        for &bad in &[0, MAX_CHUNK + 1] {
+            assert!(make_cab_from_files_with_options(&cab, &[&in_path], &options(bad)).is_err());
+        }
+    }
+
+    #[test]
+    fn durable() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("test.txt");
+        std::fs::write(&in_path, test_data(1000)).expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            durable: true,
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &[&in_path], &options)
+            .expect("failed to create cab file");
+        verify(&cab, &in_path).expect("cab file didn't verify");
+        assert_eq!(std::fs::read_dir(t.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn pack_dir() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        for name in &["app.exe", "bin/app.pdb", "bin/app.obj", "obj/x64/lib.pdb"] {
+            let path = t.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, name.as_bytes()).unwrap();
+        }
+        let cab = t.path().join("out.cab");
+        let names = |filter: &PathFilter| {
+            let summary = make_cab_from_dir(&cab, t.path(), filter, &CabOptions::default())
+                .expect("failed to create cab file");
+            summary
+                .files
+                .into_iter()
+                .map(|f| f.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&PathFilter::default()),
+            vec![
+                "app.exe",
+                "bin\\app.obj",
+                "bin\\app.pdb",
+                "obj\\x64\\lib.pdb"
+            ]
+        );
+        // The cabinet from the last run isn't packed into the next.
+        assert_eq!(
+            names(&PathFilter::new(&["**/*.pdb", "*.exe"], &["obj/**"]).unwrap()),
+            vec!["app.exe", "bin\\app.pdb"]
+        );
+        assert!(make_cab_from_dir(
+            &cab,
+            t.path(),
+            &PathFilter::new(&["*.zip"], &[] as &[&str]).unwrap(),
+            &CabOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let paths = ["a/x.txt", "b/x.txt", "c/X.TXT", "d/x (2).txt"]
+            .iter()
+            .map(|name| {
+                let path = t.path().join(name);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, name).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("test.cab");
+        let e = make_cab_from_files(&cab, &paths).unwrap_err();
+        assert!(e.to_string().contains("would both be stored as 'x.txt'"));
+        assert!(!cab.exists());
+
+        let options = CabOptions {
+            duplicate_names: DuplicateNames::Rename,
+            ..CabOptions::default()
+        };
+        let summary = make_cab_from_files_with_options(&cab, &paths, &options)
+            .expect("failed to create cab file");
+        let names = summary
+            .files
+            .iter()
+            .map(|file| (file.name.as_str(), file.renamed_from.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ("x.txt", None),
+                ("x (3).txt", Some("x.txt")),
+                ("X (4).TXT", Some("X.TXT")),
+                ("x (2).txt", None),
+            ]
+        );
+        let out = t.path().join("out");
+        crate::extract_cab(&cab, &out).expect("failed to extract cab file");
+        assert_eq!(std::fs::read(out.join("x (3).txt")).unwrap(), b"b/x.txt");
+        assert_eq!(std::fs::read(out.join("X (4).TXT")).unwrap(), b"c/X.TXT");
+
+        // Files whose names only differ in case collide when packing a
+        // directory, on file systems that can hold both.
+        let dir = t.path().join("dir");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("README"), "upper").unwrap();
+        std::fs::write(dir.join("readme"), "lower").unwrap();
+        if std::fs::read_dir(&dir).unwrap().count() == 2 {
+            let filter = PathFilter::default();
+            let defaults = CabOptions::default();
+            assert!(make_cab_from_dir(&cab, &dir, &filter, &defaults).is_err());
+            let summary = make_cab_from_dir(&cab, &dir, &filter, &options)
+                .expect("failed to create cab file");
+            assert_eq!(summary.files[1].name, "readme (2)");
+        }
+    }
+
+    #[test]
+    fn separators() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let dir = t.path().join("dir");
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::write(dir.join("bin").join("app.pdb"), "symbols").unwrap();
+        let cab = t.path().join("test.cab");
+        let stored = |separator| {
+            let options = CabOptions {
+                separator,
+                ..CabOptions::default()
+            };
+            make_cab_from_dir(&cab, &dir, &PathFilter::default(), &options)
+                .expect("failed to create cab file");
+            verify_cab(&cab).unwrap().files[0].name.clone()
+        };
+        assert_eq!(stored(PathSeparator::Backslash), "bin\\app.pdb");
+        assert_eq!(stored(PathSeparator::Slash), "bin/app.pdb");
+        assert_eq!(stored(PathSeparator::Preserve), "bin\\app.pdb");
+
+        // Names given with `/` are stored with `\\` by default.
+        let mtime = NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8);
+        let inputs = vec![LazyInput::new("lib/a.txt", mtime, || {
+            Ok(io::Cursor::new(b"a".to_vec()))
+        })];
+        make_cab_from_lazy_inputs(&cab, inputs).expect("failed to create cab file");
+        assert_eq!(verify_cab(&cab).unwrap().files[0].name, "lib\\a.txt");
+    }
+
+    #[test]
+    fn timestamp_sources() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("a.txt");
+        std::fs::write(&path, "a").unwrap();
+        let mtime = NaiveDate::from_ymd(2019, 1, 2).and_hms(3, 4, 6);
+        filetime::set_file_mtime(&path, FileTime::from_unix_time(mtime.timestamp(), 0)).unwrap();
+        let cab = t.path().join("test.cab");
+        let stored = |timestamps| {
+            let options = CabOptions {
+                timestamps,
+                ..CabOptions::default()
+            };
+            make_cab_from_files_with_options(&cab, &[&path], &options)
+                .expect("failed to create cab file");
+            list_cab(&cab).unwrap().files[0].modified.unwrap()
+        };
+        assert_eq!(stored(TimestampSource::Modified), mtime);
+        let fixed = NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8);
+        assert_eq!(stored(TimestampSource::Fixed(fixed)), fixed);
+        let recent =
+            |datetime: NaiveDateTime| (Utc::now().naive_utc() - datetime).num_seconds().abs() < 60;
+        assert!(recent(stored(TimestampSource::Now)));
+        // Not every file system records creation times.
+        let created = stored(TimestampSource::Created);
+        assert!(created == mtime || recent(created));
+    }
+
+    #[test]
+    fn member_order() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let paths = [("b.txt", 30), ("c.dll", 10), ("a.TXT", 20), ("readme", 20)]
+            .iter()
+            .map(|&(name, size)| {
+                let path = t.path().join(name);
+                std::fs::write(&path, vec![b'x'; size]).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("test.cab");
+        let names = |order| {
+            let options = CabOptions {
+                order,
+                ..CabOptions::default()
+            };
+            make_cab_from_files_with_options(&cab, &paths, &options)
+                .expect("failed to create cab file")
+                .files
+                .into_iter()
+                .map(|file| file.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(MemberOrder::Input),
+            vec!["b.txt", "c.dll", "a.TXT", "readme"]
+        );
+        assert_eq!(
+            names(MemberOrder::Name),
+            vec!["a.TXT", "b.txt", "c.dll", "readme"]
+        );
+        assert_eq!(
+            names(MemberOrder::Size),
+            vec!["c.dll", "a.TXT", "readme", "b.txt"]
+        );
+        assert_eq!(
+            names(MemberOrder::Extension),
+            vec!["readme", "c.dll", "a.TXT", "b.txt"]
+        );
+        // The summary follows the order the files were written in.
+        let files = verify_cab(&cab)
+            .expect("failed to read cab file")
+            .files
+            .into_iter()
+            .map(|file| file.name)
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec!["readme", "c.dll", "a.TXT", "b.txt"]);
+    }
+
     /// Generate a `Vec<u8>` of test data of `size` bytes.
+    #[allow(clippy::legacy_numeric_constants)]
     fn test_data(size: usize) -> Vec<u8> {
         (0..size)
             .map(|v| (v % (u8::max_value() as usize + 1)) as u8)
@@ -125,8 +2805,6 @@ Its stderr was:
         };
     }
 
-    const MAX_CHUNK: usize = 32 * 1024;
-
     t!(zeroes, vec![0; 1000]);
     t!(nonzero_many_blocks, test_data(MAX_CHUNK * 8));
     t!(firefox_exe, include_bytes!("../testdata/firefox.exe"));
@@ -0,0 +1,530 @@
+//! Checking cabinets for violations of the cabinet format specification.
+//!
+//! This works directly on the bytes of the cabinet rather than through the
+//! `cab` crate, so that it can describe problems a reader would either choke
+//! on or silently accept.
+
+#![allow(non_upper_case_globals)]
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::format::parse::{read_file_entry_raw, read_folder, read_header};
+use crate::format::{
+    cfhdrNEXT_CABINET, cfhdrPREV_CABINET, cfhdrRESERVE_PRESENT, checksum, ifoldCONTINUED_FROM_PREV,
+    ifoldCONTINUED_PREV_AND_NEXT, ifoldCONTINUED_TO_NEXT, read_u32, tcompTYPE_LZX, tcompTYPE_MASK,
+    tcompTYPE_MSZIP, tcompTYPE_NONE, tcompTYPE_QUANTUM, FileEntry, Folder, _A_ARCH, _A_EXEC,
+    _A_HIDDEN, _A_NAME_IS_UTF, _A_RDONLY, _A_SYSTEM, MAX_COMPRESSED_CHUNK, MAX_STRING,
+};
+use crate::{CFDATA_SIZE, CFHEADER_SIZE, MAX_CHUNK, MAX_HEADER_RESERVE};
+
+/// A violation of the cabinet format found by [`lint_cab`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Violation {
+    /// The offset in the cabinet file of the structure with the problem.
+    pub offset: u64,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:08x}: {}", self.offset, self.message)
+    }
+}
+
+/// Check the structure of the cabinet file at `cab_path` against the
+/// cabinet format specification, returning every violation found.
+///
+/// This checks that the header's sizes, counts and flags are consistent with
+/// the rest of the file, that offsets point inside the file and increase
+/// monotonically, that file entries refer to real folders and have valid
+/// names, attributes and dates, and that every CFDATA block has a sensible
+/// size and a correct checksum. It doesn't decompress any data; use
+/// [`verify_cab`](crate::verify_cab) for that.
+///
+/// An error is only returned if the file can't be read; a cabinet too broken
+/// to check any further is reported as a violation.
+pub fn lint_cab<T: AsRef<Path>>(cab_path: T) -> Result<Vec<Violation>> {
+    let file = File::open(cab_path)?;
+    let len = file.metadata()?.len();
+    let mut linter = Linter {
+        reader: BufReader::new(file),
+        len,
+        violations: vec![],
+    };
+    match linter.lint() {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            let offset = linter.reader.stream_position()?;
+            linter.violation(offset, "Unexpected end of file");
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(linter.violations)
+}
+
+struct Linter<R> {
+    reader: R,
+    len: u64,
+    violations: Vec<Violation>,
+}
+
+impl<R: Read + Seek> Linter<R> {
+    fn violation<S: Into<String>>(&mut self, offset: u64, message: S) {
+        self.violations.push(Violation {
+            offset,
+            message: message.into(),
+        });
+    }
+
+    fn lint(&mut self) -> io::Result<()> {
+        let mut fixed = [0; CFHEADER_SIZE as usize];
+        self.reader.read_exact(&mut fixed[..4])?;
+        if &fixed[..4] != b"MSCF" {
+            self.violation(0, "Bad signature, not a cabinet file");
+            return Ok(());
+        }
+        self.reader.read_exact(&mut fixed[4..])?;
+        if fixed[4..8] != [0; 4] || fixed[12..16] != [0; 4] || fixed[20..24] != [0; 4] {
+            self.violation(4, "Reserved header fields are not zero");
+        }
+        let header = read_header(&mut (&fixed[..]).chain(&mut self.reader))?;
+
+        if u64::from(header.cabinet_size) != self.len {
+            self.violation(
+                8,
+                format!(
+                    "cbCabinet is {} but the file is {} bytes",
+                    header.cabinet_size, self.len
+                ),
+            );
+        }
+        if (header.version_major, header.version_minor) != (1, 3) {
+            self.violation(
+                24,
+                format!(
+                    "Unknown version {}.{}",
+                    header.version_major, header.version_minor
+                ),
+            );
+        }
+        let flags = header.flags;
+        if flags & !(cfhdrPREV_CABINET | cfhdrNEXT_CABINET | cfhdrRESERVE_PRESENT) != 0 {
+            self.violation(30, format!("Unknown header flags 0x{:04x}", flags));
+        }
+        let mut offset = CFHEADER_SIZE;
+        if flags & cfhdrRESERVE_PRESENT != 0 {
+            if header.header_reserve.len() > MAX_HEADER_RESERVE as usize {
+                self.violation(
+                    CFHEADER_SIZE,
+                    format!(
+                        "cbCFHeader is {}, more than the maximum of {}",
+                        header.header_reserve.len(),
+                        MAX_HEADER_RESERVE
+                    ),
+                );
+            }
+            offset += 4 + header.header_reserve.len() as u64;
+        }
+        let links = [
+            (&header.prev, "szCabinetPrev", "szDiskPrev"),
+            (&header.next, "szCabinetNext", "szDiskNext"),
+        ];
+        for (link, cabinet_field, disk_field) in &links {
+            if let Some(link) = link {
+                for (s, field) in &[(&link.cabinet, cabinet_field), (&link.disk, disk_field)] {
+                    self.lint_string(offset, field, s);
+                    offset += s.len() as u64 + 1;
+                }
+            }
+        }
+
+        let mut folders = Vec::with_capacity(header.folder_count as usize);
+        for _ in 0..header.folder_count {
+            let offset = self.reader.stream_position()?;
+            let folder = read_folder(&mut self.reader, header.folder_reserve_size)?;
+            folders.push((offset, folder));
+        }
+        let folders_end = self.reader.stream_position()?;
+        let coffFiles = header.files_offset;
+        if u64::from(coffFiles) < folders_end {
+            self.violation(
+                16,
+                format!(
+                    "coffFiles is 0x{:x}, which overlaps the folder table ending at 0x{:x}",
+                    coffFiles, folders_end
+                ),
+            );
+        }
+        if u64::from(coffFiles) >= self.len {
+            self.violation(
+                16,
+                format!("coffFiles 0x{:x} is past the end of the file", coffFiles),
+            );
+            return Ok(());
+        }
+
+        self.reader.seek(SeekFrom::Start(u64::from(coffFiles)))?;
+        let mut files = Vec::with_capacity(header.file_count as usize);
+        for _ in 0..header.file_count {
+            files.push(self.lint_file(folders.len(), flags)?);
+        }
+        let files_end = self.reader.stream_position()?;
+
+        self.lint_file_order(&files);
+        let mut prev_end = files_end;
+        let cbCFData = u64::from(header.data_reserve_size);
+        for (index, (offset, folder)) in folders.iter().enumerate() {
+            // The first folder of a cabinet continued from a previous one
+            // starts partway through the folder's data.
+            let continued = index == 0 && flags & cfhdrPREV_CABINET != 0;
+            prev_end = self.lint_folder(
+                index, *offset, folder, continued, &files, cbCFData, prev_end,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn lint_string(&mut self, offset: u64, field: &str, s: &str) {
+        if s.len() + 1 > MAX_STRING {
+            self.violation(
+                offset,
+                format!(
+                    "{} is {} bytes long, more than the maximum of {}",
+                    field,
+                    s.len(),
+                    MAX_STRING - 1
+                ),
+            );
+        }
+    }
+
+    fn lint_file(&mut self, folder_count: usize, flags: u16) -> io::Result<(u64, FileEntry)> {
+        let offset = self.reader.stream_position()?;
+        let (file, name) = read_file_entry_raw(&mut self.reader)?;
+        let (date, time, attribs) = (file.date, file.time, file.attributes);
+        let display_name = &file.name;
+
+        match file.folder {
+            ifoldCONTINUED_FROM_PREV | ifoldCONTINUED_PREV_AND_NEXT
+                if flags & cfhdrPREV_CABINET == 0 =>
+            {
+                self.violation(
+                    offset,
+                    format!(
+                        "'{}' is continued from a previous cabinet, but there isn't one",
+                        display_name
+                    ),
+                );
+            }
+            ifoldCONTINUED_TO_NEXT if flags & cfhdrNEXT_CABINET == 0 => {
+                self.violation(
+                    offset,
+                    format!(
+                        "'{}' is continued in the next cabinet, but there isn't one",
+                        display_name
+                    ),
+                );
+            }
+            ifoldCONTINUED_FROM_PREV | ifoldCONTINUED_TO_NEXT | ifoldCONTINUED_PREV_AND_NEXT => {}
+            i if i as usize >= folder_count => {
+                self.violation(
+                    offset,
+                    format!(
+                        "'{}' is in folder {}, but there are only {} folders",
+                        display_name, i, folder_count
+                    ),
+                );
+            }
+            _ => {}
+        }
+        if name.is_empty() {
+            self.violation(offset, "File has an empty name");
+        } else if name.len() + 1 > MAX_STRING {
+            self.violation(
+                offset,
+                format!(
+                    "Name of '{}' is longer than the maximum of {} bytes",
+                    display_name,
+                    MAX_STRING - 1
+                ),
+            );
+        }
+        if attribs & _A_NAME_IS_UTF != 0 {
+            if std::str::from_utf8(&name).is_err() {
+                self.violation(
+                    offset,
+                    format!(
+                        "Name of '{}' is marked as UTF-8 but isn't valid UTF-8",
+                        display_name
+                    ),
+                );
+            }
+        } else if !name.is_ascii() && std::str::from_utf8(&name).is_ok() {
+            self.violation(
+                offset,
+                format!(
+                    "Name of '{}' looks like UTF-8 but isn't marked as UTF-8",
+                    display_name
+                ),
+            );
+        }
+        let known = _A_RDONLY | _A_HIDDEN | _A_SYSTEM | _A_ARCH | _A_EXEC | _A_NAME_IS_UTF;
+        if attribs & !known != 0 {
+            self.violation(
+                offset,
+                format!(
+                    "'{}' has unknown attributes 0x{:04x}",
+                    display_name, attribs
+                ),
+            );
+        }
+        let (month, day) = ((date >> 5) & 0xf, date & 0x1f);
+        let (hour, minute, second) = (time >> 11, (time >> 5) & 0x3f, (time & 0x1f) * 2);
+        if !(1..=12).contains(&month) || day == 0 || hour > 23 || minute > 59 || second > 59 {
+            self.violation(
+                offset,
+                format!(
+                    "'{}' has an invalid date/time 0x{:04x} 0x{:04x}",
+                    display_name, date, time
+                ),
+            );
+        }
+        Ok((offset, file))
+    }
+
+    /// Check that file entries are grouped by folder in folder order.
+    fn lint_file_order(&mut self, files: &[(u64, FileEntry)]) {
+        let mut prev = 0;
+        for (offset, file) in files {
+            let folder = match file.folder {
+                ifoldCONTINUED_FROM_PREV | ifoldCONTINUED_PREV_AND_NEXT => 0,
+                i => i,
+            };
+            if folder < prev {
+                self.violation(
+                    *offset,
+                    format!("File in folder {} follows files in folder {}", folder, prev),
+                );
+            }
+            prev = prev.max(folder);
+        }
+    }
+
+    /// Check the CFDATA blocks of a folder, which should start after
+    /// `prev_end`, and the files in it, which start partway through the
+    /// folder if it's `continued` from a previous cabinet. Returns the
+    /// offset of the end of the folder's data.
+    #[allow(clippy::too_many_arguments)]
+    fn lint_folder(
+        &mut self,
+        index: usize,
+        folder_offset: u64,
+        folder: &Folder,
+        continued: bool,
+        files: &[(u64, FileEntry)],
+        cbCFData: u64,
+        prev_end: u64,
+    ) -> io::Result<u64> {
+        let ctype = folder.compression & tcompTYPE_MASK;
+        if ![
+            tcompTYPE_NONE,
+            tcompTYPE_MSZIP,
+            tcompTYPE_QUANTUM,
+            tcompTYPE_LZX,
+        ]
+        .contains(&ctype)
+        {
+            self.violation(
+                folder_offset,
+                format!(
+                    "Folder {} has unknown compression type 0x{:04x}",
+                    index, folder.compression
+                ),
+            );
+        }
+        let start = u64::from(folder.data_offset);
+        if start < prev_end {
+            self.violation(
+                folder_offset,
+                format!(
+                    "Data for folder {} starts at 0x{:x}, overlapping data ending at 0x{:x}",
+                    index, start, prev_end
+                ),
+            );
+        }
+        if start > self.len {
+            self.violation(
+                folder_offset,
+                format!(
+                    "Data for folder {} starts at 0x{:x}, past the end of the file",
+                    index, start
+                ),
+            );
+            return Ok(prev_end);
+        }
+
+        self.reader.seek(SeekFrom::Start(start))?;
+        let mut uncompressed = 0u64;
+        let mut data = Vec::with_capacity(MAX_COMPRESSED_CHUNK);
+        for block in 0..folder.data_count {
+            let offset = self.reader.stream_position()?;
+            let what = format!("Block {} of folder {}", block, index);
+            if offset + CFDATA_SIZE + cbCFData > self.len {
+                self.violation(offset, format!("{} is past the end of the file", what));
+                return Ok(self.len);
+            }
+            let csum = read_u32(&mut self.reader)?;
+            let mut sizes = [0; 4];
+            self.reader.read_exact(&mut sizes)?;
+            let cbData = u16::from_le_bytes([sizes[0], sizes[1]]);
+            let cbUncomp = u16::from_le_bytes([sizes[2], sizes[3]]);
+            let mut reserve = vec![0; cbCFData as usize];
+            self.reader.read_exact(&mut reserve)?;
+            if offset + CFDATA_SIZE + cbCFData + u64::from(cbData) > self.len {
+                self.violation(offset, format!("{} is truncated", what));
+                return Ok(self.len);
+            }
+            data.resize(cbData as usize, 0);
+            self.reader.read_exact(&mut data)?;
+
+            if cbUncomp as usize > MAX_CHUNK {
+                self.violation(
+                    offset,
+                    format!(
+                        "{} has {} uncompressed bytes, more than the maximum of {}",
+                        what, cbUncomp, MAX_CHUNK
+                    ),
+                );
+            }
+            if cbData as usize > MAX_COMPRESSED_CHUNK {
+                self.violation(
+                    offset,
+                    format!(
+                        "{} has {} compressed bytes, more than the maximum of {}",
+                        what, cbData, MAX_COMPRESSED_CHUNK
+                    ),
+                );
+            }
+            if cbUncomp == 0 && block + 1 != folder.data_count {
+                self.violation(offset, format!("{} is empty", what));
+            }
+            if ctype == tcompTYPE_NONE && cbData != cbUncomp {
+                self.violation(
+                    offset,
+                    format!(
+                        "{} is uncompressed but has different compressed and uncompressed sizes",
+                        what
+                    ),
+                );
+            }
+            if ctype == tcompTYPE_MSZIP && !data.starts_with(b"CK") {
+                self.violation(
+                    offset,
+                    format!("{} is MSZIP compressed but doesn't start with 'CK'", what),
+                );
+            }
+            if csum != 0 {
+                let mut header = sizes.to_vec();
+                header.extend_from_slice(&reserve);
+                let actual = checksum(&header, checksum(&data, 0));
+                if actual != csum {
+                    self.violation(
+                        offset,
+                        format!(
+                            "{} has checksum 0x{:08x} but its data has checksum 0x{:08x}",
+                            what, csum, actual
+                        ),
+                    );
+                }
+            }
+            uncompressed += u64::from(cbUncomp);
+        }
+        let end = self.reader.stream_position()?;
+
+        let mut expected_offset = None;
+        for (offset, file) in files.iter().filter(|(_, f)| f.folder as usize == index) {
+            let file_start = u64::from(file.folder_offset);
+            let file_end = file_start + u64::from(file.size);
+            let expected = match expected_offset {
+                Some(expected) => expected,
+                None if continued => file_start,
+                None => 0,
+            };
+            if file_start != expected {
+                self.violation(
+                    *offset,
+                    format!(
+                        "File starts at offset {} in folder {}, but the previous file ended at {}",
+                        file_start, index, expected
+                    ),
+                );
+            }
+            if file_end > uncompressed && !continued {
+                self.violation(
+                    *offset,
+                    format!(
+                        "File ends at offset {} in folder {}, which only holds {} bytes",
+                        file_end, index, uncompressed
+                    ),
+                );
+            }
+            expected_offset = Some(file_end);
+        }
+        Ok(end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+
+    use self::tempdir::TempDir;
+    use super::lint_cab;
+    use crate::make_cab;
+
+    #[test]
+    fn lint() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original.txt");
+        fs::write(&in_path, vec![b'x'; 100_000]).expect("failed to write test data");
+        let cab = t.path().join("test.cab");
+        make_cab(&cab, &in_path).expect("failed to create cab file");
+        assert_eq!(lint_cab(&cab).expect("failed to lint cab file"), vec![]);
+
+        let good = fs::read(&cab).expect("failed to read cab file");
+        // Claim there are two files.
+        let mut bytes = good.clone();
+        bytes[28] = 2;
+        fs::write(&cab, &bytes).unwrap();
+        let violations = lint_cab(&cab).expect("failed to lint cab file");
+        assert!(!violations.is_empty());
+
+        // Corrupt the last data block.
+        let mut bytes = good.clone();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        fs::write(&cab, &bytes).unwrap();
+        let violations = lint_cab(&cab).expect("failed to lint cab file");
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].message.contains("checksum"));
+
+        // Truncate the file.
+        fs::write(&cab, &good[..good.len() - 10]).unwrap();
+        let violations = lint_cab(&cab).expect("failed to lint cab file");
+        assert!(violations.iter().any(|v| v.message.contains("cbCabinet")));
+        assert!(violations.iter().any(|v| v.message.contains("truncated")));
+
+        fs::write(&cab, b"not a cab").unwrap();
+        let violations = lint_cab(&cab).expect("failed to lint cab file");
+        assert_eq!(violations.len(), 1);
+    }
+}
@@ -0,0 +1,198 @@
+//! Listing the folders and files in a cabinet.
+
+#![allow(non_upper_case_globals)]
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::{Serialize, Serializer};
+
+use crate::format::parse::{parse, read_block_sizes};
+use crate::format::{
+    from_dos_datetime, tcompTYPE_LZX, tcompTYPE_MASK, tcompTYPE_MSZIP, tcompTYPE_NONE,
+    tcompTYPE_QUANTUM, _A_ARCH, _A_EXEC, _A_HIDDEN, _A_RDONLY, _A_SYSTEM,
+};
+use crate::{Attribute, CFDATA_SIZE};
+
+/// The folders and files in a cabinet, as listed by [`list_cab`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CabListing {
+    /// The folders, in the order they appear in the cabinet.
+    pub folders: Vec<FolderListing>,
+    /// The files, in the order they appear in the cabinet.
+    pub files: Vec<FileListing>,
+}
+
+/// A folder in a [`CabListing`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FolderListing {
+    /// The name of the folder's compression type: `None`, `MSZIP`,
+    /// `Quantum` or `LZX`.
+    pub compression: String,
+    /// The number of data blocks in the folder.
+    pub blocks: u16,
+    /// The size of the folder's data before compression.
+    pub size: u64,
+    /// The size of the folder's data blocks in the cabinet, including
+    /// their headers.
+    pub compressed_size: u64,
+}
+
+/// A file in a [`CabListing`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FileListing {
+    /// The name the file is stored under.
+    pub name: String,
+    /// The index of the folder holding the file, or one of the
+    /// `ifoldCONTINUED_*` constants.
+    pub folder: u16,
+    /// The size of the file.
+    pub size: u32,
+    /// The modification time of the file, or `None` if the date and time
+    /// stored for it aren't valid.
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub modified: Option<NaiveDateTime>,
+    /// The file's attributes.
+    pub attributes: Vec<Attribute>,
+}
+
+fn serialize_timestamp<S: Serializer>(
+    timestamp: &Option<NaiveDateTime>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match timestamp {
+        Some(timestamp) => serializer.collect_str(&timestamp.format("%Y-%m-%dT%H:%M:%S")),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// List the folders and files in the cabinet file at `cab_path`.
+///
+/// Only the cabinet's records and the headers of its data blocks are read,
+/// so this is quick even for large cabinets.
+pub fn list_cab<T: AsRef<Path>>(cab_path: T) -> Result<CabListing> {
+    let mut reader = BufReader::new(File::open(cab_path)?);
+    let cabinet = parse(&mut reader)?;
+    let data_reserve_size = cabinet.header.data_reserve_size;
+    let folders = cabinet
+        .folders
+        .iter()
+        .map(|folder| {
+            let sizes = read_block_sizes(&mut reader, folder, data_reserve_size)?;
+            let compression = match folder.compression & tcompTYPE_MASK {
+                tcompTYPE_NONE => "None",
+                tcompTYPE_MSZIP => "MSZIP",
+                tcompTYPE_QUANTUM => "Quantum",
+                tcompTYPE_LZX => "LZX",
+                _ => "Unknown",
+            };
+            Ok(FolderListing {
+                compression: compression.to_owned(),
+                blocks: folder.data_count,
+                size: sizes.iter().map(|&(_, size)| u64::from(size)).sum(),
+                compressed_size: sizes
+                    .iter()
+                    .map(|&(size, _)| CFDATA_SIZE + u64::from(data_reserve_size) + u64::from(size))
+                    .sum(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let files = cabinet
+        .files
+        .into_iter()
+        .map(|file| {
+            let attributes = [
+                (_A_RDONLY, Attribute::ReadOnly),
+                (_A_HIDDEN, Attribute::Hidden),
+                (_A_SYSTEM, Attribute::System),
+                (_A_ARCH, Attribute::Archive),
+                (_A_EXEC, Attribute::Exec),
+            ]
+            .iter()
+            .filter(|&&(bit, _)| file.attributes & bit != 0)
+            .map(|&(_, attribute)| attribute)
+            .collect();
+            FileListing {
+                modified: from_dos_datetime(file.date, file.time),
+                name: file.name,
+                folder: file.folder,
+                size: file.size,
+                attributes,
+            }
+        })
+        .collect();
+    Ok(CabListing { folders, files })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+
+    use chrono::NaiveDate;
+
+    use self::tempdir::TempDir;
+    use super::list_cab;
+    use crate::{make_cabs_from_manifest, Attribute};
+
+    #[test]
+    fn list() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        fs::write(t.path().join("a.txt"), vec![b'a'; 100_000]).unwrap();
+        fs::write(t.path().join("b.txt"), b"bbb").unwrap();
+        let manifest = t.path().join("build.json");
+        fs::write(
+            &manifest,
+            r#"{
+                "cab": "out.cab",
+                "entries": [
+                    {"source": "a.txt", "timestamp": "2021-03-04T05:06:08"},
+                    {"source": "b.txt", "compression": "none",
+                     "attributes": ["read-only", "hidden"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        make_cabs_from_manifest(&manifest).expect("failed to create cab file");
+        let cab = t.path().join("out.cab");
+
+        let listing = list_cab(&cab).expect("failed to list cab file");
+        let compression = listing
+            .folders
+            .iter()
+            .map(|f| f.compression.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(compression, vec!["MSZIP", "None"]);
+        assert_eq!(listing.folders[0].size, 100_000);
+        assert_eq!(listing.folders[0].blocks, 4);
+        assert!(listing.folders[0].compressed_size < 10_000);
+        assert_eq!(listing.folders[1].compressed_size, 8 + 3);
+        let data_size = listing
+            .folders
+            .iter()
+            .map(|f| f.compressed_size)
+            .sum::<u64>();
+        let parsed = crate::format::parse::parse_cab(&cab).unwrap();
+        assert_eq!(
+            data_size,
+            u64::from(parsed.header.cabinet_size - parsed.folders[0].data_offset)
+        );
+
+        assert_eq!(listing.files[0].name, "a.txt");
+        assert_eq!(
+            listing.files[0].modified,
+            Some(NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8))
+        );
+        assert_eq!(listing.files[1].folder, 1);
+        assert_eq!(
+            listing.files[1].attributes,
+            vec![Attribute::ReadOnly, Attribute::Hidden, Attribute::Archive]
+        );
+        let json = serde_json::to_value(&listing.files[0]).unwrap();
+        assert_eq!(json["modified"], "2021-03-04T05:06:08");
+    }
+}
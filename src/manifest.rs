@@ -0,0 +1,358 @@
+//! Writing cabinets described by a JSON manifest.
+//!
+//! A manifest is a modern stand-in for the DDF directive files Microsoft's
+//! `makecab` reads: a list of entries, each naming a file to store and,
+//! optionally, the name to store it under, its timestamp and attributes,
+//! how to compress it, and which cabinet to put it in. Build tools can
+//! generate one with any JSON library, and each entry becomes a file added
+//! to a `CabinetBuilder`.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use cab::{CabinetBuilder, CompressionType};
+use chrono::{Datelike, NaiveDateTime};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    block_summaries, check_limits, stored_name, CabSummary, EndTracker, FileSummary, Input,
+    PathSeparator, MAX_CHUNK,
+};
+
+/// A manifest describing the cabinets to write, as read from JSON like:
+///
+/// ```json
+/// {
+///   "cab": "out.cab",
+///   "entries": [
+///     { "source": "build/app.exe", "name": "bin\\app.exe" },
+///     {
+///       "source": "build/app.pdb",
+///       "cab": "symbols.cab",
+///       "timestamp": "2021-03-04T05:06:08",
+///       "attributes": ["read-only"],
+///       "compression": "none"
+///     }
+///   ]
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// The cabinet to put entries in that don't name their own.
+    #[serde(default)]
+    pub cab: Option<PathBuf>,
+    /// The separator to store in names with paths, as `"backslash"`, the
+    /// default, `"slash"` or `"preserve"`.
+    #[serde(default)]
+    pub separator: PathSeparator,
+    /// The files to store, in order.
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// A file to store in a cabinet described by a [`Manifest`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    /// Where to read the file's contents from.
+    pub source: PathBuf,
+    /// The name to store the file under, which may include a path with `\`
+    /// between components. Defaults to the source's file name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The cabinet to put the file in, if not the manifest's.
+    #[serde(default)]
+    pub cab: Option<PathBuf>,
+    /// The timestamp to store for the file, as `YYYY-MM-DDTHH:MM:SS`.
+    /// Defaults to the source's modification time.
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    pub timestamp: Option<NaiveDateTime>,
+    /// The attributes to store for the file.
+    #[serde(default)]
+    pub attributes: Vec<Attribute>,
+    /// How to compress the file.
+    #[serde(default)]
+    pub compression: EntryCompression,
+}
+
+/// A file attribute, written in a manifest as `read-only`, `hidden`,
+/// `system`, `archive` or `exec`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Attribute {
+    ReadOnly,
+    Hidden,
+    System,
+    Archive,
+    Exec,
+}
+
+/// How to compress a file, written in a manifest as `mszip` or `none`.
+///
+/// Each run of consecutive entries in a cabinet with the same compression
+/// is stored in its own folder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryCompression {
+    None,
+    #[default]
+    MsZip,
+}
+
+fn deserialize_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<NaiveDateTime>, D::Error> {
+    let timestamp = String::deserialize(deserializer)?;
+    NaiveDateTime::parse_from_str(&timestamp, "%Y-%m-%dT%H:%M:%S")
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+impl Manifest {
+    /// Read a manifest from `json`.
+    pub fn from_json(json: &str) -> Result<Manifest> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Write every cabinet the manifest describes, with relative source and
+    /// cabinet paths taken relative to `base_dir`. Returns a summary of each
+    /// cabinet, in the order they're first named.
+    pub fn make_cabs<T: AsRef<Path>>(&self, base_dir: T) -> Result<Vec<CabSummary>> {
+        let base_dir = base_dir.as_ref();
+        let mut cabs: Vec<(PathBuf, Vec<&ManifestEntry>)> = vec![];
+        for entry in &self.entries {
+            let cab_path = match entry.cab.as_ref().or(self.cab.as_ref()) {
+                Some(cab_path) => base_dir.join(cab_path),
+                None => bail!("No cabinet given for {:?}", entry.source),
+            };
+            match cabs.iter_mut().find(|(path, _)| *path == cab_path) {
+                Some((_, entries)) => entries.push(entry),
+                None => cabs.push((cab_path, vec![entry])),
+            }
+        }
+        cabs.iter()
+            .map(|(cab_path, entries)| {
+                write_manifest_cab(cab_path, entries, base_dir, self.separator)
+            })
+            .collect()
+    }
+}
+
+/// Write every cabinet described by the JSON manifest at `manifest_path`,
+/// with relative paths in it taken relative to the manifest's directory.
+/// Returns a summary of each cabinet, in the order they're first named.
+pub fn make_cabs_from_manifest<T: AsRef<Path>>(manifest_path: T) -> Result<Vec<CabSummary>> {
+    let manifest_path = manifest_path.as_ref();
+    let manifest = Manifest::from_json(&fs::read_to_string(manifest_path)?)?;
+    manifest.make_cabs(manifest_path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Write a cabinet at `cab_path` holding `entries`, with `separator` in
+/// their names.
+fn write_manifest_cab(
+    cab_path: &Path,
+    entries: &[&ManifestEntry],
+    base_dir: &Path,
+    separator: PathSeparator,
+) -> Result<CabSummary> {
+    let start = Instant::now();
+    let sources = entries
+        .iter()
+        .map(|entry| base_dir.join(&entry.source))
+        .collect::<Vec<_>>();
+    let names = entries
+        .iter()
+        .zip(&sources)
+        .map(|(entry, source)| match &entry.name {
+            Some(name) => Ok(separator.apply(name)),
+            None => stored_name(source).map(Cow::Borrowed),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut seen = HashSet::new();
+    for name in &names {
+        if name.is_empty() || !seen.insert(name.to_lowercase()) {
+            bail!("Bad or duplicate name {:?} in {:?}", name, cab_path);
+        }
+    }
+    check_limits(names.iter().map(|name| &**name), None, MAX_CHUNK)?;
+    let inputs = sources
+        .iter()
+        .zip(&names)
+        .map(|(source, name)| Input::with_name(source, name))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Each run of entries with the same compression gets its own folder.
+    let mut folders: Vec<(EntryCompression, Vec<usize>)> = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        match folders.last_mut() {
+            Some((compression, files)) if *compression == entry.compression => files.push(i),
+            _ => folders.push((entry.compression, vec![i])),
+        }
+    }
+    let mut cab_builder = CabinetBuilder::new();
+    for (compression, files) in &folders {
+        let folder = cab_builder.add_folder(match compression {
+            EntryCompression::None => CompressionType::None,
+            EntryCompression::MsZip => CompressionType::MsZip,
+        });
+        for &i in files {
+            let (entry, input) = (entries[i], &inputs[i]);
+            let file = folder.add_file(input.name);
+            let datetime = entry.timestamp.unwrap_or(input.mtime);
+            if !(1980..=2107).contains(&datetime.year()) {
+                bail!("Timestamp of {:?} can't be stored in a cabinet", input.name);
+            }
+            file.set_datetime(datetime);
+            for attribute in &entry.attributes {
+                match attribute {
+                    Attribute::ReadOnly => file.set_is_read_only(true),
+                    Attribute::Hidden => file.set_is_hidden(true),
+                    Attribute::System => file.set_is_system(true),
+                    Attribute::Archive => file.set_is_archive(true),
+                    Attribute::Exec => file.set_is_exec(true),
+                }
+            }
+        }
+    }
+
+    let end = Rc::new(Cell::new(0));
+    let writer = EndTracker {
+        inner: File::create(cab_path)?,
+        pos: 0,
+        end: end.clone(),
+    };
+    let mut cab_writer = cab_builder.build(writer)?;
+    let mut inputs = inputs.iter();
+    let mut files: Vec<FileSummary> = vec![];
+    let mut last_end = end.get();
+    while let (Some(mut writer), Some(input)) = (cab_writer.next_file()?, inputs.next()) {
+        // Moving on to a new folder flushes the last block of the previous
+        // one, which belongs to the previous file.
+        if let Some(last) = files.last_mut() {
+            last.compressed_size += end.get() - last_end;
+        }
+        let file_start = end.get();
+        let size = io::copy(&mut File::open(input.path)?, &mut writer)?;
+        files.push(FileSummary {
+            name: input.name.to_owned(),
+            size,
+            compressed_size: end.get() - file_start,
+            renamed_from: None,
+        });
+        last_end = end.get();
+    }
+    let cab_file = cab_writer.finish()?.inner;
+    if let Some(last) = files.last_mut() {
+        last.compressed_size += end.get() - last_end;
+    }
+    Ok(CabSummary {
+        path: cab_path.to_path_buf(),
+        cab_size: cab_file.metadata()?.len(),
+        cabinets: vec![],
+        files,
+        blocks: block_summaries(cab_path)?,
+        elapsed: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use self::tempdir::TempDir;
+    use super::{make_cabs_from_manifest, Manifest};
+    use crate::lint_cab;
+    use cab::{Cabinet, CompressionType};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn manifest() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        fs::create_dir(t.path().join("build")).unwrap();
+        fs::write(t.path().join("build/app.exe"), vec![b'x'; 40_000]).unwrap();
+        fs::write(t.path().join("build/app.pdb"), b"pdb").unwrap();
+        fs::write(t.path().join("readme.txt"), b"read me").unwrap();
+        let manifest = t.path().join("manifest.json");
+        fs::write(
+            &manifest,
+            r#"{
+                "cab": "out.cab",
+                "entries": [
+                    { "source": "build/app.exe", "name": "bin\\app.exe" },
+                    {
+                        "source": "readme.txt",
+                        "timestamp": "2021-03-04T05:06:08",
+                        "attributes": ["read-only", "hidden"],
+                        "compression": "none"
+                    },
+                    { "source": "build/app.pdb", "cab": "symbols.cab" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let summaries = make_cabs_from_manifest(&manifest).expect("failed to write cab files");
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].path, t.path().join("out.cab"));
+        assert_eq!(summaries[0].uncompressed_size(), 40_007);
+        assert_eq!(
+            summaries[0]
+                .files
+                .iter()
+                .map(|f| f.compressed_size)
+                .sum::<u64>(),
+            summaries[0].cab_size - 36 - 2 * 8 - (16 + 12) - (16 + 11)
+        );
+        assert_eq!(summaries[1].files[0].name, "app.pdb");
+
+        let out = t.path().join("out.cab");
+        assert_eq!(lint_cab(&out).expect("failed to lint cab file"), vec![]);
+        let mut cabinet = Cabinet::new(File::open(&out).unwrap()).unwrap();
+        let compression = cabinet
+            .folder_entries()
+            .map(|folder| folder.compression_type())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            compression,
+            vec![CompressionType::MsZip, CompressionType::None]
+        );
+        let readme = cabinet.get_file_entry("readme.txt").unwrap();
+        assert_eq!(
+            readme.datetime(),
+            Some(NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8))
+        );
+        assert!(readme.is_read_only() && readme.is_hidden() && !readme.is_system());
+        let mut data = vec![];
+        cabinet
+            .read_file("bin\\app.exe")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, vec![b'x'; 40_000]);
+
+        let no_cab = Manifest::from_json(r#"{ "entries": [{ "source": "readme.txt" }] }"#).unwrap();
+        assert!(no_cab.make_cabs(t.path()).is_err());
+        let duplicate = Manifest::from_json(
+            r#"{ "cab": "dup.cab", "entries": [{ "source": "readme.txt" }, { "source": "build/app.pdb", "name": "README.TXT" }] }"#,
+        )
+        .unwrap();
+        assert!(duplicate.make_cabs(t.path()).is_err());
+        assert!(
+            Manifest::from_json(r#"{ "entries": [{ "source": "a", "compression": "lzx" }] }"#)
+                .is_err()
+        );
+        assert!(Manifest::from_json(
+            r#"{ "entries": [{ "source": "a", "timestamp": "yesterday" }] }"#
+        )
+        .is_err());
+    }
+}
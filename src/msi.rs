@@ -0,0 +1,104 @@
+//! Writing media cabinets for Windows Installer packages.
+//!
+//! The files in an MSI's media cabinets are found by looking up the key of
+//! each row of the `File` table, and are expected to appear in the order of
+//! the `Sequence` column. This follows the conventions WiX's cabinet builder
+//! uses, so this crate can be used as the cabinet backend for MSI builders.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::{temp_cab_file, write_cab, CabOptions, Input};
+
+/// A file to store in an MSI media cabinet.
+#[derive(Clone, Debug)]
+pub struct MsiFile {
+    /// The value of the file's `Sequence` column in the `File` table.
+    pub sequence: u32,
+    /// The file's key in the `File` table, which it is stored as.
+    pub file_key: String,
+    /// Where to read the file's contents from.
+    pub source: PathBuf,
+}
+
+/// Write a media cabinet at `cab_path` containing `files`.
+///
+/// The files are sorted by sequence number and stored under their `File`
+/// table keys in a single MSZIP folder, which is what WiX produces with
+/// `CompressionLevel="mszip"`. Sequence numbers and keys must be unique, and
+/// sequence numbers start at 1.
+pub fn make_msi_media_cab<T: AsRef<Path>>(cab_path: T, files: &[MsiFile]) -> Result<()> {
+    let mut files = files.iter().collect::<Vec<_>>();
+    files.sort_by_key(|file| file.sequence);
+    let mut keys = HashSet::new();
+    for (i, file) in files.iter().enumerate() {
+        if file.sequence == 0 {
+            bail!("File '{}' has sequence number 0", file.file_key);
+        }
+        if i > 0 && files[i - 1].sequence == file.sequence {
+            bail!(
+                "Files '{}' and '{}' have the same sequence number {}",
+                files[i - 1].file_key,
+                file.file_key,
+                file.sequence
+            );
+        }
+        if file.file_key.is_empty() {
+            bail!("File with sequence number {} has no key", file.sequence);
+        }
+        if !keys.insert(&file.file_key) {
+            bail!("More than one file has the key '{}'", file.file_key);
+        }
+    }
+    let inputs = files
+        .iter()
+        .map(|file| Input::with_name(&file.source, &file.file_key))
+        .collect::<Result<Vec<_>>>()?;
+    let cab_path = cab_path.as_ref();
+    let (temp, _) = write_cab(temp_cab_file(cab_path)?, &inputs, &CabOptions::default())?;
+    temp.persist(cab_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+
+    use self::tempdir::TempDir;
+    use super::{make_msi_media_cab, MsiFile};
+    use crate::verify_cab;
+
+    #[test]
+    fn media_cab() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let file = |sequence, key: &str| {
+            let source = t.path().join(format!("{}.dll", key));
+            fs::write(&source, key).expect("failed to write test data");
+            MsiFile {
+                sequence,
+                file_key: key.to_owned(),
+                source,
+            }
+        };
+        let files = vec![file(3, "filC"), file(1, "filA"), file(2, "filB")];
+        let cab = t.path().join("media1.cab");
+        make_msi_media_cab(&cab, &files).expect("failed to create cab file");
+        let names = verify_cab(&cab)
+            .expect("failed to read cab file")
+            .files
+            .into_iter()
+            .map(|f| f.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["filA", "filB", "filC"]);
+
+        let duplicate_sequence = vec![file(1, "filA"), file(1, "filB")];
+        assert!(make_msi_media_cab(&cab, &duplicate_sequence).is_err());
+        let duplicate_key = vec![file(1, "filA"), file(2, "filA")];
+        assert!(make_msi_media_cab(&cab, &duplicate_key).is_err());
+        assert!(make_msi_media_cab(&cab, &[file(0, "filA")]).is_err());
+    }
+}
@@ -0,0 +1,857 @@
+//! Compressing a cabinet on several threads at once.
+//!
+//! The `cab` crate reads, compresses and writes each block in turn on the
+//! thread writing the cabinet, so a single large input keeps one core busy
+//! at most and the disk idle while it compresses. Here a reader thread
+//! splits the inputs into chunks of up to 32K, a pool of compressor threads turns
+//! them into blocks with a [`FolderCompressor`], MSZIP unless the options
+//! say otherwise, and the calling thread writes the blocks out in
+//! order, with bounded channels in between so memory use stays flat. With
+//! no compressor threads, each block is compressed and written in turn on
+//! the calling thread instead.
+//!
+//! Each block is compressed on its own, without the previous block's data
+//! as a dictionary. That's valid MSZIP and every extractor handles it, but
+//! it costs a little compression on data with long-range repetition. It
+//! also means a folder can end after any block, so the blocks can be spread
+//! over a linked set of cabinets as they're written.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDateTime;
+use flate2::CrcReader;
+
+use crate::codepage::Codepage;
+use crate::compressor::FolderCompressor;
+use crate::format::{
+    DataBlock, FileEntry, Folder, Header, _A_ARCH, _A_NAME_IS_UTF, MAX_COMPRESSED_CHUNK,
+};
+use crate::reserve::{fill_data_reserve, DataReserve, Reserve};
+use crate::split::{SetWriter, SplitOptions};
+use crate::{FileSummary, LimitExceeded, CFFILE_SIZE};
+
+/// How many chunks or blocks can be waiting in each channel, per
+/// compressor thread.
+const QUEUE_DEPTH: usize = 4;
+
+/// The size and CRC-32 of each input read, and the uncompressed and written
+/// size of each block written.
+type Written = (Vec<(u64, u32)>, Vec<(u16, u64)>);
+
+/// A block compressed from a [`Chunk`], with the chunk's index and inputs.
+type Compressed = (usize, Range<usize>, io::Result<DataBlock>);
+
+/// A chunk of the inputs' data, to be compressed into a block.
+struct Chunk {
+    /// Where the chunk comes in the order the inputs were read.
+    index: usize,
+    data: Vec<u8>,
+    /// The inputs the chunk holds data from, along with any empty ones
+    /// opened before it was full.
+    files: Range<usize>,
+}
+
+/// The records of a cabinet holding `entries`, before the sizes and
+/// offsets in them are known.
+struct Records {
+    header: Header,
+    folder: Folder,
+    /// The names as they're stored, in `codepage` if there is one.
+    names: Vec<Vec<u8>>,
+    files: Vec<FileEntry>,
+}
+
+impl Records {
+    /// Check that `entries` can be stored in a cabinet with `reserve`, a
+    /// folder reserve of `folder_reserve`, block reserves from
+    /// `data_reserve` and names in `codepage`, and make the records for
+    /// them.
+    fn new(
+        entries: &[(&str, NaiveDateTime)],
+        reserve: &Reserve,
+        folder_reserve: &[u8],
+        data_reserve: Option<&dyn DataReserve>,
+        compressor: &dyn FolderCompressor,
+        codepage: Option<Codepage>,
+    ) -> Result<Records> {
+        if folder_reserve.len() > u8::MAX as usize {
+            bail!(
+                "Folder reserve is {} bytes, too large",
+                folder_reserve.len()
+            );
+        }
+        if entries.len() > u16::MAX as usize {
+            return Err(LimitExceeded::TooManyFiles(entries.len()).into());
+        }
+        let header = Header {
+            header_reserve: reserve.to_bytes()?,
+            folder_reserve_size: folder_reserve.len() as u8,
+            data_reserve_size: data_reserve.map_or(0, |data_reserve| data_reserve.size()),
+            folder_count: if entries.is_empty() { 0 } else { 1 },
+            file_count: entries.len() as u16,
+            ..Header::default()
+        };
+        let folder = Folder {
+            compression: compressor.compression_type(),
+            reserve: folder_reserve.to_vec(),
+            ..Folder::default()
+        };
+        let names = entries
+            .iter()
+            .map(|&(name, _)| match codepage {
+                Some(codepage) => match codepage.encode(name) {
+                    Ok(encoded) => Ok(encoded),
+                    Err(c) => bail!(
+                        "Can't store '{}' in code page {}, which has no '{}'",
+                        name,
+                        codepage.number(),
+                        c
+                    ),
+                },
+                None => Ok(name.as_bytes().to_vec()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let files = entries
+            .iter()
+            .map(|&(name, mtime)| {
+                let mut file = FileEntry::new(name, 0, 0, 0, mtime);
+                file.attributes |= _A_ARCH;
+                if codepage.is_some() {
+                    file.attributes &= !_A_NAME_IS_UTF;
+                }
+                file
+            })
+            .collect();
+        Ok(Records {
+            header,
+            folder,
+            names,
+            files,
+        })
+    }
+}
+
+/// Like `write_entries`, but compressing on up to `threads` compressor
+/// threads, as many as fit in `memory_limit`, in blocks of `chunk_size`
+/// bytes, with `compressor`, filling in block reserves with `data_reserve`
+/// and storing names in `codepage` if there is one.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_entries_pipelined<W, I, F, R>(
+    mut writer: W,
+    entries: &[(&str, NaiveDateTime)],
+    mut reserve: Reserve,
+    folder_reserve: &[u8],
+    data_reserve: Option<&dyn DataReserve>,
+    opens: I,
+    threads: usize,
+    memory_limit: Option<u64>,
+    chunk_size: usize,
+    compressor: &dyn FolderCompressor,
+    codepage: Option<Codepage>,
+) -> Result<(W, Vec<FileSummary>)>
+where
+    W: Write + Seek,
+    I: IntoIterator<Item = F>,
+    I::IntoIter: Send,
+    F: FnOnce() -> io::Result<R>,
+    R: Read,
+{
+    let crc32 = reserve.crc32s.is_some();
+    if crc32 {
+        // Leave room for the CRC-32s, which are filled in at the end.
+        reserve.crc32s = Some(vec![0; entries.len()]);
+    }
+    let Records {
+        mut header,
+        mut folder,
+        names,
+        mut files,
+    } = Records::new(
+        entries,
+        &reserve,
+        folder_reserve,
+        data_reserve,
+        compressor,
+        codepage,
+    )?;
+    let folders_size = if entries.is_empty() { 0 } else { folder.size() };
+    let files_size = names
+        .iter()
+        .map(|name| CFFILE_SIZE + name.len() as u64 + 1)
+        .sum::<u64>();
+    let data_offset = header.size() + folders_size + files_size;
+    header.files_offset = to_u32(header.size() + folders_size)?;
+    folder.data_offset = to_u32(data_offset)?;
+    // Write the records now, to catch bad names and the like before doing
+    // any work, and again once the sizes are known.
+    let write_records = |w: &mut W, header: &Header, folder: &Folder, files: &[FileEntry]| {
+        header.write_to(&mut *w)?;
+        if !entries.is_empty() {
+            folder.write_to(&mut *w)?;
+        }
+        files
+            .iter()
+            .zip(&names)
+            .try_for_each(|(file, name)| file.write_with_name(&mut *w, name))
+    };
+    write_records(&mut writer, &header, &folder, &files)?;
+
+    let (read, written) = compress_inputs(
+        opens,
+        threads,
+        memory_limit,
+        chunk_size,
+        compressor,
+        data_reserve,
+        |block, _| write_block(&mut writer, block),
+    )?;
+    if read.len() != entries.len() {
+        bail!("Writing the cabinet stopped early");
+    }
+    let summaries = file_summaries(entries, &read, &written);
+
+    let mut folder_offset = 0u64;
+    for (file, &(size, _)) in files.iter_mut().zip(&read) {
+        file.size = to_u32(size)?;
+        file.folder_offset = to_u32(folder_offset)?;
+        folder_offset += size;
+    }
+    if written.len() > u16::MAX as usize {
+        return Err(LimitExceeded::FolderTooLarge(folder_offset).into());
+    }
+    folder.data_count = written.len() as u16;
+    let cabinet_size = data_offset + written.iter().map(|&(_, c)| c).sum::<u64>();
+    header.cabinet_size = to_u32(cabinet_size)?;
+    if crc32 {
+        reserve.crc32s = Some(read.iter().map(|&(_, crc)| crc).collect());
+        header.header_reserve = reserve.to_bytes()?;
+    }
+    writer.seek(SeekFrom::Start(0))?;
+    write_records(&mut writer, &header, &folder, &files)?;
+    writer.seek(SeekFrom::Start(cabinet_size))?;
+    Ok((writer, summaries))
+}
+
+/// Like [`write_entries_pipelined`], but spreading the blocks over a linked
+/// set of cabinets named after `cab_path`, as `split` says, starting the
+/// next cabinet whenever the next block won't fit in the current one.
+/// Each cabinet has a single folder, carried on from the cabinet before it
+/// when a file is. Returns the paths of the cabinets and a summary of each
+/// file.
+///
+/// With `durable`, each cabinet is flushed to disk before being moved into
+/// place, and the directory holding them afterwards.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_set_pipelined<I, F, R>(
+    cab_path: &Path,
+    split: &SplitOptions,
+    durable: bool,
+    entries: &[(&str, NaiveDateTime)],
+    mut reserve: Reserve,
+    folder_reserve: &[u8],
+    data_reserve: Option<&dyn DataReserve>,
+    opens: I,
+    threads: usize,
+    memory_limit: Option<u64>,
+    chunk_size: usize,
+    compressor: &dyn FolderCompressor,
+    codepage: Option<Codepage>,
+) -> Result<(Vec<PathBuf>, Vec<FileSummary>)>
+where
+    I: IntoIterator<Item = F>,
+    I::IntoIter: Send,
+    F: FnOnce() -> io::Result<R>,
+    R: Read,
+{
+    if reserve.crc32s.is_some() {
+        // Each cabinet's CRC-32s are filled in once the files are read.
+        reserve.crc32s = Some(vec![0; entries.len()]);
+    }
+    let records = Records::new(
+        entries,
+        &reserve,
+        folder_reserve,
+        data_reserve,
+        compressor,
+        codepage,
+    )?;
+    let mut set = SetWriter::new(
+        cab_path,
+        split,
+        records.header,
+        records.folder,
+        records.names,
+        records.files,
+        reserve,
+    )?;
+    let (read, written) = compress_inputs(
+        opens,
+        threads,
+        memory_limit,
+        chunk_size,
+        compressor,
+        data_reserve,
+        |block, files| set.write_block(block, files),
+    )?;
+    if read.len() != entries.len() {
+        bail!("Writing the cabinet set stopped early");
+    }
+    let cabinets = set.finish(&read, durable)?;
+    Ok((cabinets, file_summaries(entries, &read, &written)))
+}
+
+/// Summarize each of `entries`, given the size and CRC-32 of each input
+/// `read` and the sizes of each block `written`, putting each block's
+/// compressed size down to the file its last byte is in.
+fn file_summaries(
+    entries: &[(&str, NaiveDateTime)],
+    read: &[(u64, u32)],
+    written: &[(u16, u64)],
+) -> Vec<FileSummary> {
+    let mut summaries = entries
+        .iter()
+        .zip(read)
+        .map(|(&(name, _), &(size, _))| FileSummary {
+            name: name.to_owned(),
+            size,
+            compressed_size: 0,
+            renamed_from: None,
+        })
+        .collect::<Vec<_>>();
+    let mut file_ends = read
+        .iter()
+        .scan(0, |end, &(size, _)| {
+            *end += size;
+            Some(*end)
+        })
+        .enumerate()
+        .peekable();
+    let mut block_end = 0;
+    for &(uncompressed, compressed) in written {
+        block_end += u64::from(uncompressed);
+        while let Some(&(_, file_end)) = file_ends.peek() {
+            if file_end >= block_end {
+                break;
+            }
+            file_ends.next();
+        }
+        if let Some(&(file, _)) = file_ends.peek() {
+            summaries[file].compressed_size += compressed;
+        }
+    }
+    summaries
+}
+
+/// Read the inputs `opens` opens in chunks of `chunk_size` bytes and
+/// compress them with `compressor`, on up to `threads` compressor threads
+/// as `memory_limit` allows, or on the calling thread if `threads` is 0.
+/// Each block's reserve is filled in with `data_reserve`, if there is one,
+/// and the block is passed to `sink` in order, along with the inputs it
+/// holds data from.
+fn compress_inputs<I, F, R, S>(
+    opens: I,
+    threads: usize,
+    memory_limit: Option<u64>,
+    chunk_size: usize,
+    compressor: &dyn FolderCompressor,
+    data_reserve: Option<&dyn DataReserve>,
+    mut sink: S,
+) -> io::Result<Written>
+where
+    I: IntoIterator<Item = F>,
+    I::IntoIter: Send,
+    F: FnOnce() -> io::Result<R>,
+    R: Read,
+    S: FnMut(&DataBlock, Range<usize>) -> io::Result<()>,
+{
+    let opens = opens.into_iter();
+    if threads == 0 {
+        let mut written = vec![];
+        let read = read_chunks(opens, chunk_size, |chunk| {
+            let block = make_block(&chunk, compressor, data_reserve)?;
+            sink(&block, chunk.files)?;
+            written.push((block.uncompressed_size, block.size()));
+            Ok(true)
+        })?;
+        Ok((read, written))
+    } else {
+        let (threads, depth) = pipeline_shape(threads, chunk_size, memory_limit);
+        write_blocks_threaded(
+            sink,
+            opens,
+            threads,
+            depth,
+            chunk_size,
+            compressor,
+            data_reserve,
+        )
+    }
+}
+
+/// How many compressor threads to use, up to `threads`, and how many
+/// chunks each may have in flight, to buffer no more than `memory_limit`
+/// bytes while compressing in blocks of `chunk_size` bytes. Threads are
+/// given up before parallelism, leaving at least one thread with one chunk
+/// in flight however little memory there is.
+fn pipeline_shape(threads: usize, chunk_size: usize, memory_limit: Option<u64>) -> (usize, usize) {
+    let limit = match memory_limit {
+        Some(limit) => limit,
+        None => return (threads, QUEUE_DEPTH),
+    };
+    let slot = (chunk_size + MAX_COMPRESSED_CHUNK) as u64;
+    let slots = limit.saturating_sub(buffered_size(0, chunk_size)) / slot;
+    let slots = slots.min((threads * QUEUE_DEPTH) as u64) as usize;
+    let threads = threads.min(slots).max(1);
+    (threads, (slots / threads).clamp(1, QUEUE_DEPTH))
+}
+
+/// The most bytes of chunks and blocks buffered with `window` chunks in
+/// flight: the chunk being read, and each chunk in flight along with the
+/// block it's compressed into.
+fn buffered_size(window: usize, chunk_size: usize) -> u64 {
+    (chunk_size + window * (chunk_size + MAX_COMPRESSED_CHUNK)) as u64
+}
+
+/// Read the inputs `opens` opens in chunks of `chunk_size` bytes, compress
+/// them on `threads` compressor threads and pass the blocks to `sink` in
+/// order.
+///
+/// The reader only gets `depth` chunks per thread ahead of the sink, so
+/// memory use is bounded, and if one block is slow to compress, the blocks
+/// after it don't pile up waiting for it. If compressing a block fails,
+/// everything stops.
+fn write_blocks_threaded<S, I, F, R>(
+    sink: S,
+    opens: I,
+    threads: usize,
+    depth: usize,
+    chunk_size: usize,
+    compressor: &dyn FolderCompressor,
+    data_reserve: Option<&dyn DataReserve>,
+) -> io::Result<Written>
+where
+    S: FnMut(&DataBlock, Range<usize>) -> io::Result<()>,
+    I: Iterator<Item = F> + Send,
+    F: FnOnce() -> io::Result<R>,
+    R: Read,
+{
+    let window = threads * depth;
+    let (chunk_tx, chunk_rx) = sync_channel::<Chunk>(window);
+    let chunk_rx = Arc::new(Mutex::new(chunk_rx));
+    let (block_tx, block_rx) = sync_channel::<Compressed>(window);
+    // The reader takes a credit for each chunk it reads, and the writer
+    // hands one back for each block it writes, so there are never more
+    // than `window` chunks and blocks in flight.
+    let (credit_tx, credit_rx) = sync_channel::<()>(window);
+    for _ in 0..window {
+        credit_tx.send(()).expect("credits can't be refused yet");
+    }
+    thread::scope(|scope| {
+        let reader = scope.spawn(move || {
+            read_chunks(opens, chunk_size, |chunk| {
+                Ok(credit_rx.recv().is_ok() && chunk_tx.send(chunk).is_ok())
+            })
+        });
+        let compressors = (0..threads)
+            .map(|_| {
+                let chunk_rx = chunk_rx.clone();
+                let block_tx = block_tx.clone();
+                scope.spawn(move || -> io::Result<()> {
+                    while let Some(chunk) = next_chunk(&chunk_rx) {
+                        let block = match panic::catch_unwind(AssertUnwindSafe(|| {
+                            make_block(&chunk, compressor, data_reserve)
+                        })) {
+                            Ok(block) => block,
+                            Err(payload) => {
+                                // Stop the writer before passing the panic on.
+                                let panicked = io::Error::other("Compressor panicked");
+                                let _ = block_tx.send((chunk.index, chunk.files, Err(panicked)));
+                                panic::resume_unwind(payload);
+                            }
+                        };
+                        let failed = block.is_err();
+                        if block_tx.send((chunk.index, chunk.files, block)).is_err() || failed {
+                            break;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        // Only the threads hold onto the channels now, so each stage sees
+        // the channel close once the stage before it is done or fails.
+        drop(chunk_rx);
+        drop(block_tx);
+        let written = write_blocks(sink, block_rx, credit_tx);
+        for compressor in compressors {
+            join(compressor)?;
+        }
+        Ok((join(reader)?, written?))
+    })
+}
+
+/// Read the inputs `opens` opens in chunks of `chunk_size` bytes, passing
+/// each chunk to `send`, which returns `false` once no more are wanted.
+/// Returns the size and CRC-32 of each input read in full.
+fn read_chunks<I, F, R, S>(opens: I, chunk_size: usize, mut send: S) -> io::Result<Vec<(u64, u32)>>
+where
+    I: IntoIterator<Item = F>,
+    F: FnOnce() -> io::Result<R>,
+    R: Read,
+    S: FnMut(Chunk) -> io::Result<bool>,
+{
+    let mut sizes = vec![];
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let mut index = 0;
+    // The input the chunk's first byte came from.
+    let mut first_file = 0;
+    for open in opens {
+        let mut input = CrcReader::new(open()?);
+        let mut size = 0;
+        loop {
+            let len = chunk.len();
+            chunk.resize(chunk_size, 0);
+            let result = input.read(&mut chunk[len..]);
+            chunk.truncate(len + *result.as_ref().unwrap_or(&0));
+            match result {
+                Ok(0) => break,
+                Ok(read) => size += read as u64,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            if len == 0 {
+                first_file = sizes.len();
+            }
+            if chunk.len() == chunk_size {
+                let full = std::mem::replace(&mut chunk, Vec::with_capacity(chunk_size));
+                let chunk = Chunk {
+                    index,
+                    data: full,
+                    files: first_file..sizes.len() + 1,
+                };
+                if !send(chunk)? {
+                    // The writer gave up.
+                    return Ok(sizes);
+                }
+                index += 1;
+            }
+        }
+        sizes.push((size, input.crc().sum()));
+    }
+    if !chunk.is_empty() {
+        send(Chunk {
+            index,
+            data: chunk,
+            files: first_file..sizes.len(),
+        })?;
+    }
+    Ok(sizes)
+}
+
+/// Take the next chunk to compress, or `None` once there are no more.
+fn next_chunk(chunk_rx: &Mutex<Receiver<Chunk>>) -> Option<Chunk> {
+    chunk_rx.lock().ok()?.recv().ok()
+}
+
+/// Compress `chunk` into a data block with `compressor`.
+pub(crate) fn compress_block(
+    chunk: &[u8],
+    compressor: &dyn FolderCompressor,
+) -> io::Result<DataBlock> {
+    debug_assert!(!chunk.is_empty(), "empty chunks aren't compressed");
+    let data = compressor.compress_block(chunk)?;
+    if data.len() > MAX_COMPRESSED_CHUNK {
+        return Err(io::Error::other(format!(
+            "Compressed block is {} bytes, more than the {} allowed",
+            data.len(),
+            MAX_COMPRESSED_CHUNK
+        )));
+    }
+    Ok(DataBlock {
+        reserve: vec![],
+        data,
+        uncompressed_size: chunk.len() as u16,
+    })
+}
+
+/// Compress `chunk` into a data block with `compressor`, with its reserve
+/// filled in by `data_reserve` if there is one.
+fn make_block(
+    chunk: &Chunk,
+    compressor: &dyn FolderCompressor,
+    data_reserve: Option<&dyn DataReserve>,
+) -> io::Result<DataBlock> {
+    let mut block = compress_block(&chunk.data, compressor)?;
+    if let Some(data_reserve) = data_reserve {
+        fill_data_reserve(&mut block, data_reserve, chunk.index, &chunk.data)?;
+    }
+    Ok(block)
+}
+
+/// Pass the blocks received from `block_rx` to `sink` in order, returning
+/// the uncompressed and written size of each, and handing a credit back to
+/// the reader through `credits` for each one written. Stops at the first
+/// block that couldn't be compressed.
+fn write_blocks<S>(
+    mut sink: S,
+    block_rx: Receiver<Compressed>,
+    credits: SyncSender<()>,
+) -> io::Result<Vec<(u16, u64)>>
+where
+    S: FnMut(&DataBlock, Range<usize>) -> io::Result<()>,
+{
+    let mut written = vec![];
+    let mut waiting = BTreeMap::new();
+    for (index, files, block) in block_rx {
+        waiting.insert(index, (files, block?));
+        while let Some((files, block)) = waiting.remove(&written.len()) {
+            sink(&block, files)?;
+            written.push((block.uncompressed_size, block.size()));
+            let _ = credits.send(());
+        }
+    }
+    Ok(written)
+}
+
+/// Write `block` to `writer`.
+fn write_block<W: Write>(writer: &mut W, block: &DataBlock) -> io::Result<()> {
+    block
+        .write_to(&mut *writer)
+        .map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Wait for a pipeline thread to finish, passing on any panic.
+fn join<T>(handle: thread::ScopedJoinHandle<'_, io::Result<T>>) -> io::Result<T> {
+    handle
+        .join()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+fn to_u32(value: u64) -> Result<u32> {
+    if value > u64::from(u32::MAX) {
+        bail!("Cabinet is too large");
+    }
+    Ok(value as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, ThreadId};
+    use std::time::Duration;
+
+    use self::tempdir::TempDir;
+    use super::{buffered_size, pipeline_shape, QUEUE_DEPTH};
+    use crate::compressor::FolderCompressor;
+    use crate::format::tcompTYPE_NONE;
+    use crate::{lint_cab, make_cab_from_files_with_options, verify, verify_cab, CabOptions};
+
+    /// Stores blocks uncompressed, counting them and noting which threads
+    /// they're compressed on, and failing on blocks starting with 0xff once
+    /// the others have had time to get ahead.
+    #[derive(Debug, Default)]
+    struct Watched {
+        blocks: AtomicUsize,
+        threads: Mutex<Vec<ThreadId>>,
+    }
+
+    impl FolderCompressor for Watched {
+        fn compression_type(&self) -> u16 {
+            tcompTYPE_NONE
+        }
+
+        fn compress_block(&self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+            self.blocks.fetch_add(1, Ordering::SeqCst);
+            self.threads.lock().unwrap().push(thread::current().id());
+            if chunk[0] == 0xff {
+                thread::sleep(Duration::from_millis(200));
+                return Err(io::Error::other("bad block"));
+            }
+            Ok(chunk.to_vec())
+        }
+    }
+
+    #[test]
+    fn pipelined() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let contents = [
+            (0..100_000u32)
+                .map(|i| (i * 7 % 251) as u8)
+                .collect::<Vec<_>>(),
+            vec![],
+            b"a short file".to_vec(),
+            vec![b'z'; 32 * 1024],
+        ];
+        let paths = contents
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                let path = t.path().join(format!("{}.bin", i));
+                fs::write(&path, data).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            threads: 3,
+            crc32: true,
+            ..CabOptions::default()
+        };
+        let summary = make_cab_from_files_with_options(&cab, &paths, &options)
+            .expect("failed to create cab file");
+        assert_eq!(lint_cab(&cab).expect("failed to lint cab file"), vec![]);
+        for path in &paths {
+            let one = t.path().join("one.cab");
+            make_cab_from_files_with_options(&one, &[path], &options)
+                .expect("failed to create cab file");
+            verify(&one, path).expect("cab file didn't verify");
+        }
+        let report = verify_cab(&cab).expect("cab file didn't verify");
+        let sizes = report.files.iter().map(|f| f.size).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![100_000, 0, 12, 32 * 1024]);
+
+        let sizes = summary.files.iter().map(|f| f.size).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![100_000, 0, 12, 32 * 1024]);
+        assert_eq!(summary.files[1].compressed_size, 0);
+        let data_size = summary.files.iter().map(|f| f.compressed_size).sum::<u64>();
+        let parsed = crate::format::parse::parse_cab(&cab).unwrap();
+        assert_eq!(
+            data_size,
+            summary.cab_size - u64::from(parsed.folders[0].data_offset)
+        );
+    }
+
+    #[test]
+    fn compression_levels() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("data.bin");
+        let data = (0..200_000u64)
+            .map(|i| (i * i % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(&path, &data).unwrap();
+        let cab = t.path().join("test.cab");
+        let cab_size = |compression_level| {
+            let options = CabOptions {
+                compression_level,
+                ..CabOptions::default()
+            };
+            let summary = make_cab_from_files_with_options(&cab, &[&path], &options)
+                .expect("failed to create cab file");
+            verify(&cab, &path).expect("cab file didn't verify");
+            summary.cab_size
+        };
+        let stored = cab_size(Some(0));
+        let fast = cab_size(Some(1));
+        let best = cab_size(Some(9));
+        assert!(stored > data.len() as u64);
+        assert!(fast < stored);
+        assert!(best <= fast);
+        let options = CabOptions {
+            compression_level: Some(10),
+            ..CabOptions::default()
+        };
+        assert!(make_cab_from_files_with_options(&cab, &[&path], &options).is_err());
+    }
+
+    #[test]
+    fn failed_block() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("data.bin");
+        let mut data = vec![1; 1000 * 100];
+        data[0] = 0xff;
+        fs::write(&path, &data).unwrap();
+        let cab = t.path().join("test.cab");
+        let watched = Arc::new(Watched::default());
+        let options = CabOptions {
+            threads: 2,
+            chunk_size: Some(1000),
+            compressor: Some(watched.clone()),
+            ..CabOptions::default()
+        };
+        assert!(make_cab_from_files_with_options(&cab, &[&path], &options).is_err());
+        // The other threads only got as far as the reader was let ahead,
+        // rather than through the whole file.
+        assert!(watched.blocks.load(Ordering::SeqCst) <= 2 * QUEUE_DEPTH);
+    }
+
+    #[test]
+    fn no_threads() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("data.bin");
+        fs::write(&path, vec![1; 5000]).unwrap();
+        let cab = t.path().join("test.cab");
+        let watched = Arc::new(Watched::default());
+        let options = CabOptions {
+            chunk_size: Some(1000),
+            compressor: Some(watched.clone()),
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &[&path], &options)
+            .expect("failed to create cab file");
+        verify(&cab, &path).expect("cab file didn't verify");
+        assert_eq!(
+            *watched.threads.lock().unwrap(),
+            vec![thread::current().id(); 5]
+        );
+    }
+
+    #[test]
+    fn memory_limit() {
+        let chunk_size = 32768;
+        assert_eq!(pipeline_shape(8, chunk_size, None), (8, QUEUE_DEPTH));
+        for &limit in &[0, 100_000, 300_000, 1_000_000, 2_000_000, 100_000_000] {
+            let (threads, depth) = pipeline_shape(8, chunk_size, Some(limit));
+            assert!((1..=8).contains(&threads));
+            assert!((1..=QUEUE_DEPTH).contains(&depth));
+            if limit >= buffered_size(1, chunk_size) {
+                assert!(buffered_size(threads * depth, chunk_size) <= limit);
+            } else {
+                assert_eq!((threads, depth), (1, 1));
+            }
+        }
+        // Threads are given up last.
+        assert_eq!(
+            pipeline_shape(8, chunk_size, Some(buffered_size(8, chunk_size))),
+            (8, 1)
+        );
+        assert_eq!(
+            pipeline_shape(8, chunk_size, Some(buffered_size(3, chunk_size))),
+            (3, 1)
+        );
+        assert_eq!(
+            pipeline_shape(2, chunk_size, Some(buffered_size(5, chunk_size))),
+            (2, 2)
+        );
+
+        // The blocks in flight stay within the limit when writing a cabinet.
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let path = t.path().join("data.bin");
+        let mut data = vec![1; 1000 * 100];
+        data[0] = 0xff;
+        fs::write(&path, &data).unwrap();
+        let cab = t.path().join("test.cab");
+        let watched = Arc::new(Watched::default());
+        let options = CabOptions {
+            threads: 8,
+            chunk_size: Some(1000),
+            memory_limit: Some(buffered_size(3, 1000)),
+            compressor: Some(watched.clone()),
+            ..CabOptions::default()
+        };
+        assert!(make_cab_from_files_with_options(&cab, &[&path], &options).is_err());
+        assert!(watched.blocks.load(Ordering::SeqCst) <= 3);
+    }
+}
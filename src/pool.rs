@@ -0,0 +1,141 @@
+//! Reusing deflate compressors between MSZIP blocks.
+
+use std::io;
+use std::sync::Mutex;
+
+use flate2::{Compress, Compression, FlushCompress, Status};
+
+use crate::format::MAX_COMPRESSED_CHUNK;
+
+/// A pool of deflate compressors for MSZIP blocks, shared between threads.
+///
+/// Setting up a deflate stream allocates its window and hash tables, which
+/// for small blocks can cost as much as compressing them. Compressors taken
+/// from the pool with [`CompressorPool::get`] go back into it when they're
+/// dropped, and are reset rather than recreated the next time they're
+/// handed out, so threads compressing folder after folder keep reusing the
+/// same few streams.
+#[derive(Debug)]
+pub struct CompressorPool {
+    level: Compression,
+    idle: Mutex<Vec<Compress>>,
+}
+
+impl CompressorPool {
+    /// Create an empty pool of compressors that compress at `level`.
+    pub fn new(level: Compression) -> CompressorPool {
+        CompressorPool {
+            level,
+            idle: Mutex::new(vec![]),
+        }
+    }
+
+    /// Take a compressor from the pool, or create one if they're all in use.
+    pub fn get(&self) -> PooledCompressor<'_> {
+        let compress = self.idle.lock().ok().and_then(|mut idle| idle.pop());
+        PooledCompressor {
+            pool: self,
+            compress: Some(compress.unwrap_or_else(|| Compress::new(self.level, false))),
+        }
+    }
+}
+
+/// A compressor borrowed from a [`CompressorPool`], which it goes back to
+/// when dropped.
+#[derive(Debug)]
+pub struct PooledCompressor<'a> {
+    pool: &'a CompressorPool,
+    compress: Option<Compress>,
+}
+
+impl PooledCompressor<'_> {
+    /// Compress `chunk`, which can be up to 32768 bytes, into the data of an
+    /// MSZIP block: the `CK` signature followed by a complete deflate
+    /// stream.
+    pub fn compress_block(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let compress = self.compress.as_mut().unwrap();
+        compress.reset();
+        let mut data = Vec::with_capacity(MAX_COMPRESSED_CHUNK);
+        data.extend_from_slice(b"CK");
+        loop {
+            let consumed = compress.total_in() as usize;
+            let status = compress
+                .compress_vec(&chunk[consumed..], &mut data, FlushCompress::Finish)
+                .map_err(io::Error::other)?;
+            match status {
+                Status::StreamEnd => break,
+                // Out of room, which shouldn't happen for a chunk that fits
+                // in a block.
+                Status::Ok | Status::BufError => data.reserve(MAX_COMPRESSED_CHUNK),
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl Drop for PooledCompressor<'_> {
+    fn drop(&mut self) {
+        if let (Some(compress), Ok(mut idle)) = (self.compress.take(), self.pool.idle.lock()) {
+            idle.push(compress);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread;
+
+    use flate2::read::DeflateDecoder;
+    use flate2::Compression;
+
+    use super::{CompressorPool, PooledCompressor};
+    use crate::MAX_CHUNK;
+
+    fn inflate(block: &[u8]) -> Vec<u8> {
+        assert_eq!(&block[..2], b"CK");
+        let mut data = vec![];
+        DeflateDecoder::new(&block[2..])
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn reused() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<PooledCompressor>();
+        assert_sync::<CompressorPool>();
+
+        let pool = CompressorPool::new(Compression::default());
+        let chunks = [
+            (0..MAX_CHUNK)
+                .map(|i| (i * 7 % 251) as u8)
+                .collect::<Vec<_>>(),
+            vec![],
+            b"a short chunk".to_vec(),
+            vec![b'z'; MAX_CHUNK],
+        ];
+        {
+            let mut compressor = pool.get();
+            for chunk in &chunks {
+                let block = compressor.compress_block(chunk).unwrap();
+                assert_eq!(&inflate(&block), chunk);
+            }
+        }
+        // The compressor went back into the pool, and compresses the same
+        // way after being reset.
+        assert_eq!(pool.idle.lock().unwrap().len(), 1);
+        thread::scope(|scope| {
+            for chunk in &chunks {
+                let pool = &pool;
+                scope.spawn(move || {
+                    let block = pool.get().compress_block(chunk).unwrap();
+                    assert_eq!(&inflate(&block), chunk);
+                });
+            }
+        });
+        assert!(!pool.idle.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Rebuilding cabinets whose headers have been damaged.
+//!
+//! The CFHEADER, CFFOLDER and CFFILE records all live in the first few
+//! hundred bytes of a cabinet, so losing the first sector of a file loses
+//! all of them, while the CFDATA blocks holding the actual data survive.
+//! Those blocks can be found again by their shape: sizes that fit, a
+//! checksum that matches, and for MSZIP the `CK` signature at the start of
+//! the data.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use chrono::Local;
+
+use crate::format::{
+    checksum, tcompTYPE_MSZIP, tcompTYPE_NONE, DataBlock, FileEntry, Folder, Header, Layout,
+    MAX_COMPRESSED_CHUNK,
+};
+use crate::{CFDATA_SIZE, MAX_CHUNK};
+
+/// A folder recovered by [`rebuild_cab`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveredFolder {
+    /// The name given to the file holding the folder's data.
+    pub name: String,
+    /// The offset in the damaged cabinet of the folder's first block.
+    pub offset: u64,
+    /// The number of data blocks found.
+    pub blocks: usize,
+    /// The `tcompTYPE_*` compression type of the blocks.
+    pub compression: u16,
+    /// The size of the folder's data once decompressed.
+    pub size: u64,
+}
+
+/// Rebuild the cabinet at `broken_path`, whose headers are damaged, into a
+/// new cabinet at `fixed_path`, returning the folders that were recovered.
+///
+/// The file is scanned for runs of CFDATA blocks, each of which becomes a
+/// folder. File names and boundaries are lost with the headers, so each
+/// folder's data is stored as a single file named `recovered_N.bin`, which
+/// can be split up again by hand. Only blocks without a per-block reserve
+/// are found, and uncompressed blocks are only found if they have a
+/// checksum. If the first block of a folder was damaged too, the rest of
+/// the folder is still recovered, but MSZIP data after it may not
+/// decompress.
+pub fn rebuild_cab<T: AsRef<Path>, U: AsRef<Path>>(
+    broken_path: T,
+    fixed_path: U,
+) -> Result<Vec<RecoveredFolder>> {
+    let bytes = fs::read(broken_path)?;
+    let runs = find_runs(&bytes);
+    if runs.is_empty() {
+        bail!("No data blocks found");
+    }
+    let datetime = Local::now().naive_local();
+    let mut layout = Layout {
+        header: Header::default(),
+        folders: vec![],
+        files: vec![],
+    };
+    let mut recovered = vec![];
+    for (index, run) in runs.iter().enumerate() {
+        let name = format!("recovered_{}.bin", index);
+        let size = run
+            .blocks
+            .iter()
+            .map(|b| u64::from(b.uncompressed_size))
+            .sum::<u64>();
+        layout.files.push(FileEntry::new(
+            name.clone(),
+            size as u32,
+            index as u16,
+            0,
+            datetime,
+        ));
+        layout.folders.push((
+            Folder {
+                compression: run.compression,
+                ..Folder::default()
+            },
+            run.blocks.clone(),
+        ));
+        recovered.push(RecoveredFolder {
+            name,
+            offset: run.offset,
+            blocks: run.blocks.len(),
+            compression: run.compression,
+            size,
+        });
+    }
+    fs::write(fixed_path, layout.to_bytes()?)?;
+    Ok(recovered)
+}
+
+/// A run of consecutive data blocks that looks like a folder.
+struct Run {
+    offset: u64,
+    compression: u16,
+    blocks: Vec<DataBlock>,
+}
+
+/// Find every run of data blocks in `bytes`. A run ends at a block that
+/// isn't full, since only the last block of a folder may be short, or
+/// when there are too many blocks for one folder.
+fn find_runs(bytes: &[u8]) -> Vec<Run> {
+    let mut runs: Vec<Run> = vec![];
+    let mut offset = 0;
+    let mut current: Option<Run> = None;
+    while offset + CFDATA_SIZE as usize <= bytes.len() {
+        let compression = current.as_ref().map(|run| run.compression);
+        let (block, compression) = match block_at(bytes, offset, compression) {
+            Some(found) => found,
+            None => {
+                runs.extend(current.take());
+                offset += 1;
+                continue;
+            }
+        };
+        let run = current.get_or_insert_with(|| Run {
+            offset: offset as u64,
+            compression,
+            blocks: vec![],
+        });
+        offset += CFDATA_SIZE as usize + block.data.len();
+        let full = usize::from(block.uncompressed_size) == MAX_CHUNK;
+        run.blocks.push(block);
+        if !full || run.blocks.len() == u16::MAX as usize {
+            runs.extend(current.take());
+        }
+    }
+    runs.extend(current);
+    runs
+}
+
+/// Check whether there's a plausible data block at `offset` in `bytes`,
+/// with the given compression type if there is one. Returns the block and
+/// its compression type.
+fn block_at(bytes: &[u8], offset: usize, compression: Option<u16>) -> Option<(DataBlock, u16)> {
+    let header = &bytes[offset..offset + CFDATA_SIZE as usize];
+    let csum = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let cb_data = usize::from(u16::from_le_bytes([header[4], header[5]]));
+    let cb_uncomp = u16::from_le_bytes([header[6], header[7]]);
+    let start = offset + CFDATA_SIZE as usize;
+    if cb_data == 0
+        || cb_data > MAX_COMPRESSED_CHUNK
+        || cb_uncomp == 0
+        || usize::from(cb_uncomp) > MAX_CHUNK
+        || start + cb_data > bytes.len()
+    {
+        return None;
+    }
+    let data = &bytes[start..start + cb_data];
+    let found = if data.starts_with(b"CK") {
+        tcompTYPE_MSZIP
+    } else if cb_data == usize::from(cb_uncomp) && csum != 0 {
+        tcompTYPE_NONE
+    } else {
+        return None;
+    };
+    if compression.is_some_and(|c| c != found)
+        || (csum != 0 && checksum(&header[4..], checksum(data, 0)) != csum)
+    {
+        return None;
+    }
+    let block = DataBlock {
+        reserve: vec![],
+        data: data.to_vec(),
+        uncompressed_size: cb_uncomp,
+    };
+    Some((block, found))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::io::Read;
+
+    use self::tempdir::TempDir;
+    use super::rebuild_cab;
+    use crate::format::parse::parse_cab;
+    use crate::format::tcompTYPE_MSZIP;
+    use crate::{lint_cab, make_cab_from_files};
+    use cab::Cabinet;
+
+    #[test]
+    fn rebuild() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let a = t.path().join("a.txt");
+        let b = t.path().join("b.txt");
+        let a_data = (0..100_000u32)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(&a, &a_data).unwrap();
+        fs::write(&b, b"the end").unwrap();
+        let cab = t.path().join("test.cab");
+        make_cab_from_files(&cab, &[&a, &b]).expect("failed to create cab file");
+
+        // Wipe out everything before the first data block.
+        let data_offset = parse_cab(&cab).unwrap().folders[0].data_offset as usize;
+        let mut bytes = fs::read(&cab).unwrap();
+        for b in &mut bytes[..data_offset] {
+            *b = 0;
+        }
+        fs::write(&cab, &bytes).unwrap();
+        let fixed = t.path().join("fixed.cab");
+        let folders = rebuild_cab(&cab, &fixed).expect("failed to rebuild cab file");
+        assert_eq!(folders.len(), 1);
+        assert_eq!(folders[0].offset, data_offset as u64);
+        assert_eq!(folders[0].blocks, 4);
+        assert_eq!(folders[0].compression, tcompTYPE_MSZIP);
+        assert_eq!(folders[0].size, 100_007);
+
+        assert_eq!(lint_cab(&fixed).expect("failed to lint cab file"), vec![]);
+        let mut cabinet = Cabinet::new(fs::File::open(&fixed).unwrap()).unwrap();
+        let mut data = vec![];
+        cabinet
+            .read_file("recovered_0.bin")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        let mut expected = a_data;
+        expected.extend_from_slice(b"the end");
+        assert_eq!(data, expected);
+
+        fs::write(&cab, vec![0; 1000]).unwrap();
+        assert!(rebuild_cab(&cab, &fixed).is_err());
+    }
+}
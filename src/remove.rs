@@ -0,0 +1,187 @@
+//! Removing files from an existing cabinet.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use cab::{Cabinet, CabinetBuilder, CompressionType};
+
+use crate::reserve::{read_reserve, Reserve};
+use crate::temp_cab_file;
+
+/// Rewrite the cabinet file at `cab_path` without the files stored as
+/// `names`, to fix packaging mistakes without building it again.
+///
+/// The remaining files keep their folders, timestamps and attributes, and
+/// folders left empty are dropped. Files in folders compressed with Quantum
+/// or LZX are recompressed with MSZIP, since those can't be written. The
+/// header and folder reserves are kept, with the timestamps and CRC-32s
+/// stored for [`CabOptions`] updated to match. It's an error for any of
+/// `names` to be missing from the cabinet, in which case it's left alone.
+///
+/// [`CabOptions`]: crate::CabOptions
+pub fn remove_files<T: AsRef<Path>, S: AsRef<str>>(cab_path: T, names: &[S]) -> Result<()> {
+    let cab_path = cab_path.as_ref();
+    let mut cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let removed = names.iter().map(AsRef::as_ref).collect::<HashSet<_>>();
+    for name in &removed {
+        if cabinet.get_file_entry(name).is_none() {
+            bail!("No file named '{}' in cabinet", name);
+        }
+    }
+
+    let mut cab_builder = CabinetBuilder::new();
+    let mut kept = vec![];
+    for folder in cabinet.folder_entries() {
+        let files = folder
+            .file_entries()
+            .filter(|file| !removed.contains(file.name()))
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            continue;
+        }
+        let new_folder = cab_builder.add_folder(match folder.compression_type() {
+            CompressionType::None => CompressionType::None,
+            _ => CompressionType::MsZip,
+        });
+        if !folder.reserve_data().is_empty() {
+            new_folder.set_reserve_data(folder.reserve_data().to_vec());
+        }
+        for file in files {
+            let new_file = new_folder.add_file(file.name());
+            if let Some(datetime) = file.datetime() {
+                new_file.set_datetime(datetime);
+            }
+            new_file.set_is_read_only(file.is_read_only());
+            new_file.set_is_hidden(file.is_hidden());
+            new_file.set_is_system(file.is_system());
+            new_file.set_is_archive(file.is_archive());
+            new_file.set_is_exec(file.is_exec());
+            kept.push(file.name().to_owned());
+        }
+    }
+
+    let extras = read_reserve(&cabinet);
+    let header_reserve = if extras.is_empty() {
+        cabinet.reserve_data().to_vec()
+    } else {
+        let kept_extras = kept.iter().map(|name| extras[name]).collect::<Vec<_>>();
+        Reserve {
+            timestamps: kept_extras.iter().map(|extra| extra.timestamps).collect(),
+            crc32s: kept_extras.iter().map(|extra| extra.crc32).collect(),
+            ..Reserve::default()
+        }
+        .to_bytes()?
+    };
+    if !header_reserve.is_empty() {
+        cab_builder.set_reserve_data(header_reserve);
+    }
+
+    let mut cab_writer = cab_builder.build(temp_cab_file(cab_path)?)?;
+    let mut names = kept.iter();
+    while let (Some(mut writer), Some(name)) = (cab_writer.next_file()?, names.next()) {
+        io::copy(&mut cabinet.read_file(name)?, &mut writer)?;
+    }
+    let temp = cab_writer.finish()?;
+    // Windows can't replace a file that's still open.
+    drop(cabinet);
+    temp.persist(cab_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use self::tempdir::TempDir;
+    use cab::{Cabinet, CompressionType};
+
+    use super::remove_files;
+    use crate::{
+        lint_cab, make_cab_from_files_with_options, make_cabs_from_manifest, verify_cab, CabOptions,
+    };
+
+    /// The names of the files in each folder of the cabinet at `cab`.
+    fn folder_names(cab: &std::path::Path) -> Vec<Vec<String>> {
+        let cabinet = Cabinet::new(File::open(cab).unwrap()).unwrap();
+        cabinet
+            .folder_entries()
+            .map(|folder| {
+                folder
+                    .file_entries()
+                    .map(|file| file.name().to_owned())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn remove() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let paths = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .map(|name| {
+                let path = t.path().join(name);
+                fs::write(&path, name.repeat(10_000)).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("test.cab");
+        let options = CabOptions {
+            crc32: true,
+            high_res_timestamps: true,
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &paths, &options)
+            .expect("failed to create cab file");
+        assert!(remove_files(&cab, &["b.txt", "missing.txt"]).is_err());
+        assert_eq!(folder_names(&cab), vec![vec!["a.txt", "b.txt", "c.txt"]]);
+
+        remove_files(&cab, &["b.txt"]).expect("failed to remove file");
+        assert_eq!(folder_names(&cab), vec![vec!["a.txt", "c.txt"]]);
+        assert_eq!(lint_cab(&cab).expect("failed to lint cab file"), vec![]);
+        // The CRC-32s that are still there match the remaining files.
+        let report = verify_cab(&cab).expect("cab file didn't verify");
+        assert!(report.is_ok());
+        let mut cabinet = Cabinet::new(File::open(&cab).unwrap()).unwrap();
+        assert!(!cabinet.reserve_data().is_empty());
+        let mut c = vec![];
+        cabinet
+            .read_file("c.txt")
+            .unwrap()
+            .read_to_end(&mut c)
+            .unwrap();
+        assert_eq!(c, "c.txt".repeat(10_000).as_bytes());
+
+        // Emptying a folder drops it, and the other files keep their
+        // attributes.
+        let manifest = t.path().join("build.json");
+        fs::write(
+            &manifest,
+            r#"{
+                "cab": "out.cab",
+                "entries": [
+                    {"source": "a.txt"},
+                    {"source": "b.txt", "compression": "none",
+                     "attributes": ["read-only"]},
+                    {"source": "c.txt"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        make_cabs_from_manifest(&manifest).expect("failed to create cab file");
+        let cab = t.path().join("out.cab");
+        remove_files(&cab, &["a.txt"]).expect("failed to remove file");
+        assert_eq!(folder_names(&cab), vec![vec!["b.txt"], vec!["c.txt"]]);
+        let cabinet = Cabinet::new(File::open(&cab).unwrap()).unwrap();
+        let folder = cabinet.folder_entries().next().unwrap();
+        assert_eq!(folder.compression_type(), CompressionType::None);
+        assert!(cabinet.get_file_entry("b.txt").unwrap().is_read_only());
+        assert!(verify_cab(&cab).expect("cab file didn't verify").is_ok());
+    }
+}
@@ -0,0 +1,667 @@
+//! Storing extra information about each file in a cabinet's reserved area.
+//!
+//! CFFILE entries only have room for a DOS date and time, which has two
+//! second resolution and no creation time, and nothing checks a file's
+//! contents after decompression other than the per-block CFDATA checksums.
+//! The format has no per-file reserved area, so the extra information asked
+//! for with [`CabOptions`] is stored in tables in the cabinet's header
+//! reserve instead, with one entry per file in the order the files appear in
+//! the cabinet. Extractors that don't know about the tables skip the reserve.
+//!
+//! Each table starts with a four byte tag, a two byte entry size and a two
+//! byte file count, followed by the entries:
+//!
+//! * `MCTS`: the modification and creation time of the file as 64-bit
+//!   FILETIMEs. A creation time of zero means it isn't known.
+//! * `MCRC`: the CRC-32 of the file's uncompressed contents.
+//!
+//! Callers can also put their own bytes in the header reserve and the
+//! folder reserve with [`CabOptions::header_reserve`] and
+//! [`CabOptions::folder_reserve`], and read or overwrite them later. Each
+//! CFDATA block's reserve is filled in as the block is compressed by the
+//! [`DataReserve`] in [`CabOptions::data_reserve`], which sees the block's
+//! data, so it can store things like a per-block signature or hash, and
+//! read back with [`read_data_reserves`].
+//!
+//! [`CabOptions`]: crate::CabOptions
+//! [`CabOptions::header_reserve`]: crate::CabOptions::header_reserve
+//! [`CabOptions::folder_reserve`]: crate::CabOptions::folder_reserve
+//! [`CabOptions::data_reserve`]: crate::CabOptions::data_reserve
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use cab::Cabinet;
+use filetime::FileTime;
+
+use crate::format::parse::{parse, read_data_block, read_header};
+use crate::format::DataBlock;
+use crate::{LimitExceeded, CFFOLDER_SIZE, CFHEADER_SIZE, MAX_HEADER_RESERVE};
+
+/// The offset of the header reserve in a cabinet, after the CFHEADER fields
+/// and the sizes of the reserved areas.
+pub(crate) const HEADER_RESERVE_OFFSET: u64 = CFHEADER_SIZE + 4;
+
+const TABLE_HEADER_SIZE: usize = 8;
+const TIMESTAMPS_TAG: &[u8; 4] = b"MCTS";
+const TIMESTAMPS_ENTRY_SIZE: usize = 16;
+const CRC32_TAG: &[u8; 4] = b"MCRC";
+const CRC32_ENTRY_SIZE: usize = 4;
+
+/// The number of 100ns intervals between 1601-01-01, when FILETIMEs start,
+/// and the Unix epoch.
+const UNIX_EPOCH_FILETIME: i64 = 11_644_473_600 * 10_000_000;
+
+/// The full-precision timestamps of a file in a cabinet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Timestamps {
+    pub modified: FileTime,
+    pub created: Option<FileTime>,
+}
+
+/// The tables to store in a cabinet's header reserve, each with one entry
+/// per file in the order they're stored in the cabinet.
+///
+/// Alternatively, `header` holds the caller's own bytes to store as the
+/// whole header reserve; they can't be combined with the tables, which are
+/// only found by parsing the whole reserve.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Reserve {
+    pub header: Vec<u8>,
+    pub timestamps: Option<Vec<Timestamps>>,
+    pub crc32s: Option<Vec<u32>>,
+}
+
+impl Reserve {
+    /// Encode the header reserve, returning an empty buffer if there's
+    /// nothing to store.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if !self.header.is_empty() && (self.timestamps.is_some() || self.crc32s.is_some()) {
+            bail!("A header reserve can't be combined with high-resolution timestamps or CRC-32s");
+        }
+        let mut reserve = self.header.clone();
+        if let Some(timestamps) = &self.timestamps {
+            write_table_header(
+                &mut reserve,
+                TIMESTAMPS_TAG,
+                TIMESTAMPS_ENTRY_SIZE,
+                timestamps.len(),
+            )?;
+            for t in timestamps {
+                reserve.extend_from_slice(&to_filetime(t.modified).to_le_bytes());
+                let created = t.created.map(to_filetime).unwrap_or(0);
+                reserve.extend_from_slice(&created.to_le_bytes());
+            }
+        }
+        if let Some(crc32s) = &self.crc32s {
+            write_table_header(&mut reserve, CRC32_TAG, CRC32_ENTRY_SIZE, crc32s.len())?;
+            for crc32 in crc32s {
+                reserve.extend_from_slice(&crc32.to_le_bytes());
+            }
+        }
+        if reserve.len() > usize::from(MAX_HEADER_RESERVE) {
+            return Err(LimitExceeded::HeaderReserveTooLarge(reserve.len()).into());
+        }
+        Ok(reserve)
+    }
+
+    /// The reserve for just the files at `files`, as stored in one cabinet
+    /// of a set. The caller's own bytes are stored in every cabinet.
+    pub fn for_files(&self, files: Range<usize>) -> Reserve {
+        Reserve {
+            header: self.header.clone(),
+            timestamps: self
+                .timestamps
+                .as_ref()
+                .map(|timestamps| timestamps[files.clone()].to_vec()),
+            crc32s: self.crc32s.as_ref().map(|crc32s| crc32s[files].to_vec()),
+        }
+    }
+
+    /// The size of the header reserve for `count` files, as
+    /// [`Reserve::to_bytes`] would encode it.
+    pub fn size_for(&self, count: usize) -> usize {
+        let table = |present: bool, entry_size: usize| {
+            if present {
+                TABLE_HEADER_SIZE + entry_size * count
+            } else {
+                0
+            }
+        };
+        self.header.len()
+            + table(self.timestamps.is_some(), TIMESTAMPS_ENTRY_SIZE)
+            + table(self.crc32s.is_some(), CRC32_ENTRY_SIZE)
+    }
+}
+
+fn write_table_header(
+    reserve: &mut Vec<u8>,
+    tag: &[u8; 4],
+    entry_size: usize,
+    count: usize,
+) -> Result<()> {
+    if count > usize::from(u16::MAX) {
+        bail!("Too many files to store extra information about in the cabinet header");
+    }
+    reserve.extend_from_slice(tag);
+    reserve.extend_from_slice(&(entry_size as u16).to_le_bytes());
+    reserve.extend_from_slice(&(count as u16).to_le_bytes());
+    Ok(())
+}
+
+/// The extra information about a file found in a cabinet's header reserve.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FileExtras {
+    pub timestamps: Option<Timestamps>,
+    pub crc32: Option<u32>,
+}
+
+/// Get the extra information stored in `cabinet`'s header reserve, keyed by
+/// file name. Tables that don't have an entry for every file are ignored,
+/// and an empty map is returned if there aren't any tables.
+pub(crate) fn read_reserve<R: Read + Seek>(cabinet: &Cabinet<R>) -> HashMap<String, FileExtras> {
+    let names = cabinet
+        .folder_entries()
+        .flat_map(|folder| folder.file_entries().map(|file| file.name().to_owned()))
+        .collect::<Vec<_>>();
+    match read_reserve_tables(cabinet.reserve_data(), names.len()) {
+        Some(extras) => names.into_iter().zip(extras).collect(),
+        None => HashMap::new(),
+    }
+}
+
+/// Get the extra information about each of `count` files from the tables
+/// in the header reserve `reserve`. Tables that don't have `count` entries
+/// are ignored, and `None` is returned if there aren't any tables.
+pub(crate) fn read_reserve_tables(mut reserve: &[u8], count: usize) -> Option<Vec<FileExtras>> {
+    let mut extras = vec![FileExtras::default(); count];
+    let mut found = false;
+    while reserve.len() >= TABLE_HEADER_SIZE {
+        let tag = &reserve[..4];
+        let entry_size = usize::from(le_u16(&reserve[4..]));
+        let table_count = usize::from(le_u16(&reserve[6..]));
+        if entry_size == 0 {
+            // Not one of our tables, such as a reserve zeroed to make room
+            // for a signature, so there's nothing more to read.
+            break;
+        }
+        let size = entry_size * table_count;
+        if reserve.len() < TABLE_HEADER_SIZE + size {
+            break;
+        }
+        let entries = reserve[TABLE_HEADER_SIZE..TABLE_HEADER_SIZE + size].chunks(entry_size);
+        reserve = &reserve[TABLE_HEADER_SIZE + size..];
+        if table_count != count {
+            continue;
+        }
+        if tag == TIMESTAMPS_TAG && entry_size == TIMESTAMPS_ENTRY_SIZE {
+            for (extra, entry) in extras.iter_mut().zip(entries) {
+                let created = le_u64(&entry[8..]);
+                extra.timestamps = Some(Timestamps {
+                    modified: from_filetime(le_u64(entry)),
+                    created: if created == 0 {
+                        None
+                    } else {
+                        Some(from_filetime(created))
+                    },
+                });
+            }
+            found = true;
+        } else if tag == CRC32_TAG && entry_size == CRC32_ENTRY_SIZE {
+            for (extra, entry) in extras.iter_mut().zip(entries) {
+                extra.crc32 = Some(u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]));
+            }
+            found = true;
+        }
+    }
+    if found {
+        Some(extras)
+    } else {
+        None
+    }
+}
+
+/// Fills in the reserve of each CFDATA block in a cabinet as it's written,
+/// set through [`CabOptions::data_reserve`].
+///
+/// With [`CabOptions::threads`], blocks are compressed on several threads at
+/// once, so `reserve` can be called from any of them, in any order.
+///
+/// [`CabOptions::data_reserve`]: crate::CabOptions::data_reserve
+/// [`CabOptions::threads`]: crate::CabOptions::threads
+pub trait DataReserve: Debug + Send + Sync {
+    /// The size of every block's reserve.
+    fn size(&self) -> u8;
+
+    /// The reserve for the block `index`, counting from the first block in
+    /// the cabinet, or in the first cabinet of a set. The block holds
+    /// `chunk` once decompressed and is stored as `data`. The reserve must be
+    /// [`size`](DataReserve::size) bytes.
+    fn reserve(&self, index: usize, chunk: &[u8], data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// Fill in `block`'s reserve with `data_reserve`, given its `index` and the
+/// `chunk` it was compressed from.
+pub(crate) fn fill_data_reserve(
+    block: &mut DataBlock,
+    data_reserve: &dyn DataReserve,
+    index: usize,
+    chunk: &[u8],
+) -> io::Result<()> {
+    let reserve = data_reserve.reserve(index, chunk, &block.data)?;
+    if reserve.len() != usize::from(data_reserve.size()) {
+        return Err(io::Error::other(format!(
+            "{} bytes were reserved in each data block, but {} were given",
+            data_reserve.size(),
+            reserve.len()
+        )));
+    }
+    block.reserve = reserve;
+    Ok(())
+}
+
+/// The reserved areas of a cabinet, as read by [`read_reserve_data`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReserveData {
+    /// The contents of the header reserve.
+    pub header: Vec<u8>,
+    /// The contents of each folder's reserve, in the order of the folders.
+    pub folders: Vec<Vec<u8>>,
+    /// The contents of each CFDATA block's reserve, in the order the blocks
+    /// are stored, or nothing if the cabinet doesn't reserve any space in
+    /// its blocks.
+    pub blocks: Vec<Vec<u8>>,
+}
+
+/// Read the header, folder and data block reserved areas of the cabinet at
+/// `cab_path`.
+pub fn read_reserve_data<T: AsRef<Path>>(cab_path: T) -> Result<ReserveData> {
+    let cab_path = cab_path.as_ref();
+    let cabinet = Cabinet::new(File::open(cab_path)?)?;
+    let mut blocks = vec![];
+    read_data_reserves(cab_path, |_, block| {
+        if !block.reserve.is_empty() {
+            blocks.push(block.reserve.clone());
+        }
+        Ok(())
+    })?;
+    Ok(ReserveData {
+        header: cabinet.reserve_data().to_vec(),
+        folders: cabinet
+            .folder_entries()
+            .map(|folder| folder.reserve_data().to_vec())
+            .collect(),
+        blocks,
+    })
+}
+
+/// Pass each CFDATA block in the cabinet at `cab_path` to `read` in the
+/// order they're stored, along with its index counting from the first
+/// block, to check or collect what a [`DataReserve`] stored in the blocks'
+/// reserves. Stops at the first error `read` returns.
+pub fn read_data_reserves<T, F>(cab_path: T, mut read: F) -> Result<()>
+where
+    T: AsRef<Path>,
+    F: FnMut(usize, &DataBlock) -> Result<()>,
+{
+    let mut reader = BufReader::new(File::open(cab_path)?);
+    let cabinet = parse(&mut reader)?;
+    let mut index = 0;
+    for folder in &cabinet.folders {
+        reader.seek(SeekFrom::Start(u64::from(folder.data_offset)))?;
+        for _ in 0..folder.data_count {
+            let (_, block) = read_data_block(&mut reader, cabinet.header.data_reserve_size)?;
+            read(index, &block)?;
+            index += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Overwrite the header reserve of the cabinet at `cab_path` with `data`,
+/// which must be exactly the size reserved when the cabinet was built.
+///
+/// Nothing else in the cabinet changes, so this can be used to fill in a
+/// signature over the rest of the cabinet after it has been built.
+pub fn write_header_reserve<T: AsRef<Path>>(cab_path: T, data: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(cab_path)?;
+    let offsets = reserve_offsets(&mut file)?;
+    overwrite_reserve(&mut file, offsets.header, offsets.header_size, data)
+}
+
+/// Overwrite the reserve of folder `index` in the cabinet at `cab_path` with
+/// `data`, which must be exactly the size reserved when the cabinet was
+/// built.
+pub fn write_folder_reserve<T: AsRef<Path>>(cab_path: T, index: usize, data: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(cab_path)?;
+    let offsets = reserve_offsets(&mut file)?;
+    match offsets.folders.get(index) {
+        Some(&offset) => overwrite_reserve(&mut file, offset, offsets.folder_size, data),
+        None => bail!("No folder {} in cabinet", index),
+    }
+}
+
+fn overwrite_reserve(file: &mut File, offset: u64, size: usize, data: &[u8]) -> Result<()> {
+    if data.len() != size {
+        bail!(
+            "{} bytes were reserved, but {} were given",
+            size,
+            data.len()
+        );
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+/// Where the reserved areas in a cabinet are.
+struct ReserveOffsets {
+    header: u64,
+    header_size: usize,
+    folders: Vec<u64>,
+    folder_size: usize,
+}
+
+/// Find the reserved areas in the cabinet `file` by reading its CFHEADER.
+fn reserve_offsets(file: &mut File) -> Result<ReserveOffsets> {
+    let header = read_header(&mut BufReader::new(file))?;
+    let folder_size = usize::from(header.folder_reserve_size);
+    let first_folder = header.size();
+    let folders = (0..u64::from(header.folder_count))
+        .map(|i| first_folder + i * (CFFOLDER_SIZE + folder_size as u64) + CFFOLDER_SIZE)
+        .collect();
+    Ok(ReserveOffsets {
+        header: HEADER_RESERVE_OFFSET,
+        header_size: header.header_reserve.len(),
+        folders,
+        folder_size,
+    })
+}
+
+/// Set the creation time of the file at `path`, where that's possible.
+#[cfg(windows)]
+pub(crate) fn set_file_created(path: &Path, created: FileTime) -> std::io::Result<()> {
+    use std::os::windows::fs::FileTimesExt;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let created = UNIX_EPOCH + Duration::new(created.unix_seconds() as u64, created.nanoseconds());
+    let times = std::fs::FileTimes::new().set_created(created);
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_times(times)
+}
+
+/// Set the creation time of the file at `path`, where that's possible.
+#[cfg(not(windows))]
+pub(crate) fn set_file_created(_path: &Path, _created: FileTime) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn to_filetime(time: FileTime) -> u64 {
+    (time.unix_seconds() * 10_000_000 + i64::from(time.nanoseconds() / 100) + UNIX_EPOCH_FILETIME)
+        as u64
+}
+
+fn from_filetime(filetime: u64) -> FileTime {
+    let since_epoch = filetime as i64 - UNIX_EPOCH_FILETIME;
+    FileTime::from_unix_time(
+        since_epoch.div_euclid(10_000_000),
+        since_epoch.rem_euclid(10_000_000) as u32 * 100,
+    )
+}
+
+fn le_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::{self, File};
+    use std::io;
+    use std::sync::Arc;
+
+    use self::tempdir::TempDir;
+    use super::{
+        from_filetime, read_data_reserves, read_reserve, read_reserve_data, to_filetime,
+        write_folder_reserve, write_header_reserve, DataReserve, ReserveData,
+        HEADER_RESERVE_OFFSET,
+    };
+    use crate::{
+        extract_cab, join_cabs, make_cab_from_files_with_options, verify, verify_cab, CabOptions,
+        SplitOptions,
+    };
+    use cab::Cabinet;
+    use filetime::FileTime;
+    use flate2::Crc;
+
+    #[test]
+    fn filetime_conversion() {
+        let time = FileTime::from_unix_time(1_600_000_000, 123_456_700);
+        assert_eq!(to_filetime(time), 132_444_736_001_234_567);
+        assert_eq!(from_filetime(to_filetime(time)), time);
+        let before_epoch = FileTime::from_unix_time(-1, 100);
+        assert_eq!(from_filetime(to_filetime(before_epoch)), before_epoch);
+    }
+
+    #[test]
+    fn reserve_tables() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let names = ["a.txt", "b.txt"];
+        for name in &names {
+            fs::write(t.path().join(name), name).expect("failed to write test data");
+        }
+        let inputs = names
+            .iter()
+            .map(|name| t.path().join(name))
+            .collect::<Vec<_>>();
+        let options = CabOptions {
+            high_res_timestamps: true,
+            crc32: true,
+            ..CabOptions::default()
+        };
+        let cab = t.path().join("test.cab");
+        make_cab_from_files_with_options(&cab, &inputs, &options)
+            .expect("failed to create cab file");
+        let cabinet = Cabinet::new(File::open(&cab).unwrap()).expect("failed to read cab file");
+        let extras = read_reserve(&cabinet);
+        assert_eq!(extras.len(), 2);
+        let a = &extras["a.txt"];
+        assert!(a.timestamps.is_some());
+        let mut crc = Crc::new();
+        crc.update(b"a.txt");
+        assert_eq!(a.crc32, Some(crc.sum()));
+    }
+
+    #[test]
+    fn custom_reserves() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original.txt");
+        fs::write(&in_path, "hello").expect("failed to write test data");
+        let mut options = CabOptions {
+            header_reserve: vec![0; 20],
+            folder_reserve: vec![1, 2, 3, 4],
+            ..CabOptions::default()
+        };
+        let cab = t.path().join("test.cab");
+        make_cab_from_files_with_options(&cab, &[&in_path], &options)
+            .expect("failed to create cab file");
+        assert_eq!(
+            read_reserve_data(&cab).expect("failed to read reserves"),
+            ReserveData {
+                header: vec![0; 20],
+                folders: vec![vec![1, 2, 3, 4]],
+                blocks: vec![],
+            }
+        );
+        // A zeroed reserve, left for a signature, isn't mistaken for tables.
+        assert!(verify_cab(&cab).expect("failed to read cab file").is_ok());
+        let paths = extract_cab(&cab, t.path().join("out")).expect("failed to extract cab file");
+        assert_eq!(fs::read(&paths[0]).unwrap(), b"hello");
+
+        let signature = (0..20).collect::<Vec<u8>>();
+        write_header_reserve(&cab, &signature).expect("failed to write header reserve");
+        write_folder_reserve(&cab, 0, &[5, 6, 7, 8]).expect("failed to write folder reserve");
+        assert_eq!(
+            read_reserve_data(&cab).expect("failed to read reserves"),
+            ReserveData {
+                header: signature,
+                folders: vec![vec![5, 6, 7, 8]],
+                blocks: vec![],
+            }
+        );
+        verify(&cab, &in_path).expect("failed to verify cab file");
+        assert!(write_header_reserve(&cab, &[0; 21]).is_err());
+        assert!(write_folder_reserve(&cab, 1, &[0; 4]).is_err());
+
+        // The header reserve is needed for the tables.
+        options.crc32 = true;
+        assert!(make_cab_from_files_with_options(&cab, &[&in_path], &options).is_err());
+    }
+
+    /// Stores each block's index and the CRC-32 of its compressed data, or
+    /// one byte too few.
+    #[derive(Debug)]
+    struct BlockCrcs {
+        short: bool,
+    }
+
+    impl DataReserve for BlockCrcs {
+        fn size(&self) -> u8 {
+            8
+        }
+
+        fn reserve(&self, index: usize, chunk: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+            assert!(!chunk.is_empty());
+            let mut crc = Crc::new();
+            crc.update(data);
+            let mut reserve = (index as u32).to_le_bytes().to_vec();
+            reserve.extend_from_slice(&crc.sum().to_le_bytes());
+            if self.short {
+                reserve.pop();
+            }
+            Ok(reserve)
+        }
+    }
+
+    #[test]
+    fn data_reserves() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original.bin");
+        let data = (0..200_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect::<Vec<_>>();
+        fs::write(&in_path, &data).expect("failed to write test data");
+        let mut options = CabOptions {
+            data_reserve: Some(Arc::new(BlockCrcs { short: false })),
+            threads: 2,
+            ..CabOptions::default()
+        };
+        let cab = t.path().join("test.cab");
+        make_cab_from_files_with_options(&cab, &[&in_path], &options)
+            .expect("failed to create cab file");
+        let check = |cab: &std::path::Path, first: u32| {
+            let mut count = 0;
+            read_data_reserves(cab, |index, block| {
+                let mut crc = Crc::new();
+                crc.update(&block.data);
+                assert_eq!(block.reserve[..4], (first + index as u32).to_le_bytes());
+                assert_eq!(block.reserve[4..], crc.sum().to_le_bytes());
+                count += 1;
+                Ok(())
+            })
+            .expect("failed to read data reserves");
+            count
+        };
+        assert_eq!(check(&cab, 0), 7);
+        assert_eq!(read_reserve_data(&cab).unwrap().blocks.len(), 7);
+        assert!(verify_cab(&cab).expect("failed to read cab file").is_ok());
+        verify(&cab, &in_path).expect("failed to verify cab file");
+
+        // Blocks are counted across a whole set.
+        options.split = Some(SplitOptions::new(100_000));
+        let summary = make_cab_from_files_with_options(&cab, &[&in_path], &options)
+            .expect("failed to create cab set");
+        let mut first = 0;
+        for member in &summary.cabinets {
+            first += check(member, first);
+        }
+        assert_eq!(first, 7);
+        let joined = t.path().join("joined.cab");
+        join_cabs(&summary.cabinets[0], &joined).expect("failed to join cab set");
+        verify(&joined, &in_path).expect("failed to verify joined cab file");
+
+        options.split = None;
+        options.data_reserve = Some(Arc::new(BlockCrcs { short: true }));
+        assert!(make_cab_from_files_with_options(&cab, &[&in_path], &options).is_err());
+    }
+
+    #[test]
+    fn crc32_mismatch() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original.txt");
+        fs::write(&in_path, "hello").expect("failed to write test data");
+        let options = CabOptions {
+            crc32: true,
+            ..CabOptions::default()
+        };
+        let cab = t.path().join("test.cab");
+        make_cab_from_files_with_options(&cab, &[&in_path], &options)
+            .expect("failed to create cab file");
+        assert!(verify_cab(&cab).expect("failed to read cab file").is_ok());
+        extract_cab(&cab, t.path().join("out")).expect("failed to extract cab file");
+
+        // Corrupt the stored CRC, which the CFDATA checksums don't cover.
+        let mut bytes = fs::read(&cab).expect("failed to read cab file");
+        let crc_offset = HEADER_RESERVE_OFFSET as usize + 8;
+        bytes[crc_offset] ^= 0x01;
+        fs::write(&cab, &bytes).expect("failed to write cab file");
+        let report = verify_cab(&cab).expect("failed to read cab file");
+        assert!(!report.is_ok());
+        assert!(extract_cab(&cab, t.path().join("out2")).is_err());
+    }
+
+    #[test]
+    fn high_res_timestamps() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let in_path = t.path().join("original.txt");
+        fs::write(&in_path, "hello").expect("failed to write test data");
+        let mtime = FileTime::from_unix_time(1_600_000_001, 123_456_700);
+        filetime::set_file_mtime(&in_path, mtime).expect("failed to set mtime");
+
+        let extracted_mtime = |options: &CabOptions| {
+            let cab = t.path().join("test.cab");
+            let out = t.path().join("out");
+            make_cab_from_files_with_options(&cab, &[&in_path], options)
+                .expect("failed to create cab file");
+            verify(&cab, &in_path).expect("failed to verify cab file");
+            let paths = extract_cab(&cab, &out).expect("failed to extract cab file");
+            let meta = fs::metadata(&paths[0]).expect("failed to read metadata");
+            FileTime::from_last_modification_time(&meta)
+        };
+
+        // DOS times round to two seconds.
+        assert_eq!(
+            extracted_mtime(&CabOptions::default()),
+            FileTime::from_unix_time(1_600_000_000, 0)
+        );
+        let options = CabOptions {
+            high_res_timestamps: true,
+            ..CabOptions::default()
+        };
+        assert_eq!(extracted_mtime(&options), mtime);
+    }
+}
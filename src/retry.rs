@@ -0,0 +1,201 @@
+//! Retrying reads of input files after transient errors.
+//!
+//! Reading thousands of files from a network share can take hours, and a
+//! share that drops out for a moment shouldn't throw all of that away. A
+//! handle to a file on a share that reconnected is usually dead, so after
+//! an error the file is opened again and reading carries on where it left
+//! off.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::{open_input, CabOptions};
+
+/// How to retry opening and reading input files after transient errors,
+/// like timeouts and dropped network connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times to retry a failed open or read before giving up.
+    pub retries: u32,
+    /// How long to wait before the first retry. The wait doubles after each
+    /// retry, up to `max_delay`.
+    pub initial_delay: Duration,
+    /// The longest to wait between retries.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: 5,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub(crate) fn never() -> RetryPolicy {
+        RetryPolicy {
+            retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Call `f` until it succeeds, fails with an error that isn't
+    /// transient, or has been retried as many times as the policy allows.
+    fn run<T, F: FnMut() -> io::Result<T>>(&self, mut f: F) -> io::Result<T> {
+        let mut delay = self.initial_delay;
+        let mut retries = 0;
+        loop {
+            match f() {
+                Err(e) if retries < self.retries && is_transient(&e) => {
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(self.max_delay);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether `e` is the kind of error that might go away if the operation is
+/// tried again.
+fn is_transient(e: &io::Error) -> bool {
+    // ERROR_BAD_NETPATH, ERROR_NETWORK_BUSY, ERROR_UNEXP_NET_ERR,
+    // ERROR_NETNAME_DELETED and ERROR_SEM_TIMEOUT, which is what a read
+    // from an unresponsive share fails with.
+    #[cfg(windows)]
+    const TRANSIENT_WINDOWS_ERRORS: &[i32] = &[53, 54, 59, 64, 121];
+    #[cfg(windows)]
+    {
+        if e.raw_os_error()
+            .is_some_and(|code| TRANSIENT_WINDOWS_ERRORS.contains(&code))
+        {
+            return true;
+        }
+    }
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+    )
+}
+
+/// An input file that's opened again, at the same position, to retry a
+/// read that failed with a transient error.
+pub(crate) struct RetryingInput<'a> {
+    path: &'a Path,
+    options: &'a CabOptions,
+    policy: &'a RetryPolicy,
+    file: Option<File>,
+    pos: u64,
+}
+
+impl<'a> RetryingInput<'a> {
+    /// Open the input file at `path` as `options` say to, retrying as
+    /// `policy` says.
+    pub(crate) fn open(
+        path: &'a Path,
+        options: &'a CabOptions,
+        policy: &'a RetryPolicy,
+    ) -> io::Result<RetryingInput<'a>> {
+        let file = policy.run(|| open_input(path, options))?;
+        Ok(RetryingInput {
+            path,
+            options,
+            policy,
+            file: Some(file),
+            pos: 0,
+        })
+    }
+}
+
+impl<'a> Read for RetryingInput<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let RetryingInput {
+            path,
+            options,
+            policy,
+            file,
+            pos,
+        } = self;
+        policy.run(|| {
+            let current = match file {
+                Some(current) => current,
+                None => {
+                    let mut reopened = open_input(path, options)?;
+                    reopened.seek(SeekFrom::Start(*pos))?;
+                    file.insert(reopened)
+                }
+            };
+            match current.read(buf) {
+                Ok(read) => {
+                    *pos += read as u64;
+                    Ok(read)
+                }
+                Err(e) => {
+                    *file = None;
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io;
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+
+    /// A function that fails with a timeout the first `n` times it's
+    /// called, counting calls in `calls`.
+    fn fail_until(calls: &Cell<u32>, n: u32) -> impl FnMut() -> io::Result<u32> + '_ {
+        calls.set(0);
+        move || {
+            calls.set(calls.get() + 1);
+            if calls.get() <= n {
+                Err(io::Error::from(io::ErrorKind::TimedOut))
+            } else {
+                Ok(calls.get())
+            }
+        }
+    }
+
+    #[test]
+    fn retries() {
+        let policy = RetryPolicy {
+            retries: 3,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+        };
+        let calls = Cell::new(0);
+        assert_eq!(policy.run(fail_until(&calls, 3)).unwrap(), 4);
+        assert_eq!(
+            policy.run(fail_until(&calls, 4)).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(calls.get(), 4);
+        assert!(RetryPolicy::never().run(fail_until(&calls, 1)).is_err());
+
+        calls.set(0);
+        let not_found = policy.run(|| -> io::Result<()> {
+            calls.set(calls.get() + 1);
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(not_found.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls.get(), 1);
+    }
+}
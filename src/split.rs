@@ -0,0 +1,1299 @@
+//! Splitting a cabinet into a linked set of smaller cabinets, and joining
+//! a set back into one cabinet.
+//!
+//! A cabinet set is a chain of cabinets whose headers link to the previous
+//! and next cabinet. A folder can carry on from the end of one cabinet into
+//! the start of the next, split between two data blocks, and a file whose
+//! data is split like that is listed in both cabinets, marked with one of
+//! the `ifoldCONTINUED_*` constants instead of a folder index.
+
+#![allow(non_upper_case_globals)]
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use tempfile::NamedTempFile;
+
+use crate::format::parse::{parse_layout, read_header};
+use crate::format::{
+    cfhdrNEXT_CABINET, cfhdrPREV_CABINET, cfhdrRESERVE_PRESENT, ifoldCONTINUED_FROM_PREV,
+    ifoldCONTINUED_PREV_AND_NEXT, ifoldCONTINUED_TO_NEXT, CabinetLink, DataBlock, FileEntry,
+    Folder, Header, Layout,
+};
+use crate::reserve::{read_reserve_tables, FileExtras, Reserve};
+use crate::{cab_dir, sync_dir, temp_cab_file, CFFILE_SIZE, CFFOLDER_SIZE};
+
+/// The part of a folder that goes in one cabinet of a set.
+struct FolderPart {
+    /// The index of the folder in the original cabinet.
+    folder: usize,
+    /// The folder's data blocks that go in this cabinet.
+    blocks: Range<usize>,
+}
+
+/// What goes in one cabinet of a set.
+#[derive(Default)]
+struct CabPlan {
+    parts: Vec<FolderPart>,
+    /// Indexes of the files in the original cabinet, in the order they're
+    /// listed in this one.
+    files: Vec<usize>,
+}
+
+/// Options for [`split_cab_with_options`], saying how large the cabinets in
+/// a set can be and what they and the disks they're on are called.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SplitOptions {
+    /// The largest size of each cabinet, in bytes.
+    pub max_size: u64,
+    /// The file name of each cabinet, with `%d` replaced by its number in
+    /// the set, counting from 1, like `pkg%d.cab`. `None` names them after
+    /// the cabinet being split, so `big.cab` is split into `big1.cab`,
+    /// `big2.cab` and so on.
+    pub name_template: Option<String>,
+    /// The label of the disk each cabinet is on, with `%d` replaced as in
+    /// [`SplitOptions::name_template`]. Labels are stored alongside the
+    /// cabinet names in the links between cabinets, for installers that
+    /// ask for the right disk. `None` means `Disk %d`.
+    pub disk_template: Option<String>,
+}
+
+impl SplitOptions {
+    /// Split into cabinets of at most `max_size` bytes, with the default
+    /// names and disk labels.
+    pub fn new(max_size: u64) -> SplitOptions {
+        SplitOptions {
+            max_size,
+            name_template: None,
+            disk_template: None,
+        }
+    }
+}
+
+/// Split the cabinet file at `cab_path` into a linked set of cabinets, none
+/// of them larger than `max_size` bytes, returning their paths.
+///
+/// This is [`split_cab_with_options`] with the default names and disk
+/// labels.
+pub fn split_cab<T: AsRef<Path>>(cab_path: T, max_size: u64) -> Result<Vec<PathBuf>> {
+    split_cab_with_options(cab_path, &SplitOptions::new(max_size))
+}
+
+/// Split the cabinet file at `cab_path` into a linked set of cabinets, as
+/// `options` say, returning their paths.
+///
+/// The cabinets are written next to `cab_path`, named after it with a
+/// number appended unless [`SplitOptions::name_template`] says otherwise:
+/// `big.cab` is split into `big1.cab`, `big2.cab` and so on. The data blocks are copied across without being recompressed, with
+/// folders split between blocks where they don't fit, and files split
+/// across cabinets are continued in the next one as the format allows.
+/// Folders are only split partway through a file, since that's how
+/// extractors know to carry a folder on into the next cabinet. If
+/// the cabinet stores timestamps or CRC-32s for [`CabOptions`], each
+/// cabinet in the set stores them for its own files.
+///
+/// It's an error for the cabinet to already be part of a set, for
+/// [`SplitOptions::max_size`] to be too small to hold the data blocks
+/// between two places a folder can be split, along with the records that
+/// have to go with them, or for [`SplitOptions::name_template`] not to be a
+/// file name containing `%d`.
+///
+/// [`CabOptions`]: crate::CabOptions
+pub fn split_cab_with_options<T: AsRef<Path>>(
+    cab_path: T,
+    options: &SplitOptions,
+) -> Result<Vec<PathBuf>> {
+    let cab_path = cab_path.as_ref();
+    let max_size = options.max_size;
+    let mut layout = parse_layout(BufReader::new(File::open(cab_path)?))?;
+    if layout.header.prev.is_some() || layout.header.next.is_some() {
+        bail!("Cabinet is already part of a set");
+    }
+    let names = SetNames::new(cab_path, options)?;
+    let link = |index: usize| names.link(index);
+    let extras = read_reserve_tables(&layout.header.header_reserve, layout.files.len());
+
+    // The size of a cabinet's header, including the header reserve, which
+    // depends on how many files the cabinet holds if it has tables.
+    let base_header = Header {
+        header_reserve: vec![],
+        ..layout.header.clone()
+    };
+    let reserve_size = |file_count: usize| match &extras {
+        Some(extras) => {
+            let timestamps = extras.iter().all(|extra| extra.timestamps.is_some());
+            let crc32s = extras.iter().all(|extra| extra.crc32.is_some());
+            let entry_size = 16 * usize::from(timestamps) + 4 * usize::from(crc32s);
+            8 * (usize::from(timestamps) + usize::from(crc32s)) + entry_size * file_count
+        }
+        None => layout.header.header_reserve.len(),
+    };
+    let header_size = |index: usize, file_count: usize| {
+        let header = Header {
+            prev: if index > 0 {
+                Some(link(index - 1))
+            } else {
+                None
+            },
+            next: Some(link(index + 1)),
+            ..base_header.clone()
+        };
+        header.size() + reserve_size(file_count) as u64
+    };
+    let folder_size = CFFOLDER_SIZE + u64::from(layout.header.folder_reserve_size);
+
+    // Each folder's uncompressed offset at the start of each of its blocks,
+    // and at the end.
+    let block_offsets = layout
+        .folders
+        .iter()
+        .map(|(_, blocks)| {
+            let mut offsets = vec![0u64];
+            for block in blocks {
+                let last = *offsets.last().unwrap();
+                offsets.push(last + u64::from(block.uncompressed_size));
+            }
+            offsets
+        })
+        .collect::<Vec<_>>();
+    let file_range = |file: &FileEntry| {
+        let start = u64::from(file.folder_offset);
+        start..start + u64::from(file.size)
+    };
+
+    // Fill each cabinet with as many blocks as fit, along with the records
+    // for their folders and files.
+    let mut plans = vec![CabPlan::default()];
+    // The size of the current cabinet's records and data, besides its
+    // header, and whether anything has gone in it yet.
+    let mut size = 0u64;
+    let mut fresh = true;
+    for (folder_index, (_, blocks)) in layout.folders.iter().enumerate() {
+        let offsets = &block_offsets[folder_index];
+        let mut folder_files = layout
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| usize::from(file.folder) == folder_index)
+            .collect::<Vec<_>>();
+        folder_files.sort_by_key(|(_, file)| file.folder_offset);
+        let mut next_file = 0;
+        for blocks_range in split_points(offsets, &folder_files) {
+            let blocks_size = blocks[blocks_range.clone()]
+                .iter()
+                .map(DataBlock::size)
+                .sum::<u64>();
+            let start = offsets[blocks_range.start];
+            let end = offsets[blocks_range.end];
+            // The files that start in these blocks, with everything left
+            // over going with the last of them.
+            let first_file = next_file;
+            while next_file < folder_files.len()
+                && (blocks_range.end == blocks.len()
+                    || u64::from(folder_files[next_file].1.folder_offset) < end)
+            {
+                next_file += 1;
+            }
+            let new_files = &folder_files[first_file..next_file];
+            let files_size = new_files.iter().map(|(_, file)| file.size()).sum::<u64>();
+            loop {
+                let plan = plans.last().unwrap();
+                let continuing = plan
+                    .parts
+                    .last()
+                    .is_some_and(|part| part.folder == folder_index);
+                let cost = blocks_size + files_size + if continuing { 0 } else { folder_size };
+                let file_count = plan.files.len() + new_files.len();
+                if header_size(plans.len() - 1, file_count) + size + cost <= max_size {
+                    break;
+                }
+                if fresh {
+                    bail!(
+                        "A maximum size of {} bytes is too small to hold blocks {} to {} of folder {}",
+                        max_size,
+                        blocks_range.start,
+                        blocks_range.end,
+                        folder_index
+                    );
+                }
+                // Start the next cabinet, continuing this folder in it if
+                // some of the folder is already in this one.
+                let mut plan = CabPlan::default();
+                size = 0;
+                if blocks_range.start > 0 {
+                    plan.parts.push(FolderPart {
+                        folder: folder_index,
+                        blocks: blocks_range.start..blocks_range.start,
+                    });
+                    size += folder_size;
+                    for &(index, file) in &folder_files {
+                        let range = file_range(file);
+                        if range.start < start && range.end > start {
+                            plan.files.push(index);
+                            size += file.size();
+                        }
+                    }
+                }
+                plans.push(plan);
+                fresh = true;
+            }
+            let plan = plans.last_mut().unwrap();
+            match plan.parts.last_mut() {
+                Some(part) if part.folder == folder_index => part.blocks.end = blocks_range.end,
+                _ => {
+                    plan.parts.push(FolderPart {
+                        folder: folder_index,
+                        blocks: blocks_range,
+                    });
+                    size += folder_size;
+                }
+            }
+            plan.files.extend(new_files.iter().map(|&(index, _)| index));
+            size += blocks_size + files_size;
+            fresh = false;
+        }
+    }
+
+    let count = plans.len();
+    let mut paths = vec![];
+    for (index, plan) in plans.into_iter().enumerate() {
+        let mut header = Header {
+            cabinet_index: index as u16,
+            prev: if index > 0 {
+                Some(link(index - 1))
+            } else {
+                None
+            },
+            next: if index + 1 < count {
+                Some(link(index + 1))
+            } else {
+                None
+            },
+            ..layout.header.clone()
+        };
+        if let Some(extras) = &extras {
+            header.header_reserve = per_cab_reserve(extras, &plan.files)?;
+        }
+        let mut folders = vec![];
+        for part in &plan.parts {
+            let (folder, blocks) = &mut layout.folders[part.folder];
+            folders.push((
+                Folder {
+                    reserve: folder.reserve.clone(),
+                    compression: folder.compression,
+                    ..Folder::default()
+                },
+                blocks[part.blocks.clone()]
+                    .iter_mut()
+                    .map(std::mem::take)
+                    .collect(),
+            ));
+        }
+        let files = plan
+            .files
+            .iter()
+            .map(|&file_index| {
+                let mut file = layout.files[file_index].clone();
+                let folder = usize::from(file.folder);
+                let (local, part) = plan
+                    .parts
+                    .iter()
+                    .enumerate()
+                    .find(|(_, part)| part.folder == folder)
+                    .unwrap();
+                let offsets = &block_offsets[folder];
+                let range = file_range(&file);
+                let from_prev = range.start < offsets[part.blocks.start];
+                let to_next = part.blocks.end < layout.folders[folder].1.len()
+                    && range.end > offsets[part.blocks.end];
+                file.folder = match (from_prev, to_next) {
+                    (true, true) => ifoldCONTINUED_PREV_AND_NEXT,
+                    (true, false) => ifoldCONTINUED_FROM_PREV,
+                    (false, true) => ifoldCONTINUED_TO_NEXT,
+                    (false, false) => local as u16,
+                };
+                file
+            })
+            .collect();
+        let cab = Layout {
+            header,
+            folders,
+            files,
+        };
+        let path = cab_path.with_file_name(names.cab_name(index));
+        let mut writer = BufWriter::new(temp_cab_file(&path)?);
+        cab.write_to(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .persist(&path)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Join the linked set of cabinets starting with the cabinet file at
+/// `cab_path` into a single cabinet file at `out_path`, returning the paths
+/// of the cabinets in the set.
+///
+/// The next cabinet in the set is looked for next to `cab_path`, under the
+/// name in each cabinet's header. Folders and files continued from one
+/// cabinet to the next are put back together, and the data blocks are
+/// copied across without being recompressed. If every cabinet in the set
+/// stores timestamps or CRC-32s for [`CabOptions`], the joined cabinet
+/// stores them for all the files.
+///
+/// It's an error for `cab_path` not to be the first cabinet in its set, or
+/// for the cabinets to disagree about the set they're in.
+///
+/// [`CabOptions`]: crate::CabOptions
+pub fn join_cabs<T: AsRef<Path>, U: AsRef<Path>>(cab_path: T, out_path: U) -> Result<Vec<PathBuf>> {
+    let (joined, paths) = join_set(cab_path.as_ref(), &mut |_| None)?;
+    let out_path = out_path.as_ref();
+    let mut writer = BufWriter::new(temp_cab_file(out_path)?);
+    joined.write_to(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .persist(out_path)?;
+    Ok(paths)
+}
+
+/// Join the linked set of cabinets starting with the cabinet file at
+/// `cab_path` into a single cabinet, as [`join_cabs`] does, returning it
+/// along with the paths of the cabinets in the set.
+///
+/// Each cabinet after the first is looked for next to the one before it,
+/// under the name in that cabinet's header, and if it isn't there, wherever
+/// `locate` says it is, given the link to it.
+pub(crate) fn join_set(
+    cab_path: &Path,
+    locate: &mut dyn FnMut(&CabinetLink) -> Option<PathBuf>,
+) -> Result<(Layout, Vec<PathBuf>)> {
+    let mut cab = parse_layout(BufReader::new(File::open(cab_path)?))?;
+    if cab.header.prev.is_some() {
+        bail!(
+            "'{}' isn't the first cabinet in its set",
+            cab_path.display()
+        );
+    }
+    let mut joined = Layout {
+        header: Header {
+            flags: cab.header.flags & !(cfhdrPREV_CABINET | cfhdrNEXT_CABINET),
+            next: None,
+            cabinet_index: 0,
+            ..cab.header.clone()
+        },
+        ..Layout::default()
+    };
+    let tables = read_reserve_tables(&cab.header.header_reserve, cab.files.len()).is_some();
+    let mut extras = Some(vec![]);
+    let mut paths = vec![cab_path.to_path_buf()];
+    // Whether the last folder so far is carried on in the next cabinet.
+    let mut continued = false;
+    loop {
+        let header = &cab.header;
+        if header.set_id != joined.header.set_id
+            || usize::from(header.cabinet_index) != paths.len() - 1
+            || header.folder_reserve_size != joined.header.folder_reserve_size
+            || header.data_reserve_size != joined.header.data_reserve_size
+        {
+            bail!(
+                "'{}' doesn't belong in the same set as '{}'",
+                paths.last().unwrap().display(),
+                cab_path.display()
+            );
+        }
+        let cab_extras = read_reserve_tables(&header.header_reserve, cab.files.len());
+        let mut folders = cab.folders.into_iter();
+        // The index in the joined cabinet of this cabinet's first folder.
+        let mut base = joined.folders.len();
+        if continued {
+            match (folders.next(), joined.folders.last_mut()) {
+                (Some((folder, blocks)), Some((last, last_blocks)))
+                    if folder.compression == last.compression =>
+                {
+                    last_blocks.extend(blocks);
+                    base -= 1;
+                }
+                _ => bail!(
+                    "'{}' doesn't carry on the folder continued from the previous cabinet",
+                    paths.last().unwrap().display()
+                ),
+            }
+        }
+        joined.folders.extend(folders);
+        let last_folder = joined.folders.len().saturating_sub(1);
+        continued = false;
+        for (index, mut file) in cab.files.into_iter().enumerate() {
+            file.folder = match file.folder {
+                // These were listed in a previous cabinet already.
+                ifoldCONTINUED_FROM_PREV => continue,
+                ifoldCONTINUED_PREV_AND_NEXT => {
+                    continued = true;
+                    continue;
+                }
+                ifoldCONTINUED_TO_NEXT => {
+                    continued = true;
+                    last_folder as u16
+                }
+                folder => (base + usize::from(folder)) as u16,
+            };
+            joined.files.push(file);
+            extras = match (extras, &cab_extras) {
+                (Some(mut extras), Some(cab_extras)) => {
+                    extras.push(cab_extras[index]);
+                    Some(extras)
+                }
+                _ => None,
+            };
+        }
+
+        let next = match &cab.header.next {
+            Some(next) => find_linked(paths.last().unwrap(), next, locate)?,
+            None => break,
+        };
+        if paths.contains(&next) {
+            bail!("The cabinet set loops back to '{}'", next.display());
+        }
+        cab = match File::open(&next) {
+            Ok(file) => parse_layout(BufReader::new(file))?,
+            Err(e) => bail!(
+                "Can't open the next cabinet in the set, '{}': {}",
+                next.display(),
+                e
+            ),
+        };
+        paths.push(next);
+    }
+
+    joined.header.header_reserve = match extras {
+        Some(extras) if tables => Reserve {
+            timestamps: extras.iter().map(|extra| extra.timestamps).collect(),
+            crc32s: extras.iter().map(|extra| extra.crc32).collect(),
+            ..Reserve::default()
+        }
+        .to_bytes()?,
+        // Tables that only some of the cabinets have can't be kept.
+        _ if tables => vec![],
+        _ => joined.header.header_reserve,
+    };
+    Ok((joined, paths))
+}
+
+/// Find the first cabinet in the set the cabinet file at `cab_path` is in,
+/// following the links to previous cabinets the way [`join_set`] follows
+/// the links to next ones.
+pub(crate) fn first_in_set(
+    cab_path: &Path,
+    locate: &mut dyn FnMut(&CabinetLink) -> Option<PathBuf>,
+) -> Result<PathBuf> {
+    let mut path = cab_path.to_path_buf();
+    let mut seen = vec![];
+    loop {
+        let header = read_header(&mut BufReader::new(File::open(&path)?))?;
+        let prev = match header.prev {
+            Some(prev) => prev,
+            None => return Ok(path),
+        };
+        seen.push(path);
+        path = find_linked(seen.last().unwrap(), &prev, locate)?;
+        if seen.contains(&path) {
+            bail!("The cabinet set loops back to '{}'", path.display());
+        }
+    }
+}
+
+/// Find the cabinet that `link` in the cabinet file at `from` links to:
+/// next to `from`, or wherever `locate` says it is.
+fn find_linked(
+    from: &Path,
+    link: &CabinetLink,
+    locate: &mut dyn FnMut(&CabinetLink) -> Option<PathBuf>,
+) -> Result<PathBuf> {
+    let path = from.with_file_name(&link.cabinet);
+    if path.is_file() {
+        return Ok(path);
+    }
+    match locate(link) {
+        Some(path) => Ok(path),
+        None => bail!(
+            "Can't find '{}' on '{}', the cabinet linked to from '{}'",
+            link.cabinet,
+            link.disk,
+            from.display()
+        ),
+    }
+}
+
+/// Writes a linked set of cabinets as the blocks of data for them are
+/// compressed, starting the next cabinet whenever the next block won't fit
+/// in the current one, or the current one's folder holds as many blocks as
+/// a folder can.
+///
+/// Each cabinet has a single folder. When a file carries on from one
+/// cabinet into the next, so does the folder, as [`split_cab_with_options`]
+/// leaves it; otherwise the next cabinet starts a folder of its own. The
+/// records at the start of a cabinet depend on which files end up in it, so
+/// its blocks are spooled to a temporary file until it's full. The sizes
+/// and CRC-32s in the records aren't known until the files have been read,
+/// so they're filled in by [`SetWriter::finish`], and until then each
+/// cabinet is a temporary file, so nothing is left behind if writing fails.
+pub(crate) struct SetWriter<'a> {
+    cab_path: &'a Path,
+    names: SetNames<'a>,
+    max_size: u64,
+    /// The records to store, for every file in the set.
+    header: Header,
+    folder: Folder,
+    file_names: Vec<Vec<u8>>,
+    files: Vec<FileEntry>,
+    reserve: Reserve,
+    /// The cabinets filled so far.
+    cabinets: Vec<SetCabinet>,
+    /// The cabinet being filled, whose blocks are in `spool`.
+    current: SetCabinet,
+    spool: BufWriter<File>,
+    /// The total uncompressed size of the blocks so far.
+    offset: u64,
+}
+
+/// A cabinet in a set being written by a [`SetWriter`].
+struct SetCabinet {
+    /// The files listed in the cabinet.
+    files: Range<usize>,
+    /// The size of the CFFILE records for `files`.
+    files_size: u64,
+    /// Where the cabinet's folder starts in the uncompressed data of all
+    /// the blocks, which is before the cabinet if the folder carries on
+    /// from the one before.
+    folder_start: u64,
+    /// Where the cabinet's blocks start and end in the uncompressed data.
+    data: Range<u64>,
+    blocks: usize,
+    /// The size of the cabinet's blocks.
+    data_size: u64,
+    /// Once the cabinet is full, the temporary file it's in, where it's
+    /// going and the size of the records before its blocks.
+    file: Option<(NamedTempFile, PathBuf, u64)>,
+}
+
+impl SetCabinet {
+    /// A cabinet listing no files yet, from `files`, with a folder starting
+    /// at `folder_start` and blocks starting at `start`.
+    fn new(files: usize, folder_start: u64, start: u64) -> SetCabinet {
+        SetCabinet {
+            files: files..files,
+            files_size: 0,
+            folder_start,
+            data: start..start,
+            blocks: 0,
+            data_size: 0,
+            file: None,
+        }
+    }
+}
+
+impl<'a> SetWriter<'a> {
+    /// Write a set named after `cab_path`, as `options` say, storing the
+    /// files `files`, named `file_names`, with `header`, `folder` and
+    /// `reserve` as the records for every cabinet would be if there was
+    /// only one.
+    pub fn new(
+        cab_path: &'a Path,
+        options: &'a SplitOptions,
+        header: Header,
+        folder: Folder,
+        file_names: Vec<Vec<u8>>,
+        files: Vec<FileEntry>,
+        reserve: Reserve,
+    ) -> Result<SetWriter<'a>> {
+        let names = SetNames::new(cab_path, options)?;
+        let spool = tempfile::tempfile_in(cab_dir(cab_path))?;
+        Ok(SetWriter {
+            cab_path,
+            names,
+            // The cabinet size is stored in 32 bits.
+            max_size: options.max_size.min(u64::from(u32::MAX)),
+            header,
+            folder,
+            file_names,
+            files,
+            reserve,
+            cabinets: vec![],
+            current: SetCabinet::new(0, 0, 0),
+            spool: BufWriter::new(spool),
+            offset: 0,
+        })
+    }
+
+    /// Write `block`, which holds data from `files`, to the current cabinet,
+    /// or to the next one if it won't fit.
+    pub fn write_block(&mut self, block: &DataBlock, files: Range<usize>) -> io::Result<()> {
+        if self.current.blocks > 0
+            && (self.current.blocks == usize::from(u16::MAX)
+                || self.size_with(files.end, block.size(), false) > self.max_size)
+        {
+            let previous = self.current.files.end;
+            self.close(false)?;
+            // The folder carries on if the block starts partway through a
+            // file listed in the cabinet before.
+            let (first_file, folder_start) = if files.start < previous {
+                (files.start, self.cabinets.last().unwrap().folder_start)
+            } else {
+                (previous, self.offset)
+            };
+            self.current = SetCabinet::new(first_file, folder_start, self.offset);
+        }
+        if self.size_with(files.end, block.size(), false) > self.max_size {
+            return Err(io::Error::other(format!(
+                "A maximum size of {} bytes is too small to hold a {} byte data block along \
+                 with the records that go with it",
+                self.max_size,
+                block.size()
+            )));
+        }
+        self.list_files(files.end);
+        block
+            .write_to(&mut self.spool)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.current.blocks += 1;
+        self.current.data_size += block.size();
+        self.offset += u64::from(block.uncompressed_size);
+        self.current.data.end = self.offset;
+        Ok(())
+    }
+
+    /// Finish writing the set, once the inputs have been `read`, giving the
+    /// size and CRC-32 of each, and move the cabinets into place, returning
+    /// their paths.
+    ///
+    /// With `durable`, each cabinet is flushed to disk before being moved
+    /// into place, and the directory holding them afterwards.
+    pub fn finish(mut self, read: &[(u64, u32)], durable: bool) -> Result<Vec<PathBuf>> {
+        // Files opened after the last block was read can only be empty, and
+        // go at the end of the last cabinet.
+        if self.size_with(read.len(), 0, true) > self.max_size {
+            bail!(
+                "A maximum size of {} bytes is too small to hold the last cabinet's records",
+                self.max_size
+            );
+        }
+        self.list_files(read.len());
+        self.close(true)?;
+        if let Some(crc32s) = &mut self.reserve.crc32s {
+            *crc32s = read.iter().map(|&(_, crc)| crc).collect();
+        }
+        let starts = read
+            .iter()
+            .scan(0, |start, &(size, _)| {
+                let file_start = *start;
+                *start += size;
+                Some(file_start)
+            })
+            .collect::<Vec<_>>();
+
+        let full = std::mem::take(&mut self.cabinets);
+        let count = full.len();
+        let mut cabinets = vec![];
+        for (index, mut cabinet) in full.into_iter().enumerate() {
+            let last = index + 1 == count;
+            let (mut file, path, records_size) = cabinet.file.take().unwrap();
+            let mut header = Header {
+                cabinet_index: index as u16,
+                header_reserve: self.reserve.for_files(cabinet.files.clone()).to_bytes()?,
+                file_count: cabinet.files.len() as u16,
+                prev: index.checked_sub(1).map(|prev| self.names.link(prev)),
+                next: if last {
+                    None
+                } else {
+                    Some(self.names.link(index + 1))
+                },
+                cabinet_size: (records_size + cabinet.data_size) as u32,
+                ..self.header.clone()
+            };
+            let folders_size = if self.files.is_empty() {
+                0
+            } else {
+                self.folder.size()
+            };
+            header.files_offset = (header.size() + folders_size) as u32;
+            let folder = Folder {
+                data_offset: records_size as u32,
+                data_count: cabinet.blocks as u16,
+                ..self.folder.clone()
+            };
+            let mut records = vec![];
+            header.write_to(&mut records)?;
+            if !self.files.is_empty() {
+                folder.write_to(&mut records)?;
+            }
+            for index in cabinet.files.clone() {
+                let (size, start) = (read[index].0, starts[index]);
+                let from_prev = start < cabinet.data.start;
+                let to_next = !last && start + size > cabinet.data.end;
+                let folder_offset = start - cabinet.folder_start;
+                if folder_offset > u64::from(u32::MAX) {
+                    bail!(
+                        "'{}' starts more than 4GB into a folder carried on from cabinet to \
+                         cabinet",
+                        self.files[index].name
+                    );
+                }
+                let entry = FileEntry {
+                    size: size as u32,
+                    folder_offset: folder_offset as u32,
+                    folder: match (from_prev, to_next) {
+                        (true, true) => ifoldCONTINUED_PREV_AND_NEXT,
+                        (true, false) => ifoldCONTINUED_FROM_PREV,
+                        (false, true) => ifoldCONTINUED_TO_NEXT,
+                        (false, false) => 0,
+                    },
+                    ..self.files[index].clone()
+                };
+                entry.write_with_name(&mut records, &self.file_names[index])?;
+            }
+            debug_assert_eq!(records.len() as u64, records_size);
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&records)?;
+            if durable {
+                file.as_file().sync_all()?;
+            }
+            cabinets.push((file, path));
+        }
+        let mut paths = vec![];
+        for (file, path) in cabinets {
+            file.persist(&path)?;
+            paths.push(path);
+        }
+        if durable {
+            sync_dir(cab_dir(self.cab_path))?;
+        }
+        Ok(paths)
+    }
+
+    /// List the files up to `end` in the current cabinet.
+    fn list_files(&mut self, end: usize) {
+        let cabinet = &mut self.current;
+        if end > cabinet.files.end {
+            cabinet.files_size += self.file_names[cabinet.files.end..end]
+                .iter()
+                .map(|name| CFFILE_SIZE + name.len() as u64 + 1)
+                .sum::<u64>();
+            cabinet.files.end = end;
+        }
+    }
+
+    /// The size of the current cabinet with the files up to `end` listed in
+    /// it and another `data_size` bytes of blocks, if it's the `last` in
+    /// the set or not.
+    fn size_with(&self, end: usize, data_size: u64, last: bool) -> u64 {
+        let cabinet = &self.current;
+        let end = end.max(cabinet.files.end);
+        let files_size = cabinet.files_size
+            + self.file_names[cabinet.files.end..end]
+                .iter()
+                .map(|name| CFFILE_SIZE + name.len() as u64 + 1)
+                .sum::<u64>();
+        self.records_size(cabinet.files.start..end, files_size, last)
+            + cabinet.data_size
+            + data_size
+    }
+
+    /// The size of the records at the start of the current cabinet, listing
+    /// `files` in `files_size` bytes of CFFILE records, if it's the `last`
+    /// in the set or not.
+    fn records_size(&self, files: Range<usize>, files_size: u64, last: bool) -> u64 {
+        let index = self.cabinets.len();
+        let header = Header {
+            header_reserve: vec![],
+            prev: index.checked_sub(1).map(|prev| self.names.link(prev)),
+            next: if last {
+                None
+            } else {
+                Some(self.names.link(index + 1))
+            },
+            ..self.header.clone()
+        };
+        let reserve_size = self.reserve.size_for(files.len()) as u64;
+        // The header only has room for the reserve sizes if there's a reserve.
+        let reserve_sizes =
+            if reserve_size > 0 && header.effective_flags() & cfhdrRESERVE_PRESENT == 0 {
+                4
+            } else {
+                0
+            };
+        let folders_size = if self.files.is_empty() {
+            0
+        } else {
+            self.folder.size()
+        };
+        header.size() + reserve_sizes + reserve_size + folders_size + files_size
+    }
+
+    /// Write the current cabinet to a temporary file next to where it's
+    /// going, leaving room for its records, which are written by
+    /// [`SetWriter::finish`].
+    fn close(&mut self, last: bool) -> io::Result<()> {
+        let path = self
+            .cab_path
+            .with_file_name(self.names.cab_name(self.cabinets.len()));
+        let records_size =
+            self.records_size(self.current.files.clone(), self.current.files_size, last);
+        let mut file = temp_cab_file(&path)?;
+        file.as_file().set_len(records_size)?;
+        file.seek(SeekFrom::Start(records_size))?;
+        self.spool.flush()?;
+        let spool = self.spool.get_mut();
+        spool.seek(SeekFrom::Start(0))?;
+        io::copy(&mut (&mut *spool).take(self.current.data_size), &mut file)?;
+        spool.set_len(0)?;
+        spool.seek(SeekFrom::Start(0))?;
+        self.current.file = Some((file, path, records_size));
+        let start = self.offset;
+        let full = std::mem::replace(&mut self.current, SetCabinet::new(0, 0, start));
+        self.cabinets.push(full);
+        Ok(())
+    }
+}
+
+/// The names of the cabinets in a set, and of the disks they're on, as
+/// [`SplitOptions`] says.
+struct SetNames<'a> {
+    options: &'a SplitOptions,
+    stem: String,
+    extension: String,
+}
+
+impl<'a> SetNames<'a> {
+    /// Name the cabinets in a set after `cab_path`, checking that `options`
+    /// names them as files next to it.
+    fn new(cab_path: &Path, options: &'a SplitOptions) -> Result<SetNames<'a>> {
+        if let Some(template) = &options.name_template {
+            if !template.contains("%d") || template.contains(['/', '\\']) {
+                bail!(
+                    "Cabinet name template '{}' must be a file name containing %d",
+                    template
+                );
+            }
+        }
+        let stem = match cab_path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem.to_owned(),
+            None => bail!("Bad filename: '{}'", cab_path.display()),
+        };
+        let extension = cab_path
+            .extension()
+            .map_or("cab".into(), |ext| ext.to_string_lossy().into_owned());
+        Ok(SetNames {
+            options,
+            stem,
+            extension,
+        })
+    }
+
+    /// The file name of cabinet `index` in the set.
+    fn cab_name(&self, index: usize) -> String {
+        match &self.options.name_template {
+            Some(template) => fill_template(template, index + 1),
+            None => format!("{}{}.{}", self.stem, index + 1, self.extension),
+        }
+    }
+
+    /// The link to cabinet `index` in the set.
+    fn link(&self, index: usize) -> CabinetLink {
+        let disk_template = self.options.disk_template.as_deref().unwrap_or("Disk %d");
+        CabinetLink {
+            cabinet: self.cab_name(index),
+            disk: fill_template(disk_template, index + 1),
+        }
+    }
+}
+
+/// Replace `%d` in `template` with `number`.
+fn fill_template(template: &str, number: usize) -> String {
+    template.replace("%d", &number.to_string())
+}
+
+/// Group the blocks of a folder whose blocks start at the uncompressed
+/// `offsets`, ending with the folder's size, into the runs that can't be
+/// split between cabinets, given the folder's `files` sorted by offset.
+///
+/// Extractors only carry a folder on into the next cabinet if a file does
+/// too, so the folder can only be split before a block that starts partway
+/// through a file. A folder without data is a single empty run.
+fn split_points(offsets: &[u64], files: &[(usize, &FileEntry)]) -> Vec<Range<usize>> {
+    let block_count = offsets.len() - 1;
+    let mut splittable = vec![false; block_count];
+    for (_, file) in files {
+        let start = u64::from(file.folder_offset);
+        let end = start + u64::from(file.size);
+        // The blocks starting strictly inside the file.
+        let first = offsets.partition_point(|&offset| offset <= start);
+        let last = offsets.partition_point(|&offset| offset < end);
+        for split in &mut splittable[first.min(block_count)..last.min(block_count)] {
+            *split = true;
+        }
+    }
+    let mut runs = vec![];
+    let mut run_start = 0;
+    for (block, &split) in splittable.iter().enumerate().skip(1) {
+        if split {
+            runs.push(run_start..block);
+            run_start = block;
+        }
+    }
+    runs.push(run_start..block_count);
+    runs
+}
+
+/// The header reserve for a cabinet in a set holding the files at `files`,
+/// with the tables from `extras` for just those files.
+fn per_cab_reserve(extras: &[FileExtras], files: &[usize]) -> Result<Vec<u8>> {
+    let extras = files.iter().map(|&index| extras[index]).collect::<Vec<_>>();
+    Reserve {
+        timestamps: extras.iter().map(|extra| extra.timestamps).collect(),
+        crc32s: extras.iter().map(|extra| extra.crc32).collect(),
+        ..Reserve::default()
+    }
+    .to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::sync::Arc;
+
+    use self::tempdir::TempDir;
+    use super::{join_cabs, split_cab, split_cab_with_options, split_points, SplitOptions};
+    use crate::format::parse::{parse_cab, parse_layout};
+    use crate::format::{
+        ifoldCONTINUED_FROM_PREV, ifoldCONTINUED_PREV_AND_NEXT, ifoldCONTINUED_TO_NEXT, FileEntry,
+    };
+    use crate::{
+        lint_cab, make_cab_from_files_with_options, make_cabs_from_manifest, verify, verify_cab,
+        CabOptions, NoneCompressor,
+    };
+
+    /// Bytes that don't compress.
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let a = noise(70_000, 1);
+        fs::write(t.path().join("a.bin"), &a).unwrap();
+        fs::write(t.path().join("b.txt"), b"bbbbbbbbbb").unwrap();
+        let manifest = t.path().join("build.json");
+        fs::write(
+            &manifest,
+            r#"{
+                "cab": "big.cab",
+                "entries": [
+                    {"source": "a.bin", "compression": "none"},
+                    {"source": "b.txt", "compression": "none"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        make_cabs_from_manifest(&manifest).expect("failed to create cab file");
+        let cab = t.path().join("big.cab");
+        assert!(split_cab(&cab, 1000).is_err());
+
+        let paths = split_cab(&cab, 35_000).expect("failed to split cab file");
+        let names = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["big1.cab", "big2.cab", "big3.cab"]);
+        let mut data = vec![];
+        for (index, path) in paths.iter().enumerate() {
+            assert!(fs::metadata(path).unwrap().len() <= 35_000);
+            assert_eq!(lint_cab(path).expect("failed to lint cab file"), vec![]);
+            let parsed = parse_cab(path).unwrap();
+            assert_eq!(usize::from(parsed.header.cabinet_index), index);
+            assert_eq!(
+                parsed
+                    .header
+                    .prev
+                    .as_ref()
+                    .map(|link| link.cabinet.as_str()),
+                index.checked_sub(1).map(|i| names[i])
+            );
+            assert_eq!(
+                parsed
+                    .header
+                    .next
+                    .as_ref()
+                    .map(|link| link.cabinet.as_str()),
+                names.get(index + 1).copied()
+            );
+            let a_folder = parsed
+                .files
+                .iter()
+                .find(|file| file.name == "a.bin")
+                .map(|file| file.folder);
+            let expected = [
+                ifoldCONTINUED_TO_NEXT,
+                ifoldCONTINUED_PREV_AND_NEXT,
+                ifoldCONTINUED_FROM_PREV,
+            ];
+            assert_eq!(a_folder, Some(expected[index]));
+            // The folders are uncompressed, so their data can be put back
+            // together by hand.
+            let layout = parse_layout(fs::File::open(path).unwrap()).unwrap();
+            for (_, blocks) in layout.folders {
+                for block in blocks {
+                    data.extend(block.data);
+                }
+            }
+        }
+        // Cabinets and disks can be named for the installer reading them.
+        let options = SplitOptions {
+            name_template: Some("pkg%d.cab".into()),
+            disk_template: Some("Setup Disk %d".into()),
+            ..SplitOptions::new(35_000)
+        };
+        let named = split_cab_with_options(&cab, &options).expect("failed to split cab file");
+        assert_eq!(named[0], t.path().join("pkg1.cab"));
+        let next = parse_cab(&named[1]).unwrap().header.next.unwrap();
+        assert_eq!(next.cabinet, "pkg3.cab");
+        assert_eq!(next.disk, "Setup Disk 3");
+        for template in &["pkg.cab", "dir/pkg%d.cab"] {
+            let options = SplitOptions {
+                name_template: Some(template.to_string()),
+                ..SplitOptions::new(35_000)
+            };
+            assert!(split_cab_with_options(&cab, &options).is_err());
+        }
+
+        let b = parse_cab(&paths[2]).unwrap();
+        assert!(b.files.iter().any(|f| f.name == "b.txt" && f.folder < 2));
+        assert_eq!(data.len(), 70_010);
+        assert_eq!(&data[..70_000], &a[..]);
+
+        // Each cabinet stores timestamps and CRC-32s for its own files.
+        let paths = (0..4)
+            .map(|i| {
+                let path = t.path().join(format!("file{}.bin", i));
+                fs::write(&path, noise(20_000, i)).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("tables.cab");
+        let options = CabOptions {
+            crc32: true,
+            high_res_timestamps: true,
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &paths, &options)
+            .expect("failed to create cab file");
+        let split = split_cab(&cab, 40_000).expect("failed to split cab file");
+        assert!(split.len() > 2);
+        for path in &split {
+            assert_eq!(lint_cab(path).expect("failed to lint cab file"), vec![]);
+            let parsed = parse_cab(path).unwrap();
+            assert_eq!(
+                parsed.header.header_reserve.len(),
+                16 + 20 * parsed.files.len()
+            );
+        }
+    }
+
+    #[test]
+    fn folder_split_points() {
+        let datetime = chrono::NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 8);
+        let offsets = [0, 10, 20, 30];
+        let a = FileEntry::new("a", 15, 0, 0, datetime);
+        let b = FileEntry::new("b", 15, 0, 15, datetime);
+        assert_eq!(
+            split_points(&offsets, &[(0, &a), (1, &b)]),
+            vec![0..1, 1..2, 2..3]
+        );
+        // A folder can't be split where a file ends at the end of a block.
+        let a = FileEntry::new("a", 10, 0, 0, datetime);
+        let b = FileEntry::new("b", 20, 0, 10, datetime);
+        assert_eq!(
+            split_points(&offsets, &[(0, &a), (1, &b)]),
+            vec![0..2, 2..3]
+        );
+        assert_eq!(split_points(&[0], &[]), vec![0..0]);
+    }
+
+    #[test]
+    fn join() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let paths = (0..4)
+            .map(|i| {
+                let path = t.path().join(format!("file{}.bin", i));
+                fs::write(&path, noise(30_000, i)).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let cab = t.path().join("big.cab");
+        let options = CabOptions {
+            crc32: true,
+            high_res_timestamps: true,
+            ..CabOptions::default()
+        };
+        make_cab_from_files_with_options(&cab, &paths, &options)
+            .expect("failed to create cab file");
+        let split = split_cab(&cab, 40_000).expect("failed to split cab file");
+        assert!(split.len() > 2);
+        assert!(join_cabs(&split[1], t.path().join("bad.cab")).is_err());
+
+        let joined = t.path().join("all.cab");
+        assert_eq!(join_cabs(&split[0], &joined).unwrap(), split);
+        assert_eq!(lint_cab(&joined).expect("failed to lint cab file"), vec![]);
+        // The CRC-32s for every file make it into the joined cabinet.
+        let report = verify_cab(&joined).expect("cab file didn't verify");
+        assert!(report.is_ok());
+        assert_eq!(parse_cab(&joined).unwrap(), parse_cab(&cab).unwrap());
+    }
+
+    #[test]
+    fn write_set() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let input = t.path().join("big.bin");
+        let data = noise(100_000, 7);
+        fs::write(&input, &data).unwrap();
+        let cab = t.path().join("disk.cab");
+        let mut options = CabOptions {
+            split: Some(SplitOptions {
+                name_template: Some("disk%d.cab".into()),
+                ..SplitOptions::new(40_000)
+            }),
+            ..CabOptions::default()
+        };
+        let summary = make_cab_from_files_with_options(&cab, &[&input], &options)
+            .expect("failed to create cab set");
+        assert!(!cab.exists());
+        assert_eq!(summary.cabinets.len(), 3);
+        assert_eq!(summary.cabinets[0], t.path().join("disk1.cab"));
+        // The one file carries on through every cabinet in the set.
+        let folders = summary
+            .cabinets
+            .iter()
+            .map(|path| parse_cab(path).unwrap().files[0].folder)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            folders,
+            [
+                ifoldCONTINUED_TO_NEXT,
+                ifoldCONTINUED_PREV_AND_NEXT,
+                ifoldCONTINUED_FROM_PREV
+            ]
+        );
+        let joined = t.path().join("joined.cab");
+        join_cabs(&summary.cabinets[0], &joined).expect("failed to join cab set");
+        verify(&joined, &input).expect("cab file didn't verify");
+
+        // The first cabinet in the set can be the destination itself.
+        let first = t.path().join("disk1.cab");
+        options.durable = true;
+        let summary = make_cab_from_files_with_options(&first, &[&input], &options)
+            .expect("failed to create cab set");
+        assert_eq!(summary.cabinets[0], first);
+        assert!(summary.cabinets.iter().all(|path| path.exists()));
+        assert_eq!(
+            summary.cab_size,
+            summary
+                .cabinets
+                .iter()
+                .map(|path| fs::metadata(path).unwrap().len())
+                .sum::<u64>()
+        );
+        join_cabs(&first, &joined).expect("failed to join cab set");
+        verify(&joined, &input).expect("cab file didn't verify");
+
+        // Each cabinet stores timestamps and CRC-32s for its own files.
+        let inputs = (0..4)
+            .map(|i| {
+                let path = t.path().join(format!("file{}.bin", i));
+                fs::write(&path, noise(20_000, i)).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let tables = CabOptions {
+            crc32: true,
+            high_res_timestamps: true,
+            threads: 2,
+            split: Some(SplitOptions::new(40_000)),
+            ..CabOptions::default()
+        };
+        let summary =
+            make_cab_from_files_with_options(t.path().join("tables.cab"), &inputs, &tables)
+                .expect("failed to create cab set");
+        assert!(summary.cabinets.len() > 2);
+        for path in &summary.cabinets {
+            let parsed = parse_cab(path).unwrap();
+            assert_eq!(
+                parsed.header.header_reserve.len(),
+                16 + 20 * parsed.files.len()
+            );
+        }
+        join_cabs(&summary.cabinets[0], &joined).expect("failed to join cab set");
+        assert!(verify_cab(&joined).expect("cab file didn't verify").is_ok());
+
+        options.preallocate = true;
+        assert!(make_cab_from_files_with_options(&cab, &[&input], &options).is_err());
+        options.preallocate = false;
+        options.block_index = true;
+        assert!(make_cab_from_files_with_options(&cab, &[&input], &options).is_err());
+    }
+
+    #[test]
+    fn write_set_past_folder_limit() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let input = t.path().join("big.bin");
+        let data = noise(70_000 * 16, 3);
+        fs::write(&input, &data).unwrap();
+        let cab = t.path().join("big.cab");
+        let mut options = CabOptions {
+            chunk_size: Some(16),
+            compressor: Some(Arc::new(NoneCompressor)),
+            ..CabOptions::default()
+        };
+        // More blocks than fit in one folder.
+        assert!(make_cab_from_files_with_options(&cab, &[&input], &options).is_err());
+
+        options.split = Some(SplitOptions::new(100_000_000));
+        let summary = make_cab_from_files_with_options(&cab, &[&input], &options)
+            .expect("failed to create cab set");
+        assert_eq!(summary.cabinets.len(), 2);
+        assert_eq!(summary.blocks.len(), 70_000);
+        let mut stored = vec![];
+        let mut block_counts = vec![];
+        for path in &summary.cabinets {
+            assert_eq!(lint_cab(path).expect("failed to lint cab file"), vec![]);
+            let layout = parse_layout(fs::File::open(path).unwrap()).unwrap();
+            assert_eq!(layout.folders.len(), 1);
+            for (_, blocks) in layout.folders {
+                block_counts.push(blocks.len());
+                for block in blocks {
+                    stored.extend(block.data);
+                }
+            }
+        }
+        assert_eq!(block_counts, vec![65_535, 70_000 - 65_535]);
+        assert!(stored == data);
+        let folders = summary
+            .cabinets
+            .iter()
+            .map(|path| parse_cab(path).unwrap().files[0].folder)
+            .collect::<Vec<_>>();
+        assert_eq!(folders, [ifoldCONTINUED_TO_NEXT, ifoldCONTINUED_FROM_PREV]);
+    }
+}
@@ -0,0 +1,213 @@
+//! Generators and checks for testing code that writes cabinets with this
+//! crate, built with the `test-support` feature.
+//!
+//! The generators are [proptest] strategies for file names, file contents
+//! and [`CabOptions`], aimed at the edges of the format: contents that fill
+//! blocks exactly or spill just past them, incompressible data next to data
+//! that compresses to almost nothing, and names with directories.
+//! [`check_round_trip`] writes a cabinet with this crate's encoder, then
+//! checks what its decoder and, on Windows, the system's `expand` get back
+//! out of it. Integrators can run the same checks on their own data:
+//!
+//! ```no_run
+//! use makecab::test_support::{cab_options, check_round_trip, files};
+//! use proptest::prelude::*;
+//!
+//! proptest! {
+//!     #[test]
+//!     fn round_trip(files in files(), options in cab_options()) {
+//!         check_round_trip(&files, &options).unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! [proptest]: https://docs.rs/proptest
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use proptest::prelude::*;
+use tempfile::TempDir;
+
+use crate::{
+    extract_cab, make_cab_from_dir, verify_cab, CabOptions, MemberOrder, PathFilter, MAX_CHUNK,
+};
+
+/// Words that text-like contents are made of.
+const WORDS: &[&str] = &[
+    "cabinet ",
+    "folder ",
+    "block ",
+    "MSZIP ",
+    "deflate ",
+    "\r\n",
+    "\t",
+    "0123456789 ",
+];
+
+/// Sizes of file contents, clustered around multiples of the 32768 byte
+/// block size, where bugs in splitting data into blocks show up.
+pub fn sizes() -> impl Strategy<Value = usize> {
+    prop_oneof![
+        0..100usize,
+        (1..4usize, -100..100isize)
+            .prop_map(|(blocks, offset)| (blocks * MAX_CHUNK) as isize + offset)
+            .prop_map(|size| size as usize),
+        0..MAX_CHUNK * 3,
+    ]
+}
+
+/// File contents: incompressible bytes, runs of a single byte, and text.
+pub fn contents() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        (any::<u64>(), sizes()).prop_map(|(seed, size)| noise(seed, size)),
+        (any::<u8>(), sizes()).prop_map(|(byte, size)| vec![byte; size]),
+        prop::collection::vec(prop::sample::select(WORDS), 0..5000)
+            .prop_map(|words| words.concat().into_bytes()),
+    ]
+}
+
+/// Stored names, with up to two directories separated by `/`. They're all
+/// lower case, so none of them collide when compared without case, and
+/// file names have an extension while directories don't, so a file is
+/// never also a directory.
+pub fn names() -> impl Strategy<Value = String> {
+    "([a-z0-9_]{1,8}/){0,2}[a-z0-9_]{1,8}\\.[a-z0-9]{1,3}"
+}
+
+/// Between one and eight files, as `(name, contents)`, sorted by name.
+pub fn files() -> impl Strategy<Value = Vec<(String, Vec<u8>)>> {
+    prop::collection::btree_map(names(), contents(), 1..8)
+        .prop_map(|files| files.into_iter().collect())
+}
+
+/// Options that change how the cabinet is laid out and compressed without
+/// changing what's extracted from it.
+pub fn cab_options() -> impl Strategy<Value = CabOptions> {
+    let orders = vec![
+        MemberOrder::Input,
+        MemberOrder::Name,
+        MemberOrder::Size,
+        MemberOrder::Extension,
+    ];
+    (
+        0..3usize,
+        prop::option::of(1..=MAX_CHUNK),
+        prop::option::of(0..=9u32),
+        any::<bool>(),
+        any::<bool>(),
+        prop::sample::select(orders),
+    )
+        .prop_map(
+            |(threads, chunk_size, compression_level, high_res_timestamps, crc32, order)| {
+                CabOptions {
+                    threads,
+                    chunk_size,
+                    compression_level,
+                    high_res_timestamps,
+                    crc32,
+                    order,
+                    ..CabOptions::default()
+                }
+            },
+        )
+}
+
+/// Write `files`, given as `(name, contents)` with `/` between directories,
+/// into a cabinet with `options`, and check that the cabinet's checksums
+/// verify and that extracting it gives back exactly `files`, both with this
+/// crate and, on Windows, with `expand`.
+pub fn check_round_trip(files: &[(String, Vec<u8>)], options: &CabOptions) -> Result<()> {
+    let t = TempDir::new()?;
+    let source = t.path().join("source");
+    for (name, data) in files {
+        let path = source.join(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, data)?;
+    }
+    let cab = t.path().join("test.cab");
+    make_cab_from_dir(&cab, &source, &PathFilter::default(), options)?;
+    let report = verify_cab(&cab)?;
+    if !report.is_ok() {
+        bail!("The cabinet didn't verify: {:?}", report);
+    }
+    if report.files.len() != files.len() {
+        bail!(
+            "{} files were stored, not {}",
+            report.files.len(),
+            files.len()
+        );
+    }
+
+    let ours = t.path().join("ours");
+    extract_cab(&cab, &ours)?;
+    compare_extracted(files, &ours, "this crate")?;
+
+    #[cfg(windows)]
+    {
+        let theirs = t.path().join("theirs");
+        fs::create_dir_all(&theirs)?;
+        let output = std::process::Command::new("expand")
+            .arg(&cab)
+            .arg("-F:*")
+            .arg(&theirs)
+            .output()?;
+        if !output.status.success() {
+            bail!("expand failed: {}", String::from_utf8_lossy(&output.stdout));
+        }
+        compare_extracted(files, &theirs, "expand")?;
+    }
+    Ok(())
+}
+
+/// Check that each of `files` was extracted into `dir` by `extractor` with
+/// the right contents.
+fn compare_extracted(files: &[(String, Vec<u8>)], dir: &Path, extractor: &str) -> Result<()> {
+    for (name, data) in files {
+        let extracted = match fs::read(dir.join(name)) {
+            Ok(extracted) => extracted,
+            Err(e) => bail!("{} didn't extract {}: {}", extractor, name, e),
+        };
+        if extracted != *data {
+            bail!(
+                "{} extracted {} bytes of {} that differ from the {} stored",
+                extractor,
+                extracted.len(),
+                name,
+                data.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `size` bytes that don't compress, from an xorshift generator seeded with
+/// `seed`.
+fn noise(seed: u64, size: usize) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{cab_options, check_round_trip, files};
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn round_trip(files in files(), options in cab_options()) {
+            check_round_trip(&files, &options).unwrap();
+        }
+    }
+}
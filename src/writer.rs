@@ -0,0 +1,210 @@
+//! A cabinet writer that implements `Write`.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Result};
+use cab::{CabinetBuilder, CompressionType};
+use chrono::Utc;
+use tempfile::NamedTempFile;
+
+use crate::{temp_cab_file, MAX_CHUNK};
+
+/// How many blocks of data can be queued up for compression before writes
+/// block.
+const QUEUED_BLOCKS: usize = 4;
+
+/// Writes a cabinet file containing a single file whose contents are written
+/// incrementally through the `Write` impl.
+///
+/// The cabinet is written to a temporary file next to the cabinet path, and
+/// [`CabWriter::finish`], called once all data has been written, completes it
+/// and moves it into place. Dropping a `CabWriter` without finishing it
+/// abandons the cabinet, deleting the temporary file and leaving whatever
+/// was at the cabinet path alone.
+///
+/// Compression happens on a separate thread, so that the `cab` crate's
+/// writer can borrow the file it writes to for as long as it needs. Only a
+/// few blocks of data are buffered at a time.
+pub struct CabWriter {
+    cab_path: PathBuf,
+    buf: Vec<u8>,
+    sender: Option<SyncSender<io::Result<Vec<u8>>>>,
+    /// Writes the cabinet to a temporary file next to `cab_path`, and
+    /// returns it once it's complete.
+    thread: Option<JoinHandle<Result<NamedTempFile>>>,
+}
+
+impl CabWriter {
+    /// Create a cabinet file at `cab_path` containing a single file stored as
+    /// `name`, with the current time as its modification time.
+    pub fn create<T: AsRef<Path>, S: Into<String>>(cab_path: T, name: S) -> Result<CabWriter> {
+        let cab_path = cab_path.as_ref();
+        let cab_file = temp_cab_file(cab_path)?;
+        let mut cab_builder = CabinetBuilder::new();
+        let folder = cab_builder.add_folder(CompressionType::MsZip);
+        let file = folder.add_file(name);
+        file.set_datetime(Utc::now().naive_utc());
+
+        let (sender, receiver) = sync_channel(QUEUED_BLOCKS);
+        let thread = thread::spawn(move || {
+            let mut reader = ChannelReader {
+                receiver,
+                buf: vec![],
+                pos: 0,
+            };
+            let mut cab_writer = cab_builder.build(cab_file)?;
+            while let Some(mut writer) = cab_writer.next_file()? {
+                io::copy(&mut reader, &mut writer)?;
+            }
+            Ok(cab_writer.finish()?)
+        });
+        Ok(CabWriter {
+            cab_path: cab_path.to_path_buf(),
+            buf: Vec::with_capacity(MAX_CHUNK),
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Finish writing the cabinet and move it into place, returning any
+    /// error that happened while compressing or writing it.
+    pub fn finish(mut self) -> Result<()> {
+        let send_result = self.send_buf();
+        // Closing the channel tells the compression thread it has all the data.
+        self.sender = None;
+        let temp = self.join()?;
+        send_result?;
+        if let Some(temp) = temp {
+            temp.persist(&self.cab_path)?;
+        }
+        Ok(())
+    }
+
+    /// Send any buffered data to the compression thread.
+    fn send_buf(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let buf = std::mem::replace(&mut self.buf, Vec::with_capacity(MAX_CHUNK));
+        match self.sender.as_ref().map(|sender| sender.send(Ok(buf))) {
+            Some(Ok(())) => Ok(()),
+            // The thread only stops receiving if it failed, and `finish` will
+            // report why.
+            _ => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "cabinet writer thread exited",
+            )),
+        }
+    }
+
+    /// Wait for the compression thread to finish, returning the cabinet it
+    /// wrote, or `None` if it was already waited for.
+    fn join(&mut self) -> Result<Option<NamedTempFile>> {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result.map(Some),
+            Some(Err(_)) => Err(anyhow!("cabinet writer thread panicked")),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Write for CabWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_CHUNK - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        if self.buf.len() == MAX_CHUNK {
+            self.send_buf()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buf()
+    }
+}
+
+impl Drop for CabWriter {
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(Err(io::Error::other(
+                "CabWriter dropped without calling finish",
+            )));
+        }
+        // Dropping the unfinished cabinet deletes its temporary file.
+        let _ = self.join();
+    }
+}
+
+/// Reads the data sent from a `CabWriter`.
+struct ChannelReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            match self.receiver.recv() {
+                Ok(data) => {
+                    self.buf = data?;
+                    self.pos = 0;
+                }
+                // The channel is closed once all data has been sent.
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.buf.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::io::{self, Write};
+
+    use self::tempdir::TempDir;
+    use super::CabWriter;
+    use crate::{verify, MAX_CHUNK};
+
+    #[test]
+    fn streaming() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let data = (0..MAX_CHUNK * 5 + 123)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        let in_path = t.path().join("payload.bin");
+        fs::write(&in_path, &data).expect("failed to write test data");
+
+        let cab = t.path().join("test.cab");
+        let mut writer = CabWriter::create(&cab, "payload.bin").expect("failed to create cab");
+        io::copy(&mut &data[..1000], &mut writer).expect("failed to write data");
+        writer.flush().expect("failed to flush");
+        writer
+            .write_all(&data[1000..])
+            .expect("failed to write data");
+        writer.finish().expect("failed to finish cab");
+        verify(&cab, &in_path).expect("failed to verify cab file");
+    }
+
+    #[test]
+    fn abandoned() {
+        let t = TempDir::new("makecab").expect("failed to create temp dir");
+        let cab = t.path().join("test.cab");
+        let mut writer = CabWriter::create(&cab, "payload.bin").expect("failed to create cab");
+        writer
+            .write_all(b"incomplete")
+            .expect("failed to write data");
+        drop(writer);
+        assert!(!cab.exists());
+        assert_eq!(fs::read_dir(t.path()).unwrap().count(), 0);
+    }
+}